@@ -1,8 +1,12 @@
 use wasm_bindgen::prelude::*;
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use crate::memory::MemoryCapsule;
-use crate::utils::generate_unique_id;
+use crate::utils::{generate_unique_id, now_ms, sha256_hex, sign_hex, verify_hex, parse_verifying_key, hex_encode};
+use crate::integrity::{IntegrityIssue, IntegritySeverity};
+use crate::error::DnnError;
+use crate::contract_script::evaluate_contract_code;
+use ed25519_dalek::SigningKey;
 
 // Import the console_log macro
 use crate::console_log;
@@ -14,12 +18,46 @@ use crate::console_log;
 pub struct BlockchainLedger {
     blocks: Vec<Block>,
     pending_transactions: Vec<Transaction>,
+    transaction_log: Vec<Transaction>, // Write-ahead log of every transaction, mined or not
     smart_contracts: HashMap<String, SmartContract>,
-    account_balances: HashMap<String, f64>, // device_id -> credits
-    memory_registry: HashMap<String, MemoryRecord>, // capsule_id -> record
+    contract_deployment_order: Vec<String>, // contract_ids, oldest first
+    account_balances: HashMap<String, f64>, // device_id -> credits settled by mined blocks
+    reserved_balances: HashMap<String, f64>, // device_id -> credits locked by pending outgoing transactions
+    pub(crate) memory_registry: HashMap<String, MemoryRecord>, // capsule_id -> record
     node_borrowing_registry: HashMap<String, BorrowingRecord>,
+    escrow: HashMap<String, f64>, // borrowing_id -> payment frozen while the borrowing is disputed
+    registration_rate_limit: u32, // Max capsule registrations per uploader per minute (0 = unlimited)
+    registration_times: HashMap<String, Vec<f64>>, // uploader -> recent registration timestamps
+    registration_reward_decay: f64, // Reward divisor growth per recent registration by the same uploader (0 = flat)
+    removal_refund_policy: RemovalRefundPolicy,
+    difficulty: usize, // Leading zero hex digits required of newly mined block hashes
+    miner_address: Option<String>, // Receives the coinbase of locally mined blocks; None mines without rewards
+    transaction_fee: f64, // Fee attached to each new non-system transaction
+    subsidy_halving_interval: u64, // Blocks between subsidy halvings (0 = never halve)
+    signing_keys: HashMap<String, SigningKey>, // Accounts whose debits this device signs
+    account_keys: HashMap<String, String>, // account -> hex ed25519 key its debits in received blocks must verify against
 }
 
+/// Ledger account holding disputed borrowing payments
+const ESCROW_ACCOUNT: &str = "escrow";
+
+/// Default memory validation: scores a capsule from `novelty_score`, `importance_score` and
+/// `semantic_tag_count` into `quality_score`, capped at 1
+const MEMORY_VALIDATION_CODE: &str = "quality_score = 0.5; \
+    if novelty_score > 0.7 { quality_score = quality_score + 0.2 } \
+    if importance_score > 0.8 { quality_score = quality_score + 0.2 } \
+    if semantic_tag_count > 3 { quality_score = quality_score + 0.1 } \
+    if quality_score > 1 { quality_score = 1 }";
+
+/// Default borrowing permission: `approved` when the borrower has more than 1 spendable credit
+const BORROWING_PERMISSION_CODE: &str = "approved = borrower_balance > 1";
+
+/// Coinbase subsidy for blocks before the first halving
+const BLOCK_SUBSIDY: f64 = 1.0;
+
+/// Longest meaningful difficulty: a SHA-256 hex digest has 64 digits
+const MAX_DIFFICULTY: usize = 64;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Block {
     pub index: u64,
@@ -29,6 +67,8 @@ pub struct Block {
     pub transactions: Vec<Transaction>,
     pub merkle_root: String,
     pub nonce: u64,
+    #[serde(default)]
+    pub difficulty: usize, // Proof-of-work target the block was mined at
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -37,10 +77,12 @@ pub struct Transaction {
     pub from: String,
     pub to: String,
     pub amount: f64,
+    #[serde(default)]
+    pub fee: f64, // Paid by the sender to whoever mines the transaction
     pub tx_type: TransactionType,
     pub timestamp: f64,
     pub signature: String,
-    pub metadata: HashMap<String, String>,
+    pub metadata: BTreeMap<String, String>, // Ordered so a transaction serializes, and hashes, the same everywhere
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -50,6 +92,9 @@ pub enum TransactionType {
     ContributionReward, // Reward for network contributions
     PenaltyCharge,    // Penalty for network violations
     ContractExecution, // Smart contract execution
+    BorrowingRefund,  // Refund to a borrower when a borrowed node disappears
+    DisputeResolution, // Moves a disputed borrowing payment into or out of escrow
+    Coinbase,         // Block subsidy plus collected fees, minted to the miner
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -105,6 +150,21 @@ pub enum BorrowingStatus {
     Active,
     Completed,
     Disputed,
+    Cancelled,
+}
+
+/// How a borrower is compensated when the node it borrowed is removed
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RemovalRefundPolicy {
+    Prorated, // Refund the unused share of the borrowing period
+    Full,     // Refund the entire cost
+    NoRefund, // Cancel without refunding
+}
+
+impl Default for BlockchainLedger {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[wasm_bindgen]
@@ -114,10 +174,24 @@ impl BlockchainLedger {
         let mut ledger = BlockchainLedger {
             blocks: Vec::new(),
             pending_transactions: Vec::new(),
+            transaction_log: Vec::new(),
             smart_contracts: HashMap::new(),
+            contract_deployment_order: Vec::new(),
             account_balances: HashMap::new(),
+            reserved_balances: HashMap::new(),
             memory_registry: HashMap::new(),
             node_borrowing_registry: HashMap::new(),
+            escrow: HashMap::new(),
+            registration_rate_limit: 0,
+            registration_times: HashMap::new(),
+            registration_reward_decay: 0.0,
+            removal_refund_policy: RemovalRefundPolicy::Prorated,
+            difficulty: 2,
+            miner_address: None,
+            transaction_fee: 0.0,
+            subsidy_halving_interval: 1000,
+            signing_keys: HashMap::new(),
+            account_keys: HashMap::new(),
         };
 
         // Create genesis block
@@ -131,15 +205,18 @@ impl BlockchainLedger {
     }
 
     fn create_genesis_block(&mut self) {
-        let genesis_block = Block {
+        // Fixed contents so every device derives the same genesis hash and chains can sync
+        let mut genesis_block = Block {
             index: 0,
-            timestamp: js_sys::Date::now(),
+            timestamp: 0.0,
             previous_hash: "0".to_string(),
-            hash: "genesis_hash".to_string(),
+            hash: String::new(),
             transactions: Vec::new(),
-            merkle_root: "genesis_merkle".to_string(),
+            merkle_root: self.calculate_merkle_root(&[]),
             nonce: 0,
+            difficulty: 0,
         };
+        genesis_block.hash = hash_block_contents(&genesis_block);
         
         self.blocks.push(genesis_block);
     }
@@ -155,31 +232,56 @@ impl BlockchainLedger {
             is_active: true,
             execution_cost: 0.01,
         };
-        self.smart_contracts.insert("incentive_distributor".to_string(), incentive_contract);
+        self.deploy_contract(incentive_contract);
 
         // Memory validation contract
         let memory_contract = SmartContract {
             contract_id: "memory_validator".to_string(),
             contract_type: ContractType::MemoryValidation,
             creator: "system".to_string(),
-            code: "validate_memory_capsule_integrity_and_privacy".to_string(),
+            code: MEMORY_VALIDATION_CODE.to_string(),
             state: HashMap::new(),
             is_active: true,
             execution_cost: 0.005,
         };
-        self.smart_contracts.insert("memory_validator".to_string(), memory_contract);
+        self.deploy_contract(memory_contract);
 
         // Node borrowing permission contract
         let borrowing_contract = SmartContract {
             contract_id: "node_borrowing_manager".to_string(),
             contract_type: ContractType::NodeBorrowingPermission,
             creator: "system".to_string(),
-            code: "check_borrower_credits_and_reputation_before_approval".to_string(),
+            code: BORROWING_PERMISSION_CODE.to_string(),
             state: HashMap::new(),
             is_active: true,
             execution_cost: 0.02,
         };
-        self.smart_contracts.insert("node_borrowing_manager".to_string(), borrowing_contract);
+        self.deploy_contract(borrowing_contract);
+    }
+
+    fn deploy_contract(&mut self, contract: SmartContract) {
+        self.contract_deployment_order.retain(|id| id != &contract.contract_id);
+        self.contract_deployment_order.push(contract.contract_id.clone());
+        self.smart_contracts.insert(contract.contract_id.clone(), contract);
+    }
+
+    /// The active contract governing a type of operation (e.g. "IncentiveDistribution"),
+    /// as JSON with its id, code, and state. The most recently deployed one wins.
+    #[wasm_bindgen]
+    pub fn get_active_contract_by_type(&self, contract_type: String) -> String {
+        self.active_contract_of(&contract_type)
+            .map(|contract| serde_json::to_string(contract).unwrap_or_default())
+            .unwrap_or_default()
+    }
+
+    fn active_contract_of(&self, contract_type: &str) -> Option<&SmartContract> {
+        self.contract_deployment_order.iter()
+            .rev()
+            .filter_map(|id| self.smart_contracts.get(id))
+            .find(|contract| {
+                contract.is_active
+                    && format!("{:?}", contract.contract_type).eq_ignore_ascii_case(contract_type)
+            })
     }
 
     #[wasm_bindgen]
@@ -191,35 +293,82 @@ impl BlockchainLedger {
             from: "system".to_string(),
             to: device_id.clone(),
             amount: initial_credits,
+            fee: 0.0,
             tx_type: TransactionType::ContributionReward,
-            timestamp: js_sys::Date::now(),
+            timestamp: now_ms(),
             signature: "system_signature".to_string(),
-            metadata: HashMap::new(),
+            metadata: BTreeMap::new(),
         };
         
-        self.pending_transactions.push(tx);
+        self.record_transaction(tx);
         console_log!("Registered device {} with {} initial credits", device_id, initial_credits);
         true
     }
 
+    /// Max capsule registrations per uploader per minute; 0 (the default) is unlimited
+    #[wasm_bindgen]
+    pub fn set_registration_rate_limit(&mut self, capsules_per_minute: u32) {
+        self.registration_rate_limit = capsules_per_minute;
+    }
+
+    /// Diminishing returns for bursts: a registration's reward is divided by
+    /// 1 + decay x (the uploader's registrations in the past minute). 0 (the default) pays flat.
+    #[wasm_bindgen]
+    pub fn set_registration_reward_decay(&mut self, decay: f64) -> bool {
+        if !decay.is_finite() || decay < 0.0 {
+            return false;
+        }
+        self.registration_reward_decay = decay;
+        true
+    }
+
+    /// Whether the uploader has used up its registrations for the current minute
+    #[wasm_bindgen]
+    pub fn is_rate_limited(&self, uploader: &str) -> bool {
+        if self.registration_rate_limit == 0 {
+            return false;
+        }
+        
+        let window_start = now_ms() - 60000.0;
+        let recent = self.registration_times.get(uploader)
+            .map(|times| times.iter().filter(|&&time| time > window_start).count())
+            .unwrap_or(0);
+        
+        recent >= self.registration_rate_limit as usize
+    }
+
     #[wasm_bindgen]
     pub fn register_memory_capsule(&mut self, capsule_json: &str, uploader: String) -> String {
+        if self.is_rate_limited(&uploader) {
+            console_log!("Rejected memory capsule from {}: rate limit of {} registrations per minute exceeded",
+                uploader, self.registration_rate_limit);
+            return "".to_string();
+        }
+        
         if let Ok(capsule) = serde_json::from_str::<MemoryCapsule>(capsule_json) {
+            // Record the registration for rate limiting
+            let now = now_ms();
+            let times = self.registration_times.entry(uploader.clone()).or_default();
+            times.retain(|&time| now - time < 60000.0);
+            let recent_registrations = times.len();
+            times.push(now);
+            
             // Execute memory validation contract
             let quality_score = self.execute_memory_validation_contract(&capsule);
             
-            // Calculate incentive based on quality and novelty
+            // Calculate incentive based on quality and novelty, diminished for bursts of uploads
             let base_reward = 1.0;
             let quality_multiplier = quality_score;
             let novelty_multiplier = capsule.novelty_score;
-            let incentive = base_reward * quality_multiplier * novelty_multiplier;
+            let burst_divisor = 1.0 + self.registration_reward_decay * recent_registrations as f64;
+            let incentive = base_reward * quality_multiplier * novelty_multiplier / burst_divisor;
             
             // Create memory record
             let memory_record = MemoryRecord {
                 capsule_id: capsule.capsule_id.clone(),
                 uploader: uploader.clone(),
                 timestamp: capsule.timestamp,
-                hash: self.calculate_hash(&capsule_json),
+                hash: self.calculate_hash(capsule_json),
                 privacy_level: format!("{:?}", capsule.privacy_level),
                 incentive_earned: incentive,
                 access_permissions: vec![uploader.clone()], // Default: only uploader can access
@@ -235,18 +384,19 @@ impl BlockchainLedger {
                 from: "system".to_string(),
                 to: uploader.clone(),
                 amount: incentive,
+                fee: 0.0,
                 tx_type: TransactionType::MemoryUpload,
-                timestamp: js_sys::Date::now(),
+                timestamp: now_ms(),
                 signature: "contract_signature".to_string(),
                 metadata: {
-                    let mut meta = HashMap::new();
+                    let mut meta = BTreeMap::new();
                     meta.insert("capsule_id".to_string(), capsule.capsule_id.clone());
                     meta.insert("quality_score".to_string(), quality_score.to_string());
                     meta
                 },
             };
             
-            self.pending_transactions.push(tx);
+            self.record_transaction(tx);
             
             // Update account balance
             *self.account_balances.entry(uploader.clone()).or_insert(0.0) += incentive;
@@ -261,12 +411,13 @@ impl BlockchainLedger {
     #[wasm_bindgen]
     pub fn request_node_borrowing(&mut self, borrower: String, node_owner: String, node_id: String, duration: f64) -> String {
         // Check borrower's credits and reputation
-        let borrower_balance = self.account_balances.get(&borrower).copied().unwrap_or(0.0);
+        let borrower_balance = self.spendable_balance(&borrower);
         let cost_per_hour = 0.5;
         let total_cost = cost_per_hour * duration;
+        let required = total_cost + self.fee_for(&borrower);
         
-        if borrower_balance < total_cost {
-            console_log!("Insufficient credits for borrowing. Required: {}, Available: {}", total_cost, borrower_balance);
+        if borrower_balance < required {
+            console_log!("Insufficient credits for borrowing. Required: {}, Available: {}", required, borrower_balance);
             return "".to_string();
         }
         
@@ -284,7 +435,7 @@ impl BlockchainLedger {
             borrower: borrower.clone(),
             node_owner: node_owner.clone(),
             node_id: node_id.clone(),
-            start_time: js_sys::Date::now(),
+            start_time: now_ms(),
             duration,
             cost: total_cost,
             status: BorrowingStatus::Approved,
@@ -299,11 +450,12 @@ impl BlockchainLedger {
             from: borrower.clone(),
             to: node_owner.clone(),
             amount: total_cost,
+            fee: 0.0,
             tx_type: TransactionType::NodeBorrowing,
-            timestamp: js_sys::Date::now(),
+            timestamp: now_ms(),
             signature: "borrower_signature".to_string(),
             metadata: {
-                let mut meta = HashMap::new();
+                let mut meta = BTreeMap::new();
                 meta.insert("borrowing_id".to_string(), borrowing_id.clone());
                 meta.insert("node_id".to_string(), node_id);
                 meta.insert("duration".to_string(), duration.to_string());
@@ -311,10 +463,8 @@ impl BlockchainLedger {
             },
         };
         
-        self.pending_transactions.push(tx);
-        
-        // Update balances
-        *self.account_balances.entry(borrower).or_insert(0.0) -= total_cost;
+        // The borrower's payment stays reserved until the transaction is mined
+        self.record_transaction(tx);
         *self.account_balances.entry(node_owner).or_insert(0.0) += total_cost;
         
         console_log!("Approved node borrowing request: {}", borrowing_id);
@@ -344,13 +494,14 @@ impl BlockchainLedger {
                         from: "system".to_string(),
                         to: record.borrower.clone(),
                         amount: bonus,
+                        fee: 0.0,
                         tx_type: TransactionType::ContributionReward,
-                        timestamp: js_sys::Date::now(),
+                        timestamp: now_ms(),
                         signature: "system_signature".to_string(),
-                        metadata: HashMap::new(),
+                        metadata: BTreeMap::new(),
                     };
                     
-                    self.pending_transactions.push(bonus_tx);
+                    self.record_transaction(bonus_tx);
                     console_log!("Performance bonus awarded: {}", bonus);
                 }
             }
@@ -362,148 +513,1691 @@ impl BlockchainLedger {
         }
     }
 
+    /// Freeze the payment of an in-use or completed borrowing in escrow until resolve_dispute.
+    /// Approved borrowings count as in use, since nothing marks them Active yet.
     #[wasm_bindgen]
-    pub fn mine_block(&mut self) -> String {
-        if self.pending_transactions.is_empty() {
-            return "".to_string();
+    pub fn dispute_node_borrowing(&mut self, borrowing_id: String, reason: &str) -> bool {
+        let record = match self.node_borrowing_registry.get(&borrowing_id) {
+            Some(record) if matches!(record.status,
+                BorrowingStatus::Approved | BorrowingStatus::Active | BorrowingStatus::Completed) => record.clone(),
+            _ => return false,
+        };
+        
+        if self.spendable_balance(&record.node_owner) < record.cost + self.fee_for(&record.node_owner) {
+            console_log!("Cannot dispute {}: owner {} no longer holds the payment", borrowing_id, record.node_owner);
+            return false;
         }
         
-        let previous_block = self.blocks.last().unwrap();
-        let new_block = Block {
-            index: previous_block.index + 1,
-            timestamp: js_sys::Date::now(),
-            previous_hash: previous_block.hash.clone(),
-            hash: self.calculate_block_hash(previous_block.index + 1, &self.pending_transactions),
-            transactions: self.pending_transactions.clone(),
-            merkle_root: self.calculate_merkle_root(&self.pending_transactions),
-            nonce: self.find_nonce(),
+        self.escrow.insert(borrowing_id.clone(), record.cost);
+        *self.account_balances.entry(ESCROW_ACCOUNT.to_string()).or_insert(0.0) += record.cost;
+        self.record_dispute_transaction(&record.node_owner, ESCROW_ACCOUNT, record.cost, &borrowing_id, reason);
+        
+        if let Some(record) = self.node_borrowing_registry.get_mut(&borrowing_id) {
+            record.status = BorrowingStatus::Disputed;
+        }
+        console_log!("Borrowing {} disputed: {}", borrowing_id, reason);
+        true
+    }
+
+    /// Release a disputed payment from escrow to the borrower (refund) or the node owner
+    #[wasm_bindgen]
+    pub fn resolve_dispute(&mut self, borrowing_id: String, refund_borrower: bool) -> bool {
+        let record = match self.node_borrowing_registry.get(&borrowing_id) {
+            Some(record) if matches!(record.status, BorrowingStatus::Disputed) => record.clone(),
+            _ => return false,
+        };
+        let amount = match self.escrow.remove(&borrowing_id) {
+            Some(amount) => amount,
+            None => return false,
         };
         
-        self.blocks.push(new_block.clone());
-        self.pending_transactions.clear();
+        let (recipient, outcome, status) = if refund_borrower {
+            (record.borrower.clone(), "refunded to borrower", BorrowingStatus::Cancelled)
+        } else {
+            (record.node_owner.clone(), "paid to owner", BorrowingStatus::Completed)
+        };
+        *self.account_balances.entry(recipient.clone()).or_insert(0.0) += amount;
+        self.record_dispute_transaction(ESCROW_ACCOUNT, &recipient, amount, &borrowing_id, outcome);
         
-        console_log!("Mined new block #{} with {} transactions", new_block.index, new_block.transactions.len());
-        new_block.hash
+        if let Some(record) = self.node_borrowing_registry.get_mut(&borrowing_id) {
+            record.status = status;
+        }
+        console_log!("Resolved dispute for {}: {} {}", borrowing_id, amount, outcome);
+        true
     }
 
-    fn execute_memory_validation_contract(&self, capsule: &MemoryCapsule) -> f64 {
-        // Simplified validation logic
-        let mut quality_score: f64 = 0.5; // Base score
-        
-        // Check novelty
-        if capsule.novelty_score > 0.7 {
-            quality_score += 0.2;
+    fn record_dispute_transaction(&mut self, from: &str, to: &str, amount: f64, borrowing_id: &str, note: &str) {
+        let tx = Transaction {
+            tx_id: generate_unique_id("dispute"),
+            from: from.to_string(),
+            to: to.to_string(),
+            amount,
+            fee: 0.0,
+            tx_type: TransactionType::DisputeResolution,
+            timestamp: now_ms(),
+            signature: "contract_signature".to_string(),
+            metadata: {
+                let mut meta = BTreeMap::new();
+                meta.insert("borrowing_id".to_string(), borrowing_id.to_string());
+                meta.insert("note".to_string(), note.to_string());
+                meta
+            },
+        };
+        self.record_transaction(tx);
+    }
+
+    /// Set the refund policy for node removal: "prorated", "full", or "none"
+    #[wasm_bindgen]
+    pub fn set_removal_refund_policy(&mut self, policy: &str) -> bool {
+        self.removal_refund_policy = match policy.to_lowercase().as_str() {
+            "prorated" => RemovalRefundPolicy::Prorated,
+            "full" => RemovalRefundPolicy::Full,
+            "none" => RemovalRefundPolicy::NoRefund,
+            _ => return false,
+        };
+        true
+    }
+
+    /// Cancel Approved/Active borrowings of a node that no longer exists, refunding borrowers
+    /// according to the removal refund policy. Returns the number of cancelled records.
+    #[wasm_bindgen]
+    pub fn settle_borrowings_for_node(&mut self, node_owner: &str, node_id: &str) -> u32 {
+        let now = now_ms();
+        let affected: Vec<String> = self.node_borrowing_registry.values()
+            .filter(|record| record.node_owner == node_owner && record.node_id == node_id)
+            .filter(|record| matches!(record.status, BorrowingStatus::Approved | BorrowingStatus::Active))
+            .map(|record| record.borrowing_id.clone())
+            .collect();
+
+        for borrowing_id in &affected {
+            if let Some(record) = self.node_borrowing_registry.get_mut(borrowing_id) {
+                record.status = BorrowingStatus::Cancelled;
+
+                // Duration is in hours, timestamps in milliseconds
+                let used_fraction = if record.duration > 0.0 {
+                    ((now - record.start_time) / (record.duration * 3600000.0)).clamp(0.0, 1.0)
+                } else {
+                    1.0
+                };
+                let refund = match self.removal_refund_policy {
+                    RemovalRefundPolicy::Prorated => record.cost * (1.0 - used_fraction),
+                    RemovalRefundPolicy::Full => record.cost,
+                    RemovalRefundPolicy::NoRefund => 0.0,
+                };
+                let borrower = record.borrower.clone();
+
+                let available = self.spendable_balance(node_owner);
+                if refund > 0.0 && refund + self.fee_for(node_owner) > available {
+                    console_log!("Cannot refund {} for borrowing {}: owner has only {} spendable",
+                        refund, borrowing_id, available);
+                } else if refund > 0.0 {
+                    *self.account_balances.entry(borrower.clone()).or_insert(0.0) += refund;
+
+                    let tx = Transaction {
+                        tx_id: generate_unique_id("refund"),
+                        from: node_owner.to_string(),
+                        to: borrower,
+                        amount: refund,
+                        fee: 0.0,
+                        tx_type: TransactionType::BorrowingRefund,
+                        timestamp: now,
+                        signature: "contract_signature".to_string(),
+                        metadata: {
+                            let mut meta = BTreeMap::new();
+                            meta.insert("borrowing_id".to_string(), borrowing_id.clone());
+                            meta.insert("node_id".to_string(), node_id.to_string());
+                            meta
+                        },
+                    };
+                    self.record_transaction(tx);
+                }
+
+                console_log!("Cancelled borrowing {} for removed node {}, refund: {}", borrowing_id, node_id, refund);
+            }
         }
-        
-        // Check importance
-        if capsule.importance_score > 0.8 {
-            quality_score += 0.2;
+
+        affected.len() as u32
+    }
+
+    /// Transfer a collaboration reward from the session initiator to a participant
+    #[wasm_bindgen]
+    pub fn pay_contribution_reward(&mut self, from: &str, to: &str, amount: f64, session_id: &str) -> Result<(), DnnError> {
+        if amount <= 0.0 {
+            return Err(DnnError::InvalidInput(format!("reward amount {} must be positive", amount)));
         }
-        
-        // Check semantic tags richness
-        if capsule.semantic_tags.len() > 3 {
-            quality_score += 0.1;
+        let available = self.spendable_balance(from);
+        let required = amount + self.fee_for(from);
+        if available < required {
+            return Err(DnnError::InsufficientCredits { required, available });
         }
         
-        quality_score.min(1.0)
+        *self.account_balances.entry(to.to_string()).or_insert(0.0) += amount;
+        
+        let tx = Transaction {
+            tx_id: generate_unique_id("contrib"),
+            from: from.to_string(),
+            to: to.to_string(),
+            amount,
+            fee: 0.0,
+            tx_type: TransactionType::ContributionReward,
+            timestamp: now_ms(),
+            signature: "contract_signature".to_string(),
+            metadata: {
+                let mut meta = BTreeMap::new();
+                meta.insert("session_id".to_string(), session_id.to_string());
+                meta
+            },
+        };
+        self.record_transaction(tx);
+        Ok(())
     }
 
-    fn execute_borrowing_permission_contract(&self, borrower: &str, _node_owner: &str, _node_id: &str) -> bool {
-        // Check borrower's credit history and reputation
-        let borrower_balance = self.account_balances.get(borrower).copied().unwrap_or(0.0);
+    /// Credit a sharing reward once the receiving peer's receipt proved delivery
+    pub fn finalize_sharing_reward(&mut self, sharer: &str, peer_id: &str, capsule_id: &str, receipt_hash: &str, amount: f64) -> Result<(), DnnError> {
+        if amount <= 0.0 {
+            return Err(DnnError::InvalidInput(format!("reward amount {} must be positive", amount)));
+        }
+        
+        *self.account_balances.entry(sharer.to_string()).or_insert(0.0) += amount;
         
-        // Simple approval logic
-        borrower_balance > 1.0 // Must have at least 1 credit
+        let tx = Transaction {
+            tx_id: generate_unique_id("share_reward"),
+            from: "system".to_string(),
+            to: sharer.to_string(),
+            amount,
+            fee: 0.0,
+            tx_type: TransactionType::ContributionReward,
+            timestamp: now_ms(),
+            signature: "contract_signature".to_string(),
+            metadata: {
+                let mut meta = BTreeMap::new();
+                meta.insert("capsule_id".to_string(), capsule_id.to_string());
+                meta.insert("receiver".to_string(), peer_id.to_string());
+                meta.insert("receipt_hash".to_string(), receipt_hash.to_string());
+                meta
+            },
+        };
+        self.record_transaction(tx);
+        Ok(())
     }
 
-    fn calculate_hash(&self, data: &str) -> String {
-        // Simplified hash function
-        format!("hash_{}", crate::utils::simple_hash(data))
+    // Queue a transaction for mining and append it to the write-ahead log
+    // Outgoing amounts are reserved here and only debited once the transaction is mined
+    fn record_transaction(&mut self, mut tx: Transaction) {
+        if tx.from != "system" {
+            tx.fee = self.fee_for(&tx.from);
+            *self.reserved_balances.entry(tx.from.clone()).or_insert(0.0) += tx.amount + tx.fee;
+            if let Some(key) = self.signing_keys.get(&tx.from) {
+                tx.signature = sign_hex(key, &transaction_signing_payload(&tx));
+            }
+        }
+        self.transaction_log.push(tx.clone());
+        self.pending_transactions.push(tx);
+    }
+
+    // Debit a mined transaction's sender and release the matching reservation
+    fn settle_reserved(&mut self, tx: &Transaction) {
+        if tx.from == "system" {
+            return;
+        }
+        *self.account_balances.entry(tx.from.clone()).or_insert(0.0) -= tx.amount + tx.fee;
+        if let Some(reserved) = self.reserved_balances.get_mut(&tx.from) {
+            *reserved -= tx.amount + tx.fee;
+            if *reserved <= 1e-9 {
+                self.reserved_balances.remove(&tx.from);
+            }
+        }
     }
 
-    fn calculate_block_hash(&self, index: u64, transactions: &[Transaction]) -> String {
-        let tx_data = serde_json::to_string(transactions).unwrap_or_default();
-        format!("block_{}_{}", index, crate::utils::simple_hash(&tx_data))
+    fn recompute_reservations(&mut self) {
+        self.reserved_balances.clear();
+        for tx in &self.pending_transactions {
+            if tx.from != "system" {
+                *self.reserved_balances.entry(tx.from.clone()).or_insert(0.0) += tx.amount + tx.fee;
+            }
+        }
     }
 
-    fn calculate_merkle_root(&self, transactions: &[Transaction]) -> String {
-        if transactions.is_empty() {
-            return "empty_merkle".to_string();
+    // Fee charged on a new transaction from `from`; escrow releases are exempt, since escrow only
+    // ever holds the disputed payment itself
+    fn fee_for(&self, from: &str) -> f64 {
+        if from == "system" || from == ESCROW_ACCOUNT {
+            0.0
+        } else {
+            self.transaction_fee
         }
+    }
+
+    /// Balance minus credits locked in pending transactions; every debit is checked against this
+    /// together with the fee the debit will carry
+    pub fn spendable_balance(&self, device_id: &str) -> f64 {
+        self.get_account_balance(device_id) - self.reserved_balances.get(device_id).copied().unwrap_or(0.0)
+    }
+
+    #[wasm_bindgen]
+    pub fn export_transaction_log(&self) -> String {
+        serde_json::to_string(&self.transaction_log).unwrap_or_default()
+    }
+
+    /// Rebuild account balances by replaying an exported transaction log.
+    /// Logged transactions not found in a mined block are re-queued as pending.
+    #[wasm_bindgen]
+    pub fn replay_transaction_log(&mut self, log_json: &str) -> Result<(), DnnError> {
+        let log = serde_json::from_str::<Vec<Transaction>>(log_json)
+            .map_err(|e| DnnError::ParseError(format!("transaction log: {}", e)))?;
+        
+        let mined: HashSet<&String> = self.blocks.iter()
+            .flat_map(|block| block.transactions.iter().map(|tx| &tx.tx_id))
+            .collect();
         
-        // Simplified merkle root calculation
-        let tx_hashes: Vec<String> = transactions.iter()
-            .map(|tx| self.calculate_hash(&tx.tx_id))
+        // Pending transactions credit their recipient but stay reserved against the sender
+        let mut balances: HashMap<String, f64> = HashMap::new();
+        for tx in &log {
+            if mined.contains(&tx.tx_id) {
+                apply_transaction_effect(&mut balances, tx);
+            } else {
+                credit_recipient(&mut balances, tx);
+            }
+        }
+        let pending: Vec<Transaction> = log.iter()
+            .filter(|tx| !mined.contains(&tx.tx_id))
+            .cloned()
             .collect();
         
-        format!("merkle_{}", crate::utils::simple_hash(&tx_hashes.join("")))
+        console_log!("Replayed {} logged transactions ({} pending)", log.len(), pending.len());
+        self.account_balances = balances;
+        self.pending_transactions = pending;
+        self.transaction_log = log;
+        self.recompute_reservations();
+        Ok(())
     }
 
-    fn find_nonce(&self) -> u64 {
-        // Simplified proof-of-work (not secure, just for demonstration)
-        use rand::Rng;
-        rand::thread_rng().gen_range(1000..9999)
+    /// Serialize the ledger state (chain, pending work, balances, registries) for persistence
+    #[wasm_bindgen]
+    pub fn export_chain(&self) -> String {
+        serde_json::to_string(&ChainSnapshot {
+            blocks: self.blocks.clone(),
+            pending_transactions: self.pending_transactions.clone(),
+            transaction_log: self.transaction_log.clone(),
+            account_balances: self.account_balances.clone(),
+            memory_registry: self.memory_registry.clone(),
+            node_borrowing_registry: self.node_borrowing_registry.clone(),
+            escrow: self.escrow.clone(),
+            difficulty: self.difficulty,
+        }).unwrap_or_default()
     }
 
+    /// Restore an export_chain document; the current state is kept if it fails to parse or validate
     #[wasm_bindgen]
-    pub fn get_account_balance(&self, device_id: &str) -> f64 {
-        self.account_balances.get(device_id).copied().unwrap_or(0.0)
+    pub fn import_chain(&mut self, json: &str) -> bool {
+        let snapshot = match serde_json::from_str::<ChainSnapshot>(json) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                console_log!("Failed to parse chain snapshot: {:?}", e);
+                return false;
+            }
+        };
+        
+        let mut restored = self.clone();
+        restored.blocks = snapshot.blocks;
+        restored.pending_transactions = snapshot.pending_transactions;
+        restored.transaction_log = snapshot.transaction_log;
+        restored.account_balances = snapshot.account_balances;
+        restored.memory_registry = snapshot.memory_registry;
+        restored.node_borrowing_registry = snapshot.node_borrowing_registry;
+        restored.escrow = snapshot.escrow;
+        // A snapshot may raise the difficulty but not lower it below ours
+        restored.difficulty = snapshot.difficulty.clamp(self.difficulty, MAX_DIFFICULTY);
+        restored.recompute_reservations();
+        
+        if restored.blocks.is_empty() || !restored.validate_chain() {
+            console_log!("Rejected chain snapshot: chain failed validation");
+            return false;
+        }
+        // validate_chain holds blocks to the difficulty they were mined at; incoming ones must also meet ours
+        if restored.blocks.iter().any(|block| block.index > 0 && block.difficulty < self.difficulty) {
+            console_log!("Rejected chain snapshot: blocks mined below difficulty {}", self.difficulty);
+            return false;
+        }
+        
+        console_log!("Imported chain with {} blocks", restored.blocks.len());
+        *self = restored;
+        true
     }
 
+    /// Mined blocks with index >= from_index, as JSON, for peers catching up
     #[wasm_bindgen]
-    pub fn get_memory_record(&self, capsule_id: &str) -> String {
-        if let Some(record) = self.memory_registry.get(capsule_id) {
-            serde_json::to_string(record).unwrap_or_default()
+    pub fn get_blocks_from(&self, from_index: u64) -> String {
+        let blocks: Vec<&Block> = self.blocks.iter()
+            .filter(|block| block.index >= from_index)
+            .collect();
+        serde_json::to_string(&blocks).unwrap_or_default()
+    }
+
+    /// Append a block received from a peer if it links to the current tip
+    #[wasm_bindgen]
+    pub fn receive_block(&mut self, block_json: &str) -> Result<(), DnnError> {
+        let block = serde_json::from_str::<Block>(block_json)
+            .map_err(|e| DnnError::ParseError(format!("block: {}", e)))?;
+        let index = block.index;
+        if self.append_received_block(block) {
+            Ok(())
         } else {
-            "".to_string()
+            Err(DnnError::InvalidInput(format!("block #{} does not link to the local chain tip", index)))
         }
     }
 
+    /// Apply a range of blocks from a peer in index order, skipping blocks already held.
+    /// Stops at the first block that doesn't link; returns the number appended.
     #[wasm_bindgen]
-    pub fn get_blockchain_stats(&self) -> JsValue {
-        let stats = BlockchainStats {
-            total_blocks: self.blocks.len(),
-            total_transactions: self.blocks.iter().map(|b| b.transactions.len()).sum(),
-            pending_transactions: self.pending_transactions.len(),
-            total_accounts: self.account_balances.len(),
-            total_memory_capsules: self.memory_registry.len(),
-            total_borrowing_records: self.node_borrowing_registry.len(),
-            total_smart_contracts: self.smart_contracts.len(),
+    pub fn apply_blocks(&mut self, blocks_json: &str) -> u32 {
+        let mut blocks = match serde_json::from_str::<Vec<Block>>(blocks_json) {
+            Ok(blocks) => blocks,
+            Err(_) => return 0,
         };
+        blocks.sort_by_key(|block| block.index);
         
-        serde_wasm_bindgen::to_value(&stats).unwrap_or(JsValue::NULL)
+        let mut applied = 0;
+        for block in blocks {
+            if let Some(existing) = self.blocks.get(block.index as usize) {
+                if existing.hash == block.hash {
+                    continue;
+                }
+                console_log!("Block #{} conflicts with local chain; stopping sync", block.index);
+                break;
+            }
+            if !self.append_received_block(block) {
+                break;
+            }
+            applied += 1;
+        }
+        applied
     }
 
-    #[wasm_bindgen]
-    pub fn validate_chain(&self) -> bool {
-        if self.blocks.len() < 2 {
-            return true;
+    fn append_received_block(&mut self, block: Block) -> bool {
+        let tip = match self.blocks.last() {
+            Some(tip) => tip,
+            None => return false,
+        };
+        
+        if block.index != tip.index + 1
+            || block.previous_hash != tip.hash
+            || block.hash != hash_block_contents(&block)
+            || block.difficulty < self.difficulty
+            || !meets_difficulty(&block.hash, block.difficulty)
+            || block.merkle_root != self.calculate_merkle_root(&block.transactions)
+            || !self.coinbase_within_reward(&block)
+        {
+            console_log!("Rejected block #{}: fails linkage, proof-of-work or reward checks against local tip #{}",
+                block.index, tip.index);
+            return false;
         }
         
-        for i in 1..self.blocks.len() {
-            let current = &self.blocks[i];
-            let previous = &self.blocks[i - 1];
-            
-            if current.previous_hash != previous.hash {
-                return false;
-            }
-            
-            if current.index != previous.index + 1 {
-                return false;
+        // Apply transactions we haven't seen; ours are already reflected in balances
+        let known: HashSet<String> = self.transaction_log.iter().map(|tx| tx.tx_id.clone()).collect();
+        if let Err(reason) = self.check_received_transactions(&block, &known) {
+            console_log!("Rejected block #{}: {}", block.index, reason);
+            return false;
+        }
+        for tx in &block.transactions {
+            if !known.contains(&tx.tx_id) {
+                apply_transaction_effect(&mut self.account_balances, tx);
+                self.transaction_log.push(tx.clone());
             }
         }
+        let mined: HashSet<&String> = block.transactions.iter().map(|tx| &tx.tx_id).collect();
+        let (settled, pending): (Vec<Transaction>, Vec<Transaction>) = std::mem::take(&mut self.pending_transactions)
+            .into_iter()
+            .partition(|tx| mined.contains(&tx.tx_id));
+        self.pending_transactions = pending;
+        for tx in &settled {
+            self.settle_reserved(tx);
+        }
         
+        console_log!("Appended received block #{} with {} transactions", block.index, block.transactions.len());
+        self.blocks.push(block);
         true
     }
-}
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct BlockchainStats {
-    pub total_blocks: usize,
-    pub total_transactions: usize,
-    pub pending_transactions: usize,
-    pub total_accounts: usize,
-    pub total_memory_capsules: usize,
-    pub total_borrowing_records: usize,
-    pub total_smart_contracts: usize,
-} 
\ No newline at end of file
+    /// Block at `index` as JSON, or "" if the chain has no such block
+    #[wasm_bindgen]
+    pub fn get_block(&self, index: u64) -> String {
+        self.blocks.iter()
+            .find(|block| block.index == index)
+            .map(|block| serde_json::to_string(block).unwrap_or_default())
+            .unwrap_or_default()
+    }
+
+    /// The last `count` blocks, newest first, as JSON
+    #[wasm_bindgen]
+    pub fn get_latest_blocks(&self, count: usize) -> String {
+        let blocks: Vec<&Block> = self.blocks.iter().rev().take(count).collect();
+        serde_json::to_string(&blocks).unwrap_or_default()
+    }
+
+    #[wasm_bindgen]
+    pub fn get_block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    #[wasm_bindgen]
+    pub fn get_chain_tip_index(&self) -> u64 {
+        self.blocks.last().map(|block| block.index).unwrap_or(0)
+    }
+
+    #[wasm_bindgen]
+    pub fn mine_block(&mut self) -> String {
+        if self.pending_transactions.is_empty() {
+            return "".to_string();
+        }
+        
+        let previous_block = self.blocks.last().unwrap();
+        let index = previous_block.index + 1;
+        let previous_hash = previous_block.hash.clone();
+        let mut transactions = self.pending_transactions.clone();
+        
+        // Coinbase: the subsidy plus every fee in the block, minted to the miner
+        if let Some(miner) = self.miner_address.clone() {
+            let reward = self.block_subsidy(index) + transactions.iter().map(|tx| tx.fee).sum::<f64>();
+            let coinbase = Transaction {
+                tx_id: generate_unique_id("coinbase"),
+                from: "system".to_string(),
+                to: miner.clone(),
+                amount: reward,
+                fee: 0.0,
+                tx_type: TransactionType::Coinbase,
+                timestamp: now_ms(),
+                signature: "coinbase_signature".to_string(),
+                metadata: {
+                    let mut meta = BTreeMap::new();
+                    meta.insert("block_index".to_string(), index.to_string());
+                    meta
+                },
+            };
+            *self.account_balances.entry(miner).or_insert(0.0) += reward;
+            self.transaction_log.push(coinbase.clone());
+            transactions.push(coinbase);
+        }
+        
+        let mut new_block = Block {
+            index,
+            timestamp: now_ms(),
+            previous_hash,
+            hash: String::new(),
+            merkle_root: self.calculate_merkle_root(&transactions),
+            transactions,
+            nonce: 0,
+            difficulty: self.difficulty,
+        };
+        new_block.hash = self.find_nonce(&mut new_block);
+        
+        self.blocks.push(new_block.clone());
+        for tx in std::mem::take(&mut self.pending_transactions) {
+            self.settle_reserved(&tx);
+        }
+        
+        console_log!("Mined new block #{} with {} transactions", new_block.index, new_block.transactions.len());
+        new_block.hash
+    }
+
+    /// Sign new debits from `account` with a 32-byte ed25519 secret, e.g. the device's P2P identity
+    /// key, and pin its public key; false if the secret is malformed or another key is pinned
+    #[wasm_bindgen]
+    pub fn set_account_signing_key(&mut self, account: String, secret_key: &[u8]) -> bool {
+        let Ok(secret) = <[u8; 32]>::try_from(secret_key) else {
+            return false;
+        };
+        let key = SigningKey::from_bytes(&secret);
+        if !self.register_account_key(account.clone(), hex_encode(key.verifying_key().as_bytes())) {
+            return false;
+        }
+        self.signing_keys.insert(account, key);
+        true
+    }
+
+    /// Pin the hex ed25519 key that debits from `account` in received blocks must be signed with;
+    /// false if it does not parse or a different key is already pinned
+    #[wasm_bindgen]
+    pub fn register_account_key(&mut self, account: String, public_key: String) -> bool {
+        if parse_verifying_key(&public_key).is_none() {
+            return false;
+        }
+        match self.account_keys.get(&account) {
+            Some(pinned) if *pinned != public_key => {
+                console_log!("Refusing to replace the pinned key of account {}", account);
+                false
+            },
+            _ => {
+                self.account_keys.insert(account, public_key);
+                true
+            }
+        }
+    }
+
+    /// Credit the coinbase of locally mined blocks to `device_id`
+    #[wasm_bindgen]
+    pub fn set_miner_address(&mut self, device_id: String) {
+        self.miner_address = Some(device_id);
+    }
+
+    /// Fee attached to each new non-system transaction and collected by the miner
+    #[wasm_bindgen]
+    pub fn set_transaction_fee(&mut self, fee: f64) -> bool {
+        if !fee.is_finite() || fee < 0.0 {
+            return false;
+        }
+        self.transaction_fee = fee;
+        true
+    }
+
+    /// Halve the block subsidy every `blocks` blocks; 0 keeps it fixed
+    #[wasm_bindgen]
+    pub fn set_subsidy_halving_interval(&mut self, blocks: u64) {
+        self.subsidy_halving_interval = blocks;
+    }
+
+    /// Coinbase subsidy for the block at `index`, before fees
+    #[wasm_bindgen]
+    pub fn block_subsidy(&self, index: u64) -> f64 {
+        if self.subsidy_halving_interval == 0 {
+            return BLOCK_SUBSIDY;
+        }
+        let halvings = index / self.subsidy_halving_interval;
+        if halvings >= 64 {
+            0.0
+        } else {
+            BLOCK_SUBSIDY / (1u64 << halvings) as f64
+        }
+    }
+
+    // A block may mint at most its subsidy plus the fees it collects. Only fees paid by real
+    // senders count: "system" is never debited, so a fee on its transactions would be free money
+    fn coinbase_within_reward(&self, block: &Block) -> bool {
+        if block.transactions.iter().any(|tx| !tx.fee.is_finite() || tx.fee < 0.0) {
+            return false;
+        }
+        let minted: f64 = block.transactions.iter()
+            .filter(|tx| matches!(tx.tx_type, TransactionType::Coinbase))
+            .map(|tx| tx.amount)
+            .sum();
+        let fees: f64 = block.transactions.iter()
+            .filter(|tx| tx.from != "system")
+            .map(|tx| tx.fee)
+            .sum();
+        minted <= self.block_subsidy(block.index) + fees + 1e-9
+    }
+
+    /// Evaluate a contract's code against `context_json` (an object of numeric variables),
+    /// charging `caller` the contract's execution cost. Returns the final variables as JSON,
+    /// or "" if the contract is missing, inactive, unaffordable, or fails to evaluate.
+    #[wasm_bindgen]
+    pub fn execute_contract(&mut self, caller: &str, contract_id: &str, context_json: &str) -> String {
+        let contract = match self.smart_contracts.get(contract_id) {
+            Some(contract) if contract.is_active => contract.clone(),
+            _ => {
+                console_log!("Contract {} not found or inactive", contract_id);
+                return String::new();
+            }
+        };
+        let mut variables: HashMap<String, f64> = match serde_json::from_str(context_json) {
+            Ok(variables) => variables,
+            Err(e) => {
+                console_log!("Invalid context for contract {}: {}", contract_id, e);
+                return String::new();
+            }
+        };
+        if contract.execution_cost > 0.0
+            && self.spendable_balance(caller) < contract.execution_cost + self.fee_for(caller)
+        {
+            console_log!("{} cannot afford to execute contract {}", caller, contract_id);
+            return String::new();
+        }
+        
+        // A failed evaluation still consumed the execution, so the cost is charged either way;
+        // the cost of a system contract is burned
+        let result = evaluate_contract_code(&contract.code, &mut variables);
+        if contract.execution_cost > 0.0 {
+            if contract.creator != "system" {
+                *self.account_balances.entry(contract.creator.clone()).or_insert(0.0) += contract.execution_cost;
+            }
+            let tx = Transaction {
+                tx_id: generate_unique_id("contract"),
+                from: caller.to_string(),
+                to: contract.creator.clone(),
+                amount: contract.execution_cost,
+                fee: 0.0,
+                tx_type: TransactionType::ContractExecution,
+                timestamp: now_ms(),
+                signature: "contract_signature".to_string(),
+                metadata: {
+                    let mut meta = BTreeMap::new();
+                    meta.insert("contract_id".to_string(), contract_id.to_string());
+                    meta
+                },
+            };
+            self.record_transaction(tx);
+        }
+        
+        match result {
+            Ok(()) => {
+                let sorted: std::collections::BTreeMap<_, _> = variables.into_iter().collect();
+                serde_json::to_string(&sorted).unwrap_or_default()
+            },
+            Err(e) => {
+                console_log!("Contract {} failed: {}", contract_id, e);
+                String::new()
+            }
+        }
+    }
+
+    // Run the active contract of `contract_type` on `context` and read `output` from it; None if
+    // there is no such contract or it fails. Internal checks are not charged the execution cost.
+    fn evaluate_active_contract(&self, contract_type: &str, mut context: HashMap<String, f64>, output: &str) -> Option<f64> {
+        let contract = self.active_contract_of(contract_type)?;
+        match evaluate_contract_code(&contract.code, &mut context) {
+            Ok(()) => context.get(output).copied().filter(|value| value.is_finite()),
+            Err(e) => {
+                console_log!("Contract {} failed: {}", contract.contract_id, e);
+                None
+            }
+        }
+    }
+
+    fn execute_memory_validation_contract(&self, capsule: &MemoryCapsule) -> f64 {
+        let context = HashMap::from([
+            ("novelty_score".to_string(), capsule.novelty_score),
+            ("importance_score".to_string(), capsule.importance_score),
+            ("semantic_tag_count".to_string(), capsule.semantic_tags.len() as f64),
+        ]);
+        // Without a working validator a capsule gets only the base score
+        self.evaluate_active_contract("MemoryValidation", context, "quality_score")
+            .unwrap_or(0.5)
+            .clamp(0.0, 1.0)
+    }
+
+    fn execute_borrowing_permission_contract(&self, borrower: &str, _node_owner: &str, _node_id: &str) -> bool {
+        let context = HashMap::from([("borrower_balance".to_string(), self.spendable_balance(borrower))]);
+        self.evaluate_active_contract("NodeBorrowingPermission", context, "approved")
+            .is_some_and(|approved| approved != 0.0)
+    }
+
+    fn calculate_hash(&self, data: &str) -> String {
+        sha256_hex(data.as_bytes())
+    }
+
+    fn calculate_merkle_root(&self, transactions: &[Transaction]) -> String {
+        if transactions.is_empty() {
+            return sha256_hex(&[]);
+        }
+        
+        let leaves = transactions.iter().map(transaction_hash).collect();
+        merkle_levels(leaves).pop().and_then(|mut root| root.pop()).unwrap_or_default()
+    }
+
+    /// Sibling path proving a transaction is in a block, as JSON [MerkleProofStep]; "" if not found
+    #[wasm_bindgen]
+    pub fn get_merkle_proof(&self, block_index: u64, tx_id: &str) -> String {
+        let block = match self.blocks.iter().find(|block| block.index == block_index) {
+            Some(block) => block,
+            None => return "".to_string(),
+        };
+        let mut position = match block.transactions.iter().position(|tx| tx.tx_id == tx_id) {
+            Some(position) => position,
+            None => return "".to_string(),
+        };
+        
+        let leaves = block.transactions.iter().map(transaction_hash).collect();
+        let levels = merkle_levels(leaves);
+        let mut proof = Vec::new();
+        for level in &levels[..levels.len() - 1] {
+            let sibling_position = position ^ 1;
+            proof.push(MerkleProofStep {
+                sibling: level.get(sibling_position).unwrap_or(&level[position]).clone(),
+                sibling_on_left: sibling_position < position,
+            });
+            position /= 2;
+        }
+        
+        serde_json::to_string(&proof).unwrap_or_default()
+    }
+
+    /// Leaf hash of a mined transaction, the starting point for verify_merkle_proof
+    #[wasm_bindgen]
+    pub fn get_transaction_hash(&self, block_index: u64, tx_id: &str) -> String {
+        self.blocks.iter()
+            .find(|block| block.index == block_index)
+            .and_then(|block| block.transactions.iter().find(|tx| tx.tx_id == tx_id))
+            .map(transaction_hash)
+            .unwrap_or_default()
+    }
+
+    // Proof-of-work: advance the nonce until the block hash meets its difficulty; returns the hash
+    fn find_nonce(&self, block: &mut Block) -> String {
+        loop {
+            let hash = hash_block_contents(block);
+            if meets_difficulty(&hash, block.difficulty) {
+                return hash;
+            }
+            block.nonce += 1;
+        }
+    }
+
+    /// Leading zero hex digits required of blocks mined from now on, and the minimum a received
+    /// or imported block must meet; blocks already on the chain keep the difficulty they were mined at
+    #[wasm_bindgen]
+    pub fn set_difficulty(&mut self, difficulty: usize) {
+        self.difficulty = difficulty.min(MAX_DIFFICULTY);
+    }
+
+    #[wasm_bindgen]
+    pub fn get_difficulty(&self) -> usize {
+        self.difficulty
+    }
+
+    #[wasm_bindgen]
+    pub fn get_account_balance(&self, device_id: &str) -> f64 {
+        self.account_balances.get(device_id).copied().unwrap_or(0.0)
+    }
+
+    /// Most recent `max` mined and pending transactions touching a device, newest first, as JSON
+    #[wasm_bindgen]
+    pub fn get_transaction_history(&self, device_id: &str, max: usize) -> String {
+        let mined = self.blocks.iter()
+            .flat_map(|block| block.transactions.iter().map(move |tx| (Some(block.index), tx)));
+        let pending = self.pending_transactions.iter().map(|tx| (None, tx));
+        
+        let mut history: Vec<TransactionHistoryEntry> = mined.chain(pending)
+            .filter(|(_, tx)| tx.from == device_id || tx.to == device_id)
+            .map(|(block_index, tx)| TransactionHistoryEntry {
+                direction: if tx.from == device_id { "debit" } else { "credit" }.to_string(),
+                block_index,
+                transaction: tx.clone(),
+            })
+            .collect();
+        history.sort_by(|a, b| b.transaction.timestamp.total_cmp(&a.transaction.timestamp));
+        history.truncate(max);
+        
+        serde_json::to_string(&history).unwrap_or_default()
+    }
+
+    #[wasm_bindgen]
+    pub fn get_spendable_balance(&self, device_id: &str) -> f64 {
+        self.spendable_balance(device_id)
+    }
+
+    #[wasm_bindgen]
+    pub fn get_memory_record(&self, capsule_id: &str) -> String {
+        if let Some(record) = self.memory_registry.get(capsule_id) {
+            serde_json::to_string(record).unwrap_or_default()
+        } else {
+            "".to_string()
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn get_memory_record_hash(&self, capsule_id: &str) -> String {
+        self.memory_registry.get(capsule_id)
+            .map(|record| record.hash.clone())
+            .unwrap_or_default()
+    }
+
+    #[wasm_bindgen]
+    pub fn get_blockchain_stats(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.blockchain_stats()).unwrap_or(JsValue::NULL)
+    }
+
+    fn blockchain_stats(&self) -> BlockchainStats {
+        BlockchainStats {
+            total_blocks: self.blocks.len(),
+            total_transactions: self.blocks.iter().map(|b| b.transactions.len()).sum(),
+            pending_transactions: self.pending_transactions.len(),
+            total_accounts: self.account_balances.len(),
+            total_memory_capsules: self.memory_registry.len(),
+            total_borrowing_records: self.node_borrowing_registry.len(),
+            total_smart_contracts: self.smart_contracts.len(),
+        }
+    }
+
+    /// Mined transactions as CSV rows for offline accounting; not meant for reloading state
+    #[wasm_bindgen]
+    pub fn export_ledger_csv(&self) -> String {
+        let mut csv = String::from("block_index,tx_id,from,to,amount,tx_type,timestamp\n");
+        for row in self.ledger_rows() {
+            csv.push_str(&format!("{},{},{},{},{},{},{}\n",
+                row.block_index,
+                csv_field(&row.tx_id),
+                csv_field(&row.from),
+                csv_field(&row.to),
+                row.amount,
+                row.tx_type,
+                row.timestamp));
+        }
+        csv
+    }
+
+    /// Same rows as export_ledger_csv, as a JSON array
+    #[wasm_bindgen]
+    pub fn export_ledger_json(&self) -> String {
+        serde_json::to_string(&self.ledger_rows()).unwrap_or_default()
+    }
+
+    fn ledger_rows(&self) -> Vec<LedgerRow> {
+        self.blocks.iter()
+            .flat_map(|block| block.transactions.iter().map(move |tx| LedgerRow {
+                block_index: block.index,
+                tx_id: tx.tx_id.clone(),
+                from: tx.from.clone(),
+                to: tx.to.clone(),
+                amount: tx.amount,
+                tx_type: format!("{:?}", tx.tx_type),
+                timestamp: tx.timestamp,
+            }))
+            .collect()
+    }
+
+    /// Whether every block's hash, proof-of-work and linkage check out; blocks after genesis
+    /// must also have been mined at no less than the ledger's difficulty
+    #[wasm_bindgen]
+    pub fn validate_chain(&self) -> bool {
+        // Recomputing hashes catches tampering with block headers or their transactions
+        for block in &self.blocks {
+            if block.hash != hash_block_contents(block)
+                || !meets_difficulty(&block.hash, block.difficulty)
+                || block.merkle_root != self.calculate_merkle_root(&block.transactions)
+            {
+                return false;
+            }
+        }
+        
+        for i in 1..self.blocks.len() {
+            let current = &self.blocks[i];
+            let previous = &self.blocks[i - 1];
+            
+            if current.previous_hash != previous.hash {
+                return false;
+            }
+            
+            if current.index != previous.index + 1 {
+                return false;
+            }
+        }
+        
+        true
+    }
+}
+
+impl BlockchainLedger {
+    // Peers may only mint through the coinbase, every debit must be signed by the debited account's
+    // pinned key, and transfers are replayed against a scratch copy of the balances: no sender may
+    // end up spending more than it holds or has reserved
+    fn check_received_transactions(&self, block: &Block, known: &HashSet<String>) -> Result<(), String> {
+        let pending: HashSet<&String> = self.pending_transactions.iter().map(|tx| &tx.tx_id).collect();
+        let mut balances = self.account_balances.clone();
+        let mut reserved = self.reserved_balances.clone();
+        
+        for tx in &block.transactions {
+            if known.contains(&tx.tx_id) {
+                // One of ours, credited when recorded; mining debits the sender for real
+                if pending.contains(&tx.tx_id) && tx.from != "system" {
+                    *balances.entry(tx.from.clone()).or_insert(0.0) -= tx.amount + tx.fee;
+                    *reserved.entry(tx.from.clone()).or_insert(0.0) -= tx.amount + tx.fee;
+                }
+                continue;
+            }
+            if tx.from == "system" && !matches!(tx.tx_type, TransactionType::Coinbase) {
+                return Err(format!("transaction {} mints {:?} credits outside the coinbase", tx.tx_id, tx.tx_type));
+            }
+            if !tx.amount.is_finite() || tx.amount < 0.0 {
+                return Err(format!("transaction {} has invalid amount {}", tx.tx_id, tx.amount));
+            }
+            if tx.from != "system" && !self.account_keys.get(&tx.from)
+                .is_some_and(|key| verify_hex(key, &transaction_signing_payload(tx), &tx.signature)) {
+                return Err(format!("transaction {} debits {} without its signature", tx.tx_id, tx.from));
+            }
+            apply_transaction_effect(&mut balances, tx);
+        }
+        
+        for tx in block.transactions.iter().filter(|tx| tx.from != "system") {
+            let spendable = balances.get(&tx.from).copied().unwrap_or(0.0)
+                - reserved.get(&tx.from).copied().unwrap_or(0.0).max(0.0);
+            if spendable < -1e-9 {
+                return Err(format!("sender {} would overspend by {}", tx.from, -spendable));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn memory_record_ids(&self) -> Vec<&String> {
+        self.memory_registry.keys().collect()
+    }
+
+    /// Check that balances equal the credits ever minted, with pending transfers counted
+    /// once on the receiving side (senders are debited at mining), mined fees removed, and
+    /// payments to "system" burned once mined
+    pub fn check_supply_integrity(&self) -> Vec<IntegrityIssue> {
+        let mined: HashSet<&String> = self.blocks.iter()
+            .flat_map(|block| block.transactions.iter().map(|tx| &tx.tx_id))
+            .collect();
+        let expected: f64 = self.transaction_log.iter()
+            .map(|tx| {
+                let burned = tx.to == "system";
+                if tx.from == "system" {
+                    tx.amount
+                } else if mined.contains(&tx.tx_id) {
+                    if burned { -tx.amount - tx.fee } else { -tx.fee }
+                } else if burned {
+                    0.0
+                } else {
+                    tx.amount
+                }
+            })
+            .sum();
+        let actual: f64 = self.account_balances.values().sum();
+        
+        let mut issues = Vec::new();
+        if (expected - actual).abs() > 1e-6 {
+            issues.push(IntegrityIssue::new(IntegritySeverity::Error, "blockchain",
+                format!("account supply {:.6} does not match {:.6} implied by the transaction log", actual, expected)));
+        }
+        if !self.validate_chain() {
+            issues.push(IntegrityIssue::new(IntegritySeverity::Error, "blockchain",
+                "chain fails hash, proof-of-work or linkage validation".to_string()));
+        }
+        issues
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LedgerRow {
+    pub block_index: u64,
+    pub tx_id: String,
+    pub from: String,
+    pub to: String,
+    pub amount: f64,
+    pub tx_type: String,
+    pub timestamp: f64,
+}
+
+/// SHA-256 over index || timestamp || previous_hash || merkle_root || nonce, hex-encoded
+pub fn hash_block_contents(block: &Block) -> String {
+    let mut data = Vec::new();
+    data.extend_from_slice(&block.index.to_be_bytes());
+    data.extend_from_slice(&block.timestamp.to_bits().to_be_bytes());
+    data.extend_from_slice(block.previous_hash.as_bytes());
+    data.extend_from_slice(block.merkle_root.as_bytes());
+    data.extend_from_slice(&block.nonce.to_be_bytes());
+    sha256_hex(&data)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TransactionHistoryEntry {
+    pub direction: String, // "debit" when the device sent the amount, otherwise "credit"
+    pub block_index: Option<u64>, // None while the transaction is pending
+    #[serde(flatten)]
+    pub transaction: Transaction,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChainSnapshot {
+    blocks: Vec<Block>,
+    pending_transactions: Vec<Transaction>,
+    #[serde(default)]
+    transaction_log: Vec<Transaction>,
+    account_balances: HashMap<String, f64>,
+    memory_registry: HashMap<String, MemoryRecord>,
+    node_borrowing_registry: HashMap<String, BorrowingRecord>,
+    #[serde(default)]
+    escrow: HashMap<String, f64>,
+    #[serde(default)]
+    difficulty: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MerkleProofStep {
+    pub sibling: String,
+    pub sibling_on_left: bool,
+}
+
+// Domain prefixes so a leaf can never be passed off as an internal node, or vice versa
+const MERKLE_LEAF_PREFIX: u8 = 0x00;
+const MERKLE_NODE_PREFIX: u8 = 0x01;
+
+/// Merkle leaf for a transaction: SHA-256 of 0x00 || its JSON serialization, which is canonical
+/// because metadata is ordered and floats round-trip exactly
+pub fn transaction_hash(tx: &Transaction) -> String {
+    let mut data = vec![MERKLE_LEAF_PREFIX];
+    data.extend_from_slice(serde_json::to_string(tx).unwrap_or_default().as_bytes());
+    sha256_hex(&data)
+}
+
+// SHA-256 of 0x01 || left || right over the children's hex digests
+fn merkle_parent(left: &str, right: &str) -> String {
+    let mut data = vec![MERKLE_NODE_PREFIX];
+    data.extend_from_slice(left.as_bytes());
+    data.extend_from_slice(right.as_bytes());
+    sha256_hex(&data)
+}
+
+// Every level of a binary merkle tree from the leaves up to the root; an odd node is paired with itself
+fn merkle_levels(leaves: Vec<String>) -> Vec<Vec<String>> {
+    let mut levels = vec![leaves];
+    while levels.last().map(|level| level.len() > 1).unwrap_or(false) {
+        let next = levels.last().unwrap().chunks(2)
+            .map(|pair| merkle_parent(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+/// Check a get_merkle_proof path from a transaction hash up to a block's merkle root,
+/// without needing the block's full transaction list
+#[wasm_bindgen]
+pub fn verify_merkle_proof(root: &str, tx_hash: &str, proof_json: &str) -> bool {
+    let proof = match serde_json::from_str::<Vec<MerkleProofStep>>(proof_json) {
+        Ok(proof) => proof,
+        Err(_) => return false,
+    };
+    
+    let computed = proof.iter().fold(tx_hash.to_string(), |hash, step| {
+        if step.sibling_on_left {
+            merkle_parent(&step.sibling, &hash)
+        } else {
+            merkle_parent(&hash, &step.sibling)
+        }
+    });
+    computed == root
+}
+
+fn meets_difficulty(hash: &str, difficulty: usize) -> bool {
+    hash.len() >= difficulty && hash.bytes().take(difficulty).all(|digit| digit == b'0')
+}
+
+// What a debit's signature covers: the whole transaction apart from the signature itself
+fn transaction_signing_payload(tx: &Transaction) -> String {
+    let mut unsigned = tx.clone();
+    unsigned.signature = String::new();
+    serde_json::to_string(&unsigned).unwrap_or_default()
+}
+
+// Transfer a transaction's amount, debiting its fee too; fees reach the miner through the
+// block's coinbase. "system" mints credits, so it is never debited, and what is paid to it
+// (e.g. executing a default contract) is burned rather than credited
+fn apply_transaction_effect(balances: &mut HashMap<String, f64>, tx: &Transaction) {
+    if tx.from != "system" {
+        *balances.entry(tx.from.clone()).or_insert(0.0) -= tx.amount + tx.fee;
+    }
+    credit_recipient(balances, tx);
+}
+
+fn credit_recipient(balances: &mut HashMap<String, f64>, tx: &Transaction) {
+    if tx.to != "system" {
+        *balances.entry(tx.to.clone()).or_insert(0.0) += tx.amount;
+    }
+}
+
+// Quote a CSV field when it contains a delimiter, quote, or newline
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockchainStats {
+    pub total_blocks: usize,
+    pub total_transactions: usize,
+    pub pending_transactions: usize,
+    pub total_accounts: usize,
+    pub total_memory_capsules: usize,
+    pub total_borrowing_records: usize,
+    pub total_smart_contracts: usize,
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn forged_transaction(from: &str, to: &str, amount: f64, tx_type: TransactionType) -> Transaction {
+        Transaction {
+            tx_id: generate_unique_id("forged"),
+            from: from.to_string(),
+            to: to.to_string(),
+            amount,
+            fee: 0.0,
+            tx_type,
+            timestamp: now_ms(),
+            signature: String::new(),
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    // A ledger that mined one block funding alice, whose debits it signs, and a copy of it left
+    // one block behind later
+    fn synced_pair() -> (BlockchainLedger, BlockchainLedger) {
+        let mut peer = BlockchainLedger::new();
+        peer.set_miner_address("miner".to_string());
+        assert!(peer.set_account_signing_key("alice".to_string(), &[7; 32]));
+        peer.register_device("alice".to_string(), 10.0);
+        assert!(!peer.mine_block().is_empty());
+        let behind = peer.clone();
+        (peer, behind)
+    }
+
+    // `tx` signed with the key `ledger` holds for its sender
+    fn signed_by(ledger: &BlockchainLedger, mut tx: Transaction) -> Transaction {
+        tx.signature = sign_hex(&ledger.signing_keys[&tx.from], &transaction_signing_payload(&tx));
+        tx
+    }
+
+    // Mine a block on a copy of `ledger` that includes `tx` without any local checks
+    fn block_with(ledger: &BlockchainLedger, tx: Transaction) -> String {
+        let mut attacker = ledger.clone();
+        attacker.pending_transactions.push(tx);
+        attacker.mine_block();
+        attacker.get_block(attacker.get_chain_tip_index())
+    }
+
+    #[test]
+    fn merkle_root_survives_a_json_round_trip() {
+        let ledger = BlockchainLedger::new();
+        let mut tx = forged_transaction("alice", "bob", 0.1 + 0.2, TransactionType::MemoryUpload);
+        tx.fee = 1.0 / 3.0;
+        tx.metadata.insert("quality_score".to_string(), "0.7".to_string());
+        tx.metadata.insert("capsule_id".to_string(), "capsule_a".to_string());
+        let original = vec![tx];
+
+        let round_tripped: Vec<Transaction> = serde_json::from_str(&serde_json::to_string(&original).unwrap()).unwrap();
+        assert_eq!(ledger.calculate_merkle_root(&round_tripped), ledger.calculate_merkle_root(&original));
+    }
+
+    // A mined ledger whose tip block holds `count` transfers, plus their tx ids
+    fn block_of_transfers(count: usize) -> (BlockchainLedger, Vec<String>) {
+        let mut ledger = BlockchainLedger::new();
+        ledger.set_miner_address("miner".to_string());
+        ledger.register_device("alice".to_string(), 100.0);
+        ledger.mine_block();
+        for session in 0..count {
+            ledger.pay_contribution_reward("alice", "bob", 1.0, &format!("session_{}", session)).unwrap();
+        }
+        ledger.mine_block();
+        let tip = ledger.get_chain_tip_index();
+        let tx_ids = ledger.blocks[tip as usize].transactions.iter().map(|tx| tx.tx_id.clone()).collect();
+        (ledger, tx_ids)
+    }
+
+    #[test]
+    fn merkle_proofs_verify_for_every_transaction() {
+        for count in [1, 2, 3, 5] {
+            let (ledger, tx_ids) = block_of_transfers(count);
+            let tip = ledger.get_chain_tip_index();
+            let root = ledger.blocks[tip as usize].merkle_root.clone();
+            for tx_id in &tx_ids {
+                let proof = ledger.get_merkle_proof(tip, tx_id);
+                assert!(verify_merkle_proof(&root, &ledger.get_transaction_hash(tip, tx_id), &proof));
+            }
+        }
+    }
+
+    #[test]
+    fn merkle_proofs_reject_wrong_leaves_and_internal_nodes() {
+        let (ledger, tx_ids) = block_of_transfers(3);
+        let tip = ledger.get_chain_tip_index();
+        let block = &ledger.blocks[tip as usize];
+        let proof = ledger.get_merkle_proof(tip, &tx_ids[0]);
+        let other_leaf = ledger.get_transaction_hash(tip, &tx_ids[1]);
+        assert!(!verify_merkle_proof(&block.merkle_root, &other_leaf, &proof));
+
+        let mut steps: Vec<MerkleProofStep> = serde_json::from_str(&proof).unwrap();
+        steps[0].sibling = ledger.get_transaction_hash(tip, &tx_ids[2]);
+        let tampered = serde_json::to_string(&steps).unwrap();
+        assert!(!verify_merkle_proof(&block.merkle_root, &ledger.get_transaction_hash(tip, &tx_ids[0]), &tampered));
+
+        // The bytes of an internal node hash differently when claimed as a leaf
+        let leaves: Vec<String> = block.transactions.iter().map(transaction_hash).collect();
+        let levels = merkle_levels(leaves);
+        let node_preimage = format!("{}{}", levels[0][0], levels[0][1]);
+        let mut as_leaf = vec![MERKLE_LEAF_PREFIX];
+        as_leaf.extend_from_slice(node_preimage.as_bytes());
+        assert_ne!(sha256_hex(&as_leaf), levels[1][0]);
+        assert_ne!(sha256_hex(node_preimage.as_bytes()), levels[1][0]);
+    }
+
+    #[test]
+    fn import_rejects_chains_mined_below_our_difficulty() {
+        let mut cheap = BlockchainLedger::new();
+        cheap.set_difficulty(0);
+        cheap.set_miner_address("miner".to_string());
+        cheap.register_device("alice".to_string(), 10.0);
+        cheap.mine_block();
+        assert!(cheap.validate_chain());
+
+        let mut ledger = BlockchainLedger::new();
+        assert!(!ledger.import_chain(&cheap.export_chain()));
+        assert_eq!(ledger.get_chain_tip_index(), 0);
+
+        ledger.set_miner_address("miner".to_string());
+        ledger.mine_block();
+        assert!(ledger.validate_chain());
+        assert!(BlockchainLedger::new().import_chain(&ledger.export_chain()));
+    }
+
+    fn active_contract(ledger: &BlockchainLedger, contract_type: &str) -> SmartContract {
+        serde_json::from_str(&ledger.get_active_contract_by_type(contract_type.to_string())).unwrap()
+    }
+
+    #[test]
+    fn active_contract_lookup_returns_the_newest_active_of_a_type() {
+        let mut ledger = BlockchainLedger::new();
+        let default = active_contract(&ledger, "IncentiveDistribution");
+        assert_eq!(default.contract_id, "incentive_distributor");
+        assert!(default.code.contains("reward"));
+        assert!(ledger.get_active_contract_by_type("Unknown".to_string()).is_empty());
+
+        let mut newer = default.clone();
+        newer.contract_id = "incentive_v2".to_string();
+        ledger.deploy_contract(newer.clone());
+        assert_eq!(active_contract(&ledger, "incentivedistribution").contract_id, "incentive_v2");
+
+        newer.is_active = false;
+        ledger.deploy_contract(newer);
+        assert_eq!(active_contract(&ledger, "IncentiveDistribution").contract_id, "incentive_distributor");
+    }
+
+    // Bob borrows alice's node for 4 hours at 2.0 credits, 1 hour of which has been used
+    fn borrowed_for_an_hour() -> (BlockchainLedger, String) {
+        let mut ledger = BlockchainLedger::new();
+        ledger.register_device("alice".to_string(), 10.0);
+        ledger.register_device("bob".to_string(), 10.0);
+        let borrowing_id = ledger.request_node_borrowing("bob".to_string(), "alice".to_string(), "node_0".to_string(), 4.0);
+        assert!(!borrowing_id.is_empty());
+        ledger.node_borrowing_registry.get_mut(&borrowing_id).unwrap().start_time -= 3_600_000.0;
+        (ledger, borrowing_id)
+    }
+
+    fn refunds(ledger: &BlockchainLedger) -> Vec<&Transaction> {
+        ledger.pending_transactions.iter()
+            .filter(|tx| matches!(tx.tx_type, TransactionType::BorrowingRefund))
+            .collect()
+    }
+
+    #[test]
+    fn removing_a_borrowed_node_refunds_the_unused_share() {
+        let (mut ledger, borrowing_id) = borrowed_for_an_hour();
+        assert_eq!(ledger.settle_borrowings_for_node("alice", "node_0"), 1);
+        assert!(matches!(ledger.node_borrowing_registry[&borrowing_id].status, BorrowingStatus::Cancelled));
+
+        let refunds = refunds(&ledger);
+        assert_eq!(refunds.len(), 1);
+        assert_eq!((refunds[0].from.as_str(), refunds[0].to.as_str()), ("alice", "bob"));
+        assert!((refunds[0].amount - 1.5).abs() < 1e-3, "refund {}", refunds[0].amount);
+        assert_eq!(refunds[0].metadata["borrowing_id"], borrowing_id);
+
+        // Already cancelled, so a second removal settles nothing
+        assert_eq!(ledger.settle_borrowings_for_node("alice", "node_0"), 0);
+    }
+
+    #[test]
+    fn removal_refund_policy_selects_the_refund() {
+        let (mut ledger, _) = borrowed_for_an_hour();
+        assert!(!ledger.set_removal_refund_policy("sometimes"));
+        assert!(ledger.set_removal_refund_policy("none"));
+        assert_eq!(ledger.settle_borrowings_for_node("alice", "node_0"), 1);
+        assert!(refunds(&ledger).is_empty());
+
+        let (mut ledger, _) = borrowed_for_an_hour();
+        assert!(ledger.set_removal_refund_policy("full"));
+        ledger.settle_borrowings_for_node("alice", "node_0");
+        assert_eq!(refunds(&ledger)[0].amount, 2.0);
+    }
+
+    fn register_capsules(ledger: &mut BlockchainLedger, count: usize) -> Vec<String> {
+        (0..count)
+            .map(|i| {
+                let capsule = crate::memory::tests::sample_capsule(&format!("capsule_{}", i), &[]);
+                ledger.register_memory_capsule(&serde_json::to_string(&capsule).unwrap(), "uploader".to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn registrations_are_unlimited_by_default() {
+        let mut ledger = BlockchainLedger::new();
+        assert!(register_capsules(&mut ledger, 50).iter().all(|id| !id.is_empty()));
+    }
+
+    #[test]
+    fn registrations_beyond_the_rate_limit_are_rejected() {
+        let mut ledger = BlockchainLedger::new();
+        ledger.set_registration_rate_limit(5);
+        let ids = register_capsules(&mut ledger, 8);
+        assert!(ids[..5].iter().all(|id| !id.is_empty()));
+        assert!(ids[5..].iter().all(|id| id.is_empty()));
+        assert!(ledger.is_rate_limited("uploader"));
+        assert!(!ledger.is_rate_limited("someone_else"));
+        assert_eq!(ledger.memory_registry.len(), 5);
+    }
+
+    #[test]
+    fn burst_registrations_earn_diminishing_rewards() {
+        let mut ledger = BlockchainLedger::new();
+        assert!(!ledger.set_registration_reward_decay(-1.0));
+        assert!(ledger.set_registration_reward_decay(1.0));
+        let ids = register_capsules(&mut ledger, 3);
+        let rewards: Vec<f64> = ids.iter().map(|id| ledger.memory_registry[id].incentive_earned).collect();
+        assert!(rewards[0] > 0.0);
+        assert!((rewards[1] - rewards[0] / 2.0).abs() < 1e-12);
+        assert!((rewards[2] - rewards[0] / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn behind_node_backfills_three_blocks_from_a_peer() {
+        let (mut peer, mut behind) = synced_pair();
+        for session in 0..3 {
+            peer.pay_contribution_reward("alice", "bob", 1.0, &format!("session_{}", session)).unwrap();
+            assert!(!peer.mine_block().is_empty());
+        }
+
+        let missing = peer.get_blocks_from(behind.get_chain_tip_index() + 1);
+        assert_eq!(behind.apply_blocks(&missing), 3);
+        assert_eq!(behind.get_chain_tip_index(), peer.get_chain_tip_index());
+        assert_eq!(behind.get_block(3), peer.get_block(3));
+        assert!(behind.validate_chain());
+        assert_eq!(behind.get_account_balance("bob"), 3.0);
+        assert_eq!(behind.get_account_balance("alice"), 7.0);
+    }
+
+    #[test]
+    fn received_blocks_cannot_mint_outside_the_coinbase() {
+        let (peer, mut behind) = synced_pair();
+        let block = block_with(&peer, forged_transaction("system", "mallory", 1e12, TransactionType::ContributionReward));
+
+        assert!(behind.receive_block(&block).is_err());
+        assert_eq!(behind.get_account_balance("mallory"), 0.0);
+        assert_eq!(behind.get_block_count(), 2);
+    }
+
+    #[test]
+    fn received_blocks_cannot_overdraw_a_sender() {
+        let (peer, mut behind) = synced_pair();
+        let overdraft = signed_by(&peer, forged_transaction("alice", "mallory", 11.0, TransactionType::NodeBorrowing));
+        assert!(behind.receive_block(&block_with(&peer, overdraft)).is_err());
+        assert_eq!(behind.get_account_balance("alice"), 10.0);
+
+        // An affordable transfer is still refused unless alice's key signed it
+        let unsigned = block_with(&peer, forged_transaction("alice", "bob", 10.0, TransactionType::NodeBorrowing));
+        assert!(behind.receive_block(&unsigned).is_err());
+        assert_eq!(behind.get_account_balance("alice"), 10.0);
+
+        let affordable = block_with(&peer, signed_by(&peer, forged_transaction("alice", "bob", 10.0, TransactionType::NodeBorrowing)));
+        assert!(behind.receive_block(&affordable).is_ok());
+        assert_eq!(behind.get_account_balance("alice"), 0.0);
+    }
+
+    #[test]
+    fn miner_earns_subsidy_plus_fees() {
+        let (mut peer, _) = synced_pair();
+        peer.set_transaction_fee(0.25);
+        let before = peer.get_account_balance("miner");
+        peer.pay_contribution_reward("alice", "bob", 1.0, "session").unwrap();
+        peer.pay_contribution_reward("alice", "bob", 1.0, "session").unwrap();
+        peer.mine_block();
+
+        assert!((peer.get_account_balance("miner") - before - (peer.block_subsidy(2) + 0.5)).abs() < 1e-9);
+        assert!((peer.get_account_balance("alice") - 7.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn system_fees_do_not_raise_the_coinbase_cap() {
+        let (peer, mut behind) = synced_pair();
+        let mut inflated = forged_transaction("system", "mallory", 1e9, TransactionType::Coinbase);
+        inflated.fee = 1e9;
+        let mut attacker = peer.clone();
+        attacker.miner_address = None;
+        attacker.pending_transactions.push(inflated);
+        attacker.mine_block();
+
+        assert!(behind.receive_block(&attacker.get_block(2)).is_err());
+        assert_eq!(behind.get_account_balance("mallory"), 0.0);
+    }
+
+    #[test]
+    fn negative_fees_are_rejected() {
+        let (peer, mut behind) = synced_pair();
+        let mut tx = forged_transaction("alice", "bob", 1.0, TransactionType::NodeBorrowing);
+        tx.fee = -100.0;
+        assert!(behind.receive_block(&block_with(&peer, signed_by(&peer, tx))).is_err());
+    }
+
+    #[test]
+    fn debits_reserve_room_for_the_fee() {
+        let (mut ledger, _) = synced_pair();
+        ledger.set_transaction_fee(1.0);
+
+        assert!(ledger.pay_contribution_reward("alice", "bob", 9.5, "session").is_err());
+        assert!(ledger.pay_contribution_reward("alice", "bob", 9.0, "session").is_ok());
+        assert!(ledger.spendable_balance("alice").abs() < 1e-9);
+        assert!(ledger.request_node_borrowing("alice".to_string(), "owner".to_string(), "node".to_string(), 1.0).is_empty());
+        ledger.mine_block();
+        assert!(ledger.get_account_balance("alice").abs() < 1e-9);
+    }
+
+    #[test]
+    fn escrow_releases_are_fee_free() {
+        let (mut ledger, _) = synced_pair();
+        ledger.register_device("owner".to_string(), 5.0);
+        ledger.mine_block();
+        ledger.set_transaction_fee(0.5);
+
+        let borrowing_id = ledger.request_node_borrowing("alice".to_string(), "owner".to_string(), "node".to_string(), 2.0);
+        assert!(!borrowing_id.is_empty());
+        ledger.mine_block();
+        assert!(ledger.dispute_node_borrowing(borrowing_id.clone(), "no output"));
+        assert!(ledger.resolve_dispute(borrowing_id, true));
+        ledger.mine_block();
+
+        assert!(ledger.get_account_balance(ESCROW_ACCOUNT).abs() < 1e-9);
+        assert!(ledger.account_balances.values().all(|&balance| balance >= -1e-9));
+    }
+
+
+    #[test]
+    fn ledger_csv_has_a_row_per_mined_transaction() {
+        let (mut ledger, _) = block_of_transfers(3);
+        ledger.pay_contribution_reward("alice", "bob", 1.0, "unmined").unwrap();
+        let csv = ledger.export_ledger_csv();
+        let rows = csv.lines().skip(1).count();
+        assert_eq!(rows, ledger.blockchain_stats().total_transactions);
+
+        let json: Vec<serde_json::Value> = serde_json::from_str(&ledger.export_ledger_json()).unwrap();
+        assert_eq!(json.len(), rows);
+    }
+
+
+    fn assert_same_balances(left: &BlockchainLedger, right: &BlockchainLedger) {
+        let accounts: HashSet<&String> = left.account_balances.keys().chain(right.account_balances.keys()).collect();
+        for account in accounts {
+            let (l, r) = (left.get_account_balance(account), right.get_account_balance(account));
+            assert!((l - r).abs() < 1e-9, "{}: {} vs {}", account, l, r);
+            assert!((left.spendable_balance(account) - right.spendable_balance(account)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn replaying_the_log_matches_the_mined_balances() {
+        let (mut ledger, _) = block_of_transfers(2);
+        ledger.pay_contribution_reward("alice", "bob", 2.5, "unmined").unwrap();
+        let log = ledger.export_transaction_log();
+
+        // A reload that lost its balances before the last block was mined
+        let mut reloaded = ledger.clone();
+        reloaded.account_balances.clear();
+        reloaded.pending_transactions.clear();
+        reloaded.replay_transaction_log(&log).unwrap();
+        assert_eq!(reloaded.pending_transactions.len(), 1);
+        assert_same_balances(&reloaded, &ledger);
+
+        ledger.mine_block();
+        reloaded.mine_block();
+        assert_same_balances(&reloaded, &ledger);
+        assert!(reloaded.replay_transaction_log("not json").is_err());
+    }
+
+
+    #[test]
+    fn validate_chain_rejects_hashes_below_the_difficulty() {
+        let mut ledger = BlockchainLedger::new();
+        ledger.set_difficulty(2);
+        ledger.set_miner_address("miner".to_string());
+        ledger.register_device("alice".to_string(), 10.0);
+        ledger.mine_block();
+        assert!(ledger.validate_chain());
+        let tip = ledger.blocks.last_mut().unwrap();
+        assert!(tip.hash.starts_with("00"));
+        assert_eq!(tip.hash, hash_block_contents(tip));
+
+        // A consistent hash for another nonce that misses the required prefix
+        loop {
+            tip.nonce += 1;
+            tip.hash = hash_block_contents(tip);
+            if !tip.hash.starts_with("00") {
+                break;
+            }
+        }
+        assert!(!ledger.validate_chain());
+    }
+
+
+    #[test]
+    fn only_the_first_of_two_unaffordable_borrows_succeeds() {
+        // 12 hours cost 6 credits, and bob's 10 cover only one such borrow
+        let mut ledger = BlockchainLedger::new();
+        ledger.register_device("alice".to_string(), 10.0);
+        ledger.register_device("bob".to_string(), 10.0);
+
+        let first = ledger.request_node_borrowing("bob".to_string(), "alice".to_string(), "node_0".to_string(), 12.0);
+        let second = ledger.request_node_borrowing("bob".to_string(), "alice".to_string(), "node_1".to_string(), 12.0);
+        assert!(!first.is_empty());
+        assert!(second.is_empty());
+        assert!(ledger.spendable_balance("bob") >= 0.0);
+        assert_eq!(ledger.node_borrowing_registry.len(), 1);
+    }
+
+
+    fn dispute_transactions(ledger: &BlockchainLedger) -> usize {
+        ledger.pending_transactions.iter()
+            .filter(|tx| matches!(tx.tx_type, TransactionType::DisputeResolution))
+            .count()
+    }
+
+    // Spendable balances of alice (owner) and bob (borrower) after disputing and resolving
+    fn disputed_then_resolved(refund_borrower: bool) -> (BlockchainLedger, String, f64, f64) {
+        let (mut ledger, borrowing_id) = borrowed_for_an_hour();
+        let (owner_before, borrower_before) = (ledger.spendable_balance("alice"), ledger.spendable_balance("bob"));
+        assert!(!ledger.resolve_dispute(borrowing_id.clone(), refund_borrower));
+
+        assert!(ledger.dispute_node_borrowing(borrowing_id.clone(), "node went offline"));
+        assert!(!ledger.dispute_node_borrowing(borrowing_id.clone(), "again"));
+        assert!(matches!(ledger.node_borrowing_registry[&borrowing_id].status, BorrowingStatus::Disputed));
+        assert_eq!(ledger.escrow[&borrowing_id], 2.0);
+        assert!((ledger.spendable_balance("alice") - (owner_before - 2.0)).abs() < 1e-9);
+
+        assert!(ledger.resolve_dispute(borrowing_id.clone(), refund_borrower));
+        assert!(!ledger.resolve_dispute(borrowing_id.clone(), refund_borrower));
+        assert!(ledger.escrow.is_empty());
+        assert_eq!(dispute_transactions(&ledger), 2);
+        let owner_change = ledger.spendable_balance("alice") - owner_before;
+        let borrower_change = ledger.spendable_balance("bob") - borrower_before;
+        (ledger, borrowing_id, owner_change, borrower_change)
+    }
+
+    #[test]
+    fn refunded_disputes_return_the_payment_to_the_borrower() {
+        let (ledger, borrowing_id, owner_change, borrower_change) = disputed_then_resolved(true);
+        assert!((owner_change + 2.0).abs() < 1e-9);
+        assert!((borrower_change - 2.0).abs() < 1e-9);
+        assert!(matches!(ledger.node_borrowing_registry[&borrowing_id].status, BorrowingStatus::Cancelled));
+    }
+
+    #[test]
+    fn unrefunded_disputes_pay_the_owner_from_escrow() {
+        let (ledger, borrowing_id, owner_change, borrower_change) = disputed_then_resolved(false);
+        assert!(owner_change.abs() < 1e-9);
+        assert!(borrower_change.abs() < 1e-9);
+        assert!(matches!(ledger.node_borrowing_registry[&borrowing_id].status, BorrowingStatus::Completed));
+    }
+
+
+    #[test]
+    fn raising_the_difficulty_keeps_the_mined_chain_valid() {
+        let mut ledger = BlockchainLedger::new();
+        ledger.set_difficulty(2);
+        ledger.set_miner_address("miner".to_string());
+        ledger.register_device("alice".to_string(), 10.0);
+        ledger.mine_block();
+        ledger.set_difficulty(3);
+        assert!(ledger.validate_chain());
+        assert!(ledger.check_supply_integrity().is_empty());
+
+        // The raised minimum still applies to blocks arriving from elsewhere
+        let mut stricter = BlockchainLedger::new();
+        stricter.set_difficulty(3);
+        assert!(!stricter.import_chain(&ledger.export_chain()));
+    }
+
+
+    #[test]
+    fn system_contract_costs_are_burned_consistently() {
+        let (mut ledger, _) = synced_pair();
+        let context = r#"{"memory_quality": 0.9, "base_reward": 1.0, "quality_multiplier": 2.0}"#;
+        assert!(!ledger.execute_contract("alice", "incentive_distributor", context).is_empty());
+        assert!(ledger.check_supply_integrity().is_empty());
+
+        let mut replayed = ledger.clone();
+        replayed.account_balances.clear();
+        replayed.replay_transaction_log(&ledger.export_transaction_log()).unwrap();
+        assert_same_balances(&replayed, &ledger);
+
+        ledger.mine_block();
+        assert!(ledger.check_supply_integrity().is_empty());
+        assert!((ledger.get_account_balance("alice") - 9.99).abs() < 1e-9);
+        assert_eq!(ledger.get_account_balance("system"), 0.0);
+        let mut reloaded = ledger.clone();
+        reloaded.account_balances.clear();
+        reloaded.replay_transaction_log(&ledger.export_transaction_log()).unwrap();
+        assert_same_balances(&reloaded, &ledger);
+    }
+
+
+    #[test]
+    fn default_contracts_run_as_scripts() {
+        let (mut ledger, _) = synced_pair();
+        let mut run = |contract_id: &str, context: &str| -> BTreeMap<String, f64> {
+            serde_json::from_str(&ledger.execute_contract("alice", contract_id, context)).unwrap()
+        };
+        let incentive = run("incentive_distributor", r#"{"memory_quality": 0.9, "base_reward": 1.0, "quality_multiplier": 2.0}"#);
+        assert_eq!(incentive["reward"], 2.0);
+        let validation = run("memory_validator", r#"{"novelty_score": 0.8, "importance_score": 0.9, "semantic_tag_count": 2}"#);
+        assert!((validation["quality_score"] - 0.9).abs() < 1e-12);
+        let permission = run("node_borrowing_manager", r#"{"borrower_balance": 0.5}"#);
+        assert_eq!(permission["approved"], 0.0);
+        assert!(ledger.check_supply_integrity().is_empty());
+    }
+
+    #[test]
+    fn capsule_registration_scores_with_the_active_validator() {
+        let mut ledger = BlockchainLedger::new();
+        let capsule = crate::memory::tests::sample_capsule("capsule", &["a", "b", "c", "d"]);
+        let capsule_json = serde_json::to_string(&capsule).unwrap();
+        ledger.register_memory_capsule(&capsule_json, "alice".to_string());
+        assert!((ledger.memory_registry["capsule"].quality_score - 0.6).abs() < 1e-12);
+
+        let mut strict = active_contract(&ledger, "MemoryValidation");
+        strict.contract_id = "strict_validator".to_string();
+        strict.code = "quality_score = 0.25".to_string();
+        ledger.deploy_contract(strict);
+        ledger.register_memory_capsule(&capsule_json, "alice".to_string());
+        assert_eq!(ledger.memory_registry["capsule"].quality_score, 0.25);
+    }
+}
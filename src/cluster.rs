@@ -1,9 +1,10 @@
 use wasm_bindgen::prelude::*;
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
-use rand::Rng;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
 
-use crate::threshold_node::ThresholdGatingNode;
+use crate::threshold_node::{ThresholdGatingNode, NodeParameters, OutputFunction, NODE_PARAMETERS_VERSION};
 use crate::memory::ClusterMemory;
 
 // Import the console_log macro
@@ -12,10 +13,10 @@ use crate::console_log;
 /// Device-level cluster managing multiple threshold-gating nodes
 /// Implements local coordination, specialization, and memory management
 #[wasm_bindgen]
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct DeviceCluster {
     cluster_id: String,
-    nodes: HashMap<String, ThresholdGatingNode>,
+    nodes: BTreeMap<String, ThresholdGatingNode>, // Ordered so seeded runs visit nodes identically
     cluster_memory: ClusterMemory,
     topology: NetworkTopology,
     current_time: f64,
@@ -31,16 +32,107 @@ pub struct DeviceCluster {
     
     // Specialization tracking
     specialization_scores: HashMap<String, f64>,
+    specialization_decay: f64, // EMA rate pulling scores toward 1 (active) or 0 (idle)
     node_usage_stats: HashMap<String, u32>,
+    
+    // Noise injected into split nodes so they diverge from the original
+    split_noise_magnitude: f64,
+    split_noise_iterations: u32,
+    
+    // Context vector from a linked cluster's capsule, blended into the next input
+    external_context: Option<Vec<f64>>,
+    
+    // Optional schedule interpolating node error_sensitivity over process cycles
+    error_annealing: Option<ErrorAnnealing>,
+    
+    // Seeded randomness for topology adaptation; checkpoints carry the generator's position
+    rng_seed: u64,
+    rng: ChaCha12Rng,
+    
+    // Fraction of silent nodes over recent output_sparsity passes
+    sparsity_history: VecDeque<f64>,
+    
+    // Feed weighted upstream outputs into each node's accumulator before it fires,
+    // instead of summing neighbor activations after every node fired independently
+    feedforward_integration: bool,
+    
+    // When false, process_input never splits, duplicates, or prunes; nodes still learn
+    topology_adaptation: bool,
+    
+    // History caps applied to new nodes; existing nodes are updated by set_history_sizes
+    node_activation_history: usize,
+    node_firing_history: usize,
+    node_memory_size: usize,
+}
+
+/// Number of recent passes averaged by average_output_sparsity
+const SPARSITY_WINDOW: usize = 20;
+
+/// Output of DeviceCluster::export_weights
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClusterWeights {
+    pub version: u32,
+    pub nodes: BTreeMap<String, NodeParameters>,
+    pub edge_weights: Vec<(String, String, f64)>, // (from, to, weight), sorted
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ErrorAnnealing {
+    pub initial: f64,
+    pub final_value: f64,
+    pub steps: u32,
+    pub elapsed: u32,
+}
+
+impl ErrorAnnealing {
+    pub fn current_value(&self) -> f64 {
+        let progress = if self.steps == 0 {
+            1.0
+        } else {
+            (self.elapsed as f64 / self.steps as f64).min(1.0)
+        };
+        self.initial + (self.final_value - self.initial) * progress
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct NetworkTopology {
     pub connections: HashMap<String, Vec<String>>, // node_id -> connected_node_ids
+    #[serde(with = "edge_map")]
     pub edge_weights: HashMap<(String, String), f64>,
+    #[serde(with = "edge_map")]
     pub edge_usage: HashMap<(String, String), u32>,
 }
 
+/// Serializes tuple-keyed edge maps as (from, to, value) lists, since JSON keys must be strings
+mod edge_map {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+
+    pub fn serialize<S, V>(map: &HashMap<(String, String), V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        V: Serialize,
+    {
+        serializer.collect_seq(map.iter().map(|((from, to), value)| (from, to, value)))
+    }
+
+    pub fn deserialize<'de, D, V>(deserializer: D) -> Result<HashMap<(String, String), V>, D::Error>
+    where
+        D: Deserializer<'de>,
+        V: Deserialize<'de>,
+    {
+        let edges: Vec<(String, String, V)> = Vec::deserialize(deserializer)?;
+        Ok(edges.into_iter().map(|(from, to, value)| ((from, to), value)).collect())
+    }
+}
+
+impl Default for NetworkTopology {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl NetworkTopology {
     pub fn new() -> Self {
         NetworkTopology {
@@ -67,15 +159,42 @@ impl NetworkTopology {
     pub fn get_connections(&self, node_id: &str) -> Vec<String> {
         self.connections.get(node_id).cloned().unwrap_or_default()
     }
+
+    /// Approximate size of the adjacency lists and edge maps in bytes
+    pub fn memory_footprint_estimate(&self) -> usize {
+        let string_size = std::mem::size_of::<String>();
+        let connection_bytes: usize = self.connections.iter()
+            .map(|(node_id, targets)| {
+                string_size + node_id.capacity()
+                    + targets.iter().map(|target| string_size + target.capacity()).sum::<usize>()
+            })
+            .sum();
+        let edge_key_bytes = |(from, to): &(String, String)| 2 * string_size + from.capacity() + to.capacity();
+        let weight_bytes: usize = self.edge_weights.keys()
+            .map(|key| edge_key_bytes(key) + std::mem::size_of::<f64>())
+            .sum();
+        let usage_bytes: usize = self.edge_usage.keys()
+            .map(|key| edge_key_bytes(key) + std::mem::size_of::<u32>())
+            .sum();
+        connection_bytes + weight_bytes + usage_bytes
+    }
 }
 
 #[wasm_bindgen]
 impl DeviceCluster {
     #[wasm_bindgen(constructor)]
     pub fn new(cluster_id: String, num_initial_nodes: usize) -> DeviceCluster {
+        Self::new_with_seed(cluster_id, num_initial_nodes, rand::thread_rng().gen())
+    }
+
+    /// Reproducible cluster: node parameters, initial wiring and later split noise all come
+    /// from one generator seeded with `seed`, so equal seeds and inputs give equal states
+    #[wasm_bindgen]
+    pub fn new_with_seed(cluster_id: String, num_initial_nodes: usize, seed: u64) -> DeviceCluster {
+        let rng_seed = seed;
         let mut cluster = DeviceCluster {
             cluster_id: cluster_id.clone(),
-            nodes: HashMap::new(),
+            nodes: BTreeMap::new(),
             cluster_memory: ClusterMemory::new(cluster_id.clone()),
             topology: NetworkTopology::new(),
             current_time: 0.0,
@@ -85,7 +204,20 @@ impl DeviceCluster {
             edge_duplication_threshold: 5.0,
             pruning_threshold: 0.1,
             specialization_scores: HashMap::new(),
+            specialization_decay: 0.1,
             node_usage_stats: HashMap::new(),
+            split_noise_magnitude: 0.1,
+            split_noise_iterations: 5,
+            error_annealing: None,
+            external_context: None,
+            rng_seed,
+            rng: ChaCha12Rng::seed_from_u64(rng_seed),
+            sparsity_history: VecDeque::new(),
+            feedforward_integration: false,
+            topology_adaptation: true,
+            node_activation_history: 100,
+            node_firing_history: 50,
+            node_memory_size: 50,
         };
 
         // Create initial nodes with random topology
@@ -94,23 +226,22 @@ impl DeviceCluster {
     }
 
     fn initialize_nodes(&mut self, num_nodes: usize) {
-        let mut rng = rand::thread_rng();
-        
         for i in 0..num_nodes {
             let node_id = format!("{}_node_{}", self.cluster_id, i);
-            let node = ThresholdGatingNode::new(node_id.clone(), 4); // 4 input connections
+            let mut node = ThresholdGatingNode::new_with_rng(node_id.clone(), 4, &mut self.rng); // 4 input connections
+            node.set_history_sizes(self.node_activation_history, self.node_firing_history);
             
             self.nodes.insert(node_id.clone(), node);
             self.topology.add_node(node_id.clone());
-            self.cluster_memory.add_node_memory(node_id.clone(), 50);
+            self.cluster_memory.add_node_memory(node_id.clone(), self.node_memory_size);
             
             // Create random connections to other nodes
             if i > 0 {
-                let num_connections = rng.gen_range(1..=std::cmp::min(3, i));
+                let num_connections = self.rng.gen_range(1..=std::cmp::min(3, i));
                 for _ in 0..num_connections {
-                    let target_idx = rng.gen_range(0..i);
+                    let target_idx = self.rng.gen_range(0..i);
                     let target_id = format!("{}_node_{}", self.cluster_id, target_idx);
-                    let weight = rng.gen_range(0.1..1.0);
+                    let weight = self.rng.gen_range(0.1..1.0);
                     self.topology.connect_nodes(node_id.clone(), target_id, weight);
                 }
             }
@@ -120,12 +251,27 @@ impl DeviceCluster {
     #[wasm_bindgen]
     pub fn process_input(&mut self, input_data: &[f64]) -> Vec<f64> {
         self.current_time += 1.0; // Simplified time increment
+        self.advance_error_annealing();
         let mut outputs = Vec::new();
-        let mut node_activations: HashMap<String, f64> = HashMap::new();
+        let mut node_activations: BTreeMap<String, f64> = BTreeMap::new();
+        
+        // Blend in context from a linked cluster, scaled so it nudges rather than overrides the input
+        let mut effective_input = input_data.to_vec();
+        if let Some(context) = self.external_context.take() {
+            for (value, ctx) in effective_input.iter_mut().zip(context.iter()) {
+                *value += 0.5 * ctx;
+            }
+        }
+
+        self.fit_fresh_nodes_to(effective_input.len());
+
+        if self.feedforward_integration {
+            return self.process_feedforward(&effective_input);
+        }
 
         // First pass: collect all node outputs
         for (node_id, node) in &mut self.nodes {
-            let output = node.process_input(input_data, self.current_time, 1.0);
+            let output = node.process_input(&effective_input, self.current_time, 1.0);
             node_activations.insert(node_id.clone(), output);
             
             // Update usage statistics
@@ -144,7 +290,7 @@ impl DeviceCluster {
         }
 
         // Second pass: propagate activations through topology
-        let mut processed_outputs: HashMap<String, f64> = HashMap::new();
+        let mut processed_outputs: BTreeMap<String, f64> = BTreeMap::new();
         
         for (node_id, activation) in &node_activations {
             let connections = self.topology.get_connections(node_id);
@@ -181,28 +327,130 @@ impl DeviceCluster {
         outputs
     }
 
-    fn update_specialization_scores(&mut self, outputs: &HashMap<String, f64>) {
+    // Nodes adopt the width of their first input; drawing the extra weights here keeps seeded clusters reproducible
+    fn fit_fresh_nodes_to(&mut self, input_width: usize) {
+        for node in self.nodes.values_mut() {
+            if !node.has_processed_input() && node.input_size() != input_width {
+                node.resize_inputs_with_rng(input_width, &mut self.rng);
+            }
+        }
+    }
+
+    // Upstream nodes fire first and their weighted outputs feed the downstream accumulator
+    fn process_feedforward(&mut self, input_data: &[f64]) -> Vec<f64> {
+        let order = self.feedforward_order();
+        let mut node_activations: BTreeMap<String, f64> = BTreeMap::new();
+        let mut outputs = Vec::with_capacity(order.len());
+        
+        for node_id in &order {
+            let mut upstream = 0.0;
+            for connected_id in self.topology.get_connections(node_id) {
+                if let Some(connected_activation) = node_activations.get(&connected_id) {
+                    let edge_key = (node_id.clone(), connected_id.clone());
+                    let weight = self.topology.edge_weights.get(&edge_key).unwrap_or(&1.0);
+                    upstream += connected_activation * weight;
+                    self.topology.record_edge_usage(node_id, &connected_id);
+                }
+            }
+            
+            let node = match self.nodes.get_mut(node_id) {
+                Some(node) => node,
+                None => continue,
+            };
+            let output = node.process_input_with_upstream(input_data, upstream, self.current_time, 1.0);
+            
+            if output > 0.0 {
+                *self.node_usage_stats.entry(node_id.clone()).or_insert(0) += 1;
+            }
+            self.cluster_memory.update_node_memory(
+                node_id,
+                output,
+                self.global_error,
+                node.eligibility_trace(),
+                node.threshold()
+            );
+            
+            node_activations.insert(node_id.clone(), output);
+            outputs.push(output);
+        }
+        
+        self.update_specialization_scores(&node_activations);
+        self.adapt_topology();
+        
+        if let Some(capsule) = self.cluster_memory.create_memory_capsule(self.current_time) {
+            console_log!("Generated memory capsule: {}", capsule.capsule_id);
+        }
+        
+        outputs
+    }
+
+    // Nodes ordered so each comes after the nodes it receives from; cycles are broken arbitrarily
+    fn feedforward_order(&self) -> Vec<String> {
+        let mut node_ids: Vec<&String> = self.nodes.keys().collect();
+        node_ids.sort();
+        
+        let mut order = Vec::with_capacity(node_ids.len());
+        let mut visited = std::collections::HashSet::new();
+        for root in node_ids {
+            if visited.contains(root) {
+                continue;
+            }
+            // Iterative post-order DFS over upstream connections
+            let mut stack = vec![(root.clone(), false)];
+            while let Some((node_id, expanded)) = stack.pop() {
+                if expanded {
+                    order.push(node_id);
+                    continue;
+                }
+                if !visited.insert(node_id.clone()) {
+                    continue;
+                }
+                stack.push((node_id.clone(), true));
+                for upstream in self.topology.get_connections(&node_id) {
+                    if self.nodes.contains_key(&upstream) && !visited.contains(&upstream) {
+                        stack.push((upstream, false));
+                    }
+                }
+            }
+        }
+        order
+    }
+
+    fn advance_error_annealing(&mut self) {
+        if let Some(ref mut annealing) = self.error_annealing {
+            annealing.elapsed = annealing.elapsed.saturating_add(1).min(annealing.steps);
+            let sensitivity = annealing.current_value();
+            for node in self.nodes.values_mut() {
+                node.set_error_sensitivity(sensitivity);
+            }
+        }
+    }
+
+    fn update_specialization_scores(&mut self, outputs: &BTreeMap<String, f64>) {
         for (node_id, output) in outputs {
             let current_score = self.specialization_scores.get(node_id).unwrap_or(&0.0);
             
-            // Simple specialization metric based on activation consistency
-            let new_score = if *output > 0.5 {
-                current_score + 0.1
-            } else {
-                (current_score - 0.05).max(0.0)
-            };
+            // Exponential moving average of activation consistency, bounded to [0, 1]
+            let target = if *output > 0.5 { 1.0 } else { 0.0 };
+            let new_score = current_score + self.specialization_decay * (target - current_score);
             
             self.specialization_scores.insert(node_id.clone(), new_score);
         }
     }
 
     fn adapt_topology(&mut self) {
+        if !self.topology_adaptation {
+            return;
+        }
+        
         // Node splitting: duplicate highly used nodes
-        let nodes_to_split: Vec<String> = self.node_usage_stats
+        let mut nodes_to_split: Vec<String> = self.node_usage_stats
             .iter()
             .filter(|(_, &usage)| usage as f64 > self.node_split_threshold)
             .map(|(id, _)| id.clone())
             .collect();
+        // Splits draw from the seeded generator, so visit them in a fixed order
+        nodes_to_split.sort();
 
         for node_id in nodes_to_split {
             self.split_node(&node_id);
@@ -230,21 +478,24 @@ impl DeviceCluster {
             // Create new node as a copy with slight variations
             let mut new_node = original_node.clone();
             
-            // Add some noise to make the split nodes different
-            let mut rng = rand::thread_rng();
-            for _ in 0..5 {
-                let noise_input = vec![rng.gen_range(-0.1..0.1); 4];
-                new_node.process_input(&noise_input, self.current_time, 0.1);
-            }
+            // Warm up on fresh noise each step to make the split nodes different
+            let magnitude = self.split_noise_magnitude;
+            let input_size = new_node.input_size();
+            let rng = &mut self.rng;
+            new_node.warmup_with(self.split_noise_iterations as usize, || {
+                (0..input_size)
+                    .map(|_| if magnitude > 0.0 { rng.gen_range(-magnitude..magnitude) } else { 0.0 })
+                    .collect()
+            });
             
             self.nodes.insert(new_node_id.clone(), new_node);
             self.topology.add_node(new_node_id.clone());
-            self.cluster_memory.add_node_memory(new_node_id.clone(), 50);
+            self.cluster_memory.add_node_memory(new_node_id.clone(), self.node_memory_size);
             
             // Copy some connections from original node
             let original_connections = self.topology.get_connections(node_id);
             for connected_id in original_connections.iter().take(2) {
-                let weight = rng.gen_range(0.1..1.0);
+                let weight = self.rng.gen_range(0.1..1.0);
                 self.topology.connect_nodes(new_node_id.clone(), connected_id.clone(), weight);
             }
             
@@ -258,6 +509,13 @@ impl DeviceCluster {
         }
     }
 
+    // Summed in node id order so the result doesn't depend on HashMap iteration order
+    fn average_specialization(&self) -> f64 {
+        let mut scores: Vec<(&String, &f64)> = self.specialization_scores.iter().collect();
+        scores.sort_by(|a, b| a.0.cmp(b.0));
+        scores.iter().map(|(_, score)| **score).sum::<f64>() / scores.len().max(1) as f64
+    }
+
     fn duplicate_edge(&mut self, from: &str, to: &str) {
         let edge_key = (from.to_string(), to.to_string());
         if let Some(&current_weight) = self.topology.edge_weights.get(&edge_key) {
@@ -325,8 +583,10 @@ impl DeviceCluster {
         }
         
         // Update adaptation thresholds based on cluster performance
-        let avg_specialization: f64 = self.specialization_scores.values().sum::<f64>() 
-            / self.specialization_scores.len() as f64;
+        if self.specialization_scores.is_empty() {
+            return;
+        }
+        let avg_specialization = self.average_specialization();
         
         if avg_specialization > 0.8 {
             self.node_split_threshold *= 0.95; // Make splitting easier
@@ -343,13 +603,397 @@ impl DeviceCluster {
             num_connections: self.topology.edge_weights.len(),
             current_time: self.current_time,
             global_error: self.global_error,
-            avg_specialization: self.specialization_scores.values().sum::<f64>() 
-                / self.specialization_scores.len().max(1) as f64,
+            avg_specialization: self.average_specialization(),
             total_activations: self.node_usage_stats.values().sum::<u32>(),
+            parameter_count: self.parameter_count(),
+            memory_footprint_bytes: self.memory_footprint_estimate(),
         };
         serde_wasm_bindgen::to_value(&state).unwrap_or(JsValue::NULL)
     }
 
+    /// Anneal node error_sensitivity linearly from `initial` to `final_value` over `steps` process cycles
+    #[wasm_bindgen]
+    pub fn set_error_annealing(&mut self, initial: f64, final_value: f64, steps: u32) {
+        let annealing = ErrorAnnealing { initial, final_value, steps, elapsed: 0 };
+        let sensitivity = annealing.current_value();
+        for node in self.nodes.values_mut() {
+            node.set_error_sensitivity(sensitivity);
+        }
+        self.error_annealing = Some(annealing);
+    }
+
+    #[wasm_bindgen]
+    pub fn clear_error_annealing(&mut self) {
+        self.error_annealing = None;
+    }
+
+    #[wasm_bindgen]
+    pub fn set_split_noise(&mut self, magnitude: f64, iterations: u32) {
+        self.split_noise_magnitude = magnitude.abs();
+        self.split_noise_iterations = iterations;
+    }
+
+    /// Restart the PRNG behind split noise and edge weights from `seed`; learned weights are untouched
+    #[wasm_bindgen]
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng_seed = seed;
+        self.rng = ChaCha12Rng::seed_from_u64(seed);
+    }
+
+    #[wasm_bindgen]
+    pub fn get_seed(&self) -> u64 {
+        self.rng_seed
+    }
+
+    /// Cap node activation/firing histories and the per-node memory consolidated into capsules
+    #[wasm_bindgen]
+    pub fn set_history_sizes(&mut self, activation: usize, firing: usize, node_memory: usize) {
+        self.node_activation_history = activation.max(1);
+        self.node_firing_history = firing.max(1);
+        self.node_memory_size = node_memory.max(1);
+        for node in self.nodes.values_mut() {
+            node.set_history_sizes(activation, firing);
+        }
+        self.cluster_memory.set_node_memory_size(node_memory);
+    }
+
+    /// Hold the node and edge structure fixed for controlled experiments
+    #[wasm_bindgen]
+    pub fn set_topology_adaptation(&mut self, enabled: bool) {
+        self.topology_adaptation = enabled;
+    }
+
+    #[wasm_bindgen]
+    pub fn set_feedforward_integration(&mut self, enabled: bool) {
+        self.feedforward_integration = enabled;
+    }
+
+    /// Rate in (0, 1] at which specialization scores track recent activity; higher forgets faster
+    #[wasm_bindgen]
+    pub fn set_specialization_decay(&mut self, decay: f64) -> bool {
+        if decay.is_nan() || decay <= 0.0 || decay > 1.0 {
+            return false;
+        }
+        self.specialization_decay = decay;
+        true
+    }
+
+    #[wasm_bindgen]
+    pub fn get_specialization_score(&self, node_id: &str) -> f64 {
+        self.specialization_scores.get(node_id).copied().unwrap_or(0.0)
+    }
+
+    /// Keep every node's threshold within [min, max] during firing adaptation
+    #[wasm_bindgen]
+    pub fn set_threshold_bounds(&mut self, min: f64, max: f64) -> bool {
+        if max.is_nan() || max < min.max(0.1) {
+            return false;
+        }
+        for node in self.nodes.values_mut() {
+            node.set_threshold_bounds(min, max);
+        }
+        true
+    }
+
+    /// Dendritic compartments on every node (0 = flat); false if any node has fewer inputs
+    #[wasm_bindgen]
+    pub fn enable_compartments(&mut self, num_compartments: usize) -> bool {
+        if self.nodes.values().any(|node| node.input_size() < num_compartments) {
+            return false;
+        }
+        for node in self.nodes.values_mut() {
+            node.enable_compartments(num_compartments);
+        }
+        true
+    }
+
+    #[wasm_bindgen]
+    pub fn set_max_time_to_release(&mut self, max: f64) -> bool {
+        if !max.is_finite() || max < 1.0 {
+            return false;
+        }
+        for node in self.nodes.values_mut() {
+            node.set_max_time_to_release(max);
+        }
+        true
+    }
+
+    #[wasm_bindgen]
+    pub fn set_learning_params(&mut self, learning_rate: f64) -> bool {
+        if !learning_rate.is_finite() || learning_rate <= 0.0 {
+            return false;
+        }
+        for node in self.nodes.values_mut() {
+            node.set_learning_params(learning_rate);
+        }
+        true
+    }
+
+    #[wasm_bindgen]
+    pub fn set_eligibility_output_gating(&mut self, enabled: bool) {
+        for node in self.nodes.values_mut() {
+            node.set_eligibility_output_gating(enabled);
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_max_eligibility_trace(&mut self, max_trace: f64) -> bool {
+        if max_trace.is_nan() || max_trace <= 0.0 {
+            return false;
+        }
+        for node in self.nodes.values_mut() {
+            node.set_max_eligibility_trace(max_trace);
+        }
+        true
+    }
+
+    /// Threshold firing output for every node: "raw", "overshoot" or "threshold"
+    #[wasm_bindgen]
+    pub fn set_fire_output_mode(&mut self, mode: &str) -> bool {
+        if !matches!(mode.to_ascii_lowercase().as_str(), "raw" | "overshoot" | "threshold") {
+            return false;
+        }
+        for node in self.nodes.values_mut() {
+            node.set_fire_output_mode(mode);
+        }
+        true
+    }
+
+    /// Output function for every node: "raw", "sigmoid", "tanh", "relu" or "binary"
+    #[wasm_bindgen]
+    pub fn set_output_function(&mut self, name: &str) -> bool {
+        if OutputFunction::from_name(name).is_none() {
+            return false;
+        }
+        for node in self.nodes.values_mut() {
+            node.set_output_function(name);
+        }
+        true
+    }
+
+    #[wasm_bindgen]
+    pub fn set_refractory_period(&mut self, period: f64) {
+        for node in self.nodes.values_mut() {
+            node.set_refractory_period(period);
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_accumulator_decay(&mut self, decay: f64) {
+        for node in self.nodes.values_mut() {
+            node.set_accumulator_decay(decay);
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_timer_fire_gain(&mut self, gain: f64) {
+        for node in self.nodes.values_mut() {
+            node.set_timer_fire_gain(gain);
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_consolidation_interval_bounds(&mut self, min_interval: f64, max_interval: f64) {
+        self.cluster_memory.set_consolidation_interval_bounds(min_interval, max_interval);
+    }
+
+    /// Novelty metric for new capsules: "euclidean", "cosine" or "learned", with its normalization constant
+    #[wasm_bindgen]
+    pub fn set_novelty_config(&mut self, metric: &str, normalization: f64) -> bool {
+        self.cluster_memory.set_novelty_config(metric, normalization)
+    }
+
+    /// 32-byte device secret that Personal capsules are encrypted under
+    #[wasm_bindgen]
+    pub fn set_device_secret(&mut self, key: &[u8]) -> bool {
+        self.cluster_memory.set_device_secret(key)
+    }
+
+    /// Versioned JSON of every node's parameters (see ThresholdGatingNode::export_weights)
+    /// plus the topology's edge weights, for model transfer between devices
+    #[wasm_bindgen]
+    pub fn export_weights(&self) -> String {
+        let mut edge_weights: Vec<(String, String, f64)> = self.topology.edge_weights.iter()
+            .map(|((from, to), weight)| (from.clone(), to.clone(), *weight))
+            .collect();
+        edge_weights.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        
+        serde_json::to_string(&ClusterWeights {
+            version: NODE_PARAMETERS_VERSION,
+            nodes: self.nodes.iter().map(|(id, node)| (id.clone(), node.parameters())).collect(),
+            edge_weights,
+        }).unwrap_or_default()
+    }
+
+    /// Load an export_weights document onto matching nodes and edges. Every listed node must
+    /// exist here and pass validation, otherwise nothing is changed.
+    #[wasm_bindgen]
+    pub fn load_weights(&mut self, json: &str) -> bool {
+        let weights = match serde_json::from_str::<ClusterWeights>(json) {
+            Ok(weights) => weights,
+            Err(e) => {
+                console_log!("Failed to parse cluster weights: {:?}", e);
+                return false;
+            }
+        };
+        if weights.version != NODE_PARAMETERS_VERSION {
+            console_log!("Unsupported cluster weights version {}", weights.version);
+            return false;
+        }
+        for (node_id, params) in &weights.nodes {
+            let valid = match self.nodes.get(node_id) {
+                Some(node) => node.validate_parameters(params),
+                None => Err("no such node".to_string()),
+            };
+            if let Err(e) = valid {
+                console_log!("Rejected cluster weights for node {}: {}", node_id, e);
+                return false;
+            }
+        }
+        if weights.edge_weights.iter().any(|(_, _, weight)| !weight.is_finite()) {
+            console_log!("Rejected cluster weights: non-finite edge weight");
+            return false;
+        }
+        
+        for (node_id, params) in &weights.nodes {
+            if let Some(node) = self.nodes.get_mut(node_id) {
+                node.apply_parameters(params);
+            }
+        }
+        for (from, to, weight) in weights.edge_weights {
+            if let Some(existing) = self.topology.edge_weights.get_mut(&(from, to)) {
+                *existing = weight;
+            }
+        }
+        console_log!("Loaded weights for {} nodes into cluster {}", weights.nodes.len(), self.cluster_id);
+        true
+    }
+
+    /// Capture the full in-process state (nodes, topology, memories, stats, rng position) as JSON
+    #[wasm_bindgen]
+    pub fn checkpoint(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// Replace the current state with a checkpoint of this same cluster; leaves the cluster
+    /// untouched if it can't be parsed or belongs to another cluster
+    #[wasm_bindgen]
+    pub fn restore(&mut self, snapshot: &str) -> bool {
+        match serde_json::from_str::<DeviceCluster>(snapshot) {
+            Ok(restored) if restored.cluster_id != self.cluster_id => {
+                console_log!("Refusing to restore checkpoint of cluster {} into {}", restored.cluster_id, self.cluster_id);
+                false
+            },
+            Ok(mut restored) => {
+                // The device secret is never checkpointed, so keep the one already set
+                if let Some(secret) = self.cluster_memory.device_secret() {
+                    restored.cluster_memory.set_device_secret(&secret);
+                }
+                *self = restored;
+                console_log!("Restored cluster {} from checkpoint", self.cluster_id);
+                true
+            },
+            Err(e) => {
+                console_log!("Failed to restore cluster checkpoint: {:?}", e);
+                false
+            }
+        }
+    }
+
+    /// JSON object of node_id -> node state, ordered by node id
+    #[wasm_bindgen]
+    pub fn get_all_node_states(&self) -> String {
+        let states: BTreeMap<&String, serde_json::Value> = self.nodes
+            .iter()
+            .map(|(node_id, node)| {
+                let state = serde_json::from_str(&node.get_state()).unwrap_or(serde_json::Value::Null);
+                (node_id, state)
+            })
+            .collect();
+        serde_json::to_string(&states).unwrap_or_default()
+    }
+
+    /// Total weights and biases across all nodes
+    #[wasm_bindgen]
+    pub fn parameter_count(&self) -> usize {
+        self.nodes.values().map(|node| node.parameter_count()).sum()
+    }
+
+    /// Approximate bytes used by nodes, topology, and cluster memory histories
+    #[wasm_bindgen]
+    pub fn memory_footprint_estimate(&self) -> usize {
+        let node_bytes: usize = self.nodes.values()
+            .map(|node| node.memory_footprint_estimate())
+            .sum();
+        node_bytes + self.topology.memory_footprint_estimate() + self.cluster_memory.memory_footprint_estimate()
+    }
+
+    /// Run a forward pass and return the fraction of nodes producing zero output
+    #[wasm_bindgen]
+    pub fn output_sparsity(&mut self, input_data: &[f64]) -> f64 {
+        let outputs = self.process_input(input_data);
+        if outputs.is_empty() {
+            return 0.0;
+        }
+        
+        let silent = outputs.iter().filter(|&&output| output == 0.0).count();
+        let sparsity = silent as f64 / outputs.len() as f64;
+        
+        self.sparsity_history.push_back(sparsity);
+        while self.sparsity_history.len() > SPARSITY_WINDOW {
+            self.sparsity_history.pop_front();
+        }
+        sparsity
+    }
+
+    /// Mean sparsity over recent output_sparsity passes; near 0 suggests saturation, near 1 dead nodes
+    #[wasm_bindgen]
+    pub fn average_output_sparsity(&self) -> f64 {
+        if self.sparsity_history.is_empty() {
+            return 0.0;
+        }
+        self.sparsity_history.iter().sum::<f64>() / self.sparsity_history.len() as f64
+    }
+
+    /// Provide context (e.g. another cluster's capsule context_vector) for the next process_input
+    #[wasm_bindgen]
+    pub fn set_external_context(&mut self, context: Vec<f64>) {
+        self.external_context = Some(context);
+    }
+
+    /// Remove a node with its edges, memory, and statistics
+    #[wasm_bindgen]
+    pub fn remove_node(&mut self, node_id: &str) -> bool {
+        if self.nodes.remove(node_id).is_none() {
+            return false;
+        }
+
+        self.topology.connections.remove(node_id);
+        for targets in self.topology.connections.values_mut() {
+            targets.retain(|id| id != node_id);
+        }
+        self.topology.edge_weights.retain(|(from, to), _| from != node_id && to != node_id);
+        self.topology.edge_usage.retain(|(from, to), _| from != node_id && to != node_id);
+
+        self.cluster_memory.node_memories.remove(node_id);
+        self.specialization_scores.remove(node_id);
+        self.node_usage_stats.remove(node_id);
+
+        console_log!("Removed node {} from cluster {}", node_id, self.cluster_id);
+        true
+    }
+
+    #[wasm_bindgen]
+    pub fn get_node_ids(&self) -> Vec<String> {
+        self.nodes.keys().cloned().collect()
+    }
+
+    /// Widest input any node consumes; extra input components are ignored
+    #[wasm_bindgen]
+    pub fn get_input_size(&self) -> usize {
+        self.nodes.values().map(|node| node.input_size()).max().unwrap_or(0)
+    }
+
     #[wasm_bindgen]
     pub fn get_node_count(&self) -> usize {
         self.nodes.len()
@@ -365,6 +1009,23 @@ impl DeviceCluster {
     pub fn get_latest_memory_capsule(&self) -> Option<crate::memory::MemoryCapsule> {
         self.cluster_memory.get_latest_capsule()
     }
+
+    pub fn get_node(&self, node_id: &str) -> Option<&ThresholdGatingNode> {
+        self.nodes.get(node_id)
+    }
+
+    /// Add an existing node (e.g. one borrowed from a peer) without connections; false if its id is taken
+    pub fn insert_node(&mut self, node: ThresholdGatingNode) -> bool {
+        let node_id = node.node_id().to_string();
+        if self.nodes.contains_key(&node_id) {
+            return false;
+        }
+        self.nodes.insert(node_id.clone(), node);
+        self.topology.add_node(node_id.clone());
+        self.cluster_memory.add_node_memory(node_id.clone(), self.node_memory_size);
+        console_log!("Inserted node {} into cluster {}", node_id, self.cluster_id);
+        true
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -376,4 +1037,297 @@ pub struct ClusterState {
     pub global_error: f64,
     pub avg_specialization: f64,
     pub total_activations: u32,
-} 
\ No newline at end of file
+    pub parameter_count: usize,
+    pub memory_footprint_bytes: usize,
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restore_keeps_the_device_secret() {
+        let mut cluster = DeviceCluster::new_with_seed("cluster".to_string(), 4, 7);
+        assert!(cluster.set_device_secret(&[9u8; crate::memory::DEVICE_SECRET_SIZE]));
+        let snapshot = cluster.checkpoint();
+
+        assert!(cluster.restore(&snapshot));
+        assert_eq!(cluster.cluster_memory.device_secret(), Some([9u8; crate::memory::DEVICE_SECRET_SIZE]));
+    }
+
+    #[test]
+    fn restore_rejects_another_clusters_checkpoint() {
+        let other = DeviceCluster::new_with_seed("other".to_string(), 2, 7);
+        let mut cluster = DeviceCluster::new_with_seed("cluster".to_string(), 4, 7);
+        let before = cluster.checkpoint();
+
+        assert!(!cluster.restore(&other.checkpoint()));
+        assert_eq!(cluster.checkpoint(), before);
+    }
+
+    #[test]
+    fn split_nodes_warm_up_on_fresh_noise_each_step() {
+        let mut cluster = DeviceCluster::new_with_seed("cluster".to_string(), 2, 7);
+        cluster.set_split_noise(0.5, 5);
+        let node_id = cluster.nodes.keys().min().cloned().unwrap();
+        let split_id = cluster.split_node(&node_id).unwrap();
+
+        let node: serde_json::Value = serde_json::from_str(&cluster.nodes[&split_id].to_json()).unwrap();
+        let history: Vec<f64> = serde_json::from_value(node["activation_history"].clone()).unwrap();
+        let distinct: std::collections::HashSet<u64> = history.iter().map(|value| value.to_bits()).collect();
+        assert_eq!(history.len(), 5);
+        assert_eq!(distinct.len(), 5);
+    }
+
+    #[test]
+    fn parameter_count_covers_every_weight_and_bias() {
+        // New nodes take 4 inputs: 4 weights plus a bias each
+        let cluster = DeviceCluster::new_with_seed("cluster".to_string(), 3, 7);
+        assert_eq!(cluster.parameter_count(), 3 * (4 + 1));
+        assert!(cluster.memory_footprint_estimate() > 0);
+    }
+
+    fn sensitivities(cluster: &DeviceCluster) -> Vec<f64> {
+        cluster.nodes.values().map(|node| node.error_sensitivity()).collect()
+    }
+
+    #[test]
+    fn error_annealing_reaches_the_final_value() {
+        let mut cluster = DeviceCluster::new_with_seed("cluster".to_string(), 3, 7);
+        let inputs = vec![0.5; cluster.get_input_size()];
+        cluster.set_error_annealing(0.4, 0.1, 4);
+        assert!(sensitivities(&cluster).iter().all(|&value| value == 0.4));
+
+        for _ in 0..2 {
+            cluster.process_input(&inputs);
+        }
+        assert!(sensitivities(&cluster).iter().all(|&value| (value - 0.25).abs() < 1e-12));
+
+        for _ in 0..4 {
+            cluster.process_input(&inputs);
+        }
+        assert!(sensitivities(&cluster).iter().all(|&value| (value - 0.1).abs() < 1e-12));
+    }
+
+    // Unconnected nodes where only the first `firing` (by id) respond to a positive input
+    fn cluster_with_firing_nodes(num_nodes: usize, firing: usize) -> DeviceCluster {
+        let mut cluster = DeviceCluster::new_with_seed("cluster".to_string(), num_nodes, 7);
+        cluster.set_topology_adaptation(false);
+        cluster.topology.connections.clear();
+        cluster.topology.edge_weights.clear();
+        let mut node_ids: Vec<String> = cluster.nodes.keys().cloned().collect();
+        node_ids.sort();
+        for (index, node_id) in node_ids.iter().enumerate() {
+            let node = cluster.nodes.get_mut(node_id).unwrap();
+            let weight = if index < firing { 1.0 } else { 0.0 };
+            assert!(node.set_weights(vec![weight; 4]));
+            assert!(node.set_bias(0.0));
+            assert!(node.set_threshold_bounds(1.0, 1.0));
+        }
+        cluster
+    }
+
+    #[test]
+    fn output_sparsity_counts_the_silent_nodes() {
+        let mut cluster = cluster_with_firing_nodes(4, 1);
+        assert_eq!(cluster.average_output_sparsity(), 0.0);
+        assert_eq!(cluster.output_sparsity(&[0.5; 4]), 0.75);
+        assert_eq!(cluster.output_sparsity(&[0.0; 4]), 1.0);
+        assert!((cluster.average_output_sparsity() - 0.875).abs() < 1e-12);
+    }
+
+    // Weighted sums the split node saw while warming up on noise; the parent is pinned first so
+    // only the generator's draws can make two splits differ
+    fn split_warmup_sums(cluster: &mut DeviceCluster) -> Vec<f64> {
+        cluster.set_split_noise(0.5, 5);
+        let node_id = cluster.nodes.keys().next().cloned().unwrap();
+        let parent = cluster.nodes.get_mut(&node_id).unwrap();
+        assert!(parent.set_weights(vec![0.5, -0.25, 1.0, 0.75]));
+        assert!(parent.set_bias(0.0));
+        let split_id = cluster.split_node(&node_id).unwrap();
+        let state: serde_json::Value = serde_json::from_str(&cluster.nodes[&split_id].to_json()).unwrap();
+        let sums: Vec<f64> = state["activation_history"].as_array().unwrap()
+            .iter()
+            .map(|sum| sum.as_f64().unwrap())
+            .collect();
+        assert_eq!(sums.len(), 5);
+        sums
+    }
+
+    #[test]
+    fn seeded_splits_are_reproducible() {
+        let warmup = |seed: u64| split_warmup_sums(&mut DeviceCluster::new_with_seed("cluster".to_string(), 3, seed));
+        assert_eq!(warmup(11), warmup(11));
+        assert_ne!(warmup(11), warmup(12));
+    }
+
+    #[test]
+    fn restoring_a_checkpoint_rolls_back_node_states() {
+        let mut cluster = DeviceCluster::new_with_seed("cluster".to_string(), 4, 7);
+        let inputs = vec![0.8; cluster.get_input_size()];
+        cluster.process_input(&inputs);
+        let snapshot = cluster.checkpoint();
+        let before = cluster.get_all_node_states();
+
+        for _ in 0..5 {
+            cluster.process_input(&inputs);
+        }
+        assert_ne!(cluster.get_all_node_states(), before);
+
+        assert!(cluster.restore(&snapshot));
+        assert_eq!(cluster.get_all_node_states(), before);
+    }
+
+
+    #[test]
+    fn heavy_firing_keeps_thresholds_under_the_ceiling() {
+        let mut cluster = cluster_with_firing_nodes(3, 3);
+        assert!(cluster.set_threshold_bounds(0.2, 1.5));
+        assert!(!cluster.set_threshold_bounds(2.0, 1.0));
+
+        for _ in 0..50 {
+            cluster.process_input(&[10.0; 4]);
+            assert!(cluster.nodes.values().all(|node| node.threshold() <= 1.5));
+        }
+        assert!(cluster.nodes.values().all(|node| node.threshold_fires() > 10));
+    }
+
+
+    // Output of the downstream node in a two-node chain after one feedforward pass
+    fn chain_output(edge_weight: f64) -> f64 {
+        let mut cluster = cluster_with_firing_nodes(2, 1);
+        cluster.set_feedforward_integration(true);
+        let mut node_ids: Vec<String> = cluster.nodes.keys().cloned().collect();
+        node_ids.sort();
+        let (upstream, downstream) = (node_ids[0].clone(), node_ids[1].clone());
+        cluster.topology.connections.insert(downstream.clone(), vec![upstream.clone()]);
+        cluster.topology.edge_weights.insert((downstream.clone(), upstream), edge_weight);
+
+        let outputs = cluster.process_input(&[0.5; 4]);
+        assert!(outputs[0] > 0.0);
+        outputs[1]
+    }
+
+    #[test]
+    fn edge_weight_decides_whether_the_downstream_node_fires() {
+        assert_eq!(chain_output(0.1), 0.0);
+        assert!(chain_output(1.0) > 0.0);
+    }
+
+
+    #[test]
+    fn specialization_saturates_at_the_cap_and_decays_when_idle() {
+        let mut cluster = cluster_with_firing_nodes(1, 1);
+        assert!(cluster.set_specialization_decay(0.5));
+        let node_id = cluster.nodes.keys().next().cloned().unwrap();
+
+        for _ in 0..60 {
+            cluster.process_input(&[0.5; 4]);
+            assert!(cluster.get_specialization_score(&node_id) <= 1.0);
+        }
+        let saturated = cluster.get_specialization_score(&node_id);
+        assert!(saturated > 0.99);
+
+        cluster.process_input(&[0.0; 4]);
+        let idle = cluster.get_specialization_score(&node_id);
+        assert!((idle - saturated / 2.0).abs() < 1e-12);
+        for _ in 0..20 {
+            cluster.process_input(&[0.0; 4]);
+        }
+        assert!(cluster.get_specialization_score(&node_id) < 1e-3);
+    }
+
+
+    #[test]
+    fn disabled_adaptation_keeps_the_structure_fixed() {
+        let mut cluster = DeviceCluster::new_with_seed("cluster".to_string(), 4, 7);
+        cluster.set_topology_adaptation(false);
+        let nodes_before = cluster.get_node_count();
+        let edges_before = cluster.topology.edge_weights.len();
+        let inputs = vec![1.0; cluster.get_input_size()];
+
+        for _ in 0..500 {
+            cluster.process_input(&inputs);
+        }
+        assert_eq!(cluster.get_node_count(), nodes_before);
+        assert_eq!(cluster.topology.edge_weights.len(), edges_before);
+        assert!(cluster.node_usage_stats.values().sum::<u32>() > 0);
+
+        // The same run with adaptation enabled does restructure the cluster
+        let mut adaptive = DeviceCluster::new_with_seed("cluster".to_string(), 4, 7);
+        for _ in 0..500 {
+            adaptive.process_input(&inputs);
+        }
+        assert!(adaptive.get_node_count() != nodes_before || adaptive.topology.edge_weights.len() != edges_before);
+    }
+
+
+    #[test]
+    fn reseeding_makes_the_next_split_deterministic() {
+        let warmup_after_reseed = |construction_seed: u64, seed: u64| -> Vec<f64> {
+            let mut cluster = DeviceCluster::new_with_seed("cluster".to_string(), 3, construction_seed);
+            cluster.reseed(seed);
+            assert_eq!(cluster.get_seed(), seed);
+            split_warmup_sums(&mut cluster)
+        };
+
+        // The noise comes only from the reseeded generator, whatever the cluster was built with
+        let first = warmup_after_reseed(7, 42);
+        assert_eq!(first, warmup_after_reseed(7, 42));
+        assert_eq!(first, warmup_after_reseed(8, 42));
+        assert_ne!(first, warmup_after_reseed(7, 43));
+    }
+
+    #[test]
+    fn same_seed_clusters_stay_identical() {
+        let run = || {
+            let mut cluster = DeviceCluster::new_with_seed("cluster".to_string(), 4, 7);
+            let outputs: Vec<Vec<f64>> = (0..300)
+                .map(|step| cluster.process_input(&[(step % 5) as f64 * 0.4, 1.0, 0.5, -0.2]))
+                .collect();
+            (outputs, cluster.get_all_node_states())
+        };
+
+        let (outputs, states) = run();
+        let (other_outputs, other_states) = run();
+        assert_eq!(outputs, other_outputs);
+        assert_eq!(states, other_states);
+    }
+
+
+    #[test]
+    fn same_seed_clusters_draw_the_same_weights_for_a_wider_input() {
+        let widened = || {
+            let mut cluster = DeviceCluster::new_with_seed("cluster".to_string(), 3, 7);
+            cluster.process_input(&[0.5; 8]);
+            assert_eq!(cluster.get_input_size(), 8);
+            cluster.get_all_node_states()
+        };
+
+        assert_eq!(widened(), widened());
+    }
+
+
+    #[test]
+    fn a_restored_checkpoint_resumes_the_rng_where_it_left_off() {
+        let inputs = [0.9, 1.0, 0.7, 0.8];
+        let mut cluster = DeviceCluster::new_with_seed("cluster".to_string(), 4, 7);
+        cluster.set_split_noise(0.5, 5);
+        for _ in 0..150 {
+            cluster.process_input(&inputs);
+        }
+        let snapshot = cluster.checkpoint();
+        let node_count = cluster.get_node_count();
+
+        // Enough further steps that splits draw noise and edge weights from the generator
+        let run_on = |cluster: &mut DeviceCluster| -> Vec<Vec<f64>> {
+            (0..300).map(|_| cluster.process_input(&inputs)).collect()
+        };
+        let outputs = run_on(&mut cluster);
+        assert!(cluster.get_node_count() > node_count);
+
+        let mut restored = DeviceCluster::new_with_seed("cluster".to_string(), 4, 7);
+        assert!(restored.restore(&snapshot));
+        assert_eq!(run_on(&mut restored), outputs);
+        assert_eq!(restored.get_all_node_states(), cluster.get_all_node_states());
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+
+/// Tiny expression language for smart contract code, e.g.
+/// `if memory_quality > 0.7 { reward = base_reward * quality_multiplier } else { reward = 0 }`.
+/// Values are f64; comparisons and logical operators yield 1.0 (true) or 0.0 (false).
+/// Statements are assignments or if/else blocks, optionally separated by `;`.
+pub fn evaluate_contract_code(code: &str, context: &mut HashMap<String, f64>) -> Result<(), String> {
+    if code.len() > MAX_CONTRACT_CODE_LEN {
+        return Err(format!("contract code exceeds {} bytes", MAX_CONTRACT_CODE_LEN));
+    }
+    let tokens = tokenize(code)?;
+    let mut parser = Parser { tokens, pos: 0, depth: 0 };
+    let program = parser.parse_block_body(false)?;
+    execute(&program, context)
+}
+
+/// Longest contract code accepted, in bytes
+pub const MAX_CONTRACT_CODE_LEN: usize = 4096;
+
+/// Deepest nesting of blocks, parentheses, unary operators and chained binary operators,
+/// which bounds recursion while parsing, evaluating and dropping the syntax tree
+pub const MAX_NESTING_DEPTH: usize = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(&'static str),
+    LBrace,
+    RBrace,
+    LParen,
+    RParen,
+    Semicolon,
+}
+
+const OPERATORS: [&str; 15] = ["==", "!=", "<=", ">=", "&&", "||", "<", ">", "+", "-", "*", "/", "=", "!", "%"];
+
+fn tokenize(code: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = code.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).is_some_and(|d| d.is_ascii_digit())) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let literal: String = chars[start..i].iter().collect();
+            let value = literal.parse::<f64>().map_err(|_| format!("invalid number '{}'", literal))?;
+            tokens.push(Token::Number(value));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let single = match c {
+                '{' => Some(Token::LBrace),
+                '}' => Some(Token::RBrace),
+                '(' => Some(Token::LParen),
+                ')' => Some(Token::RParen),
+                ';' => Some(Token::Semicolon),
+                _ => None,
+            };
+            if let Some(token) = single {
+                tokens.push(token);
+                i += 1;
+                continue;
+            }
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let op = OPERATORS.iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| format!("unexpected character '{}'", c))?;
+            tokens.push(Token::Op(op));
+            i += op.len();
+        }
+    }
+    Ok(tokens)
+}
+
+enum Statement {
+    Assign(String, Expr),
+    If(Expr, Vec<Statement>, Vec<Statement>),
+}
+
+enum Expr {
+    Number(f64),
+    Var(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize, // Current nesting, checked against MAX_NESTING_DEPTH
+}
+
+impl Parser {
+    // Run `parse` one nesting level deeper, failing once past MAX_NESTING_DEPTH
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, String>) -> Result<T, String> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            return Err(format!("nesting deeper than {} levels", MAX_NESTING_DEPTH));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            other => Err(format!("expected {:?}, found {:?}", expected, other)),
+        }
+    }
+
+    // Statements up to a closing brace (inside a block) or the end of input
+    fn parse_block_body(&mut self, braced: bool) -> Result<Vec<Statement>, String> {
+        let mut statements = Vec::new();
+        loop {
+            match self.peek() {
+                None if braced => return Err("unterminated block".to_string()),
+                None => return Ok(statements),
+                Some(Token::RBrace) if braced => {
+                    self.pos += 1;
+                    return Ok(statements);
+                },
+                Some(Token::Semicolon) => self.pos += 1,
+                _ => statements.push(self.parse_statement()?),
+            }
+        }
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement, String> {
+        match self.next() {
+            Some(Token::Ident(name)) if name == "if" => {
+                let condition = self.parse_expr()?;
+                self.expect(Token::LBrace)?;
+                let then_branch = self.nested(|parser| parser.parse_block_body(true))?;
+                let else_branch = if self.peek() == Some(&Token::Ident("else".to_string())) {
+                    self.pos += 1;
+                    if self.peek() == Some(&Token::Ident("if".to_string())) {
+                        vec![self.nested(|parser| parser.parse_statement())?]
+                    } else {
+                        self.expect(Token::LBrace)?;
+                        self.nested(|parser| parser.parse_block_body(true))?
+                    }
+                } else {
+                    Vec::new()
+                };
+                Ok(Statement::If(condition, then_branch, else_branch))
+            },
+            Some(Token::Ident(name)) => {
+                self.expect(Token::Op("="))?;
+                Ok(Statement::Assign(name, self.parse_expr()?))
+            },
+            other => Err(format!("expected statement, found {:?}", other)),
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, String> {
+        self.parse_binary(0).map(|(expr, _)| expr)
+    }
+
+    // Fail once a tree of `height` at the current nesting would pass MAX_NESTING_DEPTH
+    fn check_depth(&self, height: usize) -> Result<(), String> {
+        if self.depth + height > MAX_NESTING_DEPTH {
+            return Err(format!("nesting deeper than {} levels", MAX_NESTING_DEPTH));
+        }
+        Ok(())
+    }
+
+    // Precedence climbing over the binary operator levels, loosest first. Also returns the
+    // tree's height, since chained operators deepen it without recursing in the parser.
+    fn parse_binary(&mut self, level: usize) -> Result<(Expr, usize), String> {
+        const LEVELS: [&[&str]; 5] = [&["||"], &["&&"], &["==", "!=", "<", ">", "<=", ">="], &["+", "-"], &["*", "/", "%"]];
+        if level == LEVELS.len() {
+            return self.parse_unary();
+        }
+        let (mut left, mut height) = self.parse_binary(level + 1)?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            if !LEVELS[level].contains(&op) {
+                break;
+            }
+            self.pos += 1;
+            let (right, right_height) = self.parse_binary(level + 1)?;
+            height = height.max(right_height) + 1;
+            self.check_depth(height)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok((left, height))
+    }
+
+    fn parse_unary(&mut self) -> Result<(Expr, usize), String> {
+        match self.next() {
+            Some(Token::Op(op)) if op == "-" || op == "!" => {
+                let (inner, height) = self.nested(|parser| parser.parse_unary())?;
+                self.check_depth(height + 1)?;
+                Ok((Expr::Unary(op, Box::new(inner)), height + 1))
+            },
+            Some(Token::Number(value)) => Ok((Expr::Number(value), 1)),
+            Some(Token::Ident(name)) => Ok((Expr::Var(name), 1)),
+            Some(Token::LParen) => {
+                let inner = self.nested(|parser| parser.parse_binary(0))?;
+                self.expect(Token::RParen)?;
+                Ok(inner)
+            },
+            other => Err(format!("expected value, found {:?}", other)),
+        }
+    }
+}
+
+fn execute(statements: &[Statement], vars: &mut HashMap<String, f64>) -> Result<(), String> {
+    for statement in statements {
+        match statement {
+            Statement::Assign(name, expr) => {
+                let value = eval(expr, vars)?;
+                vars.insert(name.clone(), value);
+            },
+            Statement::If(condition, then_branch, else_branch) => {
+                if eval(condition, vars)? != 0.0 {
+                    execute(then_branch, vars)?;
+                } else {
+                    execute(else_branch, vars)?;
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+fn eval(expr: &Expr, vars: &HashMap<String, f64>) -> Result<f64, String> {
+    let truth = |b: bool| if b { 1.0 } else { 0.0 };
+    let value = match expr {
+        Expr::Number(value) => *value,
+        Expr::Var(name) => *vars.get(name).ok_or_else(|| format!("unbound variable '{}'", name))?,
+        Expr::Unary(op, inner) => {
+            let v = eval(inner, vars)?;
+            if *op == "-" { -v } else { truth(v == 0.0) }
+        },
+        Expr::Binary(op, left, right) => {
+            let l = eval(left, vars)?;
+            // Short-circuit so the untaken side may reference unbound variables
+            match *op {
+                "&&" if l == 0.0 => return Ok(0.0),
+                "||" if l != 0.0 => return Ok(1.0),
+                _ => {},
+            }
+            let r = eval(right, vars)?;
+            match *op {
+                "+" => l + r,
+                "-" => l - r,
+                "*" => l * r,
+                "/" | "%" if r == 0.0 => return Err("division by zero".to_string()),
+                "/" => l / r,
+                "%" => l % r,
+                "<" => truth(l < r),
+                ">" => truth(l > r),
+                "<=" => truth(l <= r),
+                ">=" => truth(l >= r),
+                "==" => truth(l == r),
+                "!=" => truth(l != r),
+                "&&" | "||" => truth(r != 0.0),
+                _ => return Err(format!("unknown operator '{}'", op)),
+            }
+        },
+    };
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err("result is not a finite number".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(code: &str, vars: &[(&str, f64)]) -> Result<HashMap<String, f64>, String> {
+        let mut context: HashMap<String, f64> = vars.iter().map(|(name, value)| (name.to_string(), *value)).collect();
+        evaluate_contract_code(code, &mut context).map(|_| context)
+    }
+
+    #[test]
+    fn evaluates_the_memory_quality_contract() {
+        let code = "if memory_quality > 0.7 { reward = base_reward * quality_multiplier } else { reward = 0 }";
+        let vars = [("memory_quality", 0.9), ("base_reward", 10.0), ("quality_multiplier", 1.5)];
+        assert_eq!(run(code, &vars).unwrap()["reward"], 15.0);
+
+        let vars = [("memory_quality", 0.5), ("base_reward", 10.0), ("quality_multiplier", 1.5)];
+        assert_eq!(run(code, &vars).unwrap()["reward"], 0.0);
+    }
+
+    #[test]
+    fn follows_precedence_else_if_and_statement_separators() {
+        let vars = run("a = 1 + 2 * 3; b = (1 + 2) * 3; c = -2 * -2 % 3; d = !(a == 7) || a >= 7 && 0", &[]).unwrap();
+        assert_eq!((vars["a"], vars["b"], vars["c"], vars["d"]), (7.0, 9.0, 1.0, 0.0));
+
+        let code = "if x < 0 { sign = -1 } else if x == 0 { sign = 0 } else { sign = 1 }";
+        assert_eq!(run(code, &[("x", 0.0)]).unwrap()["sign"], 0.0);
+        assert_eq!(run(code, &[("x", -3.0)]).unwrap()["sign"], -1.0);
+    }
+
+    #[test]
+    fn short_circuits_past_unbound_variables() {
+        assert_eq!(run("r = 0 && missing", &[]).unwrap()["r"], 0.0);
+        assert_eq!(run("r = 1 || missing", &[]).unwrap()["r"], 1.0);
+        assert!(run("r = 1 && missing", &[]).is_err());
+    }
+
+    #[test]
+    fn rejects_runtime_errors_and_bad_syntax() {
+        assert!(run("r = 1 / 0", &[]).is_err());
+        assert!(run("r = 5 % 0", &[]).is_err());
+        assert!(run("r = big * big", &[("big", f64::MAX)]).is_err());
+        assert!(run("if 1 { r = 1", &[]).is_err());
+        assert!(run("r = 1 $ 2", &[]).is_err());
+        assert!(run("validate_memory_capsule_integrity_and_privacy", &[]).is_err());
+    }
+
+    #[test]
+    fn rejects_nesting_past_the_limit() {
+        let depth = MAX_NESTING_DEPTH + 1;
+        let parens = format!("r = {}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(run(&parens, &[]).unwrap_err().contains("nesting"));
+        assert!(run(&format!("r = {}1", "-".repeat(depth)), &[]).unwrap_err().contains("nesting"));
+        assert!(run(&format!("r = 1{}", " + 1".repeat(depth)), &[]).unwrap_err().contains("nesting"));
+        let blocks = format!("{}r = 1{}", "if 1 { ".repeat(depth), " }".repeat(depth));
+        assert!(run(&blocks, &[]).unwrap_err().contains("nesting"));
+
+        let shallow = format!("r = {}1{}", "(".repeat(8), ")".repeat(8));
+        assert_eq!(run(&shallow, &[]).unwrap()["r"], 1.0);
+    }
+
+    #[test]
+    fn rejects_oversized_code() {
+        let code = format!("r = 1;{}", " ".repeat(MAX_CONTRACT_CODE_LEN));
+        assert!(run(&code, &[]).unwrap_err().contains("exceeds"));
+    }
+}
@@ -0,0 +1,80 @@
+use wasm_bindgen::prelude::*;
+use serde::Serialize;
+use std::fmt;
+
+/// Shared error type for fallible APIs across the blockchain, vector database, and P2P layers.
+/// Crosses into JavaScript as a plain `{ code, message }` object so callers can match on `code`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DnnError {
+    ParseError(String),
+    NotFound(String),
+    InsufficientCredits { required: f64, available: f64 },
+    DimensionMismatch { expected: usize, actual: usize },
+    NotConnected(String),
+    Unauthorized(String),
+    InvalidInput(String),
+}
+
+#[derive(Serialize)]
+struct JsError<'a> {
+    code: &'a str,
+    message: String,
+}
+
+impl DnnError {
+    /// Stable identifier for the failure mode
+    pub fn code(&self) -> &'static str {
+        match self {
+            DnnError::ParseError(_) => "ParseError",
+            DnnError::NotFound(_) => "NotFound",
+            DnnError::InsufficientCredits { .. } => "InsufficientCredits",
+            DnnError::DimensionMismatch { .. } => "DimensionMismatch",
+            DnnError::NotConnected(_) => "NotConnected",
+            DnnError::Unauthorized(_) => "Unauthorized",
+            DnnError::InvalidInput(_) => "InvalidInput",
+        }
+    }
+}
+
+impl fmt::Display for DnnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnnError::ParseError(detail) => write!(f, "failed to parse {}", detail),
+            DnnError::NotFound(what) => write!(f, "{} not found", what),
+            DnnError::InsufficientCredits { required, available } => {
+                write!(f, "insufficient credits: {} required, {} available", required, available)
+            },
+            DnnError::DimensionMismatch { expected, actual } => {
+                write!(f, "dimension mismatch: expected {}, got {}", expected, actual)
+            },
+            DnnError::NotConnected(peer) => write!(f, "not connected to {}", peer),
+            DnnError::Unauthorized(reason) => write!(f, "unauthorized: {}", reason),
+            DnnError::InvalidInput(reason) => write!(f, "invalid input: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for DnnError {}
+
+impl From<DnnError> for JsValue {
+    fn from(error: DnnError) -> JsValue {
+        let js_error = JsError { code: error.code(), message: error.to_string() };
+        serde_wasm_bindgen::to_value(&js_error).unwrap_or_else(|_| JsValue::from_str(&error.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blockchain::BlockchainLedger;
+
+    #[test]
+    fn failure_modes_have_distinct_codes() {
+        let mut ledger = BlockchainLedger::new();
+        let parse = ledger.replay_transaction_log("not json").unwrap_err();
+        let credits = ledger.pay_contribution_reward("alice", "bob", 5.0, "session").unwrap_err();
+
+        assert_eq!(parse.code(), "ParseError");
+        assert_eq!(credits.code(), "InsufficientCredits");
+        assert!(credits.to_string().contains("5"));
+    }
+}
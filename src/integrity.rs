@@ -0,0 +1,23 @@
+use serde::{Serialize, Deserialize};
+
+/// How serious a problem reported by an integrity check is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum IntegritySeverity {
+    Info,    // Expected divergence, e.g. a record whose vector entry was consolidated away
+    Warning, // Degraded but recoverable, e.g. duplicate index rows
+    Error,   // Data that contradicts another store
+}
+
+/// One inconsistency found by DistributedNeuralNetwork::run_integrity_check
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IntegrityIssue {
+    pub severity: IntegritySeverity,
+    pub component: String, // "vector_db", "blockchain", or "cross_check"
+    pub message: String,
+}
+
+impl IntegrityIssue {
+    pub fn new(severity: IntegritySeverity, component: &str, message: String) -> IntegrityIssue {
+        IntegrityIssue { severity, component: component.to_string(), message }
+    }
+}
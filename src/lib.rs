@@ -9,6 +9,9 @@ mod blockchain;
 mod vector_db;
 mod p2p_network;
 mod webrtc;
+mod error;
+mod contract_script;
+mod integrity;
 
 pub use threshold_node::*;
 pub use memory::*;
@@ -18,6 +21,9 @@ pub use blockchain::*;
 pub use vector_db::*;
 pub use p2p_network::*;
 pub use webrtc::*;
+pub use error::*;
+pub use contract_script::*;
+pub use integrity::*;
 
 // Re-export key types for JavaScript
 #[wasm_bindgen]
@@ -28,7 +34,7 @@ extern "C" {
 
 // Macro for easier console logging from Rust
 macro_rules! console_log {
-    ($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
+    ($($t:tt)*) => ($crate::utils::console_log_impl(&format_args!($($t)*).to_string()))
 }
 
 // Initialize the WASM module
@@ -38,6 +44,13 @@ pub fn init() {
     console_log!("Distributed Neural Network with Blockchain Vector Database WASM module initialized");
 }
 
+/// Default cap on nodes per cluster; keeps a single bad create_cluster call from
+/// allocating unbounded memory and hanging the browser's main thread
+pub const DEFAULT_MAX_NODES_PER_CLUSTER: usize = 1024;
+
+/// Cluster that nodes borrowed from peers are added to and run in
+pub const BORROWED_CLUSTER_ID: &str = "borrowed";
+
 // Main API for JavaScript interaction
 #[wasm_bindgen]
 pub struct DistributedNeuralNetwork {
@@ -47,15 +60,35 @@ pub struct DistributedNeuralNetwork {
     vector_database: VectorMemoryDatabase, // Long-term memory blockchain vector database
     p2p_network: P2PNetwork, // Direct peer-to-peer networking
     device_id: String,
+    max_nodes_per_cluster: usize,
+    memory_links: HashMap<String, Vec<String>>, // from_cluster -> clusters fed its capsules
+    last_linked_capsules: HashMap<String, String>, // from_cluster -> last capsule_id forwarded
+    cluster_pipes: HashMap<String, Vec<String>>, // source cluster -> clusters fed its output vector
+    piped_inputs: HashMap<String, Vec<f64>>, // target cluster -> summed source outputs awaiting its next input
+    collaboration_cluster: Option<String>, // Cluster trained in accepted sessions; all clusters when unset
+    device_secret: Option<Vec<u8>>, // Encrypts Personal capsules in every cluster
 }
 
 #[wasm_bindgen]
 impl DistributedNeuralNetwork {
     #[wasm_bindgen(constructor)]
     pub fn new(device_id: String) -> DistributedNeuralNetwork {
+        Self::with_p2p_network(device_id.clone(), P2PNetwork::new(device_id))
+    }
+
+    /// Like `new`, keeping the P2P identity of an earlier session (see export_identity_key)
+    #[wasm_bindgen]
+    pub fn new_with_identity_key(device_id: String, identity_key: &[u8]) -> Result<DistributedNeuralNetwork, DnnError> {
+        let p2p_network = P2PNetwork::new_with_identity_key(device_id.clone(), identity_key)?;
+        Ok(Self::with_p2p_network(device_id, p2p_network))
+    }
+
+    fn with_p2p_network(device_id: String, p2p_network: P2PNetwork) -> DistributedNeuralNetwork {
         console_log!("Creating new distributed neural network for device: {}", device_id);
         
         let mut blockchain = BlockchainLedger::new();
+        // Debits from this device are signed with its P2P identity, which peers pin on discovery
+        blockchain.set_account_signing_key(device_id.clone(), &p2p_network.export_identity_key());
         // Register this device with initial credits
         blockchain.register_device(device_id.clone(), 10.0);
         
@@ -64,45 +97,157 @@ impl DistributedNeuralNetwork {
             global_memory: GlobalMemory::new(),
             blockchain,
             vector_database: VectorMemoryDatabase::new(),
-            p2p_network: P2PNetwork::new(device_id.clone()),
+            p2p_network,
             device_id,
+            max_nodes_per_cluster: DEFAULT_MAX_NODES_PER_CLUSTER,
+            memory_links: HashMap::new(),
+            last_linked_capsules: HashMap::new(),
+            cluster_pipes: HashMap::new(),
+            piped_inputs: HashMap::new(),
+            collaboration_cluster: None,
+            device_secret: None,
         }
     }
 
+    /// 32-byte device secret for Personal capsules; applies to existing and future clusters
+    #[wasm_bindgen]
+    pub fn set_device_secret(&mut self, key: &[u8]) -> bool {
+        if key.len() != DEVICE_SECRET_SIZE {
+            console_log!("Device secret must be {} bytes, got {}", DEVICE_SECRET_SIZE, key.len());
+            return false;
+        }
+        for cluster in self.clusters.values_mut() {
+            cluster.set_device_secret(key);
+        }
+        self.device_secret = Some(key.to_vec());
+        true
+    }
+
+    #[wasm_bindgen]
+    pub fn set_max_nodes_per_cluster(&mut self, max_nodes: usize) {
+        self.max_nodes_per_cluster = max_nodes;
+    }
+
     #[wasm_bindgen]
     pub fn create_cluster(&mut self, cluster_id: String, num_nodes: usize) -> bool {
+        if num_nodes > self.max_nodes_per_cluster {
+            console_log!("Refusing to create cluster {}: {} nodes exceeds the limit of {}",
+                cluster_id, num_nodes, self.max_nodes_per_cluster);
+            return false;
+        }
+        
         console_log!("Creating cluster {} with {} nodes", cluster_id, num_nodes);
         
-        let cluster = DeviceCluster::new(cluster_id.clone(), num_nodes);
+        let mut cluster = DeviceCluster::new(cluster_id.clone(), num_nodes);
+        if let Some(key) = &self.device_secret {
+            cluster.set_device_secret(key);
+        }
+        self.clusters.insert(cluster_id, cluster);
+        true
+    }
+
+    /// Like create_cluster, but reproducible: the same seed yields the same nodes and wiring
+    #[wasm_bindgen]
+    pub fn create_cluster_with_seed(&mut self, cluster_id: String, num_nodes: usize, seed: u64) -> bool {
+        if num_nodes > self.max_nodes_per_cluster {
+            console_log!("Refusing to create cluster {}: {} nodes exceeds the limit of {}",
+                cluster_id, num_nodes, self.max_nodes_per_cluster);
+            return false;
+        }
+        
+        console_log!("Creating cluster {} with {} nodes from seed {}", cluster_id, num_nodes, seed);
+        
+        let mut cluster = DeviceCluster::new_with_seed(cluster_id.clone(), num_nodes, seed);
+        if let Some(key) = &self.device_secret {
+            cluster.set_device_secret(key);
+        }
         self.clusters.insert(cluster_id, cluster);
         true
     }
 
+    /// Remove a node and settle any borrowings of it on the blockchain
+    #[wasm_bindgen]
+    pub fn remove_node(&mut self, cluster_id: String, node_id: String) -> bool {
+        let removed = self.clusters.get_mut(&cluster_id)
+            .map(|cluster| cluster.remove_node(&node_id))
+            .unwrap_or(false);
+        
+        if removed {
+            self.blockchain.settle_borrowings_for_node(&self.device_id, &node_id);
+        }
+        removed
+    }
+
+    /// Remove a cluster and settle borrowings of all of its nodes
+    #[wasm_bindgen]
+    pub fn remove_cluster(&mut self, cluster_id: String) -> bool {
+        if let Some(cluster) = self.clusters.remove(&cluster_id) {
+            for node_id in cluster.get_node_ids() {
+                self.blockchain.settle_borrowings_for_node(&self.device_id, &node_id);
+            }
+            self.memory_links.remove(&cluster_id);
+            self.last_linked_capsules.remove(&cluster_id);
+            for targets in self.memory_links.values_mut() {
+                targets.retain(|id| id != &cluster_id);
+            }
+            self.cluster_pipes.remove(&cluster_id);
+            self.piped_inputs.remove(&cluster_id);
+            for targets in self.cluster_pipes.values_mut() {
+                targets.retain(|id| id != &cluster_id);
+            }
+            console_log!("Removed cluster {}", cluster_id);
+            true
+        } else {
+            false
+        }
+    }
+
     #[wasm_bindgen]
     pub fn process_input(&mut self, cluster_id: String, input_data: &[f64]) -> Vec<f64> {
+        let piped = self.piped_inputs.remove(&cluster_id);
         if let Some(cluster) = self.clusters.get_mut(&cluster_id) {
-            let outputs = cluster.process_input(input_data);
+            let outputs = match piped {
+                Some(piped) => {
+                    // Add upstream cluster outputs onto the caller's input, fitted to this cluster's width
+                    let width = input_data.len().max(cluster.get_input_size());
+                    let mut combined = crate::utils::resize_vector(input_data, width);
+                    for (value, upstream) in combined.iter_mut().zip(crate::utils::resize_vector(&piped, width)) {
+                        *value += upstream;
+                    }
+                    cluster.process_input(&combined)
+                },
+                None => cluster.process_input(input_data),
+            };
             
             // Check if a memory capsule was created and register it in blockchain + vector database
-            if let Some(capsule) = cluster.get_latest_memory_capsule() {
+            let latest_capsule = cluster.get_latest_memory_capsule();
+            if let Some(ref capsule) = latest_capsule {
                 let capsule_json = serde_json::to_string(&capsule).unwrap_or_default();
                 if !capsule_json.is_empty() {
                     // Register on blockchain for auditability and incentives
                     let capsule_id = self.blockchain.register_memory_capsule(&capsule_json, self.device_id.clone());
                     
                     if !capsule_id.is_empty() {
-                        // Store in vector database for long-term semantic search
-                        let blockchain_hash = format!("blockchain_hash_{}", capsule_id);
+                        // Store in vector database for long-term semantic search, linked to the ledger record
+                        let blockchain_hash = self.blockchain.get_memory_record_hash(&capsule_id);
                         self.vector_database.store_memory_capsule(&capsule_json, blockchain_hash);
                         
                         // Also store in global memory for immediate access
                         self.global_memory.store_capsule(&capsule_json);
                         
+                        // Make it available to peers pulling by capsule id
+                        self.p2p_network.publish_capsule(&capsule_json);
+                        
                         console_log!("Memory capsule {} registered in blockchain vector database", capsule_id);
                     }
                 }
             }
             
+            if let Some(capsule) = latest_capsule {
+                self.forward_to_linked_clusters(&cluster_id, &capsule);
+            }
+            
+            self.pipe_outputs(&cluster_id, &outputs);
             outputs
         } else {
             console_log!("Cluster {} not found", cluster_id);
@@ -110,8 +255,120 @@ impl DistributedNeuralNetwork {
         }
     }
 
+    /// Feed capsules produced by `from_cluster` into `to_cluster` as context for its next input
+    #[wasm_bindgen]
+    pub fn set_memory_link(&mut self, from_cluster: String, to_cluster: String) -> bool {
+        if from_cluster == to_cluster
+            || !self.clusters.contains_key(&from_cluster)
+            || !self.clusters.contains_key(&to_cluster)
+        {
+            return false;
+        }
+        
+        let targets = self.memory_links.entry(from_cluster).or_default();
+        if !targets.contains(&to_cluster) {
+            targets.push(to_cluster);
+        }
+        true
+    }
+
+    #[wasm_bindgen]
+    pub fn remove_memory_link(&mut self, from_cluster: String, to_cluster: String) -> bool {
+        if let Some(targets) = self.memory_links.get_mut(&from_cluster) {
+            let before = targets.len();
+            targets.retain(|id| id != &to_cluster);
+            targets.len() != before
+        } else {
+            false
+        }
+    }
+
+    /// Feed `source_id`'s output vector into `target_id`'s next process_input, adapted to its input width
+    #[wasm_bindgen]
+    pub fn pipe_clusters(&mut self, source_id: String, target_id: String) -> bool {
+        if source_id == target_id
+            || !self.clusters.contains_key(&source_id)
+            || !self.clusters.contains_key(&target_id)
+        {
+            return false;
+        }
+        
+        let targets = self.cluster_pipes.entry(source_id).or_default();
+        if !targets.contains(&target_id) {
+            targets.push(target_id);
+        }
+        true
+    }
+
+    #[wasm_bindgen]
+    pub fn unpipe_clusters(&mut self, source_id: String, target_id: String) -> bool {
+        if let Some(targets) = self.cluster_pipes.get_mut(&source_id) {
+            let before = targets.len();
+            targets.retain(|id| id != &target_id);
+            targets.len() != before
+        } else {
+            false
+        }
+    }
+
+    fn pipe_outputs(&mut self, source_id: &str, outputs: &[f64]) {
+        let targets = match self.cluster_pipes.get(source_id) {
+            Some(targets) if !targets.is_empty() => targets.clone(),
+            _ => return,
+        };
+        
+        for target in targets {
+            let width = match self.clusters.get(&target) {
+                Some(cluster) => cluster.get_input_size(),
+                None => continue,
+            };
+            let adapted = crate::utils::resize_vector(outputs, width);
+            let pending = self.piped_inputs.entry(target).or_insert_with(|| vec![0.0; width]);
+            for (sum, value) in pending.iter_mut().zip(adapted) {
+                *sum += value;
+            }
+        }
+    }
+
+    fn forward_to_linked_clusters(&mut self, from_cluster: &str, capsule: &MemoryCapsule) {
+        let targets = match self.memory_links.get(from_cluster) {
+            Some(targets) if !targets.is_empty() => targets.clone(),
+            _ => return,
+        };
+        
+        // Only forward each capsule once
+        if self.last_linked_capsules.get(from_cluster) == Some(&capsule.capsule_id) {
+            return;
+        }
+        self.last_linked_capsules.insert(from_cluster.to_string(), capsule.capsule_id.clone());
+        
+        for target in targets {
+            if let Some(cluster) = self.clusters.get_mut(&target) {
+                cluster.set_external_context(capsule.context_vector.clone());
+                console_log!("Forwarded capsule {} from {} to {}", capsule.capsule_id, from_cluster, target);
+            }
+        }
+    }
+
     #[wasm_bindgen]
     pub fn semantic_memory_search(&mut self, query_vector: &[f64], context_tags: &str, max_results: usize) -> String {
+        self.run_semantic_search(query_vector, context_tags, max_results, false)
+    }
+
+    /// Like semantic_memory_search, but each result embeds the capsule's tags, context vector and timestamp
+    #[wasm_bindgen]
+    pub fn semantic_memory_search_with_content(&mut self, query_vector: &[f64], context_tags: &str, max_results: usize) -> String {
+        self.run_semantic_search(query_vector, context_tags, max_results, true)
+    }
+
+    /// Search with a full VectorSearchQuery, relaxing time range, quality and context
+    /// constraints in turn until something matches
+    #[wasm_bindgen]
+    pub fn semantic_search_with_fallback(&mut self, query_json: &str) -> String {
+        self.vector_database.semantic_search_with_fallback(query_json)
+    }
+
+    fn run_semantic_search(&mut self, query_vector: &[f64], context_tags: &str, max_results: usize, include_content: bool) -> String {
         let query = crate::vector_db::VectorSearchQuery {
             query_vector: query_vector.to_vec(),
             context_filter: context_tags.split(',').map(|s| s.trim().to_string()).collect(),
@@ -119,12 +376,56 @@ impl DistributedNeuralNetwork {
             quality_threshold: 0.3,
             max_results,
             search_algorithm: crate::vector_db::SearchAlgorithm::Hybrid,
+            max_candidates: None,
+            hybrid_config: None,
+            include_content,
         };
         
         let query_json = serde_json::to_string(&query).unwrap_or_default();
         self.vector_database.semantic_search(&query_json)
     }
 
+    #[wasm_bindgen]
+    pub fn set_embedding_validation(&mut self, enabled: bool) {
+        self.vector_database.set_embedding_validation(enabled);
+    }
+
+    #[wasm_bindgen]
+    pub fn set_embedding_dim(&mut self, dim: usize) -> Result<(), DnnError> {
+        self.vector_database.set_embedding_dim(dim)
+    }
+
+    #[wasm_bindgen]
+    pub fn reembed_long_term_memory(&mut self) -> usize {
+        console_log!("Re-embedding long-term memory database");
+        self.vector_database.reembed_all()
+    }
+
+    #[wasm_bindgen]
+    pub fn get_query_context_stats(&self) -> String {
+        self.vector_database.get_query_context_stats()
+    }
+
+    #[wasm_bindgen]
+    pub fn set_query_demand_boost(&mut self, boost: f64) {
+        self.vector_database.set_query_demand_boost(boost);
+    }
+
+    #[wasm_bindgen]
+    pub fn set_importance_boost_factor(&mut self, factor: f64) {
+        self.vector_database.set_importance_boost_factor(factor);
+    }
+
+    #[wasm_bindgen]
+    pub fn merge_similar_memory_clusters(&mut self, threshold: f64) -> usize {
+        self.vector_database.merge_similar_clusters(threshold)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_min_cluster_size(&mut self, min_size: usize) {
+        self.vector_database.set_min_cluster_size(min_size);
+    }
+
     #[wasm_bindgen]
     pub fn get_memory_trends(&self) -> String {
         self.vector_database.get_memory_trends()
@@ -146,55 +447,400 @@ impl DistributedNeuralNetwork {
     }
 
     #[wasm_bindgen]
-    pub fn update_error_signal(&mut self, cluster_id: String, error: f64) {
-        if let Some(cluster) = self.clusters.get_mut(&cluster_id) {
-            cluster.update_error_signal(error);
-        }
+    pub fn output_sparsity(&mut self, cluster_id: String, input_data: &[f64]) -> f64 {
+        self.clusters.get_mut(&cluster_id)
+            .map(|cluster| cluster.output_sparsity(input_data))
+            .unwrap_or(0.0)
+    }
+
+    #[wasm_bindgen]
+    pub fn average_output_sparsity(&self, cluster_id: String) -> f64 {
+        self.clusters.get(&cluster_id)
+            .map(|cluster| cluster.average_output_sparsity())
+            .unwrap_or(0.0)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_error_annealing(&mut self, cluster_id: String, initial: f64, final_value: f64, steps: u32) -> bool {
+        if let Some(cluster) = self.clusters.get_mut(&cluster_id) {
+            cluster.set_error_annealing(initial, final_value, steps);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_feedforward_integration(&mut self, cluster_id: String, enabled: bool) -> bool {
+        if let Some(cluster) = self.clusters.get_mut(&cluster_id) {
+            cluster.set_feedforward_integration(enabled);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn reseed_cluster(&mut self, cluster_id: String, seed: u64) -> bool {
+        if let Some(cluster) = self.clusters.get_mut(&cluster_id) {
+            cluster.reseed(seed);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn get_cluster_seed(&self, cluster_id: String) -> Option<u64> {
+        self.clusters.get(&cluster_id).map(|cluster| cluster.get_seed())
+    }
+
+    #[wasm_bindgen]
+    pub fn set_novelty_config(&mut self, cluster_id: String, metric: String, normalization: f64) -> bool {
+        self.clusters.get_mut(&cluster_id)
+            .map(|cluster| cluster.set_novelty_config(&metric, normalization))
+            .unwrap_or(false)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_history_sizes(&mut self, cluster_id: String, activation: usize, firing: usize, node_memory: usize) -> bool {
+        if let Some(cluster) = self.clusters.get_mut(&cluster_id) {
+            cluster.set_history_sizes(activation, firing, node_memory);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_topology_adaptation(&mut self, cluster_id: String, enabled: bool) -> bool {
+        if let Some(cluster) = self.clusters.get_mut(&cluster_id) {
+            cluster.set_topology_adaptation(enabled);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_threshold_bounds(&mut self, cluster_id: String, min: f64, max: f64) -> bool {
+        self.clusters.get_mut(&cluster_id)
+            .map(|cluster| cluster.set_threshold_bounds(min, max))
+            .unwrap_or(false)
+    }
+
+    #[wasm_bindgen]
+    pub fn enable_compartments(&mut self, cluster_id: String, num_compartments: usize) -> bool {
+        self.clusters.get_mut(&cluster_id)
+            .map(|cluster| cluster.enable_compartments(num_compartments))
+            .unwrap_or(false)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_max_time_to_release(&mut self, cluster_id: String, max: f64) -> bool {
+        self.clusters.get_mut(&cluster_id)
+            .map(|cluster| cluster.set_max_time_to_release(max))
+            .unwrap_or(false)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_specialization_decay(&mut self, cluster_id: String, decay: f64) -> bool {
+        self.clusters.get_mut(&cluster_id)
+            .map(|cluster| cluster.set_specialization_decay(decay))
+            .unwrap_or(false)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_fire_output_mode(&mut self, cluster_id: String, mode: &str) -> bool {
+        match self.clusters.get_mut(&cluster_id) {
+            Some(cluster) => cluster.set_fire_output_mode(mode),
+            None => false,
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_output_function(&mut self, cluster_id: String, name: &str) -> bool {
+        match self.clusters.get_mut(&cluster_id) {
+            Some(cluster) => cluster.set_output_function(name),
+            None => false,
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_refractory_period(&mut self, cluster_id: String, period: f64) -> bool {
+        if let Some(cluster) = self.clusters.get_mut(&cluster_id) {
+            cluster.set_refractory_period(period);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_accumulator_decay(&mut self, cluster_id: String, decay: f64) -> bool {
+        if let Some(cluster) = self.clusters.get_mut(&cluster_id) {
+            cluster.set_accumulator_decay(decay);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_timer_fire_gain(&mut self, cluster_id: String, gain: f64) -> bool {
+        if let Some(cluster) = self.clusters.get_mut(&cluster_id) {
+            cluster.set_timer_fire_gain(gain);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_eligibility_output_gating(&mut self, cluster_id: String, enabled: bool) -> bool {
+        if let Some(cluster) = self.clusters.get_mut(&cluster_id) {
+            cluster.set_eligibility_output_gating(enabled);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_max_eligibility_trace(&mut self, cluster_id: String, max_trace: f64) -> bool {
+        self.clusters.get_mut(&cluster_id)
+            .map(|cluster| cluster.set_max_eligibility_trace(max_trace))
+            .unwrap_or(false)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_consolidation_interval_bounds(&mut self, cluster_id: String, min_interval: f64, max_interval: f64) -> bool {
+        if let Some(cluster) = self.clusters.get_mut(&cluster_id) {
+            cluster.set_consolidation_interval_bounds(min_interval, max_interval);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn checkpoint_cluster(&self, cluster_id: String) -> String {
+        self.clusters.get(&cluster_id)
+            .map(|cluster| cluster.checkpoint())
+            .unwrap_or_default()
+    }
+
+    #[wasm_bindgen]
+    pub fn export_cluster_weights(&self, cluster_id: String) -> String {
+        self.clusters.get(&cluster_id)
+            .map(|cluster| cluster.export_weights())
+            .unwrap_or_default()
+    }
+
+    #[wasm_bindgen]
+    pub fn load_cluster_weights(&mut self, cluster_id: String, weights_json: &str) -> bool {
+        match self.clusters.get_mut(&cluster_id) {
+            Some(cluster) => cluster.load_weights(weights_json),
+            None => false,
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn restore_cluster(&mut self, cluster_id: String, snapshot: &str) -> bool {
+        if let Some(cluster) = self.clusters.get_mut(&cluster_id) {
+            cluster.restore(snapshot)
+        } else {
+            false
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn update_error_signal(&mut self, cluster_id: String, error: f64) {
+        if let Some(cluster) = self.clusters.get_mut(&cluster_id) {
+            cluster.update_error_signal(error);
+        }
+    }
+
+    // === P2P NETWORKING METHODS ===
+
+    #[wasm_bindgen]
+    pub fn configure_signaling_server(&mut self, server_url: String) -> bool {
+        console_log!("Configuring signaling server: {}", server_url);
+        self.p2p_network.configure_signaling_server(server_url)
+    }
+
+    #[wasm_bindgen]
+    pub fn enable_discovery_simulation(&mut self, peers_json: &str) -> bool {
+        self.p2p_network.enable_discovery_simulation(peers_json)
+    }
+
+    #[wasm_bindgen]
+    pub fn disable_discovery_simulation(&mut self) {
+        self.p2p_network.disable_discovery_simulation();
+    }
+
+    #[wasm_bindgen]
+    pub fn set_economics_policy(&mut self, policy_json: &str) -> Result<(), DnnError> {
+        self.p2p_network.set_economics_policy(policy_json)
+    }
+
+    #[wasm_bindgen]
+    pub fn get_economics_policy(&self) -> String {
+        self.p2p_network.get_economics_policy()
+    }
+
+    #[wasm_bindgen]
+    pub fn disconnect_signaling_server(&mut self) {
+        self.p2p_network.disconnect_signaling_server();
+    }
+
+    #[wasm_bindgen]
+    pub fn start_peer_discovery(&mut self) -> bool {
+        console_log!("Starting P2P peer discovery for device: {}", self.device_id);
+        self.p2p_network.start_discovery()
+    }
+
+    #[wasm_bindgen]
+    pub fn connect_to_peer(&mut self, peer_id: String, connection_info: &str) -> bool {
+        console_log!("Connecting to peer: {}", peer_id);
+        self.p2p_network.connect_to_peer(peer_id, connection_info)
+    }
+
+    #[wasm_bindgen]
+    pub fn request_node_from_peer(&mut self, peer_id: String, node_type: String, duration_minutes: u32) -> Result<String, DnnError> {
+        let payment_offer = self.p2p_network.economics_policy().node_payment_offer;
+        self.ensure_affordable(payment_offer)?;
+        
+        console_log!("Requesting node from peer via P2P: {}", peer_id);
+        Ok(self.p2p_network.request_node_direct(peer_id, node_type, duration_minutes))
+    }
+
+    #[wasm_bindgen]
+    pub fn share_memory_with_peer(&mut self, peer_id: String, cluster_id: String) -> bool {
+        console_log!("Sharing memory directly with peer: {}", peer_id);
+        
+        if let Some(cluster) = self.clusters.get(&cluster_id) {
+            if let Some(capsule) = cluster.get_latest_memory_capsule() {
+                let capsule_json = serde_json::to_string(&capsule).unwrap_or_default();
+                return self.p2p_network.share_memory_direct(peer_id, &capsule_json);
+            }
+        }
+        false
+    }
+
+    #[wasm_bindgen]
+    pub fn share_memory_with_peer_at_level(&mut self, peer_id: String, cluster_id: String, access_level: String) -> Result<(), DnnError> {
+        let cluster = self.clusters.get(&cluster_id)
+            .ok_or_else(|| DnnError::NotFound(format!("cluster {}", cluster_id)))?;
+        let capsule = cluster.get_latest_memory_capsule()
+            .ok_or_else(|| DnnError::NotFound(format!("memory capsule in cluster {}", cluster_id)))?;
+        let capsule_json = serde_json::to_string(&capsule).unwrap_or_default();
+        self.p2p_network.share_memory_direct_at_level(peer_id, &capsule_json, access_level)
+    }
+
+    #[wasm_bindgen]
+    pub fn ping_peer(&mut self, peer_id: String) -> bool {
+        self.p2p_network.ping_peer(peer_id)
+    }
+
+    #[wasm_bindgen]
+    pub fn get_peer_clock_offset(&self, peer_id: &str) -> f64 {
+        self.p2p_network.get_peer_clock_offset(peer_id)
+    }
+
+    #[wasm_bindgen]
+    pub fn get_public_key(&self) -> String {
+        self.p2p_network.get_public_key()
+    }
+
+    /// This device's P2P identity secret, for new_with_identity_key in a later session
+    #[wasm_bindgen]
+    pub fn export_identity_key(&self) -> Vec<u8> {
+        self.p2p_network.export_identity_key()
+    }
+
+    #[wasm_bindgen]
+    pub fn sign_peer_announcement(&self, peer_json: &str) -> String {
+        self.p2p_network.sign_peer_announcement(peer_json)
     }
 
-    // === P2P NETWORKING METHODS ===
+    #[wasm_bindgen]
+    pub fn handle_peer_delta(&mut self, delta_json: &str) -> bool {
+        self.p2p_network.handle_peer_delta(delta_json)
+    }
 
     #[wasm_bindgen]
-    pub fn configure_signaling_server(&mut self, server_url: String) -> bool {
-        console_log!("Configuring signaling server: {}", server_url);
-        self.p2p_network.configure_signaling_server(server_url)
+    pub fn get_quarantined_capsules(&self) -> String {
+        self.p2p_network.get_quarantined_capsules()
     }
 
     #[wasm_bindgen]
-    pub fn start_peer_discovery(&mut self) -> bool {
-        console_log!("Starting P2P peer discovery for device: {}", self.device_id);
-        self.p2p_network.start_discovery()
+    pub fn set_quarantine_capacity(&mut self, capacity: usize) {
+        self.p2p_network.set_quarantine_capacity(capacity);
     }
 
     #[wasm_bindgen]
-    pub fn connect_to_peer(&mut self, peer_id: String, connection_info: &str) -> bool {
-        console_log!("Connecting to peer: {}", peer_id);
-        self.p2p_network.connect_to_peer(peer_id, connection_info)
+    pub fn request_capsule_from_peer(&mut self, peer_id: String, capsule_id: String) -> String {
+        console_log!("Requesting capsule {} from peer: {}", capsule_id, peer_id);
+        self.p2p_network.request_capsule(peer_id, capsule_id)
     }
 
     #[wasm_bindgen]
-    pub fn request_node_from_peer(&mut self, peer_id: String, node_type: String, duration_minutes: u32) -> String {
-        console_log!("Requesting node from peer via P2P: {}", peer_id);
-        self.p2p_network.request_node_direct(peer_id, node_type, duration_minutes)
+    pub fn start_collaborative_learning(&mut self, peer_ids: Vec<String>, task_description: String) -> Result<String, DnnError> {
+        let committed_rewards = self.p2p_network.economics_policy().collaboration_reward * peer_ids.len() as f64;
+        self.ensure_affordable(committed_rewards)?;
+        
+        console_log!("Starting collaborative learning session with {} peers", peer_ids.len());
+        Ok(self.p2p_network.initiate_collaborative_learning(peer_ids, task_description))
     }
 
     #[wasm_bindgen]
-    pub fn share_memory_with_peer(&mut self, peer_id: String, cluster_id: String) -> bool {
-        console_log!("Sharing memory directly with peer: {}", peer_id);
+    pub fn record_collaboration_contribution(&mut self, session_id: String, peer_id: String, sample_count: u32, loss_improvement: f64) -> bool {
+        self.p2p_network.record_collaboration_contribution(&session_id, peer_id, sample_count, loss_improvement)
+    }
+
+    /// Pay each participant of a session in proportion to its measured contribution.
+    /// Completes the session. Returns the rewards paid as JSON, or "" if the session is not active.
+    #[wasm_bindgen]
+    pub fn distribute_collaboration_rewards(&mut self, session_id: String) -> String {
+        let rewards = match self.p2p_network.collaboration_reward_shares(&session_id) {
+            Some(rewards) => rewards,
+            None => return "".to_string(),
+        };
         
-        if let Some(cluster) = self.clusters.get(&cluster_id) {
-            if let Some(capsule) = cluster.get_latest_memory_capsule() {
-                let capsule_json = serde_json::to_string(&capsule).unwrap_or_default();
-                return self.p2p_network.share_memory_direct(peer_id, &capsule_json);
+        let mut paid = HashMap::new();
+        for (peer_id, amount) in rewards {
+            match self.blockchain.pay_contribution_reward(&self.device_id, &peer_id, amount, &session_id) {
+                Ok(()) => {
+                    paid.insert(peer_id, amount);
+                },
+                Err(e) => console_log!("Could not pay collaboration reward to {}: {}", peer_id, e),
             }
         }
-        false
+        
+        console_log!("Distributed collaboration rewards for {} to {} participants", session_id, paid.len());
+        let paid_json = serde_json::to_string(&paid).unwrap_or_default();
+        self.p2p_network.complete_collaboration_session(&session_id, paid);
+        paid_json
     }
 
     #[wasm_bindgen]
-    pub fn start_collaborative_learning(&mut self, peer_ids: Vec<String>, task_description: String) -> String {
-        console_log!("Starting collaborative learning session with {} peers", peer_ids.len());
-        self.p2p_network.initiate_collaborative_learning(peer_ids, task_description)
+    pub fn record_collaboration_outcome(&mut self, session_id: String, final_loss: f64) -> bool {
+        self.p2p_network.record_collaboration_outcome(&session_id, final_loss)
+    }
+
+    #[wasm_bindgen]
+    pub fn get_collaboration_history(&self) -> String {
+        self.p2p_network.get_collaboration_history()
+    }
+
+    // Check the device can cover credits it is about to promise to peers
+    fn ensure_affordable(&self, amount: f64) -> Result<(), DnnError> {
+        let available = self.blockchain.spendable_balance(&self.device_id);
+        if available < amount {
+            return Err(DnnError::InsufficientCredits { required: amount, available });
+        }
+        Ok(())
     }
 
     #[wasm_bindgen]
@@ -211,7 +857,135 @@ impl DistributedNeuralNetwork {
 
     #[wasm_bindgen]
     pub fn process_p2p_messages(&mut self) -> u32 {
-        self.p2p_network.process_incoming_messages()
+        let processed = self.p2p_network.process_incoming_messages();
+        self.sync_blocks();
+        self.apply_collaboration_parameters();
+        self.settle_share_receipts();
+        self.exchange_borrowed_nodes();
+        processed
+    }
+
+    // Lend serialized nodes for approved peer requests, and run nodes lent to us in BORROWED_CLUSTER_ID
+    fn exchange_borrowed_nodes(&mut self) {
+        for request in self.p2p_network.take_node_lend_requests() {
+            let node_json = self.find_lendable_node(&request.node_type)
+                .map(|node| node.to_json())
+                .unwrap_or_default();
+            let approved = !node_json.is_empty();
+            if !approved {
+                console_log!("No node available to lend for '{}' requested by {}", request.node_type, request.peer_id);
+            }
+            self.p2p_network.send_node_response(request.peer_id, request.request_id, node_json, approved, request.payment_offer);
+        }
+        
+        for borrowed in self.p2p_network.take_borrowed_nodes() {
+            let node = match ThresholdGatingNode::from_json(&borrowed.node_data) {
+                Some(node) => node,
+                None => continue,
+            };
+            let cluster = self.clusters.entry(BORROWED_CLUSTER_ID.to_string())
+                .or_insert_with(|| {
+                    let mut cluster = DeviceCluster::new(BORROWED_CLUSTER_ID.to_string(), 0);
+                    if let Some(key) = &self.device_secret {
+                        cluster.set_device_secret(key);
+                    }
+                    cluster
+                });
+            if cluster.insert_node(node) {
+                console_log!("Borrowed '{}' node from {} for {}", borrowed.node_type, borrowed.peer_id, borrowed.rental_cost);
+            }
+        }
+    }
+
+    // A node whose id is `node_type`, else the first node of the cluster named `node_type`,
+    // else the first node of any cluster, in id order
+    fn find_lendable_node(&self, node_type: &str) -> Option<&ThresholdGatingNode> {
+        let mut cluster_ids: Vec<&String> = self.clusters.keys()
+            .filter(|id| id.as_str() != BORROWED_CLUSTER_ID)
+            .collect();
+        cluster_ids.sort();
+        let first_node = |cluster: &DeviceCluster| {
+            let mut node_ids = cluster.get_node_ids();
+            node_ids.sort();
+            node_ids.first().cloned()
+        };
+        
+        cluster_ids.iter()
+            .find_map(|id| self.clusters[*id].get_node(node_type))
+            .or_else(|| self.clusters.get(node_type)
+                .filter(|_| node_type != BORROWED_CLUSTER_ID)
+                .and_then(|cluster| first_node(cluster).and_then(|node_id| cluster.get_node(&node_id))))
+            .or_else(|| cluster_ids.iter().find_map(|id| {
+                let cluster = &self.clusters[*id];
+                first_node(cluster).and_then(|node_id| cluster.get_node(&node_id))
+            }))
+    }
+
+    // Sharing rewards are only paid for shares the receiver acknowledged with a valid receipt
+    fn settle_share_receipts(&mut self) {
+        for share in self.p2p_network.take_confirmed_shares() {
+            if let Err(e) = self.blockchain.finalize_sharing_reward(&self.device_id, &share.peer_id,
+                &share.capsule_id, &share.stored_hash, share.reward)
+            {
+                console_log!("Could not finalize sharing reward for {}: {}", share.capsule_id, e);
+            }
+        }
+    }
+
+    /// Cluster whose nodes adopt the learning parameters of accepted collaborative sessions
+    #[wasm_bindgen]
+    pub fn set_collaboration_cluster(&mut self, cluster_id: String) -> bool {
+        if !self.clusters.contains_key(&cluster_id) {
+            return false;
+        }
+        self.collaboration_cluster = Some(cluster_id);
+        true
+    }
+
+    // Train under the hyperparameters agreed in accepted collaborative learning invitations
+    fn apply_collaboration_parameters(&mut self) {
+        for session in self.p2p_network.take_accepted_collaborations() {
+            let learning_rate = session.learning_parameters.get("learning_rate").copied().unwrap_or(0.0);
+            let mut applied = 0;
+            for (cluster_id, cluster) in self.clusters.iter_mut() {
+                let targeted = self.collaboration_cluster.as_ref().is_none_or(|target| target == cluster_id);
+                if targeted && cluster.set_learning_params(learning_rate) {
+                    applied += 1;
+                }
+            }
+            console_log!("Joined collaborative learning '{}' from {}: learning_rate {} applied to {} clusters",
+                session.task_description, session.initiator, learning_rate, applied);
+        }
+    }
+
+    /// Catch up on blocks mined while this device was offline
+    #[wasm_bindgen]
+    pub fn request_blocks_from_peer(&mut self, peer_id: String, from_index: u64) -> Result<String, DnnError> {
+        self.p2p_network.request_blocks(peer_id, from_index)
+    }
+
+    // Serve block requests from the ledger and apply block batches received from peers
+    fn sync_blocks(&mut self) {
+        for (peer_id, from_index) in self.p2p_network.take_block_requests() {
+            let blocks_json = self.blockchain.get_blocks_from(from_index);
+            self.p2p_network.send_blocks(peer_id, blocks_json);
+        }
+        
+        let received_blocks = self.p2p_network.take_received_blocks();
+        if !received_blocks.is_empty() {
+            self.register_peer_account_keys();
+        }
+        for blocks_json in received_blocks {
+            let applied = self.blockchain.apply_blocks(&blocks_json);
+            console_log!("Applied {} blocks from peer, chain valid: {}", applied, self.blockchain.validate_chain());
+        }
+    }
+
+    // A peer's ledger account is its device id, so its debits must be signed by the identity pinned for it
+    fn register_peer_account_keys(&mut self) {
+        for (device_id, public_key) in self.p2p_network.pinned_peer_keys() {
+            self.blockchain.register_account_key(device_id.clone(), public_key.clone());
+        }
     }
 
     #[wasm_bindgen]
@@ -224,6 +998,47 @@ impl DistributedNeuralNetwork {
         self.p2p_network.get_discovered_peers()
     }
 
+    #[wasm_bindgen]
+    pub fn export_peers(&self) -> String {
+        self.p2p_network.export_peers()
+    }
+
+    #[wasm_bindgen]
+    pub fn import_peers(&mut self, peers_json: &str) -> usize {
+        self.p2p_network.import_peers(peers_json)
+    }
+
+    #[wasm_bindgen]
+    pub fn get_discovered_peers_paged(&self, offset: usize, limit: usize, sort_by: String) -> String {
+        self.p2p_network.get_discovered_peers_paged(offset, limit, &sort_by)
+    }
+
+    /// Deliver a P2PMessage JSON as if a peer had sent it; returns whether it passed delivery checks
+    #[wasm_bindgen]
+    pub fn inject_p2p_message(&mut self, message_json: &str) -> bool {
+        self.p2p_network.inject_incoming_message(message_json)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_routing_table_limits(&mut self, ttl_ms: f64, max_routes: usize) -> bool {
+        self.p2p_network.set_routing_table_limits(ttl_ms, max_routes)
+    }
+
+    #[wasm_bindgen]
+    pub fn prune_routing_table(&mut self) -> usize {
+        self.p2p_network.prune_routing_table()
+    }
+
+    #[wasm_bindgen]
+    pub fn set_topology_history(&mut self, enabled: bool, capacity: usize) {
+        self.p2p_network.set_topology_history(enabled, capacity);
+    }
+
+    #[wasm_bindgen]
+    pub fn get_topology_history(&self) -> String {
+        self.p2p_network.get_topology_history()
+    }
+
     #[wasm_bindgen]
     pub fn is_connected_to_signaling_server(&self) -> bool {
         self.p2p_network.is_connected_to_signaling_server()
@@ -267,7 +1082,7 @@ impl DistributedNeuralNetwork {
         static mut LAST_MINING_TIME: f64 = 0.0;
         static mut LAST_CONSOLIDATION_TIME: f64 = 0.0;
         static mut LAST_P2P_DISCOVERY_TIME: f64 = 0.0;
-        let current_time = js_sys::Date::now();
+        let current_time = now_ms();
         
         unsafe {
             // Mine blocks every 10 seconds
@@ -303,21 +1118,181 @@ impl DistributedNeuralNetwork {
         )
     }
 
+    #[wasm_bindgen]
+    pub fn dispute_node_borrowing(&mut self, borrowing_id: String, reason: &str) -> bool {
+        self.blockchain.dispute_node_borrowing(borrowing_id, reason)
+    }
+
+    #[wasm_bindgen]
+    pub fn resolve_dispute(&mut self, borrowing_id: String, refund_borrower: bool) -> bool {
+        self.blockchain.resolve_dispute(borrowing_id, refund_borrower)
+    }
+
     #[wasm_bindgen]
     pub fn complete_node_borrowing(&mut self, borrowing_id: String, performance_data: &str) -> bool {
         self.blockchain.complete_node_borrowing(borrowing_id, performance_data)
     }
 
+    #[wasm_bindgen]
+    pub fn set_registration_rate_limit(&mut self, capsules_per_minute: u32) {
+        self.blockchain.set_registration_rate_limit(capsules_per_minute);
+    }
+
+    #[wasm_bindgen]
+    pub fn set_registration_reward_decay(&mut self, decay: f64) -> bool {
+        self.blockchain.set_registration_reward_decay(decay)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_removal_refund_policy(&mut self, policy: &str) -> bool {
+        self.blockchain.set_removal_refund_policy(policy)
+    }
+
     #[wasm_bindgen]
     pub fn get_account_balance(&self) -> f64 {
         self.blockchain.get_account_balance(&self.device_id)
     }
 
+    #[wasm_bindgen]
+    pub fn get_transaction_history(&self, max: usize) -> String {
+        self.blockchain.get_transaction_history(&self.device_id, max)
+    }
+
+    /// Balance minus credits still reserved by pending outgoing transactions
+    #[wasm_bindgen]
+    pub fn get_spendable_balance(&self) -> f64 {
+        self.blockchain.spendable_balance(&self.device_id)
+    }
+
+    /// One-line summary for logging and headless monitoring
+    #[wasm_bindgen]
+    pub fn status_line(&self) -> String {
+        let node_count: usize = self.clusters.values().map(|cluster| cluster.get_node_count()).sum();
+        let (connected_peers, known_peers) = self.p2p_network.peer_counts();
+        format!("dev={} clusters={} nodes={} peers={}/{} balance={:.1} blocks={} vectors={} chain={}",
+            self.device_id,
+            self.clusters.len(),
+            node_count,
+            connected_peers,
+            known_peers,
+            self.blockchain.get_account_balance(&self.device_id),
+            self.blockchain.get_block_count(),
+            self.vector_database.get_vector_count(),
+            if self.blockchain.validate_chain() { "ok" } else { "invalid" })
+    }
+
     #[wasm_bindgen]
     pub fn get_blockchain_stats(&self) -> JsValue {
         self.blockchain.get_blockchain_stats()
     }
 
+    #[wasm_bindgen]
+    pub fn export_transaction_log(&self) -> String {
+        self.blockchain.export_transaction_log()
+    }
+
+    #[wasm_bindgen]
+    pub fn replay_transaction_log(&mut self, log_json: &str) -> Result<(), DnnError> {
+        self.blockchain.replay_transaction_log(log_json)
+    }
+
+    #[wasm_bindgen]
+    pub fn get_block(&self, index: u64) -> String {
+        self.blockchain.get_block(index)
+    }
+
+    #[wasm_bindgen]
+    pub fn get_latest_blocks(&self, count: usize) -> String {
+        self.blockchain.get_latest_blocks(count)
+    }
+
+    /// Cross-check the vector database against the ledger and each store's own indices.
+    /// Returns a JSON list of { severity, component, message }, most severe first.
+    #[wasm_bindgen]
+    pub fn run_integrity_check(&self) -> String {
+        let mut issues = self.vector_database.check_index_integrity();
+        issues.extend(self.blockchain.check_supply_integrity());
+        
+        for capsule_id in self.blockchain.memory_record_ids() {
+            if self.vector_database.has_entry(capsule_id) {
+                continue;
+            }
+            let (severity, note) = if self.vector_database.is_retired(capsule_id) {
+                (IntegritySeverity::Info, "was retired from the vector database")
+            } else {
+                (IntegritySeverity::Warning, "has no vector database entry")
+            };
+            issues.push(IntegrityIssue::new(severity, "cross_check",
+                format!("memory record {} {}", capsule_id, note)));
+        }
+        for (capsule_id, hash) in self.vector_database.blockchain_links() {
+            let record_hash = self.blockchain.get_memory_record_hash(capsule_id);
+            if record_hash.is_empty() {
+                issues.push(IntegrityIssue::new(IntegritySeverity::Error, "cross_check",
+                    format!("vector entry {} has no memory record on the ledger", capsule_id)));
+            } else if record_hash != hash {
+                issues.push(IntegrityIssue::new(IntegritySeverity::Error, "cross_check",
+                    format!("vector entry {} links to hash {} but the ledger records {}", capsule_id, hash, record_hash)));
+            }
+        }
+        
+        issues.sort_by(|a, b| b.severity.cmp(&a.severity).then_with(|| a.message.cmp(&b.message)));
+        console_log!("Integrity check found {} issues", issues.len());
+        serde_json::to_string(&issues).unwrap_or_default()
+    }
+
+    #[wasm_bindgen]
+    pub fn export_chain(&self) -> String {
+        self.blockchain.export_chain()
+    }
+
+    #[wasm_bindgen]
+    pub fn import_chain(&mut self, json: &str) -> bool {
+        self.blockchain.import_chain(json)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_mining_difficulty(&mut self, difficulty: usize) {
+        self.blockchain.set_difficulty(difficulty);
+    }
+
+    #[wasm_bindgen]
+    pub fn set_miner_address(&mut self, device_id: String) {
+        self.blockchain.set_miner_address(device_id);
+    }
+
+    #[wasm_bindgen]
+    pub fn set_transaction_fee(&mut self, fee: f64) -> bool {
+        self.blockchain.set_transaction_fee(fee)
+    }
+
+    #[wasm_bindgen]
+    pub fn set_subsidy_halving_interval(&mut self, blocks: u64) {
+        self.blockchain.set_subsidy_halving_interval(blocks);
+    }
+
+    #[wasm_bindgen]
+    pub fn export_ledger_csv(&self) -> String {
+        self.blockchain.export_ledger_csv()
+    }
+
+    #[wasm_bindgen]
+    pub fn export_ledger_json(&self) -> String {
+        self.blockchain.export_ledger_json()
+    }
+
+    /// Run a deployed contract's code against a JSON object of numeric variables,
+    /// paying its execution cost from this device. Returns the resulting variables as JSON.
+    #[wasm_bindgen]
+    pub fn execute_contract(&mut self, contract_id: &str, context_json: &str) -> String {
+        self.blockchain.execute_contract(&self.device_id, contract_id, context_json)
+    }
+
+    #[wasm_bindgen]
+    pub fn get_active_contract_by_type(&self, contract_type: String) -> String {
+        self.blockchain.get_active_contract_by_type(contract_type)
+    }
+
     #[wasm_bindgen]
     pub fn mine_block(&mut self) -> String {
         self.blockchain.mine_block()
@@ -333,11 +1308,20 @@ impl DistributedNeuralNetwork {
         self.blockchain.get_memory_record(capsule_id)
     }
 
+    /// Re-check the hash stored with a vector DB entry against the ledger's MemoryRecord
+    #[wasm_bindgen]
+    pub fn verify_capsule_on_chain(&self, capsule_id: &str) -> bool {
+        let stored_hash = self.vector_database.get_blockchain_hash(capsule_id);
+        let ledger_hash = self.blockchain.get_memory_record_hash(capsule_id);
+        !stored_hash.is_empty() && stored_hash == ledger_hash
+    }
+
     #[wasm_bindgen]
     pub fn get_vector_database_stats(&self) -> JsValue {
         let stats = VectorDatabaseStats {
             total_vectors: self.vector_database.get_vector_count(),
             total_memory_size: self.vector_database.get_total_memory_size(),
+            index_memory_estimate: self.vector_database.get_index_memory_estimate(),
             average_vector_dimension: self.vector_database.get_average_vector_dimension(),
             semantic_clusters: self.vector_database.get_semantic_cluster_count(),
             temporal_entries: self.vector_database.get_temporal_entry_count(),
@@ -353,12 +1337,28 @@ impl DistributedNeuralNetwork {
         self.p2p_network.initiate_webrtc_connection(peer_id).await
     }
 
+    #[wasm_bindgen]
+    pub async fn refresh_connection_stats(&mut self, peer_id: String) -> bool {
+        self.p2p_network.refresh_connection_stats(peer_id).await
+    }
+
     #[wasm_bindgen]
     pub fn close_webrtc_connection(&mut self, peer_id: String) -> bool {
         console_log!("Closing WebRTC connection to: {}", peer_id);
         self.p2p_network.close_peer_connection(&peer_id)
     }
 
+    #[wasm_bindgen]
+    pub fn get_pending_connections(&self) -> String {
+        self.p2p_network.get_pending_connections()
+    }
+
+    #[wasm_bindgen]
+    pub fn abort_connection(&mut self, peer_id: String) -> Result<(), DnnError> {
+        console_log!("Aborting pending WebRTC negotiation with: {}", peer_id);
+        self.p2p_network.abort_connection(&peer_id)
+    }
+
     #[wasm_bindgen]
     pub fn send_direct_message(&mut self, peer_id: String, message: String) -> bool {
         console_log!("Sending direct P2P message to {}: {}", peer_id, message);
@@ -379,6 +1379,11 @@ impl DistributedNeuralNetwork {
         self.p2p_network.get_webrtc_stats()
     }
 
+    #[wasm_bindgen]
+    pub fn add_turn_server(&mut self, url: String, username: String, credential: String) -> bool {
+        self.p2p_network.add_turn_server(url, username, credential)
+    }
+
     #[wasm_bindgen]
     pub fn is_peer_connected_webrtc(&self, peer_id: &str) -> bool {
         self.p2p_network.is_peer_connected_webrtc(peer_id)
@@ -389,6 +1394,7 @@ impl DistributedNeuralNetwork {
 struct VectorDatabaseStats {
     total_vectors: usize,
     total_memory_size: usize,
+    index_memory_estimate: usize,
     average_vector_dimension: usize,
     semantic_clusters: usize,
     temporal_entries: usize,
@@ -404,4 +1410,279 @@ static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 #[wasm_bindgen]
 pub fn greet() {
     log("Hello, distributed-neural-wasm!");
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feed inputs until the cluster has produced and registered at least one capsule
+    fn network_with_capsule() -> (DistributedNeuralNetwork, String) {
+        let mut network = DistributedNeuralNetwork::new("device".to_string());
+        assert!(network.create_cluster_with_seed("cluster".to_string(), 4, 7));
+        let width = network.clusters["cluster"].get_input_size();
+        for step in 0..500 {
+            network.process_input("cluster".to_string(), &vec![(step % 5) as f64 * 0.25; width]);
+            let linked = network.vector_database.blockchain_links().first().map(|(id, _)| id.to_string());
+            if let Some(capsule_id) = linked {
+                return (network, capsule_id);
+            }
+        }
+        panic!("no capsule was registered");
+    }
+
+    // "upstream" fed until it produces a capsule, optionally linked into "downstream"
+    fn upstream_with_capsule(linked: bool) -> DistributedNeuralNetwork {
+        let mut network = DistributedNeuralNetwork::new("device".to_string());
+        assert!(network.create_cluster_with_seed("upstream".to_string(), 4, 7));
+        assert!(network.create_cluster_with_seed("downstream".to_string(), 4, 9));
+        if linked {
+            assert!(network.set_memory_link("upstream".to_string(), "downstream".to_string()));
+        }
+        let width = network.clusters["upstream"].get_input_size();
+        for step in 0..500 {
+            network.process_input("upstream".to_string(), &vec![(step % 5) as f64 * 0.25; width]);
+            if network.vector_database.get_vector_count() > 0 {
+                return network;
+            }
+        }
+        panic!("no capsule was produced");
+    }
+
+    #[test]
+    fn linked_capsules_feed_the_next_downstream_input() {
+        let runs = [false, true].map(|linked| {
+            let mut network = upstream_with_capsule(linked);
+            let width = network.clusters["downstream"].get_input_size();
+            network.process_input("downstream".to_string(), &vec![0.5; width]);
+            network.clusters["downstream"].get_all_node_states()
+        });
+        assert_ne!(runs[0], runs[1]);
+
+        let mut network = DistributedNeuralNetwork::new("device".to_string());
+        network.create_cluster("a".to_string(), 1);
+        assert!(!network.set_memory_link("a".to_string(), "a".to_string()));
+        assert!(!network.set_memory_link("a".to_string(), "missing".to_string()));
+    }
+
+    #[test]
+    fn tampered_ledger_records_fail_on_chain_verification() {
+        let (mut network, capsule_id) = network_with_capsule();
+        assert!(network.verify_capsule_on_chain(&capsule_id));
+
+        network.blockchain.memory_registry.get_mut(&capsule_id).unwrap().hash = "tampered".to_string();
+        assert!(!network.verify_capsule_on_chain(&capsule_id));
+        assert!(!network.verify_capsule_on_chain("unknown_capsule"));
+    }
+
+    #[test]
+    fn clusters_above_the_node_cap_are_refused() {
+        let mut network = DistributedNeuralNetwork::new("device".to_string());
+        assert!(!network.create_cluster("huge".to_string(), DEFAULT_MAX_NODES_PER_CLUSTER + 1));
+        assert!(!network.clusters.contains_key("huge"));
+
+        network.set_max_nodes_per_cluster(4);
+        assert!(!network.create_cluster_with_seed("five".to_string(), 5, 1));
+        assert!(network.create_cluster("four".to_string(), 4));
+        assert_eq!(network.clusters["four"].get_node_count(), 4);
+    }
+
+    #[test]
+    fn underfunded_collaborative_learning_is_refused() {
+        // A new device holds 10 credits; each invitee is promised 10
+        let mut network = DistributedNeuralNetwork::new("device".to_string());
+        let peers = vec!["alice".to_string(), "bob".to_string()];
+        assert!(matches!(network.start_collaborative_learning(peers, "task".to_string()),
+            Err(DnnError::InsufficientCredits { required, available }) if required == 20.0 && available == 10.0));
+
+        let session_id = network.start_collaborative_learning(vec!["alice".to_string()], "task".to_string()).unwrap();
+        assert!(!session_id.is_empty());
+    }
+
+    #[test]
+    fn collaboration_rewards_follow_contributed_samples() {
+        let mut network = DistributedNeuralNetwork::new("device".to_string());
+        network.blockchain.register_device("device".to_string(), 50.0);
+        let peers: Vec<String> = ["alice", "bob", "carol"].iter().map(|peer| peer.to_string()).collect();
+        let session_id = network.start_collaborative_learning(peers, "task".to_string()).unwrap();
+
+        assert!(network.record_collaboration_contribution(session_id.clone(), "alice".to_string(), 300, 0.0));
+        assert!(network.record_collaboration_contribution(session_id.clone(), "bob".to_string(), 100, 0.0));
+        assert!(!network.record_collaboration_contribution(session_id.clone(), "mallory".to_string(), 500, 0.0));
+
+        // The 30-credit pool is split 3:1; carol contributed nothing and gets nothing
+        let paid: HashMap<String, f64> = serde_json::from_str(&network.distribute_collaboration_rewards(session_id.clone())).unwrap();
+        assert_eq!(paid.len(), 2);
+        assert!((paid["alice"] - 22.5).abs() < 1e-9);
+        assert!((paid["bob"] - 7.5).abs() < 1e-9);
+        assert_eq!(network.blockchain.get_account_balance("alice"), paid["alice"]);
+        assert_eq!(network.blockchain.get_account_balance("carol"), 0.0);
+
+        // The session is complete, so it cannot pay out twice
+        assert_eq!(network.distribute_collaboration_rewards(session_id), "");
+    }
+
+
+    #[test]
+    fn completing_a_session_records_one_history_entry() {
+        let mut network = DistributedNeuralNetwork::new("device".to_string());
+        network.blockchain.register_device("device".to_string(), 50.0);
+        let peers: Vec<String> = ["alice", "bob"].iter().map(|peer| peer.to_string()).collect();
+        let session_id = network.start_collaborative_learning(peers, "task".to_string()).unwrap();
+        assert!(network.record_collaboration_contribution(session_id.clone(), "alice".to_string(), 100, 0.0));
+        assert!(network.record_collaboration_contribution(session_id.clone(), "bob".to_string(), 100, 0.0));
+        assert!(network.record_collaboration_outcome(session_id.clone(), 0.25));
+        assert_eq!(network.get_collaboration_history(), "[]");
+
+        network.distribute_collaboration_rewards(session_id.clone());
+        let history: Vec<serde_json::Value> = serde_json::from_str(&network.get_collaboration_history()).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0]["session_id"], session_id.as_str());
+        assert_eq!(history[0]["participants"], serde_json::json!(["alice", "bob"]));
+        assert_eq!(history[0]["final_loss"], 0.25);
+        assert_eq!(history[0]["total_rewards"], 20.0);
+        assert_eq!(history[0]["rewards"]["alice"], 10.0);
+    }
+
+
+    #[test]
+    fn accepted_sessions_set_the_target_cluster_adaptation_rates() {
+        let mut network = DistributedNeuralNetwork::new("device".to_string());
+        assert!(network.create_cluster_with_seed("target".to_string(), 3, 7));
+        assert!(network.create_cluster_with_seed("other".to_string(), 3, 9));
+        assert!(network.set_collaboration_cluster("target".to_string()));
+        let rates = |network: &DistributedNeuralNetwork, cluster_id: &str| -> Vec<(f64, f64)> {
+            let cluster = &network.clusters[cluster_id];
+            cluster.get_node_ids().iter()
+                .map(|node_id| cluster.get_node(node_id).unwrap())
+                .map(|node| (node.threshold_adaptation_rate(), node.timer_adaptation_rate()))
+                .collect()
+        };
+        let other_rates = rates(&network, "other");
+
+        let invitation = P2PMessage {
+            message_id: "collab_invite_1".to_string(),
+            from: "alice".to_string(),
+            to: "device".to_string(),
+            message_type: MessageType::CollaborativeLearn,
+            payload: MessagePayload::CollaborativeLearnData {
+                task_description: "task".to_string(),
+                dataset_hash: "dataset".to_string(),
+                learning_parameters: HashMap::from([("learning_rate".to_string(), 0.04)]),
+                participant_rewards: HashMap::new(),
+            },
+            timestamp: now_ms(),
+            signature: "collab_signature".to_string(),
+            hop_count: 0,
+        };
+        assert!(network.p2p_network.inject_incoming_message(&serde_json::to_string(&invitation).unwrap()));
+        network.process_p2p_messages();
+
+        assert_eq!(rates(&network, "target"), vec![(0.04, 0.02); 3]);
+        assert_eq!(rates(&network, "other"), other_rates);
+    }
+
+
+    #[test]
+    fn status_line_counts_clusters_and_nodes() {
+        let mut network = DistributedNeuralNetwork::new("device".to_string());
+        assert!(network.status_line().contains(" clusters=0 nodes=0 "));
+
+        assert!(network.create_cluster_with_seed("first".to_string(), 4, 7));
+        assert!(network.create_cluster_with_seed("second".to_string(), 5, 9));
+        let status = network.status_line();
+        assert!(status.starts_with("dev=device clusters=2 nodes=9 "), "{}", status);
+        assert!(status.ends_with(" chain=ok"));
+    }
+
+
+    #[test]
+    fn sharing_rewards_wait_for_a_valid_receipt() {
+        use crate::p2p_network::tests::{signed_peer, take_sent, with_outbox};
+
+        let (mut network, _) = network_with_capsule();
+        network.p2p_network = with_outbox("device");
+        let mut bob = with_outbox("bob");
+        let announcement = serde_json::to_string(&vec![signed_peer(&bob)]).unwrap();
+        assert!(network.p2p_network.handle_discovery_results(&announcement));
+        let balance_before = network.blockchain.get_account_balance("device");
+
+        assert!(network.share_memory_with_peer("bob".to_string(), "cluster".to_string()));
+        let share = take_sent(&network.p2p_network).pop().unwrap();
+        assert!(bob.inject_incoming_message(&serde_json::to_string(&share).unwrap()));
+
+        // Delivered, but no receipt has come back yet
+        network.process_p2p_messages();
+        assert_eq!(network.blockchain.get_account_balance("device"), balance_before);
+
+        let receipt = take_sent(&bob).pop().unwrap();
+        assert!(matches!(receipt.message_type, MessageType::MemoryShareReceipt));
+        assert!(network.p2p_network.inject_incoming_message(&serde_json::to_string(&receipt).unwrap()));
+        network.process_p2p_messages();
+        assert!(network.blockchain.get_account_balance("device") > balance_before);
+    }
+
+
+    // Total output of "b" over rounds where "a" sees `a_input` first and "b" sees nothing
+    fn piped_b_activity(a_input: f64, piped: bool) -> f64 {
+        let mut network = DistributedNeuralNetwork::new("device".to_string());
+        assert!(network.create_cluster_with_seed("a".to_string(), 4, 7));
+        assert!(network.create_cluster_with_seed("b".to_string(), 4, 9));
+        if piped {
+            assert!(network.pipe_clusters("a".to_string(), "b".to_string()));
+        }
+        let (a_width, b_width) = (network.clusters["a"].get_input_size(), network.clusters["b"].get_input_size());
+        let mut activity = 0.0;
+        for _ in 0..10 {
+            network.process_input("a".to_string(), &vec![a_input; a_width]);
+            activity += network.process_input("b".to_string(), &vec![0.0; b_width]).iter().sum::<f64>();
+        }
+        activity
+    }
+
+    #[test]
+    fn piped_clusters_respond_to_the_source_input() {
+        assert!(!DistributedNeuralNetwork::new("device".to_string()).pipe_clusters("a".to_string(), "b".to_string()));
+        assert!((piped_b_activity(2.0, true) - piped_b_activity(0.0, true)).abs() > 1e-6);
+        assert!((piped_b_activity(2.0, false) - piped_b_activity(0.0, false)).abs() < 1e-9);
+    }
+
+
+    #[test]
+    fn integrity_check_reports_an_orphaned_vector_entry() {
+        let (mut network, capsule_id) = network_with_capsule();
+        let orphan_errors = |network: &DistributedNeuralNetwork| -> Vec<IntegrityIssue> {
+            let issues: Vec<IntegrityIssue> = serde_json::from_str(&network.run_integrity_check()).unwrap();
+            issues.into_iter()
+                .filter(|issue| issue.severity == IntegritySeverity::Error && issue.message.contains(&capsule_id))
+                .collect()
+        };
+        assert!(orphan_errors(&network).is_empty());
+
+        network.blockchain.memory_registry.remove(&capsule_id);
+        let errors = orphan_errors(&network);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].component, "cross_check");
+        assert!(errors[0].message.contains("has no memory record"));
+    }
+
+
+    #[test]
+    fn peer_debits_apply_once_their_identity_is_pinned() {
+        let mut alice = DistributedNeuralNetwork::new("alice".to_string());
+        alice.blockchain.mine_block();
+        let mut bob = DistributedNeuralNetwork::new("bob".to_string());
+        assert!(bob.import_chain(&alice.export_chain()));
+
+        alice.blockchain.pay_contribution_reward("alice", "bob", 1.0, "session").unwrap();
+        alice.blockchain.mine_block();
+        let blocks = alice.blockchain.get_blocks_from(2);
+        bob.register_peer_account_keys();
+        assert_eq!(bob.blockchain.apply_blocks(&blocks), 0);
+
+        let announcement = crate::p2p_network::tests::signed_peer(&alice.p2p_network);
+        assert!(bob.p2p_network.handle_discovery_results(&serde_json::to_string(&[announcement]).unwrap()));
+        bob.register_peer_account_keys();
+        assert_eq!(bob.blockchain.apply_blocks(&blocks), 1);
+        assert_eq!(bob.blockchain.get_account_balance("bob"), 1.0);
+    }
+}
@@ -1,6 +1,13 @@
 use wasm_bindgen::prelude::*;
 use serde::{Serialize, Deserialize};
 use std::collections::{HashMap, VecDeque};
+use crate::utils::RunningStats;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::Rng;
+
+// Import the console_log macro
+use crate::console_log;
 
 /// Three-level memory hierarchy as described in the paper
 /// Level 1: Node Memory - local to each threshold gating node
@@ -48,6 +55,19 @@ impl NodeMemory {
         }
     }
 
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size.max(1);
+        while self.activations.len() > self.max_size {
+            self.activations.pop_front();
+            self.errors.pop_front();
+            self.eligibility_history.pop_front();
+            self.threshold_history.pop_front();
+        }
+        while self.timer_events.len() > self.max_size {
+            self.timer_events.pop_front();
+        }
+    }
+
     pub fn add_event(&mut self, time: f64, event_type: String) {
         self.timer_events.push_back((time, event_type));
         if self.timer_events.len() > self.max_size {
@@ -60,6 +80,18 @@ impl NodeMemory {
             self.context_tags.push(tag);
         }
     }
+
+    /// Approximate size of the histories held by this memory in bytes
+    pub fn memory_footprint_estimate(&self) -> usize {
+        let f64_size = std::mem::size_of::<f64>();
+        std::mem::size_of::<Self>()
+            + self.node_id.capacity()
+            + (self.activations.capacity() + self.errors.capacity()
+                + self.eligibility_history.capacity() + self.threshold_history.capacity()) * f64_size
+            + self.timer_events.iter().map(|(_, event)| event.capacity()).sum::<usize>()
+            + self.timer_events.capacity() * std::mem::size_of::<(f64, String)>()
+            + self.context_tags.iter().map(|tag| tag.capacity() + std::mem::size_of::<String>()).sum::<usize>()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -72,10 +104,23 @@ pub struct MemoryCapsule {
     pub semantic_tags: Vec<String>,
     pub adaptation_summary: AdaptationSummary,
     pub compressed_data: Vec<u8>, // Encrypted and compressed node states
+    #[serde(default)]
+    pub uncompressed_size: usize, // Bytes of serialized node states before compression
     pub novelty_score: f64,
     pub importance_score: f64,
 }
 
+impl MemoryCapsule {
+    /// The donor's per-node memories, or None if the data is encrypted, corrupt or truncated
+    pub fn node_memories(&self) -> Option<HashMap<String, NodeMemory>> {
+        if matches!(self.privacy_level, PrivacyLevel::Personal) {
+            return None; // Needs the device secret; see decrypt_capsule
+        }
+        let serialized = crate::utils::try_decompress_data(&self.compressed_data).ok()?;
+        serde_json::from_slice(&serialized).ok()
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum PrivacyLevel {
     Personal,    // Encrypted, private to device
@@ -102,8 +147,41 @@ pub struct ClusterMemory {
     pub semantic_index: HashMap<String, Vec<String>>, // tag -> capsule_ids
     pub consolidation_threshold: usize,
     pub last_consolidation: f64,
+    pub min_consolidation_interval: f64, // Used when the cluster is highly active
+    pub max_consolidation_interval: f64, // Used when the cluster is quiet
+    #[serde(default)]
+    pub novelty_config: NoveltyConfig,
+    #[serde(skip)]
+    device_secret: Option<[u8; DEVICE_SECRET_SIZE]>, // Encrypts Personal capsules; never serialized
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum NoveltyMetric {
+    Euclidean,        // Nearest prior capsule distance / normalization
+    Cosine,           // 1 - max cosine similarity to prior capsules
+    LearnedThreshold, // Distance relative to a running average of past nearest distances
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NoveltyConfig {
+    pub metric: NoveltyMetric,
+    pub normalization: f64,
+    pub learned_distance: Option<f64>, // Running average nearest distance for LearnedThreshold
+}
+
+impl Default for NoveltyConfig {
+    fn default() -> Self {
+        NoveltyConfig {
+            metric: NoveltyMetric::Euclidean,
+            normalization: 2.0,
+            learned_distance: None,
+        }
+    }
 }
 
+/// Weight of each new nearest distance in NoveltyConfig::learned_distance
+const LEARNED_DISTANCE_RATE: f64 = 0.1;
+
 impl ClusterMemory {
     pub fn new(cluster_id: String) -> Self {
         ClusterMemory {
@@ -114,6 +192,52 @@ impl ClusterMemory {
             semantic_index: HashMap::new(),
             consolidation_threshold: 10,
             last_consolidation: 0.0,
+            min_consolidation_interval: 15.0,
+            max_consolidation_interval: 120.0,
+            novelty_config: NoveltyConfig::default(),
+            device_secret: None,
+        }
+    }
+
+    /// Set the 32-byte device secret used to encrypt Personal capsules
+    pub fn set_device_secret(&mut self, key: &[u8]) -> bool {
+        match <[u8; DEVICE_SECRET_SIZE]>::try_from(key) {
+            Ok(key) => {
+                self.device_secret = Some(key);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    pub(crate) fn device_secret(&self) -> Option<[u8; DEVICE_SECRET_SIZE]> {
+        self.device_secret
+    }
+
+    /// Choose the novelty metric ("euclidean", "cosine", "learned") and its normalization constant
+    pub fn set_novelty_config(&mut self, metric: &str, normalization: f64) -> bool {
+        let metric = match metric.to_lowercase().as_str() {
+            "euclidean" => NoveltyMetric::Euclidean,
+            "cosine" => NoveltyMetric::Cosine,
+            "learned" => NoveltyMetric::LearnedThreshold,
+            _ => return false,
+        };
+        if !normalization.is_finite() || normalization <= 0.0 {
+            return false;
+        }
+        self.novelty_config.metric = metric;
+        self.novelty_config.normalization = normalization;
+        true
+    }
+
+    pub fn set_consolidation_interval_bounds(&mut self, min_interval: f64, max_interval: f64) {
+        self.min_consolidation_interval = min_interval.max(0.0);
+        self.max_consolidation_interval = max_interval.max(self.min_consolidation_interval);
+    }
+
+    pub fn set_node_memory_size(&mut self, memory_size: usize) {
+        for memory in self.node_memories.values_mut() {
+            memory.set_max_size(memory_size);
         }
     }
 
@@ -145,11 +269,42 @@ impl ClusterMemory {
     }
 
     fn should_consolidate(&self, current_time: f64) -> bool {
-        // Consolidate based on time interval or buffer size
-        (current_time - self.last_consolidation) > 60.0 || // Every minute
+        // Consolidate based on an activity-scaled interval or buffer size
+        (current_time - self.last_consolidation) > self.consolidation_interval() ||
         self.node_memories.values().any(|mem| mem.activations.len() >= mem.max_size * 3/4)
     }
 
+    /// Interval between consolidations: shrinks toward the minimum as recent activity grows
+    pub fn consolidation_interval(&self) -> f64 {
+        let activity = self.recent_activity_level();
+        let level = activity / (1.0 + activity); // Squash into [0, 1)
+        self.max_consolidation_interval
+            - (self.max_consolidation_interval - self.min_consolidation_interval) * level
+    }
+
+    /// Mean absolute activation plus error over each node's most recent entries
+    fn recent_activity_level(&self) -> f64 {
+        const WINDOW: usize = 10;
+        let mut total = 0.0;
+        let mut count = 0;
+
+        for memory in self.node_memories.values() {
+            for (activation, error) in memory.activations.iter().rev()
+                .zip(memory.errors.iter().rev())
+                .take(WINDOW)
+            {
+                total += activation.abs() + error.abs();
+                count += 1;
+            }
+        }
+
+        if count == 0 {
+            0.0
+        } else {
+            total / count as f64
+        }
+    }
+
     fn consolidate_memories(&mut self, current_time: f64) -> MemoryCapsule {
         // Aggregate statistics from all nodes
         let mut adaptation_summary = AdaptationSummary {
@@ -165,11 +320,11 @@ impl ClusterMemory {
         let mut semantic_tags = Vec::new();
 
         // Process each node's memory
-        for (_node_id, memory) in &self.node_memories {
+        for memory in self.node_memories.values() {
             if !memory.activations.is_empty() {
                 // Compute summary statistics
-                let avg_activation: f64 = memory.activations.iter().sum::<f64>() / memory.activations.len() as f64;
-                let avg_error: f64 = memory.errors.iter().sum::<f64>() / memory.errors.len() as f64;
+                let avg_activation = RunningStats::from_values(&memory.activations).mean();
+                let avg_error = RunningStats::from_values(&memory.errors).mean();
                 
                 adaptation_summary.error_magnitude += avg_error.abs();
                 
@@ -208,11 +363,30 @@ impl ClusterMemory {
             PrivacyLevel::Public
         };
 
-        // Create compressed data (simplified - in real implementation would use proper compression/encryption)
-        let compressed_data = serde_json::to_vec(&self.node_memories).unwrap_or_default();
+        // DEFLATE the serialized node states
+        let serialized = serde_json::to_vec(&self.node_memories).unwrap_or_default();
+        let uncompressed_size = serialized.len();
+        let mut compressed_data = crate::utils::compress_data(&serialized);
+
+        // Personal capsules are sealed as nonce || ciphertext || tag, bound to the capsule id
+        let capsule_id = format!("{}_{}", self.cluster_id, current_time as u64);
+        if matches!(privacy_level, PrivacyLevel::Personal) {
+            compressed_data = match &self.device_secret {
+                Some(key) => {
+                    let nonce: [u8; CAPSULE_NONCE_SIZE] = rand::thread_rng().gen();
+                    let mut sealed = nonce.to_vec();
+                    sealed.extend(seal_capsule_data(key, &nonce, &compressed_data, capsule_id.as_bytes()));
+                    sealed
+                }
+                None => {
+                    console_log!("No device secret set; dropping node states from Personal capsule {}", capsule_id);
+                    Vec::new()
+                }
+            };
+        }
 
         MemoryCapsule {
-            capsule_id: format!("{}_{}", self.cluster_id, current_time as u64),
+            capsule_id,
             timestamp: current_time,
             cluster_id: self.cluster_id.clone(),
             privacy_level,
@@ -220,25 +394,46 @@ impl ClusterMemory {
             semantic_tags,
             adaptation_summary,
             compressed_data,
+            uncompressed_size,
             novelty_score,
             importance_score,
         }
     }
 
-    fn calculate_novelty(&self, context_vector: &[f64]) -> f64 {
-        // Simple novelty calculation based on distance from previous capsules
+    fn calculate_novelty(&mut self, context_vector: &[f64]) -> f64 {
         if self.capsule_buffer.is_empty() {
             return 1.0; // First capsule is novel
         }
 
-        let mut min_distance = f64::MAX;
-        for capsule in &self.capsule_buffer {
-            let distance = euclidean_distance(context_vector, &capsule.context_vector);
-            min_distance = min_distance.min(distance);
+        let normalization = self.novelty_config.normalization;
+        match self.novelty_config.metric {
+            NoveltyMetric::Cosine => {
+                let max_similarity = self.capsule_buffer.iter()
+                    .map(|capsule| cosine_similarity(context_vector, &capsule.context_vector))
+                    .fold(f64::MIN, f64::max);
+                (1.0 - max_similarity).clamp(0.0, 1.0)
+            },
+            NoveltyMetric::Euclidean => (self.nearest_capsule_distance(context_vector) / normalization).min(1.0),
+            NoveltyMetric::LearnedThreshold => {
+                let min_distance = self.nearest_capsule_distance(context_vector);
+                let novelty = match self.novelty_config.learned_distance {
+                    // A typical nearest distance scores 1 / normalization
+                    Some(learned) if learned > 0.0 => (min_distance / (learned * normalization)).min(1.0),
+                    _ => (min_distance / normalization).min(1.0),
+                };
+                self.novelty_config.learned_distance = Some(match self.novelty_config.learned_distance {
+                    Some(learned) => learned + LEARNED_DISTANCE_RATE * (min_distance - learned),
+                    None => min_distance,
+                });
+                novelty
+            },
         }
+    }
 
-        // Normalize novelty score (higher = more novel)
-        (min_distance / 2.0).min(1.0)
+    fn nearest_capsule_distance(&self, context_vector: &[f64]) -> f64 {
+        self.capsule_buffer.iter()
+            .map(|capsule| euclidean_distance(context_vector, &capsule.context_vector))
+            .fold(f64::MAX, f64::min)
     }
 
     pub fn query_similar_capsules(&self, query_vector: &[f64], num_results: usize) -> Vec<String> {
@@ -260,6 +455,82 @@ impl ClusterMemory {
     pub fn get_latest_capsule(&self) -> Option<MemoryCapsule> {
         self.capsule_buffer.back().cloned()
     }
+
+    /// Approximate size of node memories and buffered capsules in bytes
+    pub fn memory_footprint_estimate(&self) -> usize {
+        let node_bytes: usize = self.node_memories.values()
+            .map(|memory| memory.memory_footprint_estimate())
+            .sum();
+        let capsule_bytes: usize = self.capsule_buffer.iter()
+            .map(|capsule| {
+                std::mem::size_of::<MemoryCapsule>()
+                    + capsule.compressed_data.capacity()
+                    + capsule.context_vector.capacity() * std::mem::size_of::<f64>()
+                    + capsule.semantic_tags.iter().map(|tag| tag.capacity()).sum::<usize>()
+            })
+            .sum();
+        node_bytes + capsule_bytes
+    }
+}
+
+/// Node memories carried by a shared capsule as JSON; "{}" if they can't be decoded
+#[wasm_bindgen]
+pub fn reconstruct_node_memories(capsule_json: &str) -> String {
+    let memories = match serde_json::from_str::<MemoryCapsule>(capsule_json) {
+        Ok(capsule) => capsule.node_memories().unwrap_or_else(|| {
+            console_log!("Could not decode node memories of capsule {}", capsule.capsule_id);
+            HashMap::new()
+        }),
+        Err(_) => HashMap::new(),
+    };
+    serde_json::to_string(&memories).unwrap_or_else(|_| "{}".to_string())
+}
+
+/// Recover the node-states JSON from a capsule, decrypting Personal capsules with the device secret
+#[wasm_bindgen]
+pub fn decrypt_capsule(capsule_json: &str, key: &[u8]) -> Result<String, JsValue> {
+    let capsule: MemoryCapsule = serde_json::from_str(capsule_json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid capsule: {}", e)))?;
+    let serialized = decrypt_capsule_data(&capsule, key).map_err(|e| JsValue::from_str(&e))?;
+    String::from_utf8(serialized).map_err(|e| JsValue::from_str(&format!("Invalid node states: {}", e)))
+}
+
+/// Bytes of a Personal capsule's device secret (a ChaCha20-Poly1305 key)
+pub const DEVICE_SECRET_SIZE: usize = 32;
+
+/// Random nonce stored ahead of each sealed Personal capsule
+const CAPSULE_NONCE_SIZE: usize = 12;
+
+// ChaCha20-Poly1305 ciphertext followed by its 16-byte tag, authenticating `aad` as well
+fn seal_capsule_data(key: &[u8; DEVICE_SECRET_SIZE], nonce: &[u8; CAPSULE_NONCE_SIZE], data: &[u8], aad: &[u8]) -> Vec<u8> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt(Nonce::from_slice(nonce), Payload { msg: data, aad })
+        .unwrap_or_default()
+}
+
+fn open_capsule_data(key: &[u8; DEVICE_SECRET_SIZE], nonce: &[u8; CAPSULE_NONCE_SIZE], sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad })
+        .map_err(|_| "authentication failed".to_string())
+}
+
+// Decompressed node states of a capsule, opening Personal capsules with `key`
+pub(crate) fn decrypt_capsule_data(capsule: &MemoryCapsule, key: &[u8]) -> Result<Vec<u8>, String> {
+    let compressed = if matches!(capsule.privacy_level, PrivacyLevel::Personal) {
+        let key = <[u8; DEVICE_SECRET_SIZE]>::try_from(key)
+            .map_err(|_| format!("Device secret must be {} bytes", DEVICE_SECRET_SIZE))?;
+        if capsule.compressed_data.len() < CAPSULE_NONCE_SIZE {
+            return Err("Capsule carries no encrypted node states".to_string());
+        }
+        let (nonce, sealed) = capsule.compressed_data.split_at(CAPSULE_NONCE_SIZE);
+        let nonce = <[u8; CAPSULE_NONCE_SIZE]>::try_from(nonce).unwrap_or_default();
+        open_capsule_data(&key, &nonce, sealed, capsule.capsule_id.as_bytes())
+            .map_err(|e| format!("Failed to decrypt capsule: {}", e))?
+    } else {
+        capsule.compressed_data.clone()
+    };
+
+    crate::utils::try_decompress_data(&compressed).map_err(|e| format!("Failed to decompress capsule: {}", e))
 }
 
 #[wasm_bindgen]
@@ -270,6 +541,12 @@ pub struct GlobalMemory {
     incentive_scores: HashMap<String, f64>,
 }
 
+impl Default for GlobalMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[wasm_bindgen]
 impl GlobalMemory {
     #[wasm_bindgen(constructor)]
@@ -282,20 +559,26 @@ impl GlobalMemory {
         }
     }
 
+    /// Store a capsule; storing one with an existing id replaces it, so only the new
+    /// version counts towards its device's contributions and incentive score
     #[wasm_bindgen]
     pub fn store_capsule(&mut self, capsule_json: &str) -> bool {
         if let Ok(capsule) = serde_json::from_str::<MemoryCapsule>(capsule_json) {
-            // Update semantic index
+            if let Some(previous) = self.capsules.remove(&capsule.capsule_id) {
+                self.uncount_capsule(&previous);
+            }
+
+            // Update semantic index, once per (tag, capsule_id)
             for tag in &capsule.semantic_tags {
-                self.semantic_index
-                    .entry(tag.clone())
-                    .or_insert_with(Vec::new)
-                    .push(capsule.capsule_id.clone());
+                let ids = self.semantic_index.entry(tag.clone()).or_default();
+                if !ids.contains(&capsule.capsule_id) {
+                    ids.push(capsule.capsule_id.clone());
+                }
             }
 
             // Update device contributions
             *self.device_contributions.entry(capsule.cluster_id.clone()).or_insert(0) += 1;
-            
+
             // Update incentive scores based on novelty and importance
             let score = capsule.novelty_score * capsule.importance_score;
             *self.incentive_scores.entry(capsule.cluster_id.clone()).or_insert(0.0) += score;
@@ -307,24 +590,60 @@ impl GlobalMemory {
         }
     }
 
+    /// Drop a capsule from storage and the semantic index, and uncount it from its device's
+    /// contributions and incentive score
     #[wasm_bindgen]
-    pub fn query_capsules_by_tags(&self, tags: &str) -> String {
-        let tag_list: Vec<String> = tags.split(',').map(|s| s.trim().to_string()).collect();
-        let mut matching_capsules = Vec::new();
+    pub fn remove_capsule(&mut self, capsule_id: &str) -> bool {
+        match self.capsules.remove(capsule_id) {
+            Some(capsule) => {
+                self.uncount_capsule(&capsule);
+                true
+            },
+            None => false,
+        }
+    }
 
-        for tag in tag_list {
-            if let Some(capsule_ids) = self.semantic_index.get(&tag) {
-                for id in capsule_ids {
-                    if let Some(capsule) = self.capsules.get(id) {
-                        matching_capsules.push(capsule);
-                    }
-                }
-            }
+    /// Capsules matching any of the comma-separated tags, deduplicated and ranked by
+    /// incentive score (novelty x importance), optionally limited to `max_results`
+    #[wasm_bindgen]
+    pub fn query_capsules_by_tags(&self, tags: &str, max_results: Option<usize>) -> String {
+        let mut matching_capsules = self.capsules_with_any_tag(tags);
+
+        // Ties are broken by timestamp then capsule_id so repeated queries return a stable order
+        matching_capsules.sort_by(|a, b| {
+            let score_a = a.novelty_score * a.importance_score;
+            let score_b = b.novelty_score * b.importance_score;
+            score_b.total_cmp(&score_a)
+                .then_with(|| a.timestamp.total_cmp(&b.timestamp))
+                .then_with(|| a.capsule_id.cmp(&b.capsule_id))
+        });
+
+        if let Some(limit) = max_results {
+            matching_capsules.truncate(limit);
         }
 
         serde_json::to_string(&matching_capsules).unwrap_or_default()
     }
 
+    /// Capsules matching any of the comma-separated tags with a timestamp in [start_time, end_time]
+    /// and importance of at least `min_importance`, most important first
+    #[wasm_bindgen]
+    pub fn query_capsules(&self, tags: &str, start_time: f64, end_time: f64, min_importance: f64) -> String {
+        let mut matching_capsules: Vec<&MemoryCapsule> = self.capsules_with_any_tag(tags)
+            .into_iter()
+            .filter(|capsule| capsule.timestamp >= start_time && capsule.timestamp <= end_time)
+            .filter(|capsule| capsule.importance_score >= min_importance)
+            .collect();
+
+        matching_capsules.sort_by(|a, b| {
+            b.importance_score.total_cmp(&a.importance_score)
+                .then_with(|| a.timestamp.total_cmp(&b.timestamp))
+                .then_with(|| a.capsule_id.cmp(&b.capsule_id))
+        });
+
+        serde_json::to_string(&matching_capsules).unwrap_or_default()
+    }
+
     #[wasm_bindgen]
     pub fn get_device_incentive_score(&self, device_id: &str) -> f64 {
         self.incentive_scores.get(device_id).copied().unwrap_or(0.0)
@@ -336,6 +655,54 @@ impl GlobalMemory {
     }
 }
 
+impl GlobalMemory {
+    // Each capsule carrying any of the comma-separated tags, once
+    fn capsules_with_any_tag(&self, tags: &str) -> Vec<&MemoryCapsule> {
+        let mut seen = std::collections::HashSet::new();
+        let mut matching_capsules = Vec::new();
+
+        for tag in tags.split(',').map(|s| s.trim()) {
+            if let Some(capsule_ids) = self.semantic_index.get(tag) {
+                for id in capsule_ids {
+                    if let Some(capsule) = self.capsules.get(id) {
+                        if seen.insert(id) {
+                            matching_capsules.push(capsule);
+                        }
+                    }
+                }
+            }
+        }
+        matching_capsules
+    }
+
+    // Undo what storing `capsule` added to the index and to its device's tallies; a device
+    // left with no capsules loses its entries, so float rounding can't leave a residual score
+    fn uncount_capsule(&mut self, capsule: &MemoryCapsule) {
+        self.unindex_capsule(&capsule.capsule_id);
+
+        let device = &capsule.cluster_id;
+        if let Some(count) = self.device_contributions.get_mut(device) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                self.device_contributions.remove(device);
+                self.incentive_scores.remove(device);
+                return;
+            }
+        }
+        if let Some(score) = self.incentive_scores.get_mut(device) {
+            *score -= capsule.novelty_score * capsule.importance_score;
+        }
+    }
+
+    // Remove the capsule's id from every semantic index entry, dropping tags left empty
+    fn unindex_capsule(&mut self, capsule_id: &str) {
+        self.semantic_index.retain(|_, ids| {
+            ids.retain(|id| id != capsule_id);
+            !ids.is_empty()
+        });
+    }
+}
+
 // Utility functions for vector operations
 fn euclidean_distance(a: &[f64], b: &[f64]) -> f64 {
     a.iter()
@@ -355,4 +722,225 @@ fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
     } else {
         dot_product / (magnitude_a * magnitude_b)
     }
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn sample_capsule(capsule_id: &str, tags: &[&str]) -> MemoryCapsule {
+        MemoryCapsule {
+            capsule_id: capsule_id.to_string(),
+            timestamp: 1_000.0,
+            cluster_id: "cluster".to_string(),
+            privacy_level: PrivacyLevel::Public,
+            context_vector: vec![0.5; 16],
+            semantic_tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            adaptation_summary: AdaptationSummary {
+                threshold_adaptations: 0,
+                timer_adaptations: 0,
+                weight_changes: 0.0,
+                error_magnitude: 0.0,
+                learning_rate_changes: 0.0,
+                specialization_metrics: HashMap::new(),
+            },
+            compressed_data: Vec::new(),
+            uncompressed_size: 0,
+            novelty_score: 0.5,
+            importance_score: 0.5,
+        }
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn tagged_cluster_memory(tag: &str) -> ClusterMemory {
+        let mut memory = ClusterMemory::new("cluster".to_string());
+        memory.add_node_memory("node_0".to_string(), 10);
+        for step in 0..5 {
+            memory.update_node_memory("node_0", step as f64 * 0.1, 0.05, 0.2, 0.5);
+        }
+        memory.node_memories.get_mut("node_0").unwrap().add_context_tag(tag.to_string());
+        memory
+    }
+
+    #[test]
+    fn capsule_sealing_matches_rfc_8439_vector() {
+        let key: [u8; DEVICE_SECRET_SIZE] = std::array::from_fn(|i| 0x80 + i as u8);
+        let nonce = [0x07, 0x00, 0x00, 0x00, 0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47];
+        let aad = [0x50, 0x51, 0x52, 0x53, 0xc0, 0xc1, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7];
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+
+        let sealed = seal_capsule_data(&key, &nonce, plaintext, &aad);
+        assert_eq!(hex(&sealed), concat!(
+            "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d63dbea45e8ca9671282fafb69da92728b",
+            "1a71de0a9e060b2905d6a5b67ecd3b3692ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc",
+            "3ff4def08e4b7a9de576d26586cec64b61161ae10b594f09e26a7e902ecbd0600691"));
+        assert_eq!(open_capsule_data(&key, &nonce, &sealed, &aad).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn personal_capsules_are_encrypted_and_decrypt_back() {
+        let key = [7u8; DEVICE_SECRET_SIZE];
+        let mut memory = tagged_cluster_memory("personal");
+        assert!(memory.set_device_secret(&key));
+        let plaintext = serde_json::to_vec(&memory.node_memories).unwrap();
+
+        let capsule = memory.consolidate_memories(100.0);
+        assert!(matches!(capsule.privacy_level, PrivacyLevel::Personal));
+        assert_ne!(capsule.compressed_data, plaintext);
+        assert_ne!(capsule.compressed_data, crate::utils::compress_data(&plaintext));
+        assert_eq!(decrypt_capsule_data(&capsule, &key).unwrap(), plaintext);
+
+        assert!(decrypt_capsule_data(&capsule, &[8u8; DEVICE_SECRET_SIZE]).is_err());
+        let mut tampered = capsule.clone();
+        let last = tampered.compressed_data.len() - 1;
+        tampered.compressed_data[last] ^= 1;
+        assert!(decrypt_capsule_data(&tampered, &key).is_err());
+        let mut renamed = capsule.clone();
+        renamed.capsule_id.push('x');
+        assert!(decrypt_capsule_data(&renamed, &key).is_err());
+    }
+
+    #[test]
+    fn personal_capsules_without_a_secret_carry_no_node_states() {
+        let mut memory = tagged_cluster_memory("personal");
+        assert!(!memory.set_device_secret(&[1, 2, 3]));
+        assert!(memory.consolidate_memories(100.0).compressed_data.is_empty());
+    }
+
+    #[test]
+    fn behavioral_capsules_stay_unencrypted() {
+        let mut memory = tagged_cluster_memory("behavior");
+        memory.set_device_secret(&[7u8; DEVICE_SECRET_SIZE]);
+        let plaintext = serde_json::to_vec(&memory.node_memories).unwrap();
+
+        let capsule = memory.consolidate_memories(100.0);
+        assert!(matches!(capsule.privacy_level, PrivacyLevel::Behavioral));
+        assert_eq!(crate::utils::decompress_data(&capsule.compressed_data), plaintext);
+    }
+
+    fn store(global: &mut GlobalMemory, capsule: &MemoryCapsule) {
+        assert!(global.store_capsule(&serde_json::to_string(capsule).unwrap()));
+    }
+
+    fn queried_ids(global: &GlobalMemory, tags: &str) -> Vec<String> {
+        let capsules: Vec<MemoryCapsule> = serde_json::from_str(&global.query_capsules_by_tags(tags, None)).unwrap();
+        capsules.into_iter().map(|capsule| capsule.capsule_id).collect()
+    }
+
+    #[test]
+    fn global_memory_stores_queries_and_removes_capsules() {
+        let mut global = GlobalMemory::new();
+        let mut high = sample_capsule("high", &["vision", "audio"]);
+        high.importance_score = 0.9;
+        store(&mut global, &high);
+        store(&mut global, &sample_capsule("low", &["audio"]));
+
+        assert_eq!(queried_ids(&global, "audio, vision"), ["high", "low"]);
+        assert_eq!(queried_ids(&global, "vision"), ["high"]);
+        assert_eq!(global.device_contributions["cluster"], 2);
+        assert!((global.get_device_incentive_score("cluster") - (0.45 + 0.25)).abs() < 1e-12);
+
+        assert!(global.remove_capsule("high"));
+        assert!(!global.remove_capsule("high"));
+        assert!(queried_ids(&global, "vision").is_empty());
+        assert_eq!(global.device_contributions["cluster"], 1);
+        assert!((global.get_device_incentive_score("cluster") - 0.25).abs() < 1e-12);
+
+        assert!(global.remove_capsule("low"));
+        assert_eq!(global.get_total_capsules(), 0);
+        assert!(global.device_contributions.is_empty());
+        assert_eq!(global.get_device_incentive_score("cluster"), 0.0);
+    }
+
+    #[test]
+    fn replacing_a_capsule_moves_its_counts_to_the_new_version() {
+        let mut global = GlobalMemory::new();
+        store(&mut global, &sample_capsule("shared", &["old_tag"]));
+        store(&mut global, &sample_capsule("other", &["old_tag"]));
+
+        let mut replacement = sample_capsule("shared", &["new_tag"]);
+        replacement.cluster_id = "other_cluster".to_string();
+        replacement.novelty_score = 1.0;
+        store(&mut global, &replacement);
+
+        assert_eq!(global.get_total_capsules(), 2);
+        assert_eq!(queried_ids(&global, "old_tag"), ["other"]);
+        assert_eq!(queried_ids(&global, "new_tag"), ["shared"]);
+        assert_eq!(global.device_contributions["cluster"], 1);
+        assert_eq!(global.device_contributions["other_cluster"], 1);
+        assert!((global.get_device_incentive_score("cluster") - 0.25).abs() < 1e-12);
+        assert!((global.get_device_incentive_score("other_cluster") - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn tag_queries_rank_by_novelty_and_list_each_capsule_once() {
+        let mut global = GlobalMemory::new();
+        let mut dull = sample_capsule("dull", &["vision", "motion"]);
+        dull.novelty_score = 0.1;
+        let mut novel = sample_capsule("novel", &["vision", "motion"]);
+        novel.novelty_score = 0.9;
+        store(&mut global, &dull);
+        store(&mut global, &novel);
+
+        assert_eq!(queried_ids(&global, "vision,motion,vision"), ["novel", "dull"]);
+        let limited: Vec<MemoryCapsule> = serde_json::from_str(&global.query_capsules_by_tags("vision", Some(1))).unwrap();
+        assert_eq!(limited.len(), 1);
+        assert_eq!(limited[0].capsule_id, "novel");
+    }
+
+    #[test]
+    fn equally_ranked_capsules_come_back_in_a_stable_order() {
+        let capsules: Vec<MemoryCapsule> = (0..6).map(|i| {
+            let mut capsule = sample_capsule(&format!("capsule_{}", i), &["tag", "other"]);
+            capsule.timestamp = (i % 2) as f64;
+            capsule
+        }).collect();
+        let mut forward = GlobalMemory::new();
+        let mut backward = GlobalMemory::new();
+        capsules.iter().for_each(|capsule| store(&mut forward, capsule));
+        capsules.iter().rev().for_each(|capsule| store(&mut backward, capsule));
+
+        let expected = ["capsule_0", "capsule_2", "capsule_4", "capsule_1", "capsule_3", "capsule_5"];
+        for _ in 0..3 {
+            assert_eq!(queried_ids(&forward, "tag,other"), expected);
+            assert_eq!(queried_ids(&backward, "other,tag"), expected);
+        }
+    }
+
+    fn capsules_at_activity(magnitude: f64, steps: usize) -> usize {
+        let mut memory = ClusterMemory::new("cluster".to_string());
+        memory.add_node_memory("node_0".to_string(), 10_000);
+        (1..=steps).filter(|&step| {
+            memory.update_node_memory("node_0", magnitude, magnitude, 0.2, 0.5);
+            memory.create_memory_capsule(step as f64).is_some()
+        }).count()
+    }
+
+    #[test]
+    fn active_clusters_consolidate_more_often() {
+        let busy = capsules_at_activity(1.0, 300);
+        let quiet = capsules_at_activity(0.01, 300);
+        assert!(busy > quiet, "busy {} vs quiet {}", busy, quiet);
+    }
+
+
+    #[test]
+    fn cosine_novelty_ignores_magnitude_that_euclidean_counts() {
+        let mut memory = ClusterMemory::new("cluster".to_string());
+        memory.capsule_buffer.push_back(sample_capsule("prior", &[]));
+        let scaled = vec![1.0; 16]; // Same direction as the prior [0.5; 16], twice the magnitude
+        let mut rotated = vec![0.5; 16];
+        rotated[..8].fill(0.0);
+
+        assert!(memory.set_novelty_config("euclidean", 2.0));
+        assert_eq!(memory.calculate_novelty(&scaled), 1.0);
+        assert!((memory.calculate_novelty(&rotated) - 2.0_f64.sqrt() / 2.0).abs() < 1e-12);
+
+        assert!(memory.set_novelty_config("cosine", 2.0));
+        assert!(memory.calculate_novelty(&scaled).abs() < 1e-12);
+        assert!((memory.calculate_novelty(&rotated) - (1.0 - 0.5_f64.sqrt())).abs() < 1e-12);
+        assert!(!memory.set_novelty_config("manhattan", 2.0));
+    }
+}
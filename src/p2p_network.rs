@@ -1,9 +1,13 @@
 use wasm_bindgen::prelude::*;
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
-use crate::memory::MemoryCapsule;
-use crate::webrtc::WebRTCManager;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::rc::Rc;
+use crate::memory::{MemoryCapsule, PrivacyLevel};
+use crate::webrtc::{WebRTCManager, CONTROL_CHANNEL, DATA_CHANNEL};
+use crate::error::DnnError;
+use crate::utils::{now_ms, sign_hex, verify_hex};
 use web_sys::{WebSocket, MessageEvent, CloseEvent, ErrorEvent};
+use ed25519_dalek::SigningKey;
 use wasm_bindgen::closure::Closure;
 
 // Import the console_log macro
@@ -19,12 +23,42 @@ pub struct P2PNetwork {
     active_connections: HashMap<String, P2PConnection>,
     message_queue: Vec<P2PMessage>,
     discovery_protocol: DiscoveryProtocol,
-    routing_table: HashMap<String, Vec<String>>, // device_id -> path to reach it
+    routing_table: HashMap<String, RouteEntry>, // device_id -> path to reach it
+    route_ttl_ms: f64, // Routes neither refreshed nor backed by a sighting of their peer for this long are evicted
+    max_routes: usize, // Routing table cap; lowest-reputation, least recently seen routes go first
+    capsule_store: HashMap<String, MemoryCapsule>, // capsule_id -> capsule available for pull requests
+    topology_history: VecDeque<TopologySnapshot>, // Bounded ring of routing table snapshots
+    topology_history_enabled: bool,
+    topology_history_capacity: usize,
+    collaboration_sessions: HashMap<String, CollaborationSession>, // session_id -> active session
+    collaboration_history: VecDeque<CollaborationOutcome>, // Completed sessions, oldest first
+    collaboration_history_capacity: usize,
+    simulated_peers: Option<String>, // Fixed discovery results served instead of the signaling server
+    economics_policy: EconomicsPolicy,
+    quarantine: VecDeque<QuarantinedCapsule>, // Bounded ring of shared capsules rejected by provenance checks
+    quarantine_capacity: usize,
+    quarantine_penalty: f64, // Reputation deducted from the sender per quarantined capsule
+    block_requests: Vec<(String, u64)>, // (peer_id, from_index) waiting to be served from the ledger
+    received_blocks: Vec<String>, // Block batches from peers waiting to be applied to the ledger
+    accepted_collaborations: Vec<AcceptedCollaboration>, // Invitations whose learning parameters await applying
+    pending_node_requests: HashMap<String, (String, String)>, // request message_id -> (peer_id, node_type)
+    node_lend_requests: Vec<NodeLendRequest>, // Approved requests waiting for a node to be serialized
+    borrowed_nodes: Vec<BorrowedNode>, // Nodes received from peers waiting to be added to a cluster
+    pending_shares: HashMap<(String, String), PendingShare>, // (peer_id, capsule_id) -> share awaiting a receipt
+    confirmed_shares: Vec<ConfirmedShare>, // Receipted shares whose reward awaits finalizing on chain
+    peer_clock_offsets: HashMap<String, f64>, // peer_id -> estimated (peer clock - local clock) in ms
+    outstanding_pings: HashMap<String, VecDeque<f64>>, // peer_id -> send times of pings awaiting a pong
+    seen_message_ids: VecDeque<String>, // Recently delivered message ids, oldest first, for replay rejection
+    seen_message_set: HashSet<String>, // The same ids, for constant-time lookup
+    signing_key: SigningKey, // This device's ed25519 key for announcements and share receipts
+    peer_keys: HashMap<String, String>, // device_id -> public key pinned by its first accepted announcement
     signaling_server_url: String,
     is_connected_to_server: bool,
     webrtc_manager: Option<WebRTCManager>,
     websocket: Option<WebSocket>,
-    websocket_callbacks: Option<WebSocketCallbacks>,
+    websocket_callbacks: Option<Rc<WebSocketCallbacks>>, // Keeps the current socket's handlers alive
+    #[cfg(test)]
+    test_outbox: Option<std::cell::RefCell<Vec<P2PMessage>>>, // Captures outgoing messages in native tests
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -55,9 +89,127 @@ pub struct PeerInfo {
     pub cpu_usage: f64,
     pub memory_usage: f64,
     pub available_nodes: u32,
+    #[serde(default)]
+    pub provisional: bool, // Loaded by import_peers and not yet confirmed by a heartbeat
+    #[serde(default)]
+    pub announced_at: f64, // When the peer signed its announcement, on its own clock
+    #[serde(default)]
+    pub announcement_signature: String, // Hex ed25519 signature over the announced fields; see sign_announcement
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Compact wire form of PeerInfo carrying only fields that changed since the last exchange.
+/// The full PeerInfo is still sent on first contact.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PeerInfoDelta {
+    pub device_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip_address: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reputation_score: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cluster_specializations: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_status: Option<NodeStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_connections: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_usage: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_usage: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub available_nodes: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub announced_at: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub announcement_signature: Option<String>, // Signature over the PeerInfo that results from applying the delta
+}
+
+fn changed<T: PartialEq + Clone>(current: &T, previous: &T) -> Option<T> {
+    if current != previous {
+        Some(current.clone())
+    } else {
+        None
+    }
+}
+
+impl PeerInfo {
+    // Fields the peer reports about itself. last_seen, active_connections and provisional
+    // are filled in by the server or this device, so they are not covered.
+    fn announcement_payload(&self) -> String {
+        serde_json::to_string(&(
+            &self.device_id,
+            &self.ip_address,
+            self.port,
+            &self.public_key,
+            &self.capabilities,
+            self.reputation_score,
+            &self.cluster_specializations,
+            &self.node_status,
+            self.cpu_usage,
+            self.memory_usage,
+            self.available_nodes,
+            self.announced_at,
+        )).unwrap_or_default()
+    }
+
+    /// Publish `key`'s verifying key as this peer's public key and sign the announcement with it
+    pub fn sign_announcement(&mut self, key: &SigningKey) {
+        self.public_key = crate::utils::hex_encode(key.verifying_key().as_bytes());
+        self.announcement_signature = sign_hex(key, &self.announcement_payload());
+    }
+
+    /// Whether the signature verifies under the claimed public key over the announced fields
+    pub fn has_valid_announcement(&self) -> bool {
+        verify_hex(&self.public_key, &self.announcement_payload(), &self.announcement_signature)
+    }
+
+    pub fn to_wire_delta(&self, previous: &PeerInfo) -> PeerInfoDelta {
+        PeerInfoDelta {
+            device_id: self.device_id.clone(),
+            ip_address: changed(&self.ip_address, &previous.ip_address),
+            port: changed(&self.port, &previous.port),
+            public_key: changed(&self.public_key, &previous.public_key),
+            capabilities: changed(&self.capabilities, &previous.capabilities),
+            reputation_score: changed(&self.reputation_score, &previous.reputation_score),
+            last_seen: changed(&self.last_seen, &previous.last_seen),
+            cluster_specializations: changed(&self.cluster_specializations, &previous.cluster_specializations),
+            node_status: changed(&self.node_status, &previous.node_status),
+            active_connections: changed(&self.active_connections, &previous.active_connections),
+            cpu_usage: changed(&self.cpu_usage, &previous.cpu_usage),
+            memory_usage: changed(&self.memory_usage, &previous.memory_usage),
+            available_nodes: changed(&self.available_nodes, &previous.available_nodes),
+            announced_at: changed(&self.announced_at, &previous.announced_at),
+            announcement_signature: changed(&self.announcement_signature, &previous.announcement_signature),
+        }
+    }
+
+    pub fn apply_wire_delta(&mut self, delta: PeerInfoDelta) {
+        if let Some(ip_address) = delta.ip_address { self.ip_address = ip_address; }
+        if let Some(port) = delta.port { self.port = port; }
+        if let Some(public_key) = delta.public_key { self.public_key = public_key; }
+        if let Some(capabilities) = delta.capabilities { self.capabilities = capabilities; }
+        if let Some(reputation_score) = delta.reputation_score { self.reputation_score = reputation_score; }
+        if let Some(last_seen) = delta.last_seen { self.last_seen = last_seen; }
+        if let Some(specializations) = delta.cluster_specializations { self.cluster_specializations = specializations; }
+        if let Some(node_status) = delta.node_status { self.node_status = node_status; }
+        if let Some(active_connections) = delta.active_connections { self.active_connections = active_connections; }
+        if let Some(cpu_usage) = delta.cpu_usage { self.cpu_usage = cpu_usage; }
+        if let Some(memory_usage) = delta.memory_usage { self.memory_usage = memory_usage; }
+        if let Some(available_nodes) = delta.available_nodes { self.available_nodes = available_nodes; }
+        if let Some(announced_at) = delta.announced_at { self.announced_at = announced_at; }
+        if let Some(signature) = delta.announcement_signature { self.announcement_signature = signature; }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct NodeStatus {
     pub is_processing: bool,
     pub active_queries: u32,
@@ -106,15 +258,31 @@ pub struct P2PMessage {
     pub hop_count: u8,
 }
 
+/// Over WebRTC, ErrorPropagate travels on the unreliable CONTROL_CHANNEL (a stale error
+/// signal is worthless, so low latency wins); every other type uses the reliable DATA_CHANNEL
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum MessageType {
     NodeRequest,      // Request to borrow a node
     NodeResponse,     // Response with node data
     MemoryShare,      // Share memory capsule directly
+    CapsuleRequest,   // Pull a specific memory capsule from a peer
     CollaborativeLearn, // Invite to collaborative learning session
     ErrorPropagate,   // Forward error signals for distributed learning
     HeartBeat,        // Keep-alive and status updates
     Discovery,        // Peer discovery and announcement
+    BlockRequest,     // Ask a peer for blocks from an index onwards
+    BlockResponse,    // Blocks served in reply to a BlockRequest
+    MemoryShareReceipt, // Receiver's proof that a shared capsule was stored
+}
+
+impl MessageType {
+    /// Label of the WebRTC data channel this message type is sent on
+    pub fn channel_label(&self) -> &'static str {
+        match self {
+            MessageType::ErrorPropagate => CONTROL_CHANNEL,
+            _ => DATA_CHANNEL,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -126,7 +294,9 @@ pub enum MessagePayload {
         payment_offer: f64,
     },
     NodeResponseData {
-        node_data: String, // Serialized ThresholdGatingNode
+        #[serde(default)]
+        request_id: String, // message_id of the NodeRequest being answered
+        node_data: String, // ThresholdGatingNode::to_json, empty when rejected
         approval_status: bool,
         rental_cost: f64,
         availability_window: (f64, f64),
@@ -136,6 +306,19 @@ pub enum MessagePayload {
         access_level: String,
         sharing_reward: f64,
     },
+    CapsuleRequestData {
+        capsule_id: String,
+    },
+    BlockRequestData {
+        from_index: u64,
+    },
+    BlockResponseData {
+        blocks_json: String, // Serialized Vec<Block>
+    },
+    MemoryShareReceiptData {
+        capsule_id: String,
+        stored_hash: String, // capsule_content_hash of the capsule as stored by the receiver
+    },
     CollaborativeLearnData {
         task_description: String,
         dataset_hash: String,
@@ -152,6 +335,8 @@ pub enum MessagePayload {
         device_status: String,
         available_resources: HashMap<String, f64>,
         recent_activities: Vec<String>,
+        #[serde(default)]
+        echo_timestamp: Option<f64>, // Sender timestamp of the ping this heartbeat answers
     },
     DiscoveryData {
         device_info: PeerInfo,
@@ -159,6 +344,171 @@ pub enum MessagePayload {
     },
 }
 
+/// A multi-hop route learned from a peer's discovery message
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RouteEntry {
+    pub path: Vec<String>, // Hops to the destination, starting with the advertising peer
+    pub updated_at: f64, // When a discovery message last advertised this route
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TopologySnapshot {
+    pub timestamp: f64,
+    pub source_peer: String, // Peer whose discovery message triggered the snapshot
+    pub known_peers: Vec<String>,
+    pub routing_table: HashMap<String, Vec<String>>,
+}
+
+/// A memory share sent to a peer that hasn't been acknowledged with a receipt yet
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingShare {
+    pub content_hash: String,
+    pub reward: f64,
+}
+
+/// A memory share the receiving peer proved it stored
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConfirmedShare {
+    pub peer_id: String,
+    pub capsule_id: String,
+    pub stored_hash: String,
+    pub reward: f64,
+}
+
+/// An approved request from a peer to borrow one of this device's nodes
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NodeLendRequest {
+    pub peer_id: String,
+    pub request_id: String,
+    pub node_type: String,
+    pub duration_minutes: u32,
+    pub payment_offer: f64,
+}
+
+/// A node lent to this device in answer to one of its requests
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BorrowedNode {
+    pub peer_id: String,
+    pub node_type: String,
+    pub node_data: String, // ThresholdGatingNode::to_json
+    pub rental_cost: f64,
+    pub availability_window: (f64, f64),
+}
+
+/// A collaborative learning invitation this device agreed to train under
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AcceptedCollaboration {
+    pub initiator: String,
+    pub task_description: String,
+    pub learning_parameters: HashMap<String, f64>,
+}
+
+// Hash of the capsule content a receipt vouches for; timestamps are excluded because
+// receivers rebase them onto their own clock before storing
+fn capsule_content_hash(capsule: &MemoryCapsule) -> String {
+    let content = serde_json::to_string(&(
+        &capsule.capsule_id,
+        &capsule.context_vector,
+        &capsule.semantic_tags,
+        &capsule.compressed_data,
+    )).unwrap_or_default();
+    crate::utils::sha256_hex(content.as_bytes())
+}
+
+// What a receipt signs: the receiving device, the sender it acknowledges, the capsule, and the stored content
+fn receipt_payload(receiver: &str, sender: &str, capsule_id: &str, stored_hash: &str) -> String {
+    serde_json::to_string(&("share_receipt", receiver, sender, capsule_id, stored_hash)).unwrap_or_default()
+}
+
+/// How far in the future an announcement timestamp may be before it is rejected
+const ANNOUNCEMENT_CLOCK_SKEW_MS: f64 = 60_000.0;
+
+/// How many delivered message ids are remembered for replay rejection
+const SEEN_MESSAGE_CAPACITY: usize = 1000;
+
+/// How far a message's timestamp, on our clock, may be from now before it is rejected.
+/// Older messages would otherwise be replayable once their id leaves the seen set.
+const MESSAGE_CLOCK_SKEW_MS: f64 = 300_000.0;
+
+/// How many unanswered pings are remembered per peer; older ones are forgotten first
+const MAX_OUTSTANDING_PINGS: usize = 8;
+
+/// Weight given to each new round-trip sample in the running clock offset estimate
+const CLOCK_OFFSET_SMOOTHING: f64 = 0.25;
+
+/// Record of a shared capsule that failed validation, kept for moderation instead of silently dropped
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QuarantinedCapsule {
+    pub capsule_id: String,
+    pub source_device: String,
+    pub reason: String,
+    pub timestamp: f64,
+    pub content_hash: String, // capsule_content_hash of the rejected capsule; the capsule itself is dropped
+}
+
+/// Credits and learning parameters used when making offers to peers
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EconomicsPolicy {
+    pub node_payment_offer: f64,       // Offered per direct node request
+    pub memory_sharing_reward: f64,    // Advertised with each memory share
+    pub collaboration_reward: f64,     // Promised to each collaborative learning invitee
+    pub learning_parameters: HashMap<String, f64>,
+}
+
+impl Default for EconomicsPolicy {
+    fn default() -> Self {
+        let mut learning_parameters = HashMap::new();
+        learning_parameters.insert("learning_rate".to_string(), 0.01);
+        learning_parameters.insert("batch_size".to_string(), 32.0);
+        learning_parameters.insert("epochs".to_string(), 10.0);
+        
+        EconomicsPolicy {
+            node_payment_offer: 5.0,
+            memory_sharing_reward: 2.0,
+            collaboration_reward: 10.0,
+            learning_parameters,
+        }
+    }
+}
+
+/// A collaborative learning session and what each invitee actually contributed
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CollaborationSession {
+    pub session_id: String,
+    pub task_description: String,
+    pub invitees: Vec<String>,
+    pub reward_pool: f64, // Total credits promised across invitees
+    pub contributions: HashMap<String, ParticipantContribution>,
+    pub final_loss: Option<f64>,
+    pub started_at: f64,
+}
+
+/// Record of a completed collaborative learning session
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CollaborationOutcome {
+    pub session_id: String,
+    pub task_description: String,
+    pub participants: Vec<String>, // Invitees that contributed
+    pub final_loss: Option<f64>,
+    pub rewards: HashMap<String, f64>,
+    pub total_rewards: f64,
+    pub started_at: f64,
+    pub duration_ms: f64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ParticipantContribution {
+    pub sample_count: u32,
+    pub loss_improvement: f64, // Reduction in aggregated loss attributed to this participant
+}
+
+impl ParticipantContribution {
+    // Samples weighted by how much they improved the aggregated loss
+    fn score(&self) -> f64 {
+        self.sample_count as f64 * (1.0 + self.loss_improvement.max(0.0))
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DiscoveryProtocol {
     pub discovery_interval: f64, // milliseconds
@@ -167,18 +517,156 @@ pub struct DiscoveryProtocol {
     pub discovery_radius: u8, // how many hops to search
 }
 
-#[derive(Clone)]
+/// One piece of an application-level message that was too large to send in one frame:
+/// {"type": "chunk", "data": {"message_id", "chunk_index", "total_chunks", "payload"}}
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignalingChunk {
+    pub message_id: String,
+    pub chunk_index: usize,
+    pub total_chunks: usize,
+    pub payload: String,
+}
+
+/// Most chunks a single message may be split into
+pub const MAX_CHUNKS_PER_MESSAGE: usize = 1024;
+/// Most bytes a reassembler buffers across its incomplete messages
+pub const MAX_PENDING_CHUNK_BYTES: usize = 16 * 1024 * 1024;
+/// Incomplete messages are dropped once their first chunk is this old
+pub const CHUNK_REASSEMBLY_TIMEOUT_MS: f64 = 30_000.0;
+
+#[derive(Clone, Debug)]
+struct PendingChunks {
+    parts: Vec<Option<String>>,
+    started_at: f64,
+    bytes: usize, // Payload bytes plus the parts table, counted against MAX_PENDING_CHUNK_BYTES
+}
+
+/// Buffers chunked signaling messages keyed by message id until every piece has arrived.
+/// Each connection has its own reassembler, so its byte cap bounds what one peer can make us hold.
+#[derive(Clone, Debug, Default)]
+pub struct ChunkReassembler {
+    pending: HashMap<String, PendingChunks>,
+    pending_bytes: usize,
+}
+
+impl ChunkReassembler {
+    /// Returns the complete message text, either passed through unchanged or reassembled
+    /// from its chunks, or None while chunks are still outstanding
+    pub fn accept(&mut self, raw: &str) -> Option<String> {
+        self.accept_at(raw, now_ms())
+    }
+
+    /// `accept` with an explicit clock, used to expire stale messages
+    pub fn accept_at(&mut self, raw: &str, now: f64) -> Option<String> {
+        let chunk = match serde_json::from_str::<serde_json::Value>(raw) {
+            Ok(value) if value.get("type").and_then(|t| t.as_str()) == Some("chunk") => {
+                match value.get("data").cloned().map(serde_json::from_value::<SignalingChunk>) {
+                    Some(Ok(chunk)) => chunk,
+                    _ => {
+                        console_log!("❌ Dropping malformed signaling chunk");
+                        return None;
+                    }
+                }
+            },
+            _ => return Some(raw.to_string()),
+        };
+
+        self.evict_expired(now);
+
+        if chunk.total_chunks == 0 || chunk.total_chunks > MAX_CHUNKS_PER_MESSAGE || chunk.chunk_index >= chunk.total_chunks {
+            console_log!("❌ Dropping out-of-range chunk {} of message {}", chunk.chunk_index, chunk.message_id);
+            return None;
+        }
+
+        let table_bytes = chunk.total_chunks * std::mem::size_of::<Option<String>>();
+        let is_new = !self.pending.contains_key(&chunk.message_id);
+        let replaced_bytes = self.pending.get(&chunk.message_id)
+            .and_then(|entry| entry.parts.get(chunk.chunk_index))
+            .and_then(|part| part.as_ref())
+            .map_or(0, |part| part.len());
+        let added_bytes = chunk.payload.len() + if is_new { table_bytes } else { 0 };
+        if self.pending_bytes - replaced_bytes + added_bytes > MAX_PENDING_CHUNK_BYTES {
+            console_log!("❌ Dropping chunk of message {}: reassembly buffer full", chunk.message_id);
+            return None;
+        }
+
+        let entry = self.pending
+            .entry(chunk.message_id.clone())
+            .or_insert_with(|| PendingChunks { parts: vec![None; chunk.total_chunks], started_at: now, bytes: table_bytes });
+        if entry.parts.len() != chunk.total_chunks {
+            console_log!("❌ Chunk count mismatch for message {}", chunk.message_id);
+            if is_new {
+                self.pending.remove(&chunk.message_id);
+            }
+            return None;
+        }
+        entry.bytes = entry.bytes - replaced_bytes + chunk.payload.len();
+        entry.parts[chunk.chunk_index] = Some(chunk.payload);
+        self.pending_bytes = self.pending_bytes - replaced_bytes + added_bytes;
+
+        if entry.parts.iter().all(|part| part.is_some()) {
+            let entry = self.pending.remove(&chunk.message_id)?;
+            self.pending_bytes -= entry.bytes;
+            Some(entry.parts.into_iter().flatten().collect())
+        } else {
+            None
+        }
+    }
+
+    // Drop incomplete messages whose first chunk arrived more than the timeout ago
+    fn evict_expired(&mut self, now: f64) {
+        let pending_bytes = &mut self.pending_bytes;
+        self.pending.retain(|message_id, entry| {
+            let fresh = now - entry.started_at <= CHUNK_REASSEMBLY_TIMEOUT_MS;
+            if !fresh {
+                console_log!("⌛ Dropping incomplete message {} after reassembly timeout", message_id);
+                *pending_bytes -= entry.bytes;
+            }
+            fresh
+        });
+    }
+
+    /// Bytes currently buffered for incomplete messages
+    pub fn pending_bytes(&self) -> usize {
+        self.pending_bytes
+    }
+
+    pub fn pending_messages(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+// Handlers for the current WebSocket; dropping this frees them
 struct WebSocketCallbacks {
-    // We'll store callback handles here
+    _onopen: Closure<dyn FnMut(web_sys::Event)>,
+    _onmessage: Closure<dyn FnMut(MessageEvent)>,
+    _onclose: Closure<dyn FnMut(CloseEvent)>,
+    _onerror: Closure<dyn FnMut(ErrorEvent)>,
+    _onicecandidate: Closure<dyn FnMut(String, String)>, // Forwards local ICE candidates as webrtc_signal messages
 }
 
 #[wasm_bindgen]
 impl P2PNetwork {
     #[wasm_bindgen(constructor)]
     pub fn new(device_id: String) -> P2PNetwork {
+        Self::with_signing_key(device_id, SigningKey::from_bytes(&rand::random::<[u8; 32]>()))
+    }
+
+    /// Like `new`, but keeping the identity of an earlier session: `identity_key` is the 32-byte
+    /// secret from export_identity_key, so peers that pinned its public key still accept this device
+    #[wasm_bindgen]
+    pub fn new_with_identity_key(device_id: String, identity_key: &[u8]) -> Result<P2PNetwork, DnnError> {
+        let secret = <[u8; 32]>::try_from(identity_key)
+            .map_err(|_| DnnError::InvalidInput(format!("identity key must be 32 bytes, got {}", identity_key.len())))?;
+        Ok(Self::with_signing_key(device_id, SigningKey::from_bytes(&secret)))
+    }
+
+    fn with_signing_key(device_id: String, signing_key: SigningKey) -> P2PNetwork {
         console_log!("Initializing P2P network for device: {}", device_id);
         
         let webrtc_manager = WebRTCManager::new(device_id.clone());
+        let mut peer_keys = HashMap::new();
+        peer_keys.insert(device_id.clone(), crate::utils::hex_encode(signing_key.verifying_key().as_bytes()));
         
         P2PNetwork {
             device_id: device_id.clone(),
@@ -195,11 +683,41 @@ impl P2PNetwork {
                 discovery_radius: 3,
             },
             routing_table: HashMap::new(),
+            route_ttl_ms: 600_000.0, // 10 minutes
+            max_routes: 256,
+            capsule_store: HashMap::new(),
+            topology_history: VecDeque::new(),
+            topology_history_enabled: false,
+            topology_history_capacity: 100,
+            collaboration_sessions: HashMap::new(),
+            collaboration_history: VecDeque::new(),
+            collaboration_history_capacity: 50,
+            simulated_peers: None,
+            economics_policy: EconomicsPolicy::default(),
+            quarantine: VecDeque::new(),
+            quarantine_capacity: 100,
+            quarantine_penalty: 0.1,
+            block_requests: Vec::new(),
+            received_blocks: Vec::new(),
+            accepted_collaborations: Vec::new(),
+            pending_node_requests: HashMap::new(),
+            node_lend_requests: Vec::new(),
+            borrowed_nodes: Vec::new(),
+            pending_shares: HashMap::new(),
+            confirmed_shares: Vec::new(),
+            peer_clock_offsets: HashMap::new(),
+            outstanding_pings: HashMap::new(),
+            seen_message_ids: VecDeque::new(),
+            seen_message_set: HashSet::new(),
+            signing_key,
+            peer_keys,
             signaling_server_url: "ws://localhost:8080".to_string(),
             is_connected_to_server: false,
             webrtc_manager: Some(webrtc_manager),
             websocket: None,
             websocket_callbacks: None,
+            #[cfg(test)]
+            test_outbox: None,
         }
     }
 
@@ -207,10 +725,8 @@ impl P2PNetwork {
     pub fn configure_signaling_server(&mut self, server_url: String) -> bool {
         console_log!("Connecting to real signaling server: {}", server_url);
         
-        // Close existing WebSocket if any
-        if let Some(ref ws) = self.websocket {
-            ws.close().ok();
-        }
+        // Close existing WebSocket if any, so its handlers don't also process messages
+        self.detach_websocket();
         
         // Create new WebSocket connection
         match WebSocket::new(&server_url) {
@@ -237,34 +753,43 @@ impl P2PNetwork {
         // OnOpen handler
         let device_id_clone = device_id.clone();
         let ws_for_registration = ws.clone();
+        let signing_key = self.signing_key.clone();
         let onopen = Closure::wrap(Box::new(move |_event: web_sys::Event| {
             console_log!("✅ Connected to signaling server");
             
-            // Register with the server including node status
+            // Register with the server including node status, signed so peers can detect tampering
+            let now = now_ms();
+            let mut peer_info = PeerInfo {
+                device_id: device_id_clone.clone(),
+                ip_address: "browser_client".to_string(),
+                port: 0,
+                public_key: String::new(), // Filled in by sign_announcement
+                capabilities: ["memory_sharing", "collaborative_learning", "webrtc_p2p", "neural_processing"]
+                    .iter().map(|c| c.to_string()).collect(),
+                reputation_score: 1.0,
+                last_seen: now,
+                cluster_specializations: vec!["general".to_string(), "browser_based".to_string()],
+                node_status: NodeStatus {
+                    is_processing: false,
+                    active_queries: 0,
+                    last_activity: now,
+                    processing_load: 0.0,
+                    is_available: true,
+                },
+                active_connections: 0,
+                cpu_usage: 0.2, // Simulated low usage for browser
+                memory_usage: 0.3, // Simulated low usage for browser
+                available_nodes: 5, // Number of neural nodes available for processing
+                provisional: false,
+                announced_at: now,
+                announcement_signature: String::new(),
+            };
+            peer_info.sign_announcement(&signing_key);
             let registration_message = serde_json::json!({
                 "type": "register",
                 "data": {
                     "device_id": device_id_clone,
-                    "peer_info": {
-                        "device_id": device_id_clone,
-                        "ip_address": "browser_client",
-                        "port": 0,
-                        "public_key": format!("{}_public_key", device_id_clone),
-                        "capabilities": ["memory_sharing", "collaborative_learning", "webrtc_p2p", "neural_processing"],
-                        "reputation_score": 1.0,
-                        "cluster_specializations": ["general", "browser_based"],
-                        "node_status": {
-                            "is_processing": false,
-                            "active_queries": 0,
-                            "last_activity": js_sys::Date::now(),
-                            "processing_load": 0.0,
-                            "is_available": true
-                        },
-                        "active_connections": 0,
-                        "cpu_usage": 0.2, // Simulated low usage for browser
-                        "memory_usage": 0.3, // Simulated low usage for browser  
-                        "available_nodes": 5 // Number of neural nodes available for processing
-                    }
+                    "peer_info": peer_info
                 }
             });
             
@@ -279,13 +804,17 @@ impl P2PNetwork {
         }) as Box<dyn FnMut(web_sys::Event)>);
         
         ws.set_onopen(Some(onopen.as_ref().unchecked_ref()));
-        onopen.forget();
         
         // OnMessage handler - use a separate WebSocket clone
         let ws_for_discovery = ws.clone();
+        let mut reassembler = ChunkReassembler::default();
         let onmessage = Closure::wrap(Box::new(move |event: MessageEvent| {
             if let Ok(text) = event.data().dyn_into::<js_sys::JsString>() {
-                let message_str = text.as_string().unwrap_or_default();
+                // Large messages may arrive in chunks; wait until the full message is buffered
+                let message_str = match reassembler.accept(&text.as_string().unwrap_or_default()) {
+                    Some(message_str) => message_str,
+                    None => return,
+                };
                 console_log!("📨 Received signaling message: {}", message_str);
                 
                 // Parse and handle the message
@@ -402,7 +931,6 @@ impl P2PNetwork {
         }) as Box<dyn FnMut(MessageEvent)>);
         
         ws.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
-        onmessage.forget();
         
         // OnClose handler
         let onclose = Closure::wrap(Box::new(move |_event: CloseEvent| {
@@ -410,7 +938,6 @@ impl P2PNetwork {
         }) as Box<dyn FnMut(CloseEvent)>);
         
         ws.set_onclose(Some(onclose.as_ref().unchecked_ref()));
-        onclose.forget();
         
         // OnError handler
         let onerror = Closure::wrap(Box::new(move |_event: ErrorEvent| {
@@ -418,11 +945,60 @@ impl P2PNetwork {
         }) as Box<dyn FnMut(ErrorEvent)>);
         
         ws.set_onerror(Some(onerror.as_ref().unchecked_ref()));
-        onerror.forget();
+        
+        // Relay local ICE candidates to the remote peer through the signaling server
+        let ws_for_ice = ws.clone();
+        let onicecandidate = Closure::wrap(Box::new(move |peer_id: String, candidate_json: String| {
+            let signal = serde_json::json!({
+                "type": "webrtc_signal",
+                "target_device_id": peer_id,
+                "signaling_data": {
+                    "type": "ice_candidate",
+                    "candidate": candidate_json
+                }
+            });
+            if let Err(e) = ws_for_ice.send_with_str(&signal.to_string()) {
+                console_log!("❌ Failed to forward ICE candidate to {}: {:?}", peer_id, e);
+            }
+        }) as Box<dyn FnMut(String, String)>);
+        if let Some(ref mut webrtc_manager) = self.webrtc_manager {
+            webrtc_manager.set_ice_candidate_callback(onicecandidate.as_ref().unchecked_ref::<js_sys::Function>().clone());
+        }
+        
+        self.websocket_callbacks = Some(Rc::new(WebSocketCallbacks {
+            _onopen: onopen,
+            _onmessage: onmessage,
+            _onclose: onclose,
+            _onerror: onerror,
+            _onicecandidate: onicecandidate,
+        }));
         
         self.is_connected_to_server = true;
     }
 
+    // Unhook and close the current WebSocket before its callbacks are dropped
+    fn detach_websocket(&mut self) {
+        if let Some(ws) = self.websocket.take() {
+            ws.set_onopen(None);
+            ws.set_onmessage(None);
+            ws.set_onclose(None);
+            ws.set_onerror(None);
+            ws.close().ok();
+        }
+        // The ICE forwarder is freed with the socket's callbacks, so stop the manager calling it
+        if let Some(ref mut webrtc_manager) = self.webrtc_manager {
+            webrtc_manager.clear_ice_candidate_callback();
+        }
+        self.websocket_callbacks = None;
+        self.is_connected_to_server = false;
+    }
+
+    #[wasm_bindgen]
+    pub fn disconnect_signaling_server(&mut self) {
+        console_log!("Disconnecting from signaling server: {}", self.signaling_server_url);
+        self.detach_websocket();
+    }
+
     fn send_websocket_message(&self, message: serde_json::Value) -> bool {
         if let Some(ref ws) = self.websocket {
             if let Ok(message_str) = serde_json::to_string(&message) {
@@ -455,6 +1031,14 @@ impl P2PNetwork {
                 Ok(_) => {
                     console_log!("Created peer connection for: {}", target_device_id);
                     
+                    // Reliable channel for capsules and blocks, unreliable one for error signals
+                    let channels = webrtc_manager.create_data_channel(&target_device_id, DATA_CHANNEL, true, None)
+                        .and_then(|_| webrtc_manager.create_data_channel(&target_device_id, CONTROL_CHANNEL, false, Some(0)));
+                    if let Err(e) = channels {
+                        console_log!("Failed to create data channels for {}: {:?}", target_device_id, e);
+                        return false;
+                    }
+                    
                     // Create offer
                     match webrtc_manager.create_offer(&target_device_id).await {
                         Ok(offer_json) => {
@@ -474,7 +1058,7 @@ impl P2PNetwork {
                                 peer_id: target_device_id.clone(),
                                 connection_type: ConnectionType::WebRTC,
                                 status: ConnectionStatus::Connecting,
-                                established_time: js_sys::Date::now(),
+                                established_time: now_ms(),
                                 bandwidth_usage: 0.0,
                                 latency_ms: 0.0,
                                 encryption_key: "webrtc_dtls_key".to_string(),
@@ -527,7 +1111,7 @@ impl P2PNetwork {
                                 peer_id: peer_id.clone(),
                                 connection_type: ConnectionType::WebRTC,
                                 status: ConnectionStatus::Connecting,
-                                established_time: js_sys::Date::now(),
+                                established_time: now_ms(),
                                 bandwidth_usage: 0.0,
                                 latency_ms: 0.0,
                                 encryption_key: "webrtc_dtls_key".to_string(),
@@ -559,12 +1143,8 @@ impl P2PNetwork {
         if let Some(ref mut webrtc_manager) = self.webrtc_manager {
             match webrtc_manager.set_remote_answer(&peer_id, &answer_json).await {
                 Ok(_) => {
+                    // Connection stays Connecting until the data channel actually opens
                     console_log!("Successfully set remote answer for: {}", peer_id);
-                    
-                    // Update connection status to established
-                    if let Some(connection) = self.active_connections.get_mut(&peer_id) {
-                        connection.status = ConnectionStatus::Established;
-                    }
                     true
                 },
                 Err(e) => {
@@ -577,6 +1157,30 @@ impl P2PNetwork {
         }
     }
 
+    /// Refresh a connection's latency_ms and bandwidth_usage (total bytes exchanged)
+    /// from the browser's WebRTC stats
+    #[wasm_bindgen]
+    pub async fn refresh_connection_stats(&mut self, peer_id: String) -> bool {
+        let summary = match self.webrtc_manager {
+            Some(ref webrtc_manager) => webrtc_manager.collect_rtc_stats(&peer_id).await,
+            None => return false,
+        };
+        match (summary, self.active_connections.get_mut(&peer_id)) {
+            (Ok(summary), Some(connection)) => {
+                if let Some(rtt) = summary.round_trip_time_ms {
+                    connection.latency_ms = rtt;
+                }
+                connection.bandwidth_usage = summary.bytes_sent + summary.bytes_received;
+                true
+            },
+            (Err(e), _) => {
+                console_log!("Failed to read WebRTC stats for {}: {:?}", peer_id, e);
+                false
+            },
+            (Ok(_), None) => false,
+        }
+    }
+
     #[wasm_bindgen]
     pub async fn handle_ice_candidate(&mut self, peer_id: String, candidate_json: String) -> bool {
         console_log!("Handling ICE candidate from: {}", peer_id);
@@ -597,6 +1201,7 @@ impl P2PNetwork {
         }
     }
 
+    #[allow(dead_code)]
     fn send_signaling_message(&self, message_type: &str, data: serde_json::Value) {
         // In a real implementation, this would send via WebSocket to signaling server
         console_log!("Sending signaling message: {} - {:?}", message_type, data);
@@ -619,11 +1224,17 @@ impl P2PNetwork {
     }
 
     fn send_direct_message(&self, peer_id: String, message: P2PMessage) -> bool {
+        #[cfg(test)]
+        if let Some(ref outbox) = self.test_outbox {
+            outbox.borrow_mut().push(message);
+            return true;
+        }
+        
         if let Some(ref webrtc_manager) = self.webrtc_manager {
             if webrtc_manager.is_connected(&peer_id) {
                 // Send message via WebRTC data channel
                 let message_json = serde_json::to_string(&message).unwrap_or_default();
-                match webrtc_manager.send_data(&peer_id, &message_json) {
+                match webrtc_manager.send_on_channel(&peer_id, message.message_type.channel_label(), &message_json) {
                     Ok(_) => {
                         console_log!("Sent P2P message via WebRTC to: {}", peer_id);
                         return true;
@@ -637,8 +1248,8 @@ impl P2PNetwork {
         
         // Try to find a route through intermediate peers
         if let Some(route) = self.routing_table.get(&peer_id) {
-            if !route.is_empty() {
-                console_log!("Routing message to {} via {}", peer_id, route[0]);
+            if !route.path.is_empty() {
+                console_log!("Routing message to {} via {}", peer_id, route.path[0]);
                 return true;
             }
         }
@@ -647,6 +1258,18 @@ impl P2PNetwork {
         false
     }
 
+    /// TURN relay used by connections created from now on
+    #[wasm_bindgen]
+    pub fn add_turn_server(&mut self, url: String, username: String, credential: String) -> bool {
+        match self.webrtc_manager {
+            Some(ref mut webrtc_manager) => {
+                webrtc_manager.add_turn_server(url, username, credential);
+                true
+            },
+            None => false,
+        }
+    }
+
     #[wasm_bindgen]
     pub fn get_webrtc_stats(&self) -> String {
         if let Some(ref webrtc_manager) = self.webrtc_manager {
@@ -686,8 +1309,140 @@ impl P2PNetwork {
         }
     }
 
+    /// Peers whose negotiation is still in Connecting state, with age in milliseconds
+    #[wasm_bindgen]
+    pub fn get_pending_connections(&self) -> String {
+        let now = now_ms();
+        let mut pending: Vec<serde_json::Value> = self.active_connections.values()
+            .filter(|conn| conn.status == ConnectionStatus::Connecting)
+            .map(|conn| serde_json::json!({
+                "peer_id": conn.peer_id,
+                "connection_type": conn.connection_type,
+                "age_ms": now - conn.established_time,
+            }))
+            .collect();
+        pending.sort_by(|a, b| a["peer_id"].as_str().cmp(&b["peer_id"].as_str()));
+
+        serde_json::to_string(&pending).unwrap_or_default()
+    }
+
+    /// Tear down a half-open negotiation; established connections must use close_peer_connection
+    #[wasm_bindgen]
+    pub fn abort_connection(&mut self, peer_id: &str) -> Result<(), DnnError> {
+        let is_pending = self.active_connections.get(peer_id)
+            .map(|conn| conn.status == ConnectionStatus::Connecting)
+            .unwrap_or(false);
+
+        if !is_pending {
+            return Err(DnnError::NotFound(format!("pending negotiation with {}", peer_id)));
+        }
+
+        if let Some(ref mut webrtc_manager) = self.webrtc_manager {
+            if let Err(e) = webrtc_manager.close_connection(peer_id) {
+                console_log!("Failed to tear down negotiation with {}: {:?}", peer_id, e);
+            }
+        }
+
+        self.active_connections.remove(peer_id);
+        console_log!("Aborted pending negotiation with: {}", peer_id);
+        Ok(())
+    }
+
+    /// Ping a peer; its reply updates the clock offset estimate used to normalize its timestamps
+    #[wasm_bindgen]
+    pub fn ping_peer(&mut self, peer_id: String) -> bool {
+        let ping = self.build_heartbeat_message(&peer_id, "ping", None);
+        let sent_at = ping.timestamp;
+        if !self.send_direct_message(peer_id.clone(), ping) {
+            return false;
+        }
+        self.record_outstanding_ping(&peer_id, sent_at);
+        true
+    }
+
+    /// Estimated milliseconds the peer's clock runs ahead of ours (0 until a heartbeat round-trip)
+    #[wasm_bindgen]
+    pub fn get_peer_clock_offset(&self, peer_id: &str) -> f64 {
+        self.peer_clock_offsets.get(peer_id).copied().unwrap_or(0.0)
+    }
+
+    /// This device's hex-encoded ed25519 verifying key
+    #[wasm_bindgen]
+    pub fn get_public_key(&self) -> String {
+        crate::utils::hex_encode(self.signing_key.verifying_key().as_bytes())
+    }
+
+    /// device_id -> hex public key pinned from each device's first accepted announcement, this device included
+    pub(crate) fn pinned_peer_keys(&self) -> &HashMap<String, String> {
+        &self.peer_keys
+    }
+
+    /// This device's 32-byte ed25519 secret key, to persist and hand back to new_with_identity_key.
+    /// Keep it private: anyone holding it can sign announcements and receipts as this device.
+    #[wasm_bindgen]
+    pub fn export_identity_key(&self) -> Vec<u8> {
+        self.signing_key.to_bytes().to_vec()
+    }
+
+    /// Sign a PeerInfo JSON announcement as this device, returning it with `device_id`, `public_key`,
+    /// `announced_at` and `announcement_signature` filled in, or "" if it does not parse
+    #[wasm_bindgen]
+    pub fn sign_peer_announcement(&self, peer_json: &str) -> String {
+        match serde_json::from_str::<PeerInfo>(peer_json) {
+            Ok(mut peer) => {
+                peer.device_id = self.device_id.clone();
+                if peer.announced_at <= 0.0 {
+                    peer.announced_at = now_ms();
+                }
+                peer.sign_announcement(&self.signing_key);
+                serde_json::to_string(&peer).unwrap_or_default()
+            },
+            Err(_) => String::new(),
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn get_quarantined_capsules(&self) -> String {
+        serde_json::to_string(&self.quarantine).unwrap_or_default()
+    }
+
+    /// Retain records of up to `capacity` quarantined capsules, dropping the oldest first
+    #[wasm_bindgen]
+    pub fn set_quarantine_capacity(&mut self, capacity: usize) {
+        self.quarantine_capacity = capacity;
+        while self.quarantine.len() > capacity {
+            self.quarantine.pop_front();
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn set_quarantine_penalty(&mut self, penalty: f64) {
+        self.quarantine_penalty = penalty.max(0.0);
+    }
+
+    /// Serve a fixed set of peers through handle_discovery_results instead of the signaling server
+    #[wasm_bindgen]
+    pub fn enable_discovery_simulation(&mut self, peers_json: &str) -> bool {
+        if !self.handle_discovery_results(peers_json) {
+            return false;
+        }
+        self.simulated_peers = Some(peers_json.to_string());
+        console_log!("🧪 Discovery simulation enabled with {} peers", self.peer_registry.len());
+        true
+    }
+
+    #[wasm_bindgen]
+    pub fn disable_discovery_simulation(&mut self) {
+        self.simulated_peers = None;
+    }
+
     #[wasm_bindgen]
     pub fn start_discovery(&mut self) -> bool {
+        if let Some(peers_json) = self.simulated_peers.clone() {
+            console_log!("🧪 Running simulated peer discovery");
+            return self.handle_discovery_results(&peers_json);
+        }
+        
         if !self.is_connected_to_server {
             console_log!("❌ Cannot start discovery - not connected to signaling server");
             return false;
@@ -708,7 +1463,7 @@ impl P2PNetwork {
         
         if self.send_websocket_message(discovery_message) {
             console_log!("✅ Sent discovery request to signaling server");
-            self.discovery_protocol.last_discovery = js_sys::Date::now();
+            self.discovery_protocol.last_discovery = now_ms();
             true
         } else {
             console_log!("❌ Failed to send discovery request");
@@ -716,6 +1471,7 @@ impl P2PNetwork {
         }
     }
 
+    #[allow(dead_code)]
     fn simulate_server_response(&mut self, response_type: &str, data: serde_json::Value) {
         match response_type {
             "registered" => {
@@ -755,13 +1511,13 @@ impl P2PNetwork {
                 "node_status": {
                     "is_processing": false,
                     "active_queries": 0,
-                    "last_activity": js_sys::Date::now(),
-                    "processing_load": js_sys::Math::random() * 0.2, // 0-20% load
+                    "last_activity": now_ms(),
+                    "processing_load": rand::random::<f64>() * 0.2, // 0-20% load
                     "is_available": true
                 },
                 "available_resources": {
-                    "cpu_usage": 0.1 + js_sys::Math::random() * 0.3, // 10-40% CPU
-                    "memory_usage": 0.2 + js_sys::Math::random() * 0.3, // 20-50% memory
+                    "cpu_usage": 0.1 + rand::random::<f64>() * 0.3, // 10-40% CPU
+                    "memory_usage": 0.2 + rand::random::<f64>() * 0.3, // 20-50% memory
                     "available_nodes": 8
                 },
                 "recent_activities": ["neural_processing", "peer_discovery"],
@@ -779,24 +1535,156 @@ impl P2PNetwork {
         }
     }
 
+    /// Evict routes older than `ttl_ms` and cap the routing table at `max_routes` entries
+    #[wasm_bindgen]
+    pub fn set_routing_table_limits(&mut self, ttl_ms: f64, max_routes: usize) -> bool {
+        if !ttl_ms.is_finite() || ttl_ms <= 0.0 {
+            return false;
+        }
+        self.route_ttl_ms = ttl_ms;
+        self.max_routes = max_routes;
+        self.prune_routing_table();
+        true
+    }
+
+    /// Maintenance pass: drop routes whose destination hasn't been advertised or seen within
+    /// the TTL, then trim to the cap, keeping routes to high-reputation, recently seen peers.
+    /// Returns the number of routes evicted.
+    #[wasm_bindgen]
+    pub fn prune_routing_table(&mut self) -> usize {
+        let now = now_ms();
+        let before = self.routing_table.len();
+        
+        // A route is as fresh as its last advertisement or the last sighting of its destination
+        let freshness = |device_id: &String, route: &RouteEntry, peers: &HashMap<String, PeerInfo>| {
+            peers.get(device_id).map_or(route.updated_at, |peer| route.updated_at.max(peer.last_seen))
+        };
+        let peers = &self.peer_registry;
+        let ttl = self.route_ttl_ms;
+        self.routing_table.retain(|device_id, route| now - freshness(device_id, route, peers) <= ttl);
+        
+        if self.routing_table.len() > self.max_routes {
+            let mut ranked: Vec<(String, f64, f64)> = self.routing_table.iter()
+                .map(|(device_id, route)| {
+                    let reputation = peers.get(device_id).map_or(0.0, |peer| peer.reputation_score);
+                    (device_id.clone(), reputation, freshness(device_id, route, peers))
+                })
+                .collect();
+            ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(b.2.total_cmp(&a.2)));
+            for (device_id, _, _) in ranked.into_iter().skip(self.max_routes) {
+                self.routing_table.remove(&device_id);
+            }
+        }
+        
+        let evicted = before - self.routing_table.len();
+        if evicted > 0 {
+            console_log!("Evicted {} stale routes, {} remain", evicted, self.routing_table.len());
+        }
+        evicted
+    }
+
+    /// Retain up to `capacity` time-stamped topology snapshots, one per discovery round
+    #[wasm_bindgen]
+    pub fn set_topology_history(&mut self, enabled: bool, capacity: usize) {
+        self.topology_history_enabled = enabled;
+        self.topology_history_capacity = capacity;
+        while self.topology_history.len() > capacity {
+            self.topology_history.pop_front();
+        }
+    }
+
+    /// Retained topology snapshots, oldest first
+    #[wasm_bindgen]
+    pub fn get_topology_history(&self) -> String {
+        serde_json::to_string(&self.topology_history).unwrap_or_default()
+    }
+
     #[wasm_bindgen]
     pub fn get_discovered_peers(&self) -> String {
         let peers: Vec<&PeerInfo> = self.peer_registry.values().collect();
         serde_json::to_string(&peers).unwrap_or_default()
     }
 
+    /// Known peers as JSON, for caching across sessions and passing to import_peers
     #[wasm_bindgen]
-    pub fn handle_discovery_results(&mut self, peers_json: &str) -> bool {
-        console_log!("Processing real discovery results: {}", peers_json);
+    pub fn export_peers(&self) -> String {
+        let mut peers: Vec<&PeerInfo> = self.peer_registry.values().collect();
+        peers.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+        serde_json::to_string(&peers).unwrap_or_default()
+    }
+
+    /// Warm-start the registry from an export_peers dump. Imported peers are marked provisional
+    /// until they answer a heartbeat; peers already known are left as they are. Returns the number added.
+    #[wasm_bindgen]
+    pub fn import_peers(&mut self, peers_json: &str) -> usize {
+        let peers = match serde_json::from_str::<Vec<PeerInfo>>(peers_json) {
+            Ok(peers) => peers,
+            Err(e) => {
+                console_log!("Failed to parse imported peers: {:?}", e);
+                return 0;
+            }
+        };
+        
+        let mut imported = 0;
+        for mut peer in peers {
+            if peer.device_id == self.device_id || self.peer_registry.contains_key(&peer.device_id) {
+                continue;
+            }
+            peer.provisional = true;
+            self.peer_registry.insert(peer.device_id.clone(), peer);
+            imported += 1;
+        }
+        
+        console_log!("Imported {} provisional peers", imported);
+        imported
+    }
+
+    /// A page of known peers sorted by "reputation", "last_seen" (both descending),
+    /// "availability" (available and least loaded first) or "device_id", ties broken by device_id
+    #[wasm_bindgen]
+    pub fn get_discovered_peers_paged(&self, offset: usize, limit: usize, sort_by: &str) -> String {
+        let mut peers: Vec<&PeerInfo> = self.peer_registry.values().collect();
+        
+        match sort_by {
+            "reputation" => peers.sort_by(|a, b| b.reputation_score.total_cmp(&a.reputation_score)
+                .then_with(|| a.device_id.cmp(&b.device_id))),
+            "last_seen" => peers.sort_by(|a, b| b.last_seen.total_cmp(&a.last_seen)
+                .then_with(|| a.device_id.cmp(&b.device_id))),
+            "availability" => peers.sort_by(|a, b| b.node_status.is_available.cmp(&a.node_status.is_available)
+                .then_with(|| a.node_status.processing_load.total_cmp(&b.node_status.processing_load))
+                .then_with(|| b.available_nodes.cmp(&a.available_nodes))
+                .then_with(|| a.device_id.cmp(&b.device_id))),
+            "device_id" | "" => peers.sort_by(|a, b| a.device_id.cmp(&b.device_id)),
+            _ => {
+                console_log!("Unknown peer sort order: {}", sort_by);
+                return "".to_string();
+            }
+        }
+        
+        let page: Vec<&PeerInfo> = peers.into_iter().skip(offset).take(limit).collect();
+        serde_json::to_string(&page).unwrap_or_default()
+    }
+
+    #[wasm_bindgen]
+    pub fn handle_discovery_results(&mut self, peers_json: &str) -> bool {
+        console_log!("Processing real discovery results: {}", peers_json);
         
         match serde_json::from_str::<Vec<PeerInfo>>(peers_json) {
             Ok(peers) => {
                 console_log!("✅ Parsed {} real peers from discovery", peers.len());
                 
-                // Clear existing peers and add the new ones
-                self.peer_registry.clear();
+                // Clear existing peers and add the new ones, remembering which announcements
+                // were already accepted so older ones cannot be replayed over them
+                let previous_announcements: HashMap<String, f64> = self.peer_registry.drain()
+                    .map(|(id, peer)| (id, peer.announced_at))
+                    .collect();
                 
                 for peer in peers {
+                    if let Err(reason) = self.check_announcement(&peer, previous_announcements.get(&peer.device_id).copied()) {
+                        console_log!("🚫 Rejecting peer {}: {}", peer.device_id, reason);
+                        continue;
+                    }
+                    
                     console_log!("👤 Adding real peer: {} with capabilities: [{}]", 
                         peer.device_id, peer.capabilities.join(", "));
                     
@@ -831,6 +1719,7 @@ impl P2PNetwork {
                         peer.device_id != self.device_id
                     );
                     
+                    self.peer_keys.entry(peer.device_id.clone()).or_insert_with(|| peer.public_key.clone());
                     self.peer_registry.insert(peer.device_id.clone(), peer);
                 }
                 
@@ -844,11 +1733,79 @@ impl P2PNetwork {
         }
     }
 
+    // A PeerInfo may only enter the registry signed by its own key, the key pinned for its device
+    // if there is one, and no older than the announcement it replaces
+    fn check_announcement(&self, peer: &PeerInfo, previous_announced_at: Option<f64>) -> Result<(), &'static str> {
+        if !peer.has_valid_announcement() {
+            return Err("announcement not signed by its public key");
+        }
+        if self.peer_keys.get(&peer.device_id).is_some_and(|key| *key != peer.public_key) {
+            return Err("signed with a different key than first seen");
+        }
+        if peer.announced_at > now_ms() + ANNOUNCEMENT_CLOCK_SKEW_MS
+            || previous_announced_at.is_some_and(|seen| peer.announced_at < seen) {
+            return Err("stale or future-dated announcement");
+        }
+        Ok(())
+    }
+
+    /// Apply a PeerInfoDelta to a known peer; unknown peers need a full PeerInfo first.
+    /// The delta must carry a newer announcement signed by the peer's pinned key.
+    #[wasm_bindgen]
+    pub fn handle_peer_delta(&mut self, delta_json: &str) -> bool {
+        match serde_json::from_str::<PeerInfoDelta>(delta_json) {
+            Ok(delta) => match self.peer_registry.get_mut(&delta.device_id) {
+                Some(peer) => {
+                    let mut updated = peer.clone();
+                    updated.apply_wire_delta(delta);
+                    let pinned_key = self.peer_keys.get(&updated.device_id).unwrap_or(&peer.public_key);
+                    if updated.announced_at <= peer.announced_at
+                        || updated.announced_at > now_ms() + ANNOUNCEMENT_CLOCK_SKEW_MS
+                        || updated.public_key != *pinned_key
+                        || !updated.has_valid_announcement() {
+                        console_log!("🚫 Rejecting delta for {}: not a newer announcement signed by its key", updated.device_id);
+                        return false;
+                    }
+                    *peer = updated;
+                    true
+                },
+                None => {
+                    console_log!("Received delta for unknown peer {}, waiting for full peer info", delta.device_id);
+                    false
+                }
+            },
+            Err(e) => {
+                console_log!("❌ Failed to parse peer delta: {:?}", e);
+                false
+            }
+        }
+    }
+
     #[wasm_bindgen]
     pub fn is_connected_to_signaling_server(&self) -> bool {
         self.is_connected_to_server && self.websocket.is_some()
     }
 
+    /// Replace the economics policy from JSON; rejects negative amounts
+    #[wasm_bindgen]
+    pub fn set_economics_policy(&mut self, policy_json: &str) -> Result<(), DnnError> {
+        let policy = serde_json::from_str::<EconomicsPolicy>(policy_json)
+            .map_err(|e| DnnError::ParseError(format!("economics policy: {}", e)))?;
+        if policy.node_payment_offer < 0.0
+            || policy.memory_sharing_reward < 0.0
+            || policy.collaboration_reward < 0.0
+        {
+            return Err(DnnError::InvalidInput("economics policy amounts must not be negative".to_string()));
+        }
+        self.economics_policy = policy;
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn get_economics_policy(&self) -> String {
+        serde_json::to_string(&self.economics_policy).unwrap_or_default()
+    }
+
     #[wasm_bindgen]
     pub fn request_node_direct(&mut self, peer_id: String, node_type: String, duration_minutes: u32) -> String {
         console_log!("Requesting node directly from peer: {}", peer_id);
@@ -859,18 +1816,19 @@ impl P2PNetwork {
             to: peer_id.clone(),
             message_type: MessageType::NodeRequest,
             payload: MessagePayload::NodeRequestData {
-                node_type,
+                node_type: node_type.clone(),
                 required_capabilities: vec!["inference".to_string(), "adaptation".to_string()],
                 duration_minutes,
-                payment_offer: 5.0,
+                payment_offer: self.economics_policy.node_payment_offer,
             },
-            timestamp: js_sys::Date::now(),
+            timestamp: now_ms(),
             signature: "request_signature".to_string(),
             hop_count: 0,
         };
 
-        if self.send_direct_message(peer_id, request_msg.clone()) {
-            self.message_queue.push(request_msg.clone());
+        if self.send_direct_message(peer_id.clone(), request_msg.clone()) {
+            // Remembered so only a response to this request can hand us a node
+            self.pending_node_requests.insert(request_msg.message_id.clone(), (peer_id, node_type));
             request_msg.message_id
         } else {
             "".to_string()
@@ -879,27 +1837,157 @@ impl P2PNetwork {
 
     #[wasm_bindgen]
     pub fn share_memory_direct(&mut self, peer_id: String, capsule_json: &str) -> bool {
-        if let Ok(capsule) = serde_json::from_str::<MemoryCapsule>(capsule_json) {
-            console_log!("Sharing memory capsule directly with peer: {}", peer_id);
+        match self.share_memory_direct_at_level(peer_id, capsule_json, "behavioral".to_string()) {
+            Ok(()) => true,
+            Err(e) => {
+                console_log!("Memory share failed: {}", e);
+                false
+            }
+        }
+    }
 
-            let share_msg = P2PMessage {
-                message_id: crate::utils::generate_unique_id("mem_share"),
-                from: self.device_id.clone(),
-                to: peer_id.clone(),
-                message_type: MessageType::MemoryShare,
-                payload: MessagePayload::MemoryShareData {
-                    capsule,
-                    access_level: "behavioral".to_string(),
-                    sharing_reward: 2.0,
+    /// Share a capsule at "behavioral" (summary only) or "full" (includes compressed node states) level
+    #[wasm_bindgen]
+    pub fn share_memory_direct_at_level(&mut self, peer_id: String, capsule_json: &str, access_level: String) -> Result<(), DnnError> {
+        if access_level != "behavioral" && access_level != "full" {
+            return Err(DnnError::InvalidInput(format!("unknown access level: {}", access_level)));
+        }
+        
+        let capsule = serde_json::from_str::<MemoryCapsule>(capsule_json)
+            .map_err(|e| DnnError::ParseError(format!("memory capsule: {}", e)))?;
+        if matches!(capsule.privacy_level, PrivacyLevel::Personal) {
+            return Err(DnnError::Unauthorized(format!("capsule {} is personal", capsule.capsule_id)));
+        }
+        console_log!("Sharing memory capsule directly with peer: {} ({})", peer_id, access_level);
+
+        if self.send_memory_share(peer_id.clone(), capsule, &access_level) {
+            Ok(())
+        } else {
+            Err(DnnError::NotConnected(peer_id))
+        }
+    }
+
+    // Send a capsule and remember what was sent so the peer's receipt can release the reward
+    fn send_memory_share(&mut self, peer_id: String, capsule: MemoryCapsule, access_level: &str) -> bool {
+        let share_msg = self.build_memory_share_message(&peer_id, capsule, access_level);
+        let pending = match &share_msg.payload {
+            MessagePayload::MemoryShareData { capsule, sharing_reward, .. } => Some((
+                capsule.capsule_id.clone(),
+                PendingShare {
+                    content_hash: capsule_content_hash(capsule),
+                    reward: *sharing_reward,
                 },
-                timestamp: js_sys::Date::now(),
-                signature: "share_signature".to_string(),
-                hop_count: 0,
-            };
+            )),
+            _ => None,
+        };
+        
+        if !self.send_direct_message(peer_id.clone(), share_msg) {
+            return false;
+        }
+        if let Some((capsule_id, share)) = pending {
+            self.pending_shares.insert((peer_id, capsule_id), share);
+        }
+        true
+    }
 
-            return self.send_direct_message(peer_id, share_msg);
+    fn build_memory_share_message(&self, peer_id: &str, mut capsule: MemoryCapsule, access_level: &str) -> P2PMessage {
+        // Only full sharing ships the serialized node memories; behavioral peers get the summary fields
+        if access_level != "full" {
+            capsule.compressed_data.clear();
+        }
+        
+        P2PMessage {
+            message_id: crate::utils::generate_unique_id("mem_share"),
+            from: self.device_id.clone(),
+            to: peer_id.to_string(),
+            message_type: MessageType::MemoryShare,
+            payload: MessagePayload::MemoryShareData {
+                capsule,
+                access_level: access_level.to_string(),
+                sharing_reward: self.economics_policy.memory_sharing_reward,
+            },
+            timestamp: now_ms(),
+            signature: "share_signature".to_string(),
+            hop_count: 0,
+        }
+    }
+
+    /// Make a local capsule available to peers that pull it by id
+    #[wasm_bindgen]
+    pub fn publish_capsule(&mut self, capsule_json: &str) -> bool {
+        if let Ok(capsule) = serde_json::from_str::<MemoryCapsule>(capsule_json) {
+            self.capsule_store.insert(capsule.capsule_id.clone(), capsule);
+            true
+        } else {
+            false
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn get_stored_capsule(&self, capsule_id: &str) -> String {
+        if let Some(capsule) = self.capsule_store.get(capsule_id) {
+            serde_json::to_string(capsule).unwrap_or_default()
+        } else {
+            "".to_string()
+        }
+    }
+
+    /// Ask a peer for the blocks it has from `from_index` onwards
+    #[wasm_bindgen]
+    pub fn request_blocks(&mut self, peer_id: String, from_index: u64) -> Result<String, DnnError> {
+        console_log!("Requesting blocks from #{} from peer: {}", from_index, peer_id);
+
+        let request_msg = self.build_block_message(&peer_id, MessageType::BlockRequest,
+            MessagePayload::BlockRequestData { from_index });
+        if self.send_direct_message(peer_id.clone(), request_msg.clone()) {
+            Ok(request_msg.message_id)
+        } else {
+            Err(DnnError::NotConnected(peer_id))
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn send_blocks(&mut self, peer_id: String, blocks_json: String) -> bool {
+        let response_msg = self.build_block_message(&peer_id, MessageType::BlockResponse,
+            MessagePayload::BlockResponseData { blocks_json });
+        self.send_direct_message(peer_id, response_msg)
+    }
+
+    fn build_block_message(&self, peer_id: &str, message_type: MessageType, payload: MessagePayload) -> P2PMessage {
+        P2PMessage {
+            message_id: crate::utils::generate_unique_id("block_sync"),
+            from: self.device_id.clone(),
+            to: peer_id.to_string(),
+            message_type,
+            payload,
+            timestamp: now_ms(),
+            signature: "block_sync_signature".to_string(),
+            hop_count: 0,
+        }
+    }
+
+    /// Ask a peer for a capsule learned about via gossip or the blockchain.
+    /// The peer answers with a MemoryShare message if the capsule may be shared.
+    #[wasm_bindgen]
+    pub fn request_capsule(&mut self, peer_id: String, capsule_id: String) -> String {
+        console_log!("Requesting capsule {} from peer: {}", capsule_id, peer_id);
+
+        let request_msg = P2PMessage {
+            message_id: crate::utils::generate_unique_id("capsule_req"),
+            from: self.device_id.clone(),
+            to: peer_id.clone(),
+            message_type: MessageType::CapsuleRequest,
+            payload: MessagePayload::CapsuleRequestData { capsule_id },
+            timestamp: now_ms(),
+            signature: "capsule_request_signature".to_string(),
+            hop_count: 0,
+        };
+
+        if self.send_direct_message(peer_id, request_msg.clone()) {
+            request_msg.message_id
+        } else {
+            "".to_string()
         }
-        false
     }
 
     #[wasm_bindgen]
@@ -907,6 +1995,15 @@ impl P2PNetwork {
         console_log!("Initiating collaborative learning with {} peers", peer_ids.len());
 
         let session_id = crate::utils::generate_unique_id("collab");
+        self.collaboration_sessions.insert(session_id.clone(), CollaborationSession {
+            session_id: session_id.clone(),
+            task_description: task_description.clone(),
+            invitees: peer_ids.clone(),
+            reward_pool: self.economics_policy.collaboration_reward * peer_ids.len() as f64,
+            contributions: HashMap::new(),
+            final_loss: None,
+            started_at: now_ms(),
+        });
         
         for peer_id in peer_ids {
             let collab_msg = P2PMessage {
@@ -917,20 +2014,14 @@ impl P2PNetwork {
                 payload: MessagePayload::CollaborativeLearnData {
                     task_description: task_description.clone(),
                     dataset_hash: "dataset_hash_placeholder".to_string(),
-                    learning_parameters: {
-                        let mut params = HashMap::new();
-                        params.insert("learning_rate".to_string(), 0.01);
-                        params.insert("batch_size".to_string(), 32.0);
-                        params.insert("epochs".to_string(), 10.0);
-                        params
-                    },
+                    learning_parameters: self.economics_policy.learning_parameters.clone(),
                     participant_rewards: {
                         let mut rewards = HashMap::new();
-                        rewards.insert(peer_id.clone(), 10.0);
+                        rewards.insert(peer_id.clone(), self.economics_policy.collaboration_reward);
                         rewards
                     },
                 },
-                timestamp: js_sys::Date::now(),
+                timestamp: now_ms(),
                 signature: "collab_signature".to_string(),
                 hop_count: 0,
             };
@@ -941,6 +2032,57 @@ impl P2PNetwork {
         session_id
     }
 
+    /// Record a participant's measured contribution (e.g. from aggregation) to a session
+    #[wasm_bindgen]
+    pub fn record_collaboration_contribution(&mut self, session_id: &str, peer_id: String, sample_count: u32, loss_improvement: f64) -> bool {
+        if let Some(session) = self.collaboration_sessions.get_mut(session_id) {
+            if !session.invitees.contains(&peer_id) {
+                return false;
+            }
+            let contribution = session.contributions.entry(peer_id)
+                .or_insert(ParticipantContribution { sample_count: 0, loss_improvement: 0.0 });
+            contribution.sample_count += sample_count;
+            contribution.loss_improvement += loss_improvement;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Record the aggregated loss a session finished with
+    #[wasm_bindgen]
+    pub fn record_collaboration_outcome(&mut self, session_id: &str, final_loss: f64) -> bool {
+        if let Some(session) = self.collaboration_sessions.get_mut(session_id) {
+            session.final_loss = Some(final_loss);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Completed sessions, oldest first
+    #[wasm_bindgen]
+    pub fn get_collaboration_history(&self) -> String {
+        serde_json::to_string(&self.collaboration_history).unwrap_or_default()
+    }
+
+    #[wasm_bindgen]
+    pub fn set_collaboration_history_capacity(&mut self, capacity: usize) {
+        self.collaboration_history_capacity = capacity;
+        while self.collaboration_history.len() > capacity {
+            self.collaboration_history.pop_front();
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn get_collaboration_session(&self, session_id: &str) -> String {
+        if let Some(session) = self.collaboration_sessions.get(session_id) {
+            serde_json::to_string(session).unwrap_or_default()
+        } else {
+            "".to_string()
+        }
+    }
+
     #[wasm_bindgen]
     pub fn propagate_error_signal(&mut self, error_vector: Vec<f64>, urgency: u8) -> u32 {
         console_log!("Propagating error signal to {} connected peers", self.active_connections.len());
@@ -960,7 +2102,7 @@ impl P2PNetwork {
                         propagation_weight: 1.0 / (connection.latency_ms + 1.0),
                         urgency_level: urgency,
                     },
-                    timestamp: js_sys::Date::now(),
+                    timestamp: now_ms(),
                     signature: "error_signature".to_string(),
                     hop_count: 0,
                 };
@@ -974,13 +2116,37 @@ impl P2PNetwork {
         propagated_count
     }
 
+    /// Promote Connecting connections to Established once their data channel has opened
+    #[wasm_bindgen]
+    pub fn sync_connection_states(&mut self) -> u32 {
+        let opened = match self.webrtc_manager {
+            Some(ref webrtc_manager) => webrtc_manager.take_opened_channels(),
+            None => return 0,
+        };
+
+        let mut established_count = 0;
+        for peer_id in opened {
+            if let Some(connection) = self.active_connections.get_mut(&peer_id) {
+                if connection.status == ConnectionStatus::Connecting {
+                    connection.status = ConnectionStatus::Established;
+                    established_count += 1;
+                    console_log!("✅ Connection to {} established (data channel open)", peer_id);
+                }
+            }
+        }
+
+        established_count
+    }
+
     #[wasm_bindgen]
     pub fn process_incoming_messages(&mut self) -> u32 {
+        self.sync_connection_states();
+        
         // In a real implementation, this would be called by the network layer
         // when messages are received from peers
         console_log!("Processing {} queued messages", self.message_queue.len());
 
-        let processed_count = self.message_queue.len();
+        let mut processed_count = self.message_queue.len();
         
         // Clone the messages to avoid borrowing issues
         let messages_to_process = self.message_queue.clone();
@@ -989,6 +2155,22 @@ impl P2PNetwork {
         for message in messages_to_process {
             self.handle_message(message);
         }
+        
+        // Messages that arrived from peers over WebRTC data channels
+        let received = match self.webrtc_manager {
+            Some(ref webrtc_manager) => webrtc_manager.drain_inbox(),
+            None => Vec::new(),
+        };
+        for raw in received {
+            match serde_json::from_str::<P2PMessage>(&raw) {
+                Ok(message) => {
+                    if self.deliver_incoming(message) {
+                        processed_count += 1;
+                    }
+                },
+                Err(e) => console_log!("Ignoring data channel message that is not a P2PMessage: {:?}", e),
+            }
+        }
 
         processed_count as u32
     }
@@ -1007,19 +2189,80 @@ impl P2PNetwork {
         serde_wasm_bindgen::to_value(&stats).unwrap_or(JsValue::NULL)
     }
 
-    fn handle_message(&mut self, message: P2PMessage) {
+    /// Deliver a P2PMessage JSON as if it had arrived from its sender, through the same
+    /// addressing, signature and replay checks as network traffic. Returns whether it was accepted.
+    #[wasm_bindgen]
+    pub fn inject_incoming_message(&mut self, message_json: &str) -> bool {
+        match serde_json::from_str::<P2PMessage>(message_json) {
+            Ok(message) => self.deliver_incoming(message),
+            Err(e) => {
+                console_log!("❌ Failed to parse injected message: {:?}", e);
+                false
+            }
+        }
+    }
+
+    // Entry point for messages from other devices: check, then dispatch to the handlers
+    fn deliver_incoming(&mut self, message: P2PMessage) -> bool {
+        if let Err(reason) = self.check_incoming(&message) {
+            console_log!("🚫 Dropping message {} from {}: {}", message.message_id, message.from, reason);
+            return false;
+        }
+        
+        self.seen_message_ids.push_back(message.message_id.clone());
+        self.seen_message_set.insert(message.message_id.clone());
+        while self.seen_message_ids.len() > SEEN_MESSAGE_CAPACITY {
+            if let Some(expired) = self.seen_message_ids.pop_front() {
+                self.seen_message_set.remove(&expired);
+            }
+        }
+        self.handle_message(message);
+        true
+    }
+
+    fn check_incoming(&self, message: &P2PMessage) -> Result<(), &'static str> {
+        if message.to != self.device_id {
+            return Err("not addressed to this device");
+        }
+        if message.from.is_empty() || message.from == self.device_id {
+            return Err("invalid sender");
+        }
+        // Messages are not signed with peer keys yet, so an unsigned message is all that can be rejected
+        if message.signature.is_empty() {
+            return Err("unsigned");
+        }
+        if message.message_id.is_empty() || self.seen_message_set.contains(&message.message_id) {
+            return Err("missing or replayed message id");
+        }
+        let local_time = self.to_local_time(&message.from, message.timestamp);
+        if !local_time.is_finite() || (local_time - now_ms()).abs() > MESSAGE_CLOCK_SKEW_MS {
+            return Err("timestamp outside the clock skew window");
+        }
+        Ok(())
+    }
+
+    fn handle_message(&mut self, mut message: P2PMessage) {
         console_log!("Handling {} message from {}", 
             format!("{:?}", message.message_type), 
             message.from);
+        
+        // Heartbeats keep the peer's raw clock so they can feed offset estimation
+        if !matches!(message.message_type, MessageType::HeartBeat) {
+            self.normalize_timestamps(&mut message);
+        }
 
         match message.message_type {
             MessageType::NodeRequest => self.handle_node_request(message),
             MessageType::NodeResponse => self.handle_node_response(message),
             MessageType::MemoryShare => self.handle_memory_share(message),
+            MessageType::CapsuleRequest => self.handle_capsule_request(message),
             MessageType::CollaborativeLearn => self.handle_collaborative_learn(message),
             MessageType::ErrorPropagate => self.handle_error_propagate(message),
             MessageType::HeartBeat => self.handle_heartbeat(message),
             MessageType::Discovery => self.handle_discovery(message),
+            MessageType::BlockRequest => self.handle_block_request(message),
+            MessageType::BlockResponse => self.handle_block_response(message),
+            MessageType::MemoryShareReceipt => self.handle_share_receipt(message),
         }
     }
 
@@ -1028,47 +2271,232 @@ impl P2PNetwork {
             console_log!("Received node request for {} type, duration: {} min, payment: {}", 
                 node_type, duration_minutes, payment_offer);
 
-            // In a real implementation, check if we can fulfill the request
             let approval = payment_offer >= 3.0 && duration_minutes <= 60;
 
-            let response = P2PMessage {
-                message_id: crate::utils::generate_unique_id("node_resp"),
-                from: self.device_id.clone(),
-                to: message.from,
-                message_type: MessageType::NodeResponse,
-                payload: MessagePayload::NodeResponseData {
-                    node_data: "serialized_node_data".to_string(),
-                    approval_status: approval,
-                    rental_cost: payment_offer,
-                    availability_window: (js_sys::Date::now(), js_sys::Date::now() + 3600000.0),
-                },
-                timestamp: js_sys::Date::now(),
-                signature: "response_signature".to_string(),
-                hop_count: 0,
-            };
-
-            self.message_queue.push(response);
+            if approval {
+                // The node itself lives in a cluster; the owner serializes it and calls send_node_response
+                self.node_lend_requests.push(NodeLendRequest {
+                    peer_id: message.from,
+                    request_id: message.message_id,
+                    node_type,
+                    duration_minutes,
+                    payment_offer,
+                });
+            } else {
+                self.send_node_response(message.from, message.message_id, String::new(), false, payment_offer);
+            }
         }
     }
 
-    fn handle_node_response(&self, message: P2PMessage) {
-        if let MessagePayload::NodeResponseData { approval_status, rental_cost, .. } = message.payload {
+    /// Answer a NodeRequest; `node_json` is the lent node's ThresholdGatingNode::to_json
+    pub fn send_node_response(&mut self, peer_id: String, request_id: String, node_json: String, approved: bool, rental_cost: f64) -> bool {
+        let now = now_ms();
+        let response = P2PMessage {
+            message_id: crate::utils::generate_unique_id("node_resp"),
+            from: self.device_id.clone(),
+            to: peer_id.clone(),
+            message_type: MessageType::NodeResponse,
+            payload: MessagePayload::NodeResponseData {
+                request_id,
+                node_data: node_json,
+                approval_status: approved,
+                rental_cost,
+                availability_window: (now, now + 3600000.0),
+            },
+            timestamp: now,
+            signature: "response_signature".to_string(),
+            hop_count: 0,
+        };
+        self.send_direct_message(peer_id, response)
+    }
+
+    fn handle_node_response(&mut self, message: P2PMessage) {
+        if let MessagePayload::NodeResponseData { request_id, node_data, approval_status, rental_cost, availability_window } = message.payload {
             console_log!("Received node response: approved={}, cost={}", approval_status, rental_cost);
-            // Handle the response to our node request
+            
+            let node_type = match self.pending_node_requests.get(&request_id) {
+                Some((peer_id, node_type)) if *peer_id == message.from => node_type.clone(),
+                _ => {
+                    console_log!("Ignoring node response from {} to unknown request {}", message.from, request_id);
+                    return;
+                }
+            };
+            self.pending_node_requests.remove(&request_id);
+            
+            if approval_status && !node_data.is_empty() {
+                self.borrowed_nodes.push(BorrowedNode {
+                    peer_id: message.from,
+                    node_type,
+                    node_data,
+                    rental_cost,
+                    availability_window,
+                });
+            }
         }
     }
 
-    fn handle_memory_share(&self, message: P2PMessage) {
+    fn handle_memory_share(&mut self, message: P2PMessage) {
         if let MessagePayload::MemoryShareData { capsule, sharing_reward, .. } = message.payload {
             console_log!("Received memory capsule: {}, reward: {}", capsule.capsule_id, sharing_reward);
-            // Process the shared memory capsule
+            
+            if let Some(reason) = Self::provenance_failure(&message.signature, &capsule) {
+                self.quarantine_capsule(&capsule, &message.from, reason);
+                return;
+            }
+            // A share may add a capsule, never overwrite one already held
+            if self.capsule_store.contains_key(&capsule.capsule_id) {
+                console_log!("Ignoring share of {} from {}: capsule id already stored", capsule.capsule_id, message.from);
+                return;
+            }
+            
+            // Acknowledge what was stored so the sender can claim its sharing reward
+            let receipt = self.build_share_receipt(&message.from, &capsule);
+            self.send_direct_message(message.from.clone(), receipt);
+            
+            // Keep the shared capsule so it can be read back and re-served to other peers
+            self.capsule_store.insert(capsule.capsule_id.clone(), capsule);
+        }
+    }
+
+    fn build_share_receipt(&self, sender: &str, capsule: &MemoryCapsule) -> P2PMessage {
+        let stored_hash = capsule_content_hash(capsule);
+        P2PMessage {
+            message_id: crate::utils::generate_unique_id("share_receipt"),
+            from: self.device_id.clone(),
+            to: sender.to_string(),
+            message_type: MessageType::MemoryShareReceipt,
+            signature: sign_hex(&self.signing_key, &receipt_payload(&self.device_id, sender, &capsule.capsule_id, &stored_hash)),
+            payload: MessagePayload::MemoryShareReceiptData {
+                capsule_id: capsule.capsule_id.clone(),
+                stored_hash,
+            },
+            timestamp: now_ms(),
+            hop_count: 0,
         }
     }
 
-    fn handle_collaborative_learn(&self, message: P2PMessage) {
-        if let MessagePayload::CollaborativeLearnData { task_description, .. } = message.payload {
+    fn handle_share_receipt(&mut self, message: P2PMessage) {
+        if let MessagePayload::MemoryShareReceiptData { capsule_id, stored_hash } = message.payload {
+            let key = (message.from.clone(), capsule_id.clone());
+            // Only the receiver's pinned key can acknowledge, so a forged receipt cannot release the reward
+            let payload = receipt_payload(&message.from, &self.device_id, &capsule_id, &stored_hash);
+            let valid = self.pending_shares.get(&key)
+                .map(|share| share.content_hash == stored_hash)
+                .unwrap_or(false)
+                && self.peer_keys.get(&message.from)
+                    .is_some_and(|public_key| verify_hex(public_key, &payload, &message.signature));
+            if !valid {
+                console_log!("Ignoring invalid share receipt for {} from {}", capsule_id, message.from);
+                return;
+            }
+            
+            if let Some(share) = self.pending_shares.remove(&key) {
+                console_log!("Share of {} acknowledged by {}", capsule_id, message.from);
+                self.confirmed_shares.push(ConfirmedShare {
+                    peer_id: message.from,
+                    capsule_id,
+                    stored_hash,
+                    reward: share.reward,
+                });
+            }
+        }
+    }
+
+    // Why a shared capsule can't be trusted, if it can't
+    fn provenance_failure(signature: &str, capsule: &MemoryCapsule) -> Option<&'static str> {
+        if signature.is_empty() {
+            return Some("missing signature");
+        }
+        if capsule.capsule_id.is_empty() {
+            return Some("missing capsule id");
+        }
+        if matches!(capsule.privacy_level, PrivacyLevel::Personal) {
+            return Some("personal capsule shared off-device");
+        }
+        if !crate::utils::is_finite_vector(&capsule.context_vector)
+            || !capsule.novelty_score.is_finite()
+            || !capsule.importance_score.is_finite()
+        {
+            return Some("non-finite context or scores");
+        }
+        None
+    }
+
+    fn quarantine_capsule(&mut self, capsule: &MemoryCapsule, source_device: &str, reason: &str) {
+        console_log!("Quarantined capsule {} from {}: {}", capsule.capsule_id, source_device, reason);
+        
+        if let Some(peer) = self.peer_registry.get_mut(source_device) {
+            peer.reputation_score = (peer.reputation_score - self.quarantine_penalty).max(0.0);
+        }
+        
+        self.quarantine.push_back(QuarantinedCapsule {
+            capsule_id: capsule.capsule_id.clone(),
+            source_device: source_device.to_string(),
+            reason: reason.to_string(),
+            timestamp: now_ms(),
+            content_hash: capsule_content_hash(capsule),
+        });
+        while self.quarantine.len() > self.quarantine_capacity {
+            self.quarantine.pop_front();
+        }
+    }
+
+    fn handle_capsule_request(&mut self, message: P2PMessage) {
+        if let MessagePayload::CapsuleRequestData { capsule_id } = message.payload {
+            console_log!("Received capsule request for {} from {}", capsule_id, message.from);
+
+            let capsule = match self.capsule_store.get(&capsule_id) {
+                Some(capsule) => capsule.clone(),
+                None => {
+                    console_log!("Requested capsule {} not found", capsule_id);
+                    return;
+                }
+            };
+
+            // Personal capsules never leave the device
+            if matches!(capsule.privacy_level, PrivacyLevel::Personal) {
+                console_log!("Refusing to share personal capsule {} with {}", capsule_id, message.from);
+                return;
+            }
+
+            let access_level = match capsule.privacy_level {
+                PrivacyLevel::Public => "full",
+                _ => "behavioral",
+            };
+            self.send_memory_share(message.from, capsule, access_level);
+        }
+    }
+
+    fn handle_block_request(&mut self, message: P2PMessage) {
+        if let MessagePayload::BlockRequestData { from_index } = message.payload {
+            console_log!("Received block request from {} starting at #{}", message.from, from_index);
+            self.block_requests.push((message.from, from_index));
+        }
+    }
+
+    fn handle_block_response(&mut self, message: P2PMessage) {
+        if let MessagePayload::BlockResponseData { blocks_json } = message.payload {
+            console_log!("Received blocks from {}", message.from);
+            self.received_blocks.push(blocks_json);
+        }
+    }
+
+    fn handle_collaborative_learn(&mut self, message: P2PMessage) {
+        if let MessagePayload::CollaborativeLearnData { task_description, learning_parameters, .. } = message.payload {
             console_log!("Received collaborative learning invitation: {}", task_description);
-            // Decide whether to participate in collaborative learning
+            
+            // Participate only when the agreed learning rate is usable
+            let learning_rate = learning_parameters.get("learning_rate").copied().unwrap_or(0.0);
+            if !learning_rate.is_finite() || learning_rate <= 0.0 {
+                console_log!("Declining collaborative learning from {}: invalid learning_rate", message.from);
+                return;
+            }
+            
+            self.accepted_collaborations.push(AcceptedCollaboration {
+                initiator: message.from,
+                task_description,
+                learning_parameters,
+            });
         }
     }
 
@@ -1081,31 +2509,139 @@ impl P2PNetwork {
     }
 
     fn handle_heartbeat(&mut self, message: P2PMessage) {
-        if let MessagePayload::HeartBeatData { device_status, .. } = message.payload {
+        if let MessagePayload::HeartBeatData { device_status, echo_timestamp, .. } = message.payload {
             console_log!("Received heartbeat from {}: {}", message.from, device_status);
             
-            // Update peer info
+            let now = now_ms();
+            if let Some(sent_at) = echo_timestamp {
+                // Only a pong echoing one of our own unanswered pings is a real round trip
+                if !self.take_outstanding_ping(&message.from, sent_at) {
+                    console_log!("Ignoring pong from {}: echoes no outstanding ping", message.from);
+                    return;
+                }
+                // Round-trip estimate: the peer stamped its reply halfway between our send and receive
+                let sample = message.timestamp - (sent_at + now) / 2.0;
+                let offset = match self.peer_clock_offsets.get(&message.from) {
+                    Some(previous) => previous + CLOCK_OFFSET_SMOOTHING * (sample - previous),
+                    None => sample,
+                };
+                self.peer_clock_offsets.insert(message.from.clone(), offset);
+            } else if device_status == "ping" {
+                let reply = self.build_heartbeat_message(&message.from, "pong", Some(message.timestamp));
+                self.send_direct_message(message.from.clone(), reply);
+            }
+            
+            // Update peer info; a heartbeat confirms an imported peer is still reachable
+            let local_timestamp = self.to_local_time(&message.from, message.timestamp);
             if let Some(peer) = self.peer_registry.get_mut(&message.from) {
-                peer.last_seen = message.timestamp;
+                peer.last_seen = local_timestamp;
+                peer.provisional = false;
             }
         }
     }
 
+    fn record_outstanding_ping(&mut self, peer_id: &str, sent_at: f64) {
+        let pings = self.outstanding_pings.entry(peer_id.to_string()).or_default();
+        pings.push_back(sent_at);
+        while pings.len() > MAX_OUTSTANDING_PINGS {
+            pings.pop_front();
+        }
+    }
+
+    // Remove and report whether `sent_at` is an unanswered ping to `peer_id`
+    fn take_outstanding_ping(&mut self, peer_id: &str, sent_at: f64) -> bool {
+        let Some(pings) = self.outstanding_pings.get_mut(peer_id) else {
+            return false;
+        };
+        let Some(position) = pings.iter().position(|&pending| pending == sent_at) else {
+            return false;
+        };
+        pings.remove(position);
+        if pings.is_empty() {
+            self.outstanding_pings.remove(peer_id);
+        }
+        true
+    }
+
+    fn build_heartbeat_message(&self, peer_id: &str, device_status: &str, echo_timestamp: Option<f64>) -> P2PMessage {
+        P2PMessage {
+            message_id: crate::utils::generate_unique_id("heartbeat"),
+            from: self.device_id.clone(),
+            to: peer_id.to_string(),
+            message_type: MessageType::HeartBeat,
+            payload: MessagePayload::HeartBeatData {
+                device_status: device_status.to_string(),
+                available_resources: HashMap::new(),
+                recent_activities: Vec::new(),
+                echo_timestamp,
+            },
+            timestamp: now_ms(),
+            signature: "heartbeat_signature".to_string(),
+            hop_count: 0,
+        }
+    }
+
+    fn to_local_time(&self, peer_id: &str, timestamp: f64) -> f64 {
+        timestamp - self.peer_clock_offsets.get(peer_id).copied().unwrap_or(0.0)
+    }
+
+    // Shift a peer's message and capsule timestamps onto the local clock before they are stored
+    fn normalize_timestamps(&self, message: &mut P2PMessage) {
+        message.timestamp = self.to_local_time(&message.from, message.timestamp);
+        if let MessagePayload::MemoryShareData { capsule, .. } = &mut message.payload {
+            capsule.timestamp = self.to_local_time(&message.from, capsule.timestamp);
+        }
+    }
+
     fn handle_discovery(&mut self, message: P2PMessage) {
         if let MessagePayload::DiscoveryData { device_info, network_topology } = message.payload {
+            let previous_announced_at = self.peer_registry.get(&device_info.device_id).map(|peer| peer.announced_at);
+            if let Err(reason) = self.check_announcement(&device_info, previous_announced_at) {
+                console_log!("🚫 Dropping discovery from {}: {}", message.from, reason);
+                return;
+            }
             console_log!("Discovered new peer: {}", device_info.device_id);
             
             // Add to peer registry
+            self.peer_keys.entry(device_info.device_id.clone()).or_insert_with(|| device_info.public_key.clone());
             self.peer_registry.insert(device_info.device_id.clone(), device_info);
             
-            // Update routing table
+            // Update routing table; a route re-advertised by its first hop is refreshed
+            let now = now_ms();
             for (device_id, route) in network_topology {
-                if !self.routing_table.contains_key(&device_id) {
-                    let mut new_route = vec![message.from.clone()];
-                    new_route.extend(route);
-                    self.routing_table.insert(device_id, new_route);
+                let entry = self.routing_table.entry(device_id).or_insert_with(|| {
+                    let mut path = vec![message.from.clone()];
+                    path.extend(route);
+                    RouteEntry { path, updated_at: now }
+                });
+                if entry.path.first() == Some(&message.from) {
+                    entry.updated_at = now;
                 }
             }
+            self.prune_routing_table();
+            
+            self.record_topology_snapshot(&message.from);
+        }
+    }
+
+    fn record_topology_snapshot(&mut self, source_peer: &str) {
+        if !self.topology_history_enabled || self.topology_history_capacity == 0 {
+            return;
+        }
+        
+        let mut known_peers: Vec<String> = self.peer_registry.keys().cloned().collect();
+        known_peers.sort();
+        
+        self.topology_history.push_back(TopologySnapshot {
+            timestamp: now_ms(),
+            source_peer: source_peer.to_string(),
+            known_peers,
+            routing_table: self.routing_table.iter()
+                .map(|(device_id, route)| (device_id.clone(), route.path.clone()))
+                .collect(),
+        });
+        while self.topology_history.len() > self.topology_history_capacity {
+            self.topology_history.pop_front();
         }
     }
 
@@ -1190,7 +2726,7 @@ impl P2PNetwork {
                 }
                 
                 // Select random free node
-                let random_index = (js_sys::Math::random() * free_nodes.len() as f64) as usize;
+                let random_index = (rand::random::<f64>() * free_nodes.len() as f64) as usize;
                 let selected_node = &free_nodes[random_index];
                 
                 console_log!("🎯 Auto-selected free node: {} (Load: {:.1}%, Available nodes: {})", 
@@ -1268,8 +2804,9 @@ impl P2PNetwork {
                 device_status: "user_message".to_string(),
                 available_resources: std::collections::HashMap::new(),
                 recent_activities: vec![message],
+                echo_timestamp: None,
             },
-            timestamp: js_sys::Date::now(),
+            timestamp: now_ms(),
             signature: "user_message_signature".to_string(),
             hop_count: 0,
         };
@@ -1278,6 +2815,89 @@ impl P2PNetwork {
     }
 }
 
+impl P2PNetwork {
+    pub fn economics_policy(&self) -> &EconomicsPolicy {
+        &self.economics_policy
+    }
+
+    /// (connected, known) peer counts
+    pub fn peer_counts(&self) -> (usize, usize) {
+        (self.active_connections.len(), self.peer_registry.len())
+    }
+
+    pub fn take_block_requests(&mut self) -> Vec<(String, u64)> {
+        std::mem::take(&mut self.block_requests)
+    }
+
+    pub fn take_received_blocks(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.received_blocks)
+    }
+
+    pub fn take_confirmed_shares(&mut self) -> Vec<ConfirmedShare> {
+        std::mem::take(&mut self.confirmed_shares)
+    }
+
+    pub fn take_node_lend_requests(&mut self) -> Vec<NodeLendRequest> {
+        std::mem::take(&mut self.node_lend_requests)
+    }
+
+    pub fn take_borrowed_nodes(&mut self) -> Vec<BorrowedNode> {
+        std::mem::take(&mut self.borrowed_nodes)
+    }
+
+    pub fn take_accepted_collaborations(&mut self) -> Vec<AcceptedCollaboration> {
+        std::mem::take(&mut self.accepted_collaborations)
+    }
+
+    /// Split an active session's reward pool in proportion to measured contributions.
+    /// Invitees that contributed nothing receive nothing.
+    pub fn collaboration_reward_shares(&self, session_id: &str) -> Option<Vec<(String, f64)>> {
+        let session = self.collaboration_sessions.get(session_id)?;
+        
+        let total_score: f64 = session.contributions.values().map(|c| c.score()).sum();
+        let mut rewards: Vec<(String, f64)> = if total_score > 0.0 {
+            session.contributions.iter()
+                .filter(|(_, contribution)| contribution.score() > 0.0)
+                .map(|(peer_id, contribution)| {
+                    (peer_id.clone(), session.reward_pool * contribution.score() / total_score)
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        rewards.sort_by(|a, b| a.0.cmp(&b.0));
+        Some(rewards)
+    }
+
+    /// Move a session from the active map into the bounded history with the rewards actually paid
+    pub fn complete_collaboration_session(&mut self, session_id: &str, rewards: HashMap<String, f64>) -> bool {
+        let session = match self.collaboration_sessions.remove(session_id) {
+            Some(session) => session,
+            None => return false,
+        };
+        
+        let mut participants: Vec<String> = session.contributions.keys().cloned().collect();
+        participants.sort();
+        
+        if self.collaboration_history_capacity > 0 {
+            self.collaboration_history.push_back(CollaborationOutcome {
+                session_id: session.session_id,
+                task_description: session.task_description,
+                participants,
+                final_loss: session.final_loss,
+                total_rewards: rewards.values().sum(),
+                rewards,
+                started_at: session.started_at,
+                duration_ms: now_ms() - session.started_at,
+            });
+            while self.collaboration_history.len() > self.collaboration_history_capacity {
+                self.collaboration_history.pop_front();
+            }
+        }
+        true
+    }
+}
+
 #[derive(serde::Serialize)]
 struct NetworkStats {
     connected_peers: usize,
@@ -1286,4 +2906,708 @@ struct NetworkStats {
     average_latency: f64,
     total_bandwidth: f64,
     network_health: f64,
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn sample_peer(device_id: &str) -> PeerInfo {
+        PeerInfo {
+            device_id: device_id.to_string(),
+            ip_address: "browser_client".to_string(),
+            port: 0,
+            public_key: String::new(),
+            capabilities: vec!["memory_sharing".to_string()],
+            reputation_score: 1.0,
+            last_seen: 0.0,
+            cluster_specializations: vec!["general".to_string()],
+            node_status: NodeStatus {
+                is_processing: false,
+                active_queries: 0,
+                last_activity: 0.0,
+                processing_load: 0.0,
+                is_available: true,
+            },
+            active_connections: 0,
+            cpu_usage: 0.2,
+            memory_usage: 0.3,
+            available_nodes: 5,
+            provisional: false,
+            announced_at: 1_000.0,
+            announcement_signature: String::new(),
+        }
+    }
+
+    pub(crate) fn signed_peer(network: &P2PNetwork) -> PeerInfo {
+        let unsigned = serde_json::to_string(&sample_peer("ignored")).unwrap();
+        serde_json::from_str(&network.sign_peer_announcement(&unsigned)).unwrap()
+    }
+
+    fn chunk(message_id: &str, chunk_index: usize, total_chunks: usize, payload: &str) -> String {
+        serde_json::json!({
+            "type": "chunk",
+            "data": { "message_id": message_id, "chunk_index": chunk_index, "total_chunks": total_chunks, "payload": payload }
+        }).to_string()
+    }
+
+    #[test]
+    fn reassembler_joins_three_chunks_in_any_order() {
+        let mut reassembler = ChunkReassembler::default();
+        let message = r#"{"type":"registered","data":{}}"#;
+        let pieces = [&message[..10], &message[10..20], &message[20..]];
+
+        assert_eq!(reassembler.accept_at(&chunk("m", 2, 3, pieces[2]), 0.0), None);
+        assert_eq!(reassembler.accept_at(&chunk("m", 0, 3, pieces[0]), 0.0), None);
+        assert_eq!(reassembler.pending_messages(), 1);
+        assert_eq!(reassembler.accept_at(&chunk("m", 1, 3, pieces[1]), 0.0).as_deref(), Some(message));
+        assert_eq!(reassembler.pending_messages(), 0);
+        assert_eq!(reassembler.pending_bytes(), 0);
+
+        // Plain messages pass straight through
+        assert_eq!(reassembler.accept_at(message, 0.0).as_deref(), Some(message));
+    }
+
+    #[test]
+    fn reassembler_rejects_oversized_and_inconsistent_chunks() {
+        let mut reassembler = ChunkReassembler::default();
+        assert_eq!(reassembler.accept_at(&chunk("huge", 0, MAX_CHUNKS_PER_MESSAGE + 1, "x"), 0.0), None);
+        assert_eq!(reassembler.accept_at(&chunk("m", 3, 3, "x"), 0.0), None);
+        assert_eq!(reassembler.pending_messages(), 0);
+
+        reassembler.accept_at(&chunk("m", 0, 3, "x"), 0.0);
+        assert_eq!(reassembler.accept_at(&chunk("m", 1, 4, "y"), 0.0), None);
+        assert_eq!(reassembler.pending_messages(), 1);
+    }
+
+    #[test]
+    fn reassembler_caps_buffered_bytes() {
+        let mut reassembler = ChunkReassembler::default();
+        let payload = "x".repeat(MAX_PENDING_CHUNK_BYTES / 4);
+        for message in 0..8 {
+            reassembler.accept_at(&chunk(&format!("m{}", message), 0, 2, &payload), 0.0);
+        }
+        assert!(reassembler.pending_bytes() <= MAX_PENDING_CHUNK_BYTES);
+        assert!(reassembler.pending_messages() < 8);
+    }
+
+    #[test]
+    fn reassembler_evicts_incomplete_messages_after_timeout() {
+        let mut reassembler = ChunkReassembler::default();
+        reassembler.accept_at(&chunk("stale", 0, 2, "x"), 0.0);
+        reassembler.accept_at(&chunk("fresh", 0, 2, "y"), CHUNK_REASSEMBLY_TIMEOUT_MS + 1.0);
+        assert_eq!(reassembler.pending_messages(), 1);
+        // The late second half of the expired message starts a new, incomplete one
+        assert_eq!(reassembler.accept_at(&chunk("stale", 1, 2, "z"), CHUNK_REASSEMBLY_TIMEOUT_MS + 1.0), None);
+    }
+
+    #[test]
+    fn signed_announcements_verify_and_tampering_is_detected() {
+        let peer = signed_peer(&P2PNetwork::new("alice".to_string()));
+        assert_eq!(peer.device_id, "alice");
+        assert!(peer.has_valid_announcement());
+
+        let mut tampered = peer.clone();
+        tampered.available_nodes = 500;
+        assert!(!tampered.has_valid_announcement());
+
+        // Re-signing under another key does not make the original key's claim valid
+        let mut forged = peer.clone();
+        forged.announcement_signature = signed_peer(&P2PNetwork::new("alice".to_string())).announcement_signature;
+        assert!(!forged.has_valid_announcement());
+    }
+
+    #[test]
+    fn discovery_pins_the_first_key_seen_for_a_device() {
+        let mut network = P2PNetwork::new("me".to_string());
+        let original = signed_peer(&P2PNetwork::new("alice".to_string()));
+        assert!(network.handle_discovery_results(&serde_json::to_string(&vec![&original]).unwrap()));
+        assert!(network.peer_registry.contains_key("alice"));
+
+        // A validly self-signed announcement under a different key is still an impostor
+        let impostor = signed_peer(&P2PNetwork::new("alice".to_string()));
+        assert!(impostor.has_valid_announcement());
+        network.handle_discovery_results(&serde_json::to_string(&vec![&impostor]).unwrap());
+        assert!(!network.peer_registry.contains_key("alice"));
+
+        // The device's own id cannot be claimed by another key either
+        let spoofed_self = signed_peer(&P2PNetwork::new("me".to_string()));
+        network.handle_discovery_results(&serde_json::to_string(&vec![&spoofed_self]).unwrap());
+        assert!(!network.peer_registry.contains_key("me"));
+    }
+
+    // `sender` with a pending share of `capsule` to `receiver`, whose key it learned by discovery
+    fn pending_share(sender: &mut P2PNetwork, receiver: &P2PNetwork, capsule: &MemoryCapsule) {
+        let announcement = signed_peer(receiver);
+        assert!(sender.handle_discovery_results(&serde_json::to_string(&vec![&announcement]).unwrap()));
+        sender.pending_shares.insert(
+            (receiver.device_id.clone(), capsule.capsule_id.clone()),
+            PendingShare { content_hash: capsule_content_hash(capsule), reward: 1.0 },
+        );
+    }
+
+    #[test]
+    fn receipts_signed_by_the_receiver_confirm_the_share() {
+        let capsule = crate::memory::tests::sample_capsule("capsule_a", &["tag"]);
+        let mut alice = P2PNetwork::new("alice".to_string());
+        let bob = P2PNetwork::new("bob".to_string());
+        pending_share(&mut alice, &bob, &capsule);
+
+        alice.handle_share_receipt(bob.build_share_receipt("alice", &capsule));
+        assert_eq!(alice.confirmed_shares.len(), 1);
+        assert!(alice.pending_shares.is_empty());
+    }
+
+    #[test]
+    fn forged_or_altered_receipts_are_ignored() {
+        let capsule = crate::memory::tests::sample_capsule("capsule_a", &["tag"]);
+        let mut alice = P2PNetwork::new("alice".to_string());
+        let bob = P2PNetwork::new("bob".to_string());
+        pending_share(&mut alice, &bob, &capsule);
+
+        // Anyone can compute the payload, but only bob's key signs for bob
+        let forged = P2PNetwork::new("bob".to_string()).build_share_receipt("alice", &capsule);
+        alice.handle_share_receipt(forged);
+
+        // A receipt bob issued to someone else cannot be redirected to alice
+        let mut redirected = bob.build_share_receipt("carol", &capsule);
+        redirected.to = "alice".to_string();
+        alice.handle_share_receipt(redirected);
+
+        assert!(alice.confirmed_shares.is_empty());
+        assert_eq!(alice.pending_shares.len(), 1);
+    }
+
+    #[test]
+    fn shares_cannot_overwrite_a_stored_capsule() {
+        let alice = P2PNetwork::new("alice".to_string());
+        let mut bob = P2PNetwork::new("bob".to_string());
+        let original = crate::memory::tests::sample_capsule("capsule_a", &["original"]);
+        bob.handle_memory_share(alice.build_memory_share_message("bob", original.clone(), "full"));
+
+        let replacement = crate::memory::tests::sample_capsule("capsule_a", &["replacement"]);
+        bob.handle_memory_share(alice.build_memory_share_message("bob", replacement, "full"));
+        assert_eq!(bob.capsule_store["capsule_a"].semantic_tags, original.semantic_tags);
+    }
+
+    #[test]
+    fn quarantine_keeps_a_bounded_record_without_the_capsule() {
+        let alice = P2PNetwork::new("alice".to_string());
+        let mut bob = P2PNetwork::new("bob".to_string());
+        bob.set_quarantine_capacity(2);
+        for id in ["a", "b", "c"] {
+            let mut message = alice.build_memory_share_message("bob", crate::memory::tests::sample_capsule(id, &[]), "full");
+            message.signature = String::new();
+            bob.handle_memory_share(message);
+        }
+
+        let ids: Vec<&str> = bob.quarantine.iter().map(|record| record.capsule_id.as_str()).collect();
+        assert_eq!(ids, ["b", "c"]);
+        assert!(bob.capsule_store.is_empty());
+        bob.set_quarantine_capacity(1);
+        assert_eq!(bob.quarantine.len(), 1);
+    }
+
+    #[test]
+    fn incoming_messages_reject_replays_and_stale_timestamps() {
+        let alice = P2PNetwork::new("alice".to_string());
+        let mut bob = P2PNetwork::new("bob".to_string());
+        let message = alice.build_memory_share_message("bob", crate::memory::tests::sample_capsule("a", &[]), "full");
+        assert!(bob.deliver_incoming(message.clone()));
+        assert!(!bob.deliver_incoming(message));
+
+        let mut stale = alice.build_memory_share_message("bob", crate::memory::tests::sample_capsule("b", &[]), "full");
+        stale.timestamp -= MESSAGE_CLOCK_SKEW_MS + 1_000.0;
+        assert!(!bob.deliver_incoming(stale));
+        let mut future = alice.build_memory_share_message("bob", crate::memory::tests::sample_capsule("c", &[]), "full");
+        future.timestamp += MESSAGE_CLOCK_SKEW_MS + 1_000.0;
+        assert!(!bob.deliver_incoming(future));
+    }
+
+    #[test]
+    fn seen_message_ids_stay_bounded_and_in_sync() {
+        let alice = P2PNetwork::new("alice".to_string());
+        let mut bob = P2PNetwork::new("bob".to_string());
+        let first = alice.build_memory_share_message("bob", crate::memory::tests::sample_capsule("first", &[]), "full");
+        assert!(bob.deliver_incoming(first.clone()));
+        for i in 0..SEEN_MESSAGE_CAPACITY {
+            let capsule = crate::memory::tests::sample_capsule(&format!("capsule_{}", i), &[]);
+            bob.deliver_incoming(alice.build_memory_share_message("bob", capsule, "full"));
+        }
+        assert_eq!(bob.seen_message_ids.len(), SEEN_MESSAGE_CAPACITY);
+        assert_eq!(bob.seen_message_set.len(), SEEN_MESSAGE_CAPACITY);
+        assert!(!bob.seen_message_set.contains(&first.message_id));
+    }
+
+    #[test]
+    fn clock_offsets_only_come_from_echoes_of_our_pings() {
+        let alice = P2PNetwork::new("alice".to_string());
+        let mut bob = P2PNetwork::new("bob".to_string());
+
+        // An unsolicited pong claiming a huge offset is ignored
+        let mut forged = alice.build_heartbeat_message("bob", "pong", Some(now_ms() - 10.0));
+        forged.timestamp += 1_000_000.0;
+        bob.handle_heartbeat(forged);
+        assert_eq!(bob.get_peer_clock_offset("alice"), 0.0);
+
+        let ping = bob.build_heartbeat_message("alice", "ping", None);
+        bob.record_outstanding_ping("alice", ping.timestamp);
+        let mut pong = alice.build_heartbeat_message("bob", "pong", Some(ping.timestamp));
+        pong.timestamp += 5_000.0;
+        bob.handle_heartbeat(pong.clone());
+        let offset = bob.get_peer_clock_offset("alice");
+        assert!(offset > 4_000.0 && offset < 6_000.0);
+
+        // The same echo cannot be answered twice
+        pong.timestamp += 100_000.0;
+        bob.handle_heartbeat(pong);
+        assert_eq!(bob.get_peer_clock_offset("alice"), offset);
+    }
+
+    #[test]
+    fn outstanding_pings_are_bounded_per_peer() {
+        let mut bob = P2PNetwork::new("bob".to_string());
+        for sent_at in 0..(MAX_OUTSTANDING_PINGS + 2) {
+            bob.record_outstanding_ping("alice", sent_at as f64);
+        }
+        assert_eq!(bob.outstanding_pings["alice"].len(), MAX_OUTSTANDING_PINGS);
+        assert!(!bob.take_outstanding_ping("alice", 0.0));
+        assert!(bob.take_outstanding_ping("alice", (MAX_OUTSTANDING_PINGS + 1) as f64));
+    }
+
+    #[test]
+    fn wire_delta_round_trips_and_requires_a_fresh_signature() {
+        let alice = P2PNetwork::new("alice".to_string());
+        let original = signed_peer(&alice);
+        let mut network = P2PNetwork::new("me".to_string());
+        assert!(network.handle_discovery_results(&serde_json::to_string(&vec![&original]).unwrap()));
+
+        let mut updated = original.clone();
+        updated.cpu_usage = 0.9;
+        updated.announced_at = original.announced_at + 1.0;
+        let updated: PeerInfo = serde_json::from_str(&alice.sign_peer_announcement(&serde_json::to_string(&updated).unwrap())).unwrap();
+
+        let delta = updated.to_wire_delta(&original);
+        assert!(delta.ip_address.is_none() && delta.public_key.is_none());
+        let mut applied = original.clone();
+        applied.apply_wire_delta(delta.clone());
+        assert_eq!(serde_json::to_string(&applied).unwrap(), serde_json::to_string(&updated).unwrap());
+
+        // An unsigned change is rejected and leaves the peer untouched
+        let mut unsigned = delta.clone();
+        unsigned.announcement_signature = None;
+        assert!(!network.handle_peer_delta(&serde_json::to_string(&unsigned).unwrap()));
+        assert_eq!(network.peer_registry["alice"].cpu_usage, original.cpu_usage);
+
+        assert!(network.handle_peer_delta(&serde_json::to_string(&delta).unwrap()));
+        assert_eq!(network.peer_registry["alice"].cpu_usage, 0.9);
+
+        // Replaying the same delta does not move the announcement forward
+        assert!(!network.handle_peer_delta(&serde_json::to_string(&delta).unwrap()));
+    }
+
+    pub(crate) fn with_outbox(device_id: &str) -> P2PNetwork {
+        let mut network = P2PNetwork::new(device_id.to_string());
+        network.test_outbox = Some(Default::default());
+        network
+    }
+
+    pub(crate) fn take_sent(network: &P2PNetwork) -> Vec<P2PMessage> {
+        network.test_outbox.as_ref().map(|outbox| outbox.take()).unwrap_or_default()
+    }
+
+    #[test]
+    fn peers_pull_capsules_they_do_not_hold() {
+        let mut alice = with_outbox("alice");
+        let mut bob = with_outbox("bob");
+        let capsule = crate::memory::tests::sample_capsule("only_on_bob", &["tag"]);
+        assert!(bob.publish_capsule(&serde_json::to_string(&capsule).unwrap()));
+
+        assert!(!alice.request_capsule("bob".to_string(), "only_on_bob".to_string()).is_empty());
+        for request in take_sent(&alice) {
+            assert!(bob.deliver_incoming(request));
+        }
+        let shares = take_sent(&bob);
+        assert_eq!(shares.len(), 1);
+        assert!(matches!(shares[0].message_type, MessageType::MemoryShare));
+        for share in shares {
+            assert!(alice.deliver_incoming(share));
+        }
+
+        let received = alice.capsule_store.get("only_on_bob").expect("pulled capsule is stored");
+        assert_eq!(capsule_content_hash(received), capsule_content_hash(&capsule));
+    }
+
+    fn start_connecting(network: &mut P2PNetwork, peer_id: &str, started_at: f64) {
+        network.active_connections.insert(peer_id.to_string(), P2PConnection {
+            peer_id: peer_id.to_string(),
+            connection_type: ConnectionType::WebRTC,
+            status: ConnectionStatus::Connecting,
+            established_time: started_at,
+            bandwidth_usage: 0.0,
+            latency_ms: 0.0,
+            encryption_key: "webrtc_dtls_key".to_string(),
+        });
+    }
+
+    #[test]
+    fn connections_stay_connecting_until_the_data_channel_opens() {
+        let mut network = P2PNetwork::new("me".to_string());
+        start_connecting(&mut network, "alice", 0.0);
+
+        assert_eq!(network.sync_connection_states(), 0);
+        assert_eq!(network.active_connections["alice"].status, ConnectionStatus::Connecting);
+
+        network.webrtc_manager.as_ref().unwrap().simulate_channel_open("alice");
+        assert_eq!(network.sync_connection_states(), 1);
+        assert_eq!(network.active_connections["alice"].status, ConnectionStatus::Established);
+        assert_eq!(network.sync_connection_states(), 0);
+    }
+
+    #[test]
+    fn pending_negotiations_can_be_listed_and_aborted() {
+        let mut network = P2PNetwork::new("me".to_string());
+        start_connecting(&mut network, "bob", now_ms() - 5_000.0);
+        start_connecting(&mut network, "alice", now_ms());
+
+        let pending: Vec<serde_json::Value> = serde_json::from_str(&network.get_pending_connections()).unwrap();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0]["peer_id"], "alice");
+        assert!(pending[1]["age_ms"].as_f64().unwrap() >= 5_000.0);
+
+        assert!(network.abort_connection("bob").is_ok());
+        assert!(!network.active_connections.contains_key("bob"));
+        assert!(network.abort_connection("bob").is_err());
+        let pending: Vec<serde_json::Value> = serde_json::from_str(&network.get_pending_connections()).unwrap();
+        assert_eq!(pending.len(), 1);
+
+        // Established connections are not negotiations
+        network.active_connections.get_mut("alice").unwrap().status = ConnectionStatus::Established;
+        assert!(network.abort_connection("alice").is_err());
+        assert_eq!(network.get_pending_connections(), "[]");
+    }
+
+    // Discovery from `from`, announcing itself with an identity derived from its name
+    fn discovery_message(from: &str, to: &str, topology: &[(&str, &[&str])]) -> P2PMessage {
+        let identity_key = crate::utils::hex_decode(&crate::utils::sha256_hex(from.as_bytes())).unwrap();
+        let sender = P2PNetwork::new_with_identity_key(from.to_string(), &identity_key).unwrap();
+        P2PMessage {
+            message_id: crate::utils::generate_unique_id("discovery"),
+            from: from.to_string(),
+            to: to.to_string(),
+            message_type: MessageType::Discovery,
+            payload: MessagePayload::DiscoveryData {
+                device_info: signed_peer(&sender),
+                network_topology: topology.iter()
+                    .map(|(device_id, path)| (device_id.to_string(), path.iter().map(|hop| hop.to_string()).collect()))
+                    .collect(),
+            },
+            timestamp: now_ms(),
+            signature: "discovery_signature".to_string(),
+            hop_count: 0,
+        }
+    }
+
+    fn topology_history(network: &P2PNetwork) -> Vec<TopologySnapshot> {
+        serde_json::from_str(&network.get_topology_history()).unwrap()
+    }
+
+    #[test]
+    fn each_discovery_round_retains_a_topology_snapshot() {
+        let mut network = P2PNetwork::new("me".to_string());
+        assert!(network.deliver_incoming(discovery_message("alice", "me", &[])));
+        assert!(topology_history(&network).is_empty());
+
+        network.set_topology_history(true, 2);
+        assert!(network.deliver_incoming(discovery_message("alice", "me", &[("carol", &[])])));
+        assert!(network.deliver_incoming(discovery_message("bob", "me", &[("dave", &["erin"])])));
+        let history = topology_history(&network);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].source_peer, "alice");
+        assert_eq!(history[0].routing_table["carol"], ["alice"]);
+        assert!(!history[0].routing_table.contains_key("dave"));
+        assert_eq!(history[1].known_peers, ["alice", "bob"]);
+        assert_eq!(history[1].routing_table["dave"], ["bob", "erin"]);
+
+        // The ring keeps only the newest snapshots
+        assert!(network.deliver_incoming(discovery_message("carol", "me", &[])));
+        let history = topology_history(&network);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].source_peer, "carol");
+    }
+
+    // Share a capsule with node data from alice to bob and return what bob stored
+    fn shared_at_level(access_level: &str) -> MemoryCapsule {
+        let mut alice = with_outbox("alice");
+        let mut bob = with_outbox("bob");
+        let mut capsule = crate::memory::tests::sample_capsule("shared", &["tag"]);
+        capsule.compressed_data = vec![1, 2, 3];
+        let capsule_json = serde_json::to_string(&capsule).unwrap();
+        alice.share_memory_direct_at_level("bob".to_string(), &capsule_json, access_level.to_string()).unwrap();
+
+        for message in take_sent(&alice) {
+            assert!(bob.deliver_incoming(message));
+        }
+        bob.capsule_store.remove("shared").expect("shared capsule is stored")
+    }
+
+    #[test]
+    fn behavioral_shares_arrive_without_node_data() {
+        let behavioral = shared_at_level("behavioral");
+        assert!(behavioral.compressed_data.is_empty());
+        assert_eq!(behavioral.context_vector, vec![0.5; 16]);
+        assert_eq!(shared_at_level("full").compressed_data, vec![1, 2, 3]);
+
+        let mut alice = with_outbox("alice");
+        let capsule_json = serde_json::to_string(&crate::memory::tests::sample_capsule("c", &[])).unwrap();
+        assert!(alice.share_memory_direct_at_level("bob".to_string(), &capsule_json, "raw".to_string()).is_err());
+    }
+
+    #[test]
+    fn simulated_discovery_feeds_find_free_nodes() {
+        let alice = P2PNetwork::new("alice".to_string());
+        let bob = P2PNetwork::new("bob".to_string());
+        let mut busy = sample_peer("carol");
+        busy.node_status.is_processing = true;
+        busy.cpu_usage = 0.95;
+        let busy: PeerInfo = serde_json::from_str(&P2PNetwork::new("carol".to_string())
+            .sign_peer_announcement(&serde_json::to_string(&busy).unwrap())).unwrap();
+        let peers = serde_json::to_string(&vec![signed_peer(&alice), signed_peer(&bob), busy]).unwrap();
+
+        let mut network = P2PNetwork::new("me".to_string());
+        assert!(network.enable_discovery_simulation(&peers));
+        assert_eq!(network.peer_registry.len(), 3);
+        let free: Vec<PeerInfo> = serde_json::from_str(&network.find_free_nodes()).unwrap();
+        let mut free_ids: Vec<String> = free.into_iter().map(|peer| peer.device_id).collect();
+        free_ids.sort();
+        assert_eq!(free_ids, ["alice", "bob"]);
+
+        // Discovery rounds replay the simulated peers without a signaling server
+        network.peer_registry.clear();
+        assert!(network.start_discovery());
+        assert_eq!(network.peer_registry.len(), 3);
+        network.disable_discovery_simulation();
+        assert!(!network.start_discovery());
+    }
+
+    #[test]
+    fn node_requests_offer_the_configured_payment() {
+        let mut network = with_outbox("me");
+        let mut policy: serde_json::Value = serde_json::from_str(&network.get_economics_policy()).unwrap();
+        policy["node_payment_offer"] = serde_json::json!(7.5);
+        network.set_economics_policy(&policy.to_string()).unwrap();
+
+        assert!(!network.request_node_direct("alice".to_string(), "vision".to_string(), 30).is_empty());
+        let sent = take_sent(&network);
+        assert!(matches!(sent[0].payload, MessagePayload::NodeRequestData { payment_offer, .. } if payment_offer == 7.5));
+
+        policy["node_payment_offer"] = serde_json::json!(-1.0);
+        assert!(network.set_economics_policy(&policy.to_string()).is_err());
+        assert_eq!(network.economics_policy().node_payment_offer, 7.5);
+    }
+
+    #[test]
+    fn peers_sorted_by_last_seen_list_the_most_recent_first() {
+        let mut network = P2PNetwork::new("device".to_string());
+        for (device_id, last_seen) in [("alice", 100.0), ("bob", 300.0), ("carol", 200.0), ("dave", 300.0)] {
+            let mut peer = sample_peer(device_id);
+            peer.last_seen = last_seen;
+            network.peer_registry.insert(device_id.to_string(), peer);
+        }
+
+        let page = |offset, limit| -> Vec<String> {
+            let peers: Vec<PeerInfo> = serde_json::from_str(&network.get_discovered_peers_paged(offset, limit, "last_seen")).unwrap();
+            peers.into_iter().map(|peer| peer.device_id).collect()
+        };
+        assert_eq!(page(0, 3), ["bob", "dave", "carol"]);
+        assert_eq!(page(3, 10), ["alice"]);
+        assert!(network.get_discovered_peers_paged(0, 10, "unknown").is_empty());
+    }
+
+
+    #[test]
+    fn provenance_failures_are_quarantined_and_penalize_the_sender() {
+        let alice = P2PNetwork::new("alice".to_string());
+        let mut bob = P2PNetwork::new("bob".to_string());
+        bob.peer_registry.insert("alice".to_string(), sample_peer("alice"));
+        bob.set_quarantine_penalty(0.25);
+
+        let mut capsule = crate::memory::tests::sample_capsule("personal", &[]);
+        capsule.privacy_level = PrivacyLevel::Personal;
+        bob.handle_memory_share(alice.build_memory_share_message("bob", capsule, "full"));
+
+        let quarantined: Vec<QuarantinedCapsule> = serde_json::from_str(&bob.get_quarantined_capsules()).unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].capsule_id, "personal");
+        assert_eq!(quarantined[0].source_device, "alice");
+        assert_eq!(quarantined[0].reason, "personal capsule shared off-device");
+        assert_eq!(bob.peer_registry["alice"].reputation_score, 0.75);
+        assert!(bob.capsule_store.is_empty());
+    }
+
+
+    #[test]
+    fn future_skewed_timestamps_are_corrected_to_local_time() {
+        // Within the replay window, so the first pong is accepted before any offset is known
+        const SKEW_MS: f64 = 120_000.0;
+        let alice = P2PNetwork::new("alice".to_string());
+        let mut bob = P2PNetwork::new("bob".to_string());
+
+        // Alice's clock runs two minutes ahead of bob's
+        let ping = bob.build_heartbeat_message("alice", "ping", None);
+        bob.record_outstanding_ping("alice", ping.timestamp);
+        let mut pong = alice.build_heartbeat_message("bob", "pong", Some(ping.timestamp));
+        pong.timestamp += SKEW_MS;
+        assert!(bob.deliver_incoming(pong));
+        assert!((bob.get_peer_clock_offset("alice") - SKEW_MS).abs() < 1_000.0);
+
+        let mut capsule = crate::memory::tests::sample_capsule("capsule_a", &[]);
+        capsule.timestamp = now_ms() + SKEW_MS;
+        let mut share = alice.build_memory_share_message("bob", capsule, "full");
+        share.timestamp += SKEW_MS;
+        assert!(bob.deliver_incoming(share));
+        assert!((bob.capsule_store["capsule_a"].timestamp - now_ms()).abs() < 1_000.0);
+    }
+
+
+    #[test]
+    fn imported_peers_stay_provisional_until_a_heartbeat() {
+        let mut cached = P2PNetwork::new("device".to_string());
+        for device_id in ["alice", "carol"] {
+            cached.peer_registry.insert(device_id.to_string(), sample_peer(device_id));
+        }
+        let mut restarted = P2PNetwork::new("device".to_string());
+        assert_eq!(restarted.import_peers(&cached.export_peers()), 2);
+        assert_eq!(restarted.import_peers(&cached.export_peers()), 0);
+
+        let discovered: Vec<PeerInfo> = serde_json::from_str(&restarted.get_discovered_peers()).unwrap();
+        assert_eq!(discovered.len(), 2);
+        assert!(discovered.iter().all(|peer| peer.provisional));
+
+        let alice = P2PNetwork::new("alice".to_string());
+        assert!(restarted.deliver_incoming(alice.build_heartbeat_message("device", "ping", None)));
+        assert!(!restarted.peer_registry["alice"].provisional);
+        assert!(restarted.peer_registry["carol"].provisional);
+    }
+
+
+    #[test]
+    fn routes_to_peers_unseen_within_the_ttl_are_evicted() {
+        let mut network = P2PNetwork::new("device".to_string());
+        let now = now_ms();
+        for (device_id, last_seen) in [("gone", now - 120_000.0), ("recent", now)] {
+            let mut peer = sample_peer(device_id);
+            peer.last_seen = last_seen;
+            network.peer_registry.insert(device_id.to_string(), peer);
+            let route = RouteEntry { path: vec!["relay".to_string(), device_id.to_string()], updated_at: now - 120_000.0 };
+            network.routing_table.insert(device_id.to_string(), route);
+        }
+
+        assert!(network.set_routing_table_limits(60_000.0, 256));
+        assert!(!network.routing_table.contains_key("gone"));
+        assert!(network.routing_table.contains_key("recent"));
+        assert_eq!(network.prune_routing_table(), 0);
+    }
+
+
+    #[test]
+    fn injected_node_requests_are_answered() {
+        let mut alice = with_outbox("alice");
+        let mut bob = with_outbox("bob");
+        let mut policy: serde_json::Value = serde_json::from_str(&alice.get_economics_policy()).unwrap();
+        policy["node_payment_offer"] = serde_json::json!(1.0);
+        alice.set_economics_policy(&policy.to_string()).unwrap();
+
+        // Too low an offer is declined straight away
+        alice.request_node_direct("bob".to_string(), "vision".to_string(), 30);
+        let request = take_sent(&alice).pop().unwrap();
+        let request_json = serde_json::to_string(&request).unwrap();
+        assert!(bob.inject_incoming_message(&request_json));
+        assert!(!bob.inject_incoming_message(&request_json));
+
+        let sent = take_sent(&bob);
+        assert_eq!(sent.len(), 1);
+        assert!(matches!(sent[0].message_type, MessageType::NodeResponse));
+        assert_eq!(sent[0].to, "alice");
+        assert!(matches!(&sent[0].payload, MessagePayload::NodeResponseData { request_id, approval_status: false, .. }
+            if *request_id == request.message_id));
+        assert!(!bob.inject_incoming_message("not a message"));
+    }
+
+
+    #[test]
+    fn an_imported_identity_key_keeps_the_pinned_identity() {
+        let original = P2PNetwork::new("alice".to_string());
+        let announcement = signed_peer(&original);
+        let key = original.export_identity_key();
+        let restarted = P2PNetwork::new_with_identity_key("alice".to_string(), &key).unwrap();
+        assert_eq!(restarted.get_public_key(), original.get_public_key());
+        assert!(P2PNetwork::new_with_identity_key("alice".to_string(), &key[..31]).is_err());
+
+        // A peer that pinned the first session's key accepts the restarted device's announcements
+        let mut observer = P2PNetwork::new("observer".to_string());
+        assert!(observer.handle_discovery_results(&serde_json::to_string(&[announcement]).unwrap()));
+        let mut reannounced = signed_peer(&restarted);
+        reannounced.announced_at = 2_000.0;
+        reannounced.sign_announcement(&restarted.signing_key);
+        assert!(observer.handle_discovery_results(&serde_json::to_string(&[reannounced]).unwrap()));
+        assert!(observer.peer_registry.get("alice").is_some_and(|peer| peer.announced_at == 2_000.0));
+
+        // A fresh identity under the same device id is still refused
+        let impostor_network = P2PNetwork::new("alice".to_string());
+        let mut impostor = signed_peer(&impostor_network);
+        impostor.announced_at = 3_000.0;
+        impostor.sign_announcement(&impostor_network.signing_key);
+        assert!(impostor.has_valid_announcement());
+        assert!(observer.handle_discovery_results(&serde_json::to_string(&[impostor]).unwrap()));
+        assert!(!observer.peer_registry.contains_key("alice"));
+    }
+
+
+    #[test]
+    fn forged_discovery_messages_are_dropped() {
+        let mut network = P2PNetwork::new("me".to_string());
+        let mut unsigned = discovery_message("alice", "me", &[("carol", &[])]);
+        if let MessagePayload::DiscoveryData { device_info, .. } = &mut unsigned.payload {
+            *device_info = sample_peer("alice");
+        }
+        assert!(network.deliver_incoming(unsigned));
+        assert!(!network.peer_registry.contains_key("alice"));
+        assert!(!network.routing_table.contains_key("carol"));
+
+        assert!(network.deliver_incoming(discovery_message("alice", "me", &[])));
+        let pinned_key = network.peer_registry["alice"].public_key.clone();
+
+        // A validly signed announcement under a different key than the one pinned for alice
+        let mut impostor = discovery_message("alice", "me", &[("carol", &[])]);
+        if let MessagePayload::DiscoveryData { device_info, .. } = &mut impostor.payload {
+            *device_info = signed_peer(&P2PNetwork::new("alice".to_string()));
+        }
+        assert!(network.deliver_incoming(impostor));
+        assert_eq!(network.peer_registry["alice"].public_key, pinned_key);
+        assert!(!network.routing_table.contains_key("carol"));
+    }
+}
+
+// Needs a JS WebSocket, so these run under wasm-bindgen-test rather than cargo test
+#[cfg(all(test, target_arch = "wasm32"))]
+mod wasm_tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    #[wasm_bindgen_test]
+    fn reconfiguring_unhooks_the_previous_socket() {
+        let mut network = P2PNetwork::new("me".to_string());
+        assert!(network.configure_signaling_server("ws://localhost:9".to_string()));
+        let first_socket = network.websocket.clone().unwrap();
+        let first_callbacks = Rc::downgrade(network.websocket_callbacks.as_ref().unwrap());
+
+        assert!(network.configure_signaling_server("ws://localhost:9".to_string()));
+        // The old socket can no longer deliver messages, and its handlers are freed
+        assert!(first_socket.onmessage().is_none());
+        assert!(first_callbacks.upgrade().is_none());
+        assert!(network.websocket.as_ref().unwrap().onmessage().is_some());
+
+        network.disconnect_signaling_server();
+        assert!(network.websocket.is_none() && network.websocket_callbacks.is_none());
+    }
+}
@@ -3,6 +3,10 @@ use serde::{Serialize, Deserialize};
 use rand::Rng;
 use std::collections::VecDeque;
 
+// Import the console_log macro
+use crate::console_log;
+use crate::utils::{relu, sigmoid, tanh_activation};
+
 /// Core threshold-gating node implementing forward-only learning
 /// Based on the research paper's specifications for biological plausibility
 #[wasm_bindgen]
@@ -10,6 +14,16 @@ use std::collections::VecDeque;
 pub struct ThresholdGatingNode {
     // Core accumulator and threshold mechanism
     accumulator: f64,
+    // Leak applied to the accumulator on every input; 1.0 integrates forever.
+    // Nodes saved before the leak existed keep integrating without one.
+    #[serde(default = "no_accumulator_decay")]
+    accumulator_decay: f64,
+    // Dendritic compartments, one sub-accumulator and local threshold each over a contiguous
+    // slice of the weights; empty means the flat single-accumulator path
+    #[serde(default)]
+    compartment_accumulators: Vec<f64>,
+    #[serde(default)]
+    compartment_thresholds: Vec<f64>,
     threshold: f64,
     timer: f64,
     time_to_release: f64,
@@ -18,9 +32,29 @@ pub struct ThresholdGatingNode {
     threshold_adaptation_rate: f64,
     timer_adaptation_rate: f64,
     
+    // Band that firing adaptation keeps the threshold within
+    threshold_floor: f64,
+    threshold_ceiling: Option<f64>,
+    // Upper bound on the timer interval, which threshold firings otherwise lengthen without limit
+    #[serde(default)]
+    time_to_release_max: Option<f64>,
+    
+    // Output scaling for timer firings (weaker evidence than a threshold crossing)
+    timer_fire_gain: f64,
+    
+    // What a threshold firing emits: the raw accumulator, the overshoot past threshold, or the threshold
+    #[serde(default)]
+    fire_output_mode: FireOutputMode,
+    
+    // Squashing applied to every firing's output, so activations can be kept bounded
+    #[serde(default)]
+    output_fn: OutputFunction,
+    
     // Eligibility trace for temporal credit assignment
     eligibility_trace: f64,
     eligibility_decay: f64,
+    max_eligibility_trace: f64, // Value the trace resets to on firing
+    eligibility_output_gating: bool, // Scale outputs by the trace left from earlier firings
     
     // Error signal input (forward-only, no backprop)
     error_input: f64,
@@ -28,8 +62,14 @@ pub struct ThresholdGatingNode {
     
     // Memory and history
     activation_history: VecDeque<f64>,
-    firing_history: VecDeque<(f64, FiringType)>, // (time, type)
+    firing_history: VecDeque<(f64, FiringType, u32)>, // (time, type, step)
     last_firing_time: f64,
+    // Minimum time between firings; the accumulator keeps integrating meanwhile
+    #[serde(default)]
+    refractory_period: f64,
+    spiked: bool, // Whether the node fired on the most recent step
+    activation_history_size: usize,
+    firing_history_size: usize,
     
     // Node parameters
     node_id: String,
@@ -48,54 +88,117 @@ pub enum FiringType {
     Timer,
 }
 
+/// Time step of each warmup activation
+pub const WARMUP_DELTA_TIME: f64 = 0.1;
+
+/// Accumulator leak per process_input call for new nodes; 1.0 means none, so leaking is opt-in
+pub const DEFAULT_ACCUMULATOR_DECAY: f64 = 1.0;
+
+fn no_accumulator_decay() -> f64 {
+    DEFAULT_ACCUMULATOR_DECAY
+}
+
+/// Output of a threshold firing
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum FireOutputMode {
+    #[default]
+    Raw,       // The full accumulator
+    Overshoot, // accumulator - threshold
+    Threshold, // The threshold that was crossed
+}
+
+/// Function applied to a firing's output before it is returned
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum OutputFunction {
+    #[default]
+    Raw,
+    Sigmoid,
+    Tanh,
+    ReLU,
+    Binary, // 1.0 whenever the node fires
+}
+
+impl OutputFunction {
+    pub fn from_name(name: &str) -> Option<OutputFunction> {
+        match name.to_ascii_lowercase().as_str() {
+            "raw" => Some(OutputFunction::Raw),
+            "sigmoid" => Some(OutputFunction::Sigmoid),
+            "tanh" => Some(OutputFunction::Tanh),
+            "relu" => Some(OutputFunction::ReLU),
+            "binary" => Some(OutputFunction::Binary),
+            _ => None,
+        }
+    }
+
+    pub fn apply(&self, value: f64) -> f64 {
+        match self {
+            OutputFunction::Raw => value,
+            OutputFunction::Sigmoid => sigmoid(value),
+            OutputFunction::Tanh => tanh_activation(value),
+            OutputFunction::ReLU => relu(value),
+            OutputFunction::Binary => 1.0,
+        }
+    }
+}
+
 #[wasm_bindgen]
 impl ThresholdGatingNode {
     #[wasm_bindgen(constructor)]
     pub fn new(node_id: String, input_size: usize) -> ThresholdGatingNode {
-        let mut rng = rand::thread_rng();
-        
-        ThresholdGatingNode {
-            accumulator: 0.0,
-            threshold: rng.gen_range(0.5..2.0), // Initial random threshold
-            timer: 0.0,
-            time_to_release: rng.gen_range(5.0..15.0), // Initial timer interval
-            
-            threshold_adaptation_rate: 0.01,
-            timer_adaptation_rate: 0.005,
-            
-            eligibility_trace: 0.0,
-            eligibility_decay: 0.95, // Exponential decay factor
-            
-            error_input: 0.0,
-            error_sensitivity: 0.1,
-            
-            activation_history: VecDeque::with_capacity(100),
-            firing_history: VecDeque::with_capacity(50),
-            last_firing_time: 0.0,
-            
-            node_id,
-            weights: (0..input_size).map(|_| rng.gen_range(-0.5..0.5)).collect(),
-            bias: rng.gen_range(-0.1..0.1),
-            
-            threshold_fires: 0,
-            timer_fires: 0,
-            total_activations: 0,
-        }
+        Self::new_with_rng(node_id, input_size, &mut rand::thread_rng())
     }
 
     /// Process input and return output (fires if threshold/timer condition met)
     #[wasm_bindgen]
     pub fn process_input(&mut self, inputs: &[f64], current_time: f64, delta_time: f64) -> f64 {
+        self.process_input_with_upstream(inputs, 0.0, current_time, delta_time)
+    }
+
+    /// Process a recorded stream in one call: `inputs_flat` holds consecutive rows of
+    /// `input_width` values, fed at start_time, start_time + delta_time, ... Returns one output
+    /// per row, or an empty vec if the length is not a whole number of rows
+    #[wasm_bindgen]
+    pub fn process_batch(&mut self, inputs_flat: &[f64], input_width: usize, start_time: f64, delta_time: f64) -> Vec<f64> {
+        if input_width == 0 || !inputs_flat.len().is_multiple_of(input_width) {
+            console_log!("Node {} rejected batch of {} values: not a multiple of input width {}",
+                        self.node_id, inputs_flat.len(), input_width);
+            return Vec::new();
+        }
+        inputs_flat.chunks(input_width)
+            .enumerate()
+            .map(|(step, row)| self.process_input(row, start_time + step as f64 * delta_time, delta_time))
+            .collect()
+    }
+
+    /// Process input plus already-weighted upstream node outputs, integrated before the firing decision.
+    /// The first activation resizes the weights to the input length; after that an input of a
+    /// different length is rejected (logged, node state untouched) and yields 0.0
+    #[wasm_bindgen]
+    pub fn process_input_with_upstream(&mut self, inputs: &[f64], upstream: f64, current_time: f64, delta_time: f64) -> f64 {
+        if inputs.len() != self.weights.len() {
+            if self.total_activations > 0 {
+                console_log!("Node {} rejected input of length {}: expected {} values",
+                            self.node_id, inputs.len(), self.weights.len());
+                return 0.0;
+            }
+            self.resize_inputs(inputs.len());
+        }
         self.total_activations += 1;
         
-        // Compute weighted input
-        let weighted_sum: f64 = inputs.iter()
-            .zip(self.weights.iter())
-            .map(|(input, weight)| input * weight)
-            .sum::<f64>() + self.bias;
+        // Compute weighted input, gated through the compartments when they are enabled
+        let dendritic_input = if self.compartment_thresholds.is_empty() {
+            inputs.iter()
+                .zip(self.weights.iter())
+                .map(|(input, weight)| input * weight)
+                .sum::<f64>()
+        } else {
+            self.integrate_compartments(inputs)
+        };
+        let weighted_sum = dendritic_input + self.bias + upstream;
         
-        // Add to accumulator
-        self.accumulator += weighted_sum;
+        // Leak, then add to accumulator; with decay < 1 a constant input settles at
+        // weighted_sum / (1 - decay) instead of growing without bound
+        self.accumulator = self.accumulator * self.accumulator_decay + weighted_sum;
         
         // Update timer
         self.timer += delta_time;
@@ -105,7 +208,7 @@ impl ThresholdGatingNode {
         
         // Store activation in history
         self.activation_history.push_back(weighted_sum);
-        if self.activation_history.len() > 100 {
+        if self.activation_history.len() > self.activation_history_size {
             self.activation_history.pop_front();
         }
         
@@ -114,24 +217,34 @@ impl ThresholdGatingNode {
         let mut fired = false;
         let mut firing_type = FiringType::Threshold;
         
+        // Inside the refractory window nothing fires or adapts; a window exactly elapsed allows firing again
+        let has_fired = self.threshold_fires + self.timer_fires > 0;
+        let refractory = has_fired && current_time - self.last_firing_time < self.refractory_period;
+        
         // Check threshold firing
-        if self.accumulator >= self.threshold {
+        if !refractory && self.accumulator >= self.threshold {
             output = self.fire(FiringType::Threshold, current_time);
             fired = true;
             firing_type = FiringType::Threshold;
         }
         // Check timer firing
-        else if self.timer >= self.time_to_release {
+        else if !refractory && self.timer >= self.time_to_release {
             output = self.fire(FiringType::Timer, current_time);
             fired = true;
             firing_type = FiringType::Timer;
         }
         
+        // Gate by the decayed trace before it is reset, so nodes that fired recently are emphasized
+        if fired && self.eligibility_output_gating {
+            output *= self.eligibility_trace / self.max_eligibility_trace;
+        }
+        
         // Update eligibility trace if fired
+        self.spiked = fired;
         if fired {
-            self.eligibility_trace = 1.0; // Reset to maximum on firing
-            self.firing_history.push_back((current_time, firing_type));
-            if self.firing_history.len() > 50 {
+            self.eligibility_trace = self.max_eligibility_trace; // Reset to maximum on firing
+            self.firing_history.push_back((current_time, firing_type, self.total_activations));
+            if self.firing_history.len() > self.firing_history_size {
                 self.firing_history.pop_front();
             }
         }
@@ -141,7 +254,17 @@ impl ThresholdGatingNode {
 
     /// Fire the node and adapt parameters according to paper's equations
     fn fire(&mut self, firing_type: FiringType, current_time: f64) -> f64 {
-        let output = self.accumulator; // Output is the accumulated value
+        // Output is the accumulated value (or its overshoot/threshold, per the output mode),
+        // scaled down for timer firings
+        let output = match firing_type {
+            FiringType::Threshold => match self.fire_output_mode {
+                FireOutputMode::Raw => self.accumulator,
+                FireOutputMode::Overshoot => self.accumulator - self.threshold,
+                FireOutputMode::Threshold => self.threshold,
+            },
+            FiringType::Timer => self.accumulator * self.timer_fire_gain,
+        };
+        let output = self.output_fn.apply(output);
         
         // Adaptation based on firing type (from paper's equations)
         match firing_type {
@@ -161,8 +284,9 @@ impl ThresholdGatingNode {
             }
         }
         
-        // Ensure threshold stays positive
-        self.threshold = self.threshold.max(0.1);
+        // Ensure threshold stays positive and within the configured band, and the timer below its cap
+        self.clamp_threshold();
+        self.clamp_time_to_release();
         
         // Reset accumulator and timer
         self.accumulator = 0.0;
@@ -172,6 +296,149 @@ impl ThresholdGatingNode {
         output
     }
 
+    fn clamp_threshold(&mut self) {
+        self.threshold = self.threshold.max(self.threshold_floor);
+        if let Some(ceiling) = self.threshold_ceiling {
+            self.threshold = self.threshold.min(ceiling);
+        }
+    }
+
+    fn clamp_time_to_release(&mut self) {
+        if let Some(max) = self.time_to_release_max {
+            self.time_to_release = self.time_to_release.min(max);
+        }
+    }
+
+    /// Cap the timer interval during adaptation; must be at least the 1.0 minimum interval
+    #[wasm_bindgen]
+    pub fn set_max_time_to_release(&mut self, max: f64) -> bool {
+        if !max.is_finite() || max < 1.0 {
+            return false;
+        }
+        self.time_to_release_max = Some(max);
+        self.clamp_time_to_release();
+        true
+    }
+
+    /// Keep the threshold within [min, max] during adaptation; min is never below 0.1
+    #[wasm_bindgen]
+    pub fn set_threshold_bounds(&mut self, min: f64, max: f64) -> bool {
+        let floor = min.max(0.1);
+        if max.is_nan() || max < floor {
+            return false;
+        }
+        self.threshold_floor = floor;
+        self.threshold_ceiling = Some(max);
+        self.clamp_threshold();
+        true
+    }
+
+    #[wasm_bindgen]
+    pub fn get_weights(&self) -> Vec<f64> {
+        self.weights.clone()
+    }
+
+    /// Replace the input weights, e.g. from a pretrained set; false (unchanged) if the length
+    /// differs from input_size or any weight is non-finite
+    #[wasm_bindgen]
+    pub fn set_weights(&mut self, weights: Vec<f64>) -> bool {
+        if weights.len() != self.weights.len() || !crate::utils::is_finite_vector(&weights) {
+            return false;
+        }
+        self.weights = weights;
+        true
+    }
+
+    #[wasm_bindgen]
+    pub fn get_bias(&self) -> f64 {
+        self.bias
+    }
+
+    #[wasm_bindgen]
+    pub fn set_bias(&mut self, bias: f64) -> bool {
+        if !bias.is_finite() {
+            return false;
+        }
+        self.bias = bias;
+        true
+    }
+
+    #[wasm_bindgen]
+    pub fn set_error_sensitivity(&mut self, sensitivity: f64) {
+        self.error_sensitivity = sensitivity;
+    }
+
+    /// Map a shared learning rate onto the threshold and timer adaptation rates
+    #[wasm_bindgen]
+    pub fn set_learning_params(&mut self, learning_rate: f64) {
+        self.threshold_adaptation_rate = learning_rate.clamp(0.001, 0.1);
+        self.timer_adaptation_rate = (0.5 * learning_rate).clamp(0.001, 0.05);
+    }
+
+    /// Scale each firing's output by the eligibility trace relative to its maximum
+    #[wasm_bindgen]
+    pub fn set_eligibility_output_gating(&mut self, enabled: bool) {
+        self.eligibility_output_gating = enabled;
+    }
+
+    #[wasm_bindgen]
+    pub fn set_max_eligibility_trace(&mut self, max_trace: f64) -> bool {
+        if max_trace.is_nan() || max_trace <= 0.0 {
+            return false;
+        }
+        self.max_eligibility_trace = max_trace;
+        self.eligibility_trace = self.eligibility_trace.min(max_trace);
+        true
+    }
+
+    /// Minimum time between firings (0.0 = none); negative or non-finite values are ignored
+    #[wasm_bindgen]
+    pub fn set_refractory_period(&mut self, period: f64) {
+        if period.is_finite() && period >= 0.0 {
+            self.refractory_period = period;
+        }
+    }
+
+    /// Fraction of the accumulator kept on each input, clamped to [0, 1] (1.0 = no leak)
+    #[wasm_bindgen]
+    pub fn set_accumulator_decay(&mut self, decay: f64) {
+        if decay.is_finite() {
+            self.accumulator_decay = decay.clamp(0.0, 1.0);
+        }
+    }
+
+    /// Set the gain applied to the output of timer firings (1.0 = same as threshold firings)
+    #[wasm_bindgen]
+    pub fn set_timer_fire_gain(&mut self, gain: f64) {
+        self.timer_fire_gain = gain.max(0.0);
+    }
+
+    /// What threshold firings emit: "raw" (accumulator, the default), "overshoot"
+    /// (accumulator - threshold) or "threshold". Timer firings always emit the scaled accumulator.
+    #[wasm_bindgen]
+    pub fn set_fire_output_mode(&mut self, mode: &str) -> bool {
+        self.fire_output_mode = match mode.to_ascii_lowercase().as_str() {
+            "raw" => FireOutputMode::Raw,
+            "overshoot" => FireOutputMode::Overshoot,
+            "threshold" => FireOutputMode::Threshold,
+            _ => return false,
+        };
+        true
+    }
+
+    /// Function applied to firing outputs: "raw" (the default), "sigmoid", "tanh", "relu" or
+    /// "binary" (1.0 on fire). Nodes that don't fire still output 0.0.
+    #[wasm_bindgen]
+    pub fn set_output_function(&mut self, name: &str) -> bool {
+        match OutputFunction::from_name(name) {
+            Some(output_fn) => {
+                self.output_fn = output_fn;
+                true
+            },
+            None => false,
+        }
+    }
+
     /// Update error signal and adapt learning rates (forward-only)
     #[wasm_bindgen]
     pub fn update_error(&mut self, error: f64) {
@@ -197,6 +464,168 @@ impl ThresholdGatingNode {
         }
     }
 
+    /// How many recent activations and firings are kept (defaults 100 and 50); at least 1 each
+    #[wasm_bindgen]
+    pub fn set_history_sizes(&mut self, activation: usize, firing: usize) {
+        self.activation_history_size = activation.max(1);
+        self.firing_history_size = firing.max(1);
+        while self.activation_history.len() > self.activation_history_size {
+            self.activation_history.pop_front();
+        }
+        while self.firing_history.len() > self.firing_history_size {
+            self.firing_history.pop_front();
+        }
+    }
+
+    /// Split the inputs into `num_compartments` contiguous groups, each integrated in its own
+    /// sub-accumulator that passes its value to the soma only on crossing a local threshold
+    /// (initially threshold / num_compartments). 0 restores the flat path; false if there are
+    /// more compartments than inputs.
+    #[wasm_bindgen]
+    pub fn enable_compartments(&mut self, num_compartments: usize) -> bool {
+        if num_compartments > self.weights.len() {
+            return false;
+        }
+        let local_threshold = self.threshold / num_compartments.max(1) as f64;
+        self.compartment_accumulators = vec![0.0; num_compartments];
+        self.compartment_thresholds = vec![local_threshold; num_compartments];
+        true
+    }
+
+    /// Set the local threshold of one compartment; false if it doesn't exist or threshold isn't positive
+    #[wasm_bindgen]
+    pub fn set_compartment_threshold(&mut self, compartment: usize, threshold: f64) -> bool {
+        match self.compartment_thresholds.get_mut(compartment) {
+            Some(local) if threshold.is_finite() && threshold > 0.0 => {
+                *local = threshold;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn num_compartments(&self) -> usize {
+        self.compartment_thresholds.len()
+    }
+
+    /// Clear transient state (accumulator, timer, traces, error input, histories) so the node can
+    /// be reused; learned weights, bias, thresholds and firing counters are kept
+    #[wasm_bindgen]
+    pub fn reset(&mut self) {
+        self.accumulator = 0.0;
+        self.timer = 0.0;
+        self.compartment_accumulators.iter_mut().for_each(|value| *value = 0.0);
+        self.eligibility_trace = 0.0;
+        self.error_input = 0.0;
+        self.activation_history.clear();
+        self.firing_history.clear();
+        self.last_firing_time = 0.0;
+        self.spiked = false;
+    }
+
+    /// Run `steps` short (WARMUP_DELTA_TIME) activations on `inputs` to settle the timer and
+    /// accumulator before real processing; time is held at the node's last firing time
+    #[wasm_bindgen]
+    pub fn warmup(&mut self, inputs: &[f64], steps: usize) {
+        self.warmup_with(steps, || inputs.to_vec());
+    }
+
+    #[wasm_bindgen]
+    pub fn activation_history_len(&self) -> usize {
+        self.activation_history.len()
+    }
+
+    /// Binary spike train over the last `window` steps (oldest first), 1 where the node fired.
+    /// Reconstructed from firing_history, so spikes older than its size cap read as 0.
+    #[wasm_bindgen]
+    pub fn get_recent_spike_train(&self, window: u32) -> Vec<u8> {
+        let window = window.min(self.total_activations);
+        let first_step = self.total_activations - window + 1;
+        let mut spike_train = vec![0u8; window as usize];
+        
+        for &(_, _, step) in &self.firing_history {
+            if step >= first_step {
+                spike_train[(step - first_step) as usize] = 1;
+            }
+        }
+        
+        spike_train
+    }
+
+    #[wasm_bindgen]
+    pub fn input_size(&self) -> usize {
+        self.weights.len()
+    }
+
+    /// Change the number of inputs, keeping the existing weights and drawing new ones at random
+    #[wasm_bindgen]
+    pub fn resize_inputs(&mut self, new_size: usize) {
+        self.resize_inputs_with_rng(new_size, &mut rand::thread_rng());
+    }
+
+    /// Number of learned parameters: one weight per input plus the bias
+    #[wasm_bindgen]
+    pub fn parameter_count(&self) -> usize {
+        self.weights.len() + 1
+    }
+
+    /// Approximate heap + inline size of this node in bytes
+    #[wasm_bindgen]
+    pub fn memory_footprint_estimate(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.node_id.capacity()
+            + self.weights.capacity() * std::mem::size_of::<f64>()
+            + self.activation_history.capacity() * std::mem::size_of::<f64>()
+            + self.firing_history.capacity() * std::mem::size_of::<(f64, FiringType, u32)>()
+    }
+
+    /// Versioned JSON of everything that shapes this node's outputs: weights, bias,
+    /// threshold and timer parameters, adaptation rates, and the live accumulator/timer state
+    #[wasm_bindgen]
+    pub fn export_weights(&self) -> String {
+        serde_json::to_string(&self.parameters()).unwrap_or_default()
+    }
+
+    /// Load an export_weights document; rejected (node untouched) if the version, input size,
+    /// or any value is invalid. The node keeps its own id.
+    #[wasm_bindgen]
+    pub fn load_weights(&mut self, json: &str) -> bool {
+        match serde_json::from_str::<NodeParameters>(json) {
+            Ok(params) => self.apply_parameters(&params),
+            Err(e) => {
+                console_log!("Failed to parse node weights: {:?}", e);
+                false
+            }
+        }
+    }
+
+    /// The whole node, learned parameters, live state and histories included, as JSON
+    #[wasm_bindgen]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// Rebuild a node from `to_json` output, e.g. one lent by a peer; None if it does not
+    /// parse or its parameters or histories are inconsistent
+    #[wasm_bindgen]
+    pub fn from_json(json: &str) -> Option<ThresholdGatingNode> {
+        let node: ThresholdGatingNode = match serde_json::from_str(json) {
+            Ok(node) => node,
+            Err(e) => {
+                console_log!("Failed to parse node JSON: {:?}", e);
+                return None;
+            }
+        };
+        match node.validate_parameters(&node.parameters()).and_then(|_| node.validate_histories()) {
+            Ok(()) => Some(node),
+            Err(e) => {
+                console_log!("Rejected node JSON for {}: {}", node.node_id, e);
+                None
+            }
+        }
+    }
+
     /// Get current node state for monitoring and debugging
     #[wasm_bindgen]
     pub fn get_state(&self) -> String {
@@ -228,6 +657,18 @@ impl ThresholdGatingNode {
     #[wasm_bindgen(getter)]
     pub fn eligibility_trace(&self) -> f64 { self.eligibility_trace }
     
+    #[wasm_bindgen(getter)]
+    pub fn error_sensitivity(&self) -> f64 { self.error_sensitivity }
+    
+    #[wasm_bindgen(getter)]
+    pub fn threshold_adaptation_rate(&self) -> f64 { self.threshold_adaptation_rate }
+    
+    #[wasm_bindgen(getter)]
+    pub fn timer_adaptation_rate(&self) -> f64 { self.timer_adaptation_rate }
+    
+    #[wasm_bindgen(getter)]
+    pub fn spiked(&self) -> bool { self.spiked }
+    
     #[wasm_bindgen(getter)]
     pub fn threshold_fires(&self) -> u32 { self.threshold_fires }
     
@@ -235,6 +676,287 @@ impl ThresholdGatingNode {
     pub fn timer_fires(&self) -> u32 { self.timer_fires }
 }
 
+impl ThresholdGatingNode {
+    // Leak and charge each compartment from its slice of the inputs; compartments at or past
+    // their local threshold hand their value to the soma and restart from zero
+    fn integrate_compartments(&mut self, inputs: &[f64]) -> f64 {
+        let num_compartments = self.compartment_thresholds.len();
+        let num_inputs = self.weights.len();
+        let mut soma_input = 0.0;
+        for compartment in 0..num_compartments {
+            let start = compartment * num_inputs / num_compartments;
+            let end = (compartment + 1) * num_inputs / num_compartments;
+            let local_sum: f64 = inputs[start..end].iter()
+                .zip(&self.weights[start..end])
+                .map(|(input, weight)| input * weight)
+                .sum();
+            
+            let sub_accumulator = &mut self.compartment_accumulators[compartment];
+            *sub_accumulator = *sub_accumulator * self.accumulator_decay + local_sum;
+            if *sub_accumulator >= self.compartment_thresholds[compartment] {
+                soma_input += *sub_accumulator;
+                *sub_accumulator = 0.0;
+            }
+        }
+        soma_input
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// Like `new`, drawing the random initial threshold, timer, weights and bias from `rng`
+    pub(crate) fn new_with_rng<R: Rng + ?Sized>(node_id: String, input_size: usize, rng: &mut R) -> ThresholdGatingNode {
+        ThresholdGatingNode {
+            accumulator: 0.0,
+            accumulator_decay: DEFAULT_ACCUMULATOR_DECAY,
+            compartment_accumulators: Vec::new(),
+            compartment_thresholds: Vec::new(),
+            threshold: rng.gen_range(0.5..2.0), // Initial random threshold
+            timer: 0.0,
+            time_to_release: rng.gen_range(5.0..15.0), // Initial timer interval
+            
+            threshold_adaptation_rate: 0.01,
+            timer_adaptation_rate: 0.005,
+            
+            threshold_floor: 0.1,
+            threshold_ceiling: None,
+            time_to_release_max: None,
+            
+            timer_fire_gain: 1.0,
+            
+            fire_output_mode: FireOutputMode::Raw,
+            output_fn: OutputFunction::Raw,
+            
+            eligibility_trace: 0.0,
+            eligibility_decay: 0.95, // Exponential decay factor
+            max_eligibility_trace: 1.0,
+            eligibility_output_gating: false,
+            
+            error_input: 0.0,
+            error_sensitivity: 0.1,
+            
+            activation_history: VecDeque::with_capacity(100),
+            firing_history: VecDeque::with_capacity(50),
+            last_firing_time: 0.0,
+            refractory_period: 0.0,
+            spiked: false,
+            activation_history_size: 100,
+            firing_history_size: 50,
+            
+            node_id,
+            weights: (0..input_size).map(|_| rng.gen_range(-0.5..0.5)).collect(),
+            bias: rng.gen_range(-0.1..0.1),
+            
+            threshold_fires: 0,
+            timer_fires: 0,
+            total_activations: 0,
+        }
+    }
+
+    /// Like `resize_inputs`, drawing the new weights from `rng`
+    pub(crate) fn resize_inputs_with_rng<R: Rng + ?Sized>(&mut self, new_size: usize, rng: &mut R) {
+        let current = self.weights.len();
+        self.weights.truncate(new_size);
+        self.weights.extend((current..new_size).map(|_| rng.gen_range(-0.5..0.5)));
+        // At most one compartment per input
+        self.compartment_accumulators.truncate(new_size);
+        self.compartment_thresholds.truncate(new_size);
+    }
+
+    /// Whether the node has processed any input yet (only then may its input width still change)
+    pub(crate) fn has_processed_input(&self) -> bool {
+        self.total_activations > 0
+    }
+
+    pub fn parameters(&self) -> NodeParameters {
+        NodeParameters {
+            version: NODE_PARAMETERS_VERSION,
+            weights: self.weights.clone(),
+            bias: self.bias,
+            threshold: self.threshold,
+            time_to_release: self.time_to_release,
+            threshold_adaptation_rate: self.threshold_adaptation_rate,
+            timer_adaptation_rate: self.timer_adaptation_rate,
+            threshold_floor: self.threshold_floor,
+            threshold_ceiling: self.threshold_ceiling,
+            time_to_release_max: self.time_to_release_max,
+            timer_fire_gain: self.timer_fire_gain,
+            accumulator_decay: self.accumulator_decay,
+            compartment_thresholds: self.compartment_thresholds.clone(),
+            compartment_accumulators: self.compartment_accumulators.clone(),
+            refractory_period: self.refractory_period,
+            fire_output_mode: self.fire_output_mode,
+            output_fn: self.output_fn,
+            eligibility_decay: self.eligibility_decay,
+            max_eligibility_trace: self.max_eligibility_trace,
+            eligibility_output_gating: self.eligibility_output_gating,
+            error_sensitivity: self.error_sensitivity,
+            accumulator: self.accumulator,
+            timer: self.timer,
+            eligibility_trace: self.eligibility_trace,
+            error_input: self.error_input,
+            last_firing_time: self.last_firing_time,
+        }
+    }
+
+    /// Check `params` against this node's input size and value ranges
+    pub fn validate_parameters(&self, params: &NodeParameters) -> Result<(), String> {
+        if params.version != NODE_PARAMETERS_VERSION {
+            return Err(format!("unsupported parameter version {}", params.version));
+        }
+        if params.weights.len() != self.weights.len() {
+            return Err(format!("expected {} weights, got {}", self.weights.len(), params.weights.len()));
+        }
+        let mut values = params.weights.iter().chain([
+            &params.bias, &params.threshold, &params.time_to_release,
+            &params.threshold_adaptation_rate, &params.timer_adaptation_rate,
+            &params.threshold_floor, &params.timer_fire_gain, &params.accumulator_decay,
+            &params.refractory_period, &params.eligibility_decay,
+            &params.max_eligibility_trace, &params.error_sensitivity, &params.accumulator,
+            &params.timer, &params.eligibility_trace, &params.error_input, &params.last_firing_time,
+        ]).chain(params.threshold_ceiling.iter()).chain(params.time_to_release_max.iter())
+            .chain(&params.compartment_thresholds).chain(&params.compartment_accumulators);
+        if values.any(|v| !v.is_finite()) {
+            return Err("non-finite value".to_string());
+        }
+        if params.threshold <= 0.0 || params.time_to_release <= 0.0 || params.threshold_floor <= 0.0 {
+            return Err("threshold, time_to_release and threshold_floor must be positive".to_string());
+        }
+        if params.threshold_ceiling.is_some_and(|ceiling| ceiling < params.threshold_floor) {
+            return Err("threshold_ceiling is below threshold_floor".to_string());
+        }
+        if params.compartment_accumulators.len() != params.compartment_thresholds.len()
+            || params.compartment_thresholds.len() > params.weights.len()
+            || params.compartment_thresholds.iter().any(|threshold| *threshold <= 0.0)
+        {
+            return Err("compartments must each have an accumulator and a positive threshold, at most one per input".to_string());
+        }
+        if params.time_to_release_max.is_some_and(|max| max < 1.0) {
+            return Err("time_to_release_max is below the 1.0 minimum interval".to_string());
+        }
+        if params.threshold_adaptation_rate < 0.0 || params.timer_adaptation_rate < 0.0 {
+            return Err("adaptation rates must be non-negative".to_string());
+        }
+        if !(0.0..=1.0).contains(&params.accumulator_decay) {
+            return Err("accumulator_decay must be in [0, 1]".to_string());
+        }
+        if params.refractory_period < 0.0 {
+            return Err("refractory_period must be non-negative".to_string());
+        }
+        if !(0.0..=1.0).contains(&params.eligibility_decay) || params.max_eligibility_trace <= 0.0 {
+            return Err("eligibility_decay must be in [0, 1] and max_eligibility_trace positive".to_string());
+        }
+        Ok(())
+    }
+
+    /// `warmup` drawing a fresh input for every step. The refractory period is lifted while
+    /// warming up, since time does not advance and the node could otherwise never fire.
+    pub fn warmup_with(&mut self, steps: usize, mut next_input: impl FnMut() -> Vec<f64>) {
+        let current_time = self.last_firing_time;
+        let refractory_period = std::mem::take(&mut self.refractory_period);
+        for _ in 0..steps {
+            self.process_input(&next_input(), current_time, WARMUP_DELTA_TIME);
+        }
+        self.refractory_period = refractory_period;
+    }
+
+    // History bounds the rest of the node relies on, e.g. get_recent_spike_train indexing by step
+    fn validate_histories(&self) -> Result<(), String> {
+        if self.activation_history_size == 0 || self.firing_history_size == 0 {
+            return Err("history sizes must be at least 1".to_string());
+        }
+        if self.activation_history.len() > self.activation_history_size
+            || self.firing_history.len() > self.firing_history_size
+        {
+            return Err("history longer than its size cap".to_string());
+        }
+        if self.firing_history.iter().any(|&(time, _, step)| !time.is_finite() || step > self.total_activations) {
+            return Err("firing history step beyond total_activations".to_string());
+        }
+        if self.activation_history.iter().any(|value| !value.is_finite()) {
+            return Err("non-finite activation history".to_string());
+        }
+        Ok(())
+    }
+
+    /// Validate and apply exported parameters; returns false and leaves the node unchanged on failure
+    pub fn apply_parameters(&mut self, params: &NodeParameters) -> bool {
+        if let Err(e) = self.validate_parameters(params) {
+            console_log!("Rejected weights for node {}: {}", self.node_id, e);
+            return false;
+        }
+        self.weights = params.weights.clone();
+        self.bias = params.bias;
+        self.threshold = params.threshold;
+        self.time_to_release = params.time_to_release;
+        self.threshold_adaptation_rate = params.threshold_adaptation_rate;
+        self.timer_adaptation_rate = params.timer_adaptation_rate;
+        self.threshold_floor = params.threshold_floor;
+        self.threshold_ceiling = params.threshold_ceiling;
+        self.time_to_release_max = params.time_to_release_max;
+        self.timer_fire_gain = params.timer_fire_gain;
+        self.accumulator_decay = params.accumulator_decay;
+        self.compartment_thresholds = params.compartment_thresholds.clone();
+        self.compartment_accumulators = params.compartment_accumulators.clone();
+        self.refractory_period = params.refractory_period;
+        self.fire_output_mode = params.fire_output_mode;
+        self.output_fn = params.output_fn;
+        self.eligibility_decay = params.eligibility_decay;
+        self.max_eligibility_trace = params.max_eligibility_trace;
+        self.eligibility_output_gating = params.eligibility_output_gating;
+        self.error_sensitivity = params.error_sensitivity;
+        self.accumulator = params.accumulator;
+        self.timer = params.timer;
+        self.eligibility_trace = params.eligibility_trace;
+        self.error_input = params.error_input;
+        self.last_firing_time = params.last_firing_time;
+        true
+    }
+}
+
+/// Current schema version of NodeParameters
+pub const NODE_PARAMETERS_VERSION: u32 = 1;
+
+/// Exported node behavior: learned parameters, firing configuration, and live state
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NodeParameters {
+    pub version: u32,
+    pub weights: Vec<f64>,
+    pub bias: f64,
+    pub threshold: f64,
+    pub time_to_release: f64,
+    pub threshold_adaptation_rate: f64,
+    pub timer_adaptation_rate: f64,
+    pub threshold_floor: f64,
+    pub threshold_ceiling: Option<f64>,
+    #[serde(default)]
+    pub time_to_release_max: Option<f64>,
+    pub timer_fire_gain: f64,
+    #[serde(default = "no_accumulator_decay")]
+    pub accumulator_decay: f64,
+    #[serde(default)]
+    pub compartment_thresholds: Vec<f64>,
+    #[serde(default)]
+    pub compartment_accumulators: Vec<f64>, // Live state, like accumulator
+    #[serde(default)]
+    pub refractory_period: f64,
+    #[serde(default)]
+    pub fire_output_mode: FireOutputMode,
+    #[serde(default)]
+    pub output_fn: OutputFunction,
+    pub eligibility_decay: f64,
+    pub max_eligibility_trace: f64,
+    pub eligibility_output_gating: bool,
+    pub error_sensitivity: f64,
+    // Live state, so a loaded node continues exactly where its source left off
+    pub accumulator: f64,
+    pub timer: f64,
+    pub eligibility_trace: f64,
+    pub error_input: f64,
+    pub last_firing_time: f64,
+}
+
 #[derive(Serialize, Deserialize)]
 struct NodeState {
     node_id: String,
@@ -248,4 +970,270 @@ struct NodeState {
     timer_fires: u32,
     total_activations: u32,
     adaptation_rate: f64,
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A node that has fired, serialized as a JSON value for tampering
+    fn fired_node_json() -> serde_json::Value {
+        let mut node = ThresholdGatingNode::new("node".to_string(), 4);
+        node.enable_compartments(2);
+        for step in 0..20 {
+            node.process_input(&[1.0; 4], step as f64 * 0.1, 0.1);
+        }
+        serde_json::from_str(&node.to_json()).unwrap()
+    }
+
+    #[test]
+    fn from_json_round_trips_a_valid_node() {
+        let json = fired_node_json();
+        let node = ThresholdGatingNode::from_json(&json.to_string()).unwrap();
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&node.to_json()).unwrap(), json);
+    }
+
+    #[test]
+    fn from_json_rejects_mismatched_compartments() {
+        let mut json = fired_node_json();
+        json["compartment_accumulators"] = serde_json::json!([0.0]);
+        assert!(ThresholdGatingNode::from_json(&json.to_string()).is_none());
+    }
+
+    #[test]
+    fn from_json_rejects_firing_steps_beyond_total_activations() {
+        let mut json = fired_node_json();
+        json["firing_history"] = serde_json::json!([[0.0, "Threshold", 1000]]);
+        assert!(ThresholdGatingNode::from_json(&json.to_string()).is_none());
+    }
+
+    #[test]
+    fn from_json_rejects_invalid_parameters() {
+        let mut json = fired_node_json();
+        json["threshold"] = serde_json::json!(-1.0);
+        assert!(ThresholdGatingNode::from_json(&json.to_string()).is_none());
+    }
+
+    // Unit weights, no bias or leak, and a threshold pinned at 1.0 so firing is predictable
+    fn pinned_node(input_size: usize) -> ThresholdGatingNode {
+        let mut node = ThresholdGatingNode::new("node".to_string(), input_size);
+        assert!(node.set_weights(vec![1.0; input_size]));
+        assert!(node.set_bias(0.0));
+        assert!(node.set_threshold_bounds(1.0, 1.0));
+        node.set_accumulator_decay(1.0);
+        node
+    }
+
+    #[test]
+    fn spike_train_marks_exactly_the_firing_steps() {
+        let mut node = pinned_node(1);
+        let firing_steps = [1, 4, 5];
+        for step in 0..8 {
+            let input = if firing_steps.contains(&step) { 2.0 } else { 0.0 };
+            node.process_input(&[input], step as f64 * 0.1, 0.1);
+        }
+        assert_eq!(node.get_recent_spike_train(8), vec![0, 1, 0, 0, 1, 1, 0, 0]);
+        assert_eq!(node.get_recent_spike_train(3), vec![1, 0, 0]);
+        assert_eq!(node.get_recent_spike_train(100).len(), 8);
+    }
+
+    #[test]
+    fn timer_fires_are_scaled_by_the_gain() {
+        let mut timer_node = pinned_node(1);
+        timer_node.set_timer_fire_gain(0.25);
+        // Below threshold, but far past the release time
+        let output = timer_node.process_input(&[0.5], 0.0, 1e6);
+        assert_eq!(timer_node.threshold_fires(), 0);
+        assert!((output - 0.25 * 0.5).abs() < 1e-12);
+
+        let mut threshold_node = pinned_node(1);
+        threshold_node.set_timer_fire_gain(0.25);
+        let output = threshold_node.process_input(&[2.0], 0.0, 0.1);
+        assert_eq!(threshold_node.threshold_fires(), 1);
+        assert!((output - 2.0).abs() < 1e-12);
+    }
+
+    // A node with positive weights that has already fired once, inside a long refractory period
+    fn refractory_node() -> ThresholdGatingNode {
+        let mut node = ThresholdGatingNode::new("node".to_string(), 2);
+        assert!(node.set_weights(vec![1.0, 1.0]));
+        assert!(node.set_bias(0.0));
+        node.set_refractory_period(1_000.0);
+        while node.threshold_fires() == 0 {
+            node.process_input(&[5.0, 5.0], 0.0, 0.1);
+        }
+        node
+    }
+
+    #[test]
+    fn warmup_can_fire_during_the_refractory_period() {
+        let mut node = refractory_node();
+        let fires = node.threshold_fires();
+        node.process_input(&[5.0, 5.0], 0.0, 0.1);
+        assert_eq!(node.threshold_fires(), fires);
+
+        node.warmup(&[5.0, 5.0], 3);
+        assert!(node.threshold_fires() > fires);
+
+        // The period itself is restored afterwards
+        let fires = node.threshold_fires();
+        node.process_input(&[5.0, 5.0], 0.0, 0.1);
+        assert_eq!(node.threshold_fires(), fires);
+    }
+
+    #[test]
+    fn warmup_with_draws_an_input_per_step() {
+        let mut node = ThresholdGatingNode::new("node".to_string(), 2);
+        let mut draws = 0;
+        node.warmup_with(4, || {
+            draws += 1;
+            vec![draws as f64, 0.0]
+        });
+        assert_eq!(draws, 4);
+        assert_eq!(node.activation_history_len(), 4);
+        let distinct: std::collections::HashSet<u64> = node.activation_history.iter().map(|v| v.to_bits()).collect();
+        assert_eq!(distinct.len(), 4);
+    }
+
+
+    #[test]
+    fn gated_output_shrinks_with_the_decayed_trace() {
+        let mut gated = pinned_node(1);
+        gated.set_eligibility_output_gating(true);
+        let mut plain = pinned_node(1);
+
+        // Fire, idle for four steps, then fire again with the trace decayed five times
+        let mut outputs = (0.0, 0.0);
+        for step in 0..6 {
+            let input = if step == 0 || step == 5 { 2.0 } else { 0.0 };
+            outputs = (gated.process_input(&[input], step as f64 * 0.1, 0.1), plain.process_input(&[input], step as f64 * 0.1, 0.1));
+        }
+        assert!(outputs.1 > 0.0);
+        assert!((outputs.0 - outputs.1 * 0.95_f64.powi(5)).abs() < 1e-12);
+        assert_eq!(gated.eligibility_trace(), 1.0);
+    }
+
+
+    #[test]
+    fn a_larger_activation_history_retains_more_entries() {
+        let mut default_node = pinned_node(1);
+        let mut larger = pinned_node(1);
+        larger.set_history_sizes(200, 50);
+        for step in 0..150 {
+            default_node.process_input(&[0.0], step as f64 * 0.1, 0.1);
+            larger.process_input(&[0.0], step as f64 * 0.1, 0.1);
+        }
+        assert_eq!(default_node.activation_history.len(), 100);
+        assert_eq!(larger.activation_history.len(), 150);
+
+        larger.set_history_sizes(20, 5);
+        assert_eq!(larger.activation_history.len(), 20);
+        assert!(larger.firing_history.len() <= 5);
+    }
+
+
+    #[test]
+    fn weight_round_trip_reproduces_outputs_and_firing_types() {
+        let mut source = ThresholdGatingNode::new("source".to_string(), 4);
+        assert!(source.set_bias(0.1));
+        for step in 0..30 {
+            source.process_input(&[(step % 4) as f64 * 0.3; 4], step as f64, 1.0);
+        }
+        let mut loaded = ThresholdGatingNode::new("loaded".to_string(), 4);
+        assert!(loaded.load_weights(&source.export_weights()));
+        source.firing_history.clear();
+
+        for step in 30..90 {
+            let inputs = [(step % 5) as f64 * 0.4; 4];
+            assert_eq!(source.process_input(&inputs, step as f64, 1.0), loaded.process_input(&inputs, step as f64, 1.0));
+        }
+        let types = |node: &ThresholdGatingNode| -> Vec<String> {
+            node.firing_history.iter().map(|(_, firing_type, _)| format!("{:?}", firing_type)).collect()
+        };
+        assert!(!types(&source).is_empty());
+        assert_eq!(types(&source), types(&loaded));
+        assert!(!loaded.load_weights(&ThresholdGatingNode::new("wide".to_string(), 6).export_weights()));
+    }
+
+
+    #[test]
+    fn overshoot_mode_outputs_the_excess_over_threshold() {
+        let first_output = |mode: &str| {
+            let mut node = pinned_node(1);
+            assert!(node.set_fire_output_mode(mode));
+            let output = node.process_input(&[3.5], 0.0, 0.1);
+            assert_eq!(node.threshold_fires(), 1);
+            output
+        };
+        assert_eq!(first_output("overshoot"), 2.5);
+        assert_eq!(first_output("raw"), 3.5);
+        assert_eq!(first_output("threshold"), 1.0);
+        assert!(!pinned_node(1).set_fire_output_mode("clipped"));
+    }
+
+
+    #[test]
+    fn a_four_weight_node_adopts_an_eight_value_first_input() {
+        let mut node = ThresholdGatingNode::new("node".to_string(), 4);
+        let original = node.get_weights();
+        node.process_input(&[0.1; 8], 0.0, 0.1);
+        let weights = node.get_weights();
+        assert_eq!(weights.len(), 8);
+        assert_eq!(weights[..4], original[..]);
+
+        // Once active, a differently sized input is rejected without touching the node
+        let activations = node.total_activations;
+        assert_eq!(node.process_input(&[5.0; 4], 0.1, 0.1), 0.0);
+        assert_eq!(node.total_activations, activations);
+        assert_eq!(node.get_weights().len(), 8);
+
+        node.resize_inputs(6);
+        assert_eq!(node.get_weights(), weights[..6]);
+    }
+
+
+    #[test]
+    fn leaky_accumulator_settles_below_threshold() {
+        // delta_time 0 keeps the timer from firing, so only the accumulator is in play
+        let mut leaky = pinned_node(1);
+        leaky.set_accumulator_decay(0.9);
+        let mut lossless = pinned_node(1);
+        for step in 0..500 {
+            leaky.process_input(&[0.05], step as f64, 0.0);
+            lossless.process_input(&[0.05], step as f64, 0.0);
+            assert!(leaky.accumulator() <= 0.5 + 1e-12);
+        }
+        assert!((leaky.accumulator() - 0.5).abs() < 1e-9);
+        assert_eq!(leaky.threshold_fires(), 0);
+        assert!(lossless.threshold_fires() > 0);
+
+        leaky.set_accumulator_decay(1.5);
+        assert_eq!(leaky.accumulator_decay, 1.0);
+    }
+
+
+    #[test]
+    fn hammered_thresholds_and_timers_stay_within_their_caps() {
+        let mut node = ThresholdGatingNode::new("node".to_string(), 2);
+        assert!(node.set_weights(vec![1.0; 2]));
+        assert!(node.set_threshold_bounds(0.5, 2.0));
+        assert!(node.set_max_time_to_release(12.0));
+        assert!(!node.set_threshold_bounds(3.0, 2.0));
+        assert!(!node.set_max_time_to_release(0.5));
+
+        for step in 0..2_000 {
+            node.process_input(&[50.0; 2], step as f64, 1.0);
+            assert!(node.threshold() <= 2.0);
+            assert!(node.time_to_release <= 12.0);
+        }
+        assert_eq!(node.threshold(), 2.0);
+        assert!(node.threshold_fires() > 1_000);
+    }
+
+
+    #[test]
+    fn new_nodes_integrate_without_a_leak() {
+        let node = ThresholdGatingNode::new("node".to_string(), 4);
+        assert_eq!(node.accumulator_decay, 1.0);
+        assert_eq!(node.parameters().accumulator_decay, 1.0);
+    }
+}
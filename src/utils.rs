@@ -1,4 +1,12 @@
 use wasm_bindgen::prelude::*;
+use sha2::{Digest, Sha256};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+use std::sync::OnceLock;
+use regex::{Captures, Regex};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
 
 // Import the `console.log` function from the browser
 #[wasm_bindgen]
@@ -10,12 +18,31 @@ extern "C" {
 // Define a macro for easier console logging
 #[macro_export]
 macro_rules! console_log {
-    ($($t:tt)*) => (crate::utils::console_log_impl(&format_args!($($t)*).to_string()))
+    ($($t:tt)*) => ($crate::utils::console_log_impl(&format_args!($($t)*).to_string()))
 }
 
 // Re-export log function for the macro with a different name to avoid conflict
+// Native builds (unit tests) have no console, so they log to stderr
 pub fn console_log_impl(s: &str) {
+    #[cfg(target_arch = "wasm32")]
     log(s);
+    #[cfg(not(target_arch = "wasm32"))]
+    eprintln!("{}", s);
+}
+
+// Whole milliseconds since the Unix epoch; Date.now() in the browser, the system clock natively
+pub fn now_ms() -> f64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        js_sys::Date::now()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as f64)
+            .unwrap_or(0.0)
+    }
 }
 
 // Set up better panic messages for debugging
@@ -40,6 +67,11 @@ pub fn normalize_vector(vec: &mut [f64]) {
     }
 }
 
+// Check that every component is a finite number (no NaN or Inf)
+pub fn is_finite_vector(vec: &[f64]) -> bool {
+    vec.iter().all(|x| x.is_finite())
+}
+
 pub fn sigmoid(x: f64) -> f64 {
     1.0 / (1.0 + (-x).exp())
 }
@@ -65,37 +97,131 @@ pub fn simple_hash(input: &str) -> u64 {
     hash
 }
 
+// SHA-256 digest as 64 lowercase hex characters
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex_encode(&Sha256::digest(data))
+}
+
+// Lowercase hex encoding of arbitrary bytes
+pub fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Inverse of hex_encode; None on odd length or non-hex characters
+pub fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+// Hex ed25519 signature over `message`
+pub fn sign_hex(key: &SigningKey, message: &str) -> String {
+    hex_encode(&key.sign(message.as_bytes()).to_bytes())
+}
+
+// Whether `signature` is a hex ed25519 signature over `message` by the hex `public_key`
+pub fn verify_hex(public_key: &str, message: &str, signature: &str) -> bool {
+    let Some(key) = parse_verifying_key(public_key) else {
+        return false;
+    };
+    hex_decode(signature)
+        .and_then(|bytes| <[u8; 64]>::try_from(bytes).ok())
+        .is_some_and(|bytes| key.verify_strict(message.as_bytes(), &Signature::from_bytes(&bytes)).is_ok())
+}
+
+// Hex-encoded ed25519 verifying key, e.g. PeerInfo::public_key
+pub fn parse_verifying_key(public_key: &str) -> Option<VerifyingKey> {
+    let bytes = <[u8; 32]>::try_from(hex_decode(public_key)?).ok()?;
+    VerifyingKey::from_bytes(&bytes).ok()
+}
+
 // Generate a unique ID based on timestamp and random component
 pub fn generate_unique_id(prefix: &str) -> String {
     use rand::Rng;
     let mut rng = rand::thread_rng();
-    let timestamp = js_sys::Date::now() as u64;
+    let timestamp = now_ms() as u64;
     let random_part: u32 = rng.gen();
     format!("{}_{:x}_{:x}", prefix, timestamp, random_part)
 }
 
 // Semantic masking for privacy protection
+// Patterns are tried as one alternation, so matching is leftmost-first: the earliest match in the
+// text wins, ties at the same position go to the earlier pattern, and every occurrence is replaced
+const MASK_PATTERNS: [(&str, &str); 5] = [
+    (r"\b[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}\b", "[EMAIL]"),
+    (r"\b\d{4}[\s-]?\d{4}[\s-]?\d{4}[\s-]?\d{4}\b", "[CREDIT_CARD]"),
+    (r"\b\d{3}-\d{2}-\d{4}\b", "[SSN]"),
+    (r"\b\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3}\b", "[IP_ADDRESS]"),
+    (r"\b[A-Z][a-z]+ [A-Z][a-z]+\b", "[PERSON_NAME]"),
+];
+
+// MASK_PATTERNS compiled once, each pattern in its own capture group
+fn mask_regex() -> &'static Regex {
+    static MASK_REGEX: OnceLock<Regex> = OnceLock::new();
+    MASK_REGEX.get_or_init(|| {
+        let alternation: Vec<String> = MASK_PATTERNS.iter().map(|(pattern, _)| format!("({})", pattern)).collect();
+        Regex::new(&alternation.join("|")).expect("mask patterns are valid")
+    })
+}
+
 pub fn apply_semantic_mask(text: &str) -> String {
-    let mut masked = text.to_string();
-    
-    // Simple patterns for demonstration
-    // In a real implementation, this would use NLP models
-    let patterns = vec![
-        (r"\b[A-Z][a-z]+ [A-Z][a-z]+\b", "[PERSON_NAME]"),
-        (r"\b\d{3}-\d{2}-\d{4}\b", "[SSN]"),
-        (r"\b\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3}\b", "[IP_ADDRESS]"),
-        (r"\b[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}\b", "[EMAIL]"),
-        (r"\b\d{4}[\s-]?\d{4}[\s-]?\d{4}[\s-]?\d{4}\b", "[CREDIT_CARD]"),
-    ];
-    
-    for (_pattern, replacement) in patterns {
-        // Note: This is a simplified version - real implementation would use regex
-        if text.contains("@") && replacement == "[EMAIL]" {
-            masked = masked.replace(&text[text.find("@").unwrap_or(0)..], replacement);
+    mask_regex()
+        .replace_all(text, |captures: &Captures| {
+            MASK_PATTERNS.iter()
+                .enumerate()
+                .find(|(i, _)| captures.get(i + 1).is_some())
+                .map_or("", |(_, (_, label))| *label)
+        })
+        .into_owned()
+}
+
+// Numerically stable running mean/variance (Welford's algorithm)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RunningStats {
+    count: usize,
+    mean: f64,
+    m2: f64,
+}
+
+impl RunningStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_values<'a>(values: impl IntoIterator<Item = &'a f64>) -> Self {
+        let mut stats = Self::new();
+        for &value in values {
+            stats.push(value);
+        }
+        stats
+    }
+
+    pub fn push(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    // Population variance, matching the sum-of-squares / n used elsewhere
+    pub fn variance(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.m2 / self.count as f64
         }
     }
-    
-    masked
 }
 
 // Context vector generation utilities
@@ -108,19 +234,15 @@ pub fn generate_context_vector(
     
     if !activation_history.is_empty() {
         // Basic statistics
-        context[0] = activation_history.iter().sum::<f64>() / activation_history.len() as f64;
+        let stats = RunningStats::from_values(activation_history);
+        context[0] = stats.mean();
         context[1] = activation_history.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
         context[2] = activation_history.iter().cloned().fold(f64::INFINITY, f64::min);
-        
-        // Variance
-        let mean = context[0];
-        context[3] = activation_history.iter()
-            .map(|x| (x - mean).powi(2))
-            .sum::<f64>() / activation_history.len() as f64;
+        context[3] = stats.variance();
     }
     
     if !error_history.is_empty() {
-        context[4] = error_history.iter().sum::<f64>() / error_history.len() as f64;
+        context[4] = RunningStats::from_values(error_history).mean();
         context[5] = error_history.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
         context[6] = error_history.iter().cloned().fold(f64::INFINITY, f64::min);
     }
@@ -183,6 +305,30 @@ pub fn euclidean_distance(a: &[f64], b: &[f64]) -> f64 {
         .sqrt()
 }
 
+pub fn manhattan_distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(x, y)| (x - y).abs())
+        .sum()
+}
+
+// Fit a vector to `dim` components: zero-pad when shorter, average contiguous buckets when longer
+pub fn resize_vector(values: &[f64], dim: usize) -> Vec<f64> {
+    if values.len() <= dim {
+        let mut resized = values.to_vec();
+        resized.resize(dim, 0.0);
+        return resized;
+    }
+    
+    (0..dim)
+        .map(|i| {
+            let start = i * values.len() / dim;
+            let end = (i + 1) * values.len() / dim;
+            values[start..end].iter().sum::<f64>() / (end - start) as f64
+        })
+        .collect()
+}
+
 pub fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
     let dot_product: f64 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
     let magnitude_a: f64 = a.iter().map(|x| x * x).sum::<f64>().sqrt();
@@ -195,15 +341,119 @@ pub fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
     }
 }
 
-// Compress data for memory capsules
+// Compress data for memory capsules (raw DEFLATE)
 pub fn compress_data(data: &[u8]) -> Vec<u8> {
-    // Simplified compression - in real implementation would use proper compression
-    // For now, just return the data as-is
-    data.to_vec()
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    // Writing into a Vec can't fail
+    let _ = encoder.write_all(data);
+    encoder.finish().unwrap_or_default()
 }
 
-// Decompress data from memory capsules  
+/// Largest decompressed capsule payload accepted, so a small peer-supplied stream can't expand without bound
+pub const MAX_DECOMPRESSED_SIZE: usize = 8 * 1024 * 1024;
+
+// Inflate a raw DEFLATE stream produced by compress_data; Err past MAX_DECOMPRESSED_SIZE bytes
+pub fn try_decompress_data(compressed: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decompressed = Vec::new();
+    DeflateDecoder::new(compressed)
+        .take(MAX_DECOMPRESSED_SIZE as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| e.to_string())?;
+    if decompressed.len() > MAX_DECOMPRESSED_SIZE {
+        return Err(format!("decompressed data exceeds {} bytes", MAX_DECOMPRESSED_SIZE));
+    }
+    Ok(decompressed)
+}
+
+// Decompress data from memory capsules; empty if the input is not a valid DEFLATE stream
 pub fn decompress_data(compressed: &[u8]) -> Vec<u8> {
-    // Simplified decompression - in real implementation would use proper decompression
-    compressed.to_vec()
-} 
\ No newline at end of file
+    try_decompress_data(compressed).unwrap_or_else(|e| {
+        crate::console_log!("Failed to decompress data: {}", e);
+        Vec::new()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_matches_fips_180_vectors() {
+        assert_eq!(sha256_hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+    }
+
+    #[test]
+    fn running_stats_survive_high_magnitude_series() {
+        let series: Vec<f64> = [4.0, 7.0, 13.0, 16.0].iter().map(|x| 1e9 + x).collect();
+        let stats = RunningStats::from_values(&series);
+        assert_eq!(stats.count(), 4);
+        assert!((stats.mean() - (1e9 + 10.0)).abs() < 1e-6);
+        assert!((stats.variance() - 22.5).abs() < 1e-6);
+
+        // The naive sum-of-squares formula cancels catastrophically at this magnitude
+        let n = series.len() as f64;
+        let naive = series.iter().map(|x| x * x).sum::<f64>() / n - (series.iter().sum::<f64>() / n).powi(2);
+        assert!((naive - 22.5).abs() > 1.0);
+
+        // On small values it agrees with the two-pass computation
+        let small = [0.5, -1.25, 3.0, 2.0];
+        let mean = small.iter().sum::<f64>() / 4.0;
+        let two_pass = small.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / 4.0;
+        assert!((RunningStats::from_values(&small).variance() - two_pass).abs() < 1e-12);
+        assert_eq!(RunningStats::new().variance(), 0.0);
+    }
+
+    #[test]
+    fn semantic_mask_redacts_every_category_at_once() {
+        assert_eq!(apply_semantic_mask("Mail john.doe@example.com from 192.168.1.10, SSN 123-45-6789"),
+            "Mail [EMAIL] from [IP_ADDRESS], SSN [SSN]");
+        assert_eq!(apply_semantic_mask("John Smith paid with 1234-5678-9012-3456 and 1234 5678 9012 3456"),
+            "[PERSON_NAME] paid with [CREDIT_CARD] and [CREDIT_CARD]");
+        assert_eq!(apply_semantic_mask("a@b.io, c@d.io, 10.0.0.1, 10.0.0.2"), "[EMAIL], [EMAIL], [IP_ADDRESS], [IP_ADDRESS]");
+        assert_eq!(apply_semantic_mask("nothing to hide"), "nothing to hide");
+    }
+
+    #[test]
+    fn semantic_mask_resolves_overlaps_deterministically() {
+        // An IP-shaped local part is part of the email, which is listed first
+        assert_eq!(apply_semantic_mask("10.0.0.1@host.com"), "[EMAIL]");
+        // A name directly before an email is the earlier match, so it wins
+        assert_eq!(apply_semantic_mask("John Smith@x.com"), "[PERSON_NAME]@x.com");
+        assert_eq!(apply_semantic_mask("1234.5.6.7"), "1234.5.6.7");
+    }
+
+    #[test]
+    fn compression_round_trips() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).chain(b"node state".repeat(50)).collect();
+        let compressed = compress_data(&data);
+        assert!(compressed.len() < data.len());
+        assert_eq!(decompress_data(&compressed), data);
+        assert_eq!(decompress_data(&compress_data(&[])), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn decompression_reads_rfc_1951_streams() {
+        // "abc" as a fixed-Huffman block and as a stored block
+        assert_eq!(decompress_data(&[0x4b, 0x4c, 0x4a, 0x06, 0x00]), b"abc");
+        assert_eq!(decompress_data(&[0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c']), b"abc");
+        assert!(try_decompress_data(&[0x07, 0xff]).is_err());
+    }
+
+    #[test]
+    fn decompression_is_bounded() {
+        let bomb = compress_data(&vec![0u8; MAX_DECOMPRESSED_SIZE + 1]);
+        assert!(bomb.len() < 64 * 1024);
+        assert!(try_decompress_data(&bomb).is_err());
+        assert_eq!(try_decompress_data(&compress_data(&vec![0u8; MAX_DECOMPRESSED_SIZE])).map(|data| data.len()),
+            Ok(MAX_DECOMPRESSED_SIZE));
+    }
+
+    #[test]
+    fn truncated_streams_fail_to_decompress() {
+        let compressed = compress_data(&b"node state ".repeat(100));
+        assert!(try_decompress_data(&compressed[..compressed.len() / 2]).is_err());
+    }
+}
@@ -1,8 +1,10 @@
 use wasm_bindgen::prelude::*;
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use crate::memory::MemoryCapsule;
-use crate::utils::{cosine_similarity, euclidean_distance};
+use crate::error::DnnError;
+use crate::utils::{cosine_similarity, euclidean_distance, is_finite_vector, manhattan_distance, now_ms, normalize_vector};
+use crate::integrity::{IntegrityIssue, IntegritySeverity};
 
 // Import the console_log macro
 use crate::console_log;
@@ -20,18 +22,47 @@ pub struct VectorMemoryDatabase {
     
     // Hierarchical indexing for fast retrieval
     semantic_clusters: HashMap<String, Vec<String>>, // cluster_tag -> capsule_ids
+    cluster_aliases: HashMap<String, String>, // merged tag -> tag of the cluster it was merged into
     temporal_index: Vec<(f64, String)>, // (timestamp, capsule_id) sorted by time
     
     // Quality and importance metrics
     quality_rankings: Vec<(f64, String)>, // (quality_score, capsule_id) sorted by quality
     usage_frequencies: HashMap<String, u32>, // capsule_id -> access_count
+    query_context_counts: HashMap<String, u32>, // context tag -> times it matched a search result
+    query_demand_boost: f64, // Relevance boost for capsules in frequently queried contexts (0 = off)
     
     // Network-wide statistics
     total_memory_size: usize,
     average_vector_dimension: usize,
     last_consolidation_time: f64,
+    
+    // Reject NaN/Inf vectors and L2-normalize embeddings before storing
+    embedding_validation: bool,
+    
+    // Tag clusters smaller than this are reported under MISC_CLUSTER_TAG
+    min_cluster_size: usize,
+    
+    // Embedding configuration; source capsules are kept so entries can be re-embedded after a change
+    embedding_dim: usize,
+    source_capsules: HashMap<String, MemoryCapsule>, // capsule_id -> original capsule
+    
+    // Compaction: identical compressed_data is stored once and referenced by hash
+    blob_store: HashMap<u64, Vec<u8>>, // content hash -> compressed_data
+    blob_refs: HashMap<String, u64>, // capsule_id -> content hash of its compacted data
+    
+    // How strongly downstream usage raises importance during consolidation
+    importance_boost_factor: f64,
+    
+    // Capsules deliberately dropped (e.g. by consolidation) whose ledger records remain
+    retired_capsules: HashSet<String>,
 }
 
+/// Width of the feature layout produced before projecting to embedding_dim
+const BASE_EMBEDDING_DIM: usize = 128;
+
+/// Bucket that collects tag clusters below min_cluster_size in distribution outputs
+pub const MISC_CLUSTER_TAG: &str = "misc";
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct VectorEntry {
     pub capsule_id: String,
@@ -62,6 +93,58 @@ pub struct VectorSearchQuery {
     pub quality_threshold: f64,
     pub max_results: usize,
     pub search_algorithm: SearchAlgorithm,
+    #[serde(default)]
+    pub max_candidates: Option<usize>, // Scan budget; candidates are visited highest-quality first
+    #[serde(default)]
+    pub hybrid_config: Option<HybridConfig>, // Metric blend for Hybrid; defaults to 0.7 cosine / 0.3 euclidean
+    #[serde(default)]
+    pub include_content: bool, // Inline capsule content for shareable (non-personal) capsules
+}
+
+/// Weighted blend of similarity metrics; weights are normalized to sum to 1
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HybridConfig {
+    pub metrics: Vec<(SimilarityMetric, f64)>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SimilarityMetric {
+    Cosine,
+    Euclidean, // Scored as 1 / (1 + distance)
+    DotProduct,
+    Manhattan, // Scored as 1 / (1 + distance)
+}
+
+impl SimilarityMetric {
+    pub fn score(&self, a: &[f64], b: &[f64]) -> f64 {
+        match self {
+            SimilarityMetric::Cosine => cosine_similarity(a, b),
+            SimilarityMetric::Euclidean => 1.0 / (1.0 + euclidean_distance(a, b)),
+            SimilarityMetric::DotProduct => a.iter().zip(b.iter()).map(|(x, y)| x * y).sum(),
+            SimilarityMetric::Manhattan => 1.0 / (1.0 + manhattan_distance(a, b)),
+        }
+    }
+}
+
+impl Default for HybridConfig {
+    fn default() -> Self {
+        HybridConfig {
+            metrics: vec![(SimilarityMetric::Cosine, 0.7), (SimilarityMetric::Euclidean, 0.3)],
+        }
+    }
+}
+
+impl HybridConfig {
+    pub fn score(&self, a: &[f64], b: &[f64]) -> f64 {
+        let total_weight: f64 = self.metrics.iter().map(|(_, weight)| weight.max(0.0)).sum();
+        if total_weight <= 0.0 {
+            return 0.0;
+        }
+        
+        self.metrics.iter()
+            .map(|(metric, weight)| metric.score(a, b) * weight.max(0.0))
+            .sum::<f64>() / total_weight
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -80,6 +163,29 @@ pub struct SearchResult {
     pub relevance_score: f64, // Combined similarity + quality + recency
     pub context_match: f64,
     pub blockchain_verified: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<CapsuleContent>,
+}
+
+/// Output of semantic_search_with_fallback
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RelaxedSearchResponse {
+    pub results: Vec<SearchResult>,
+    pub relaxed_constraints: Vec<String>, // "time_range", "quality_threshold", "context_filter"
+}
+
+/// Capsule context inlined into a SearchResult when the query sets include_content
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CapsuleContent {
+    pub semantic_tags: Vec<String>,
+    pub context_vector: Vec<f64>,
+    pub timestamp: f64,
+}
+
+impl Default for VectorMemoryDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[wasm_bindgen]
@@ -92,177 +198,381 @@ impl VectorMemoryDatabase {
             vector_index: HashMap::new(),
             blockchain_hashes: HashMap::new(),
             semantic_clusters: HashMap::new(),
+            cluster_aliases: HashMap::new(),
             temporal_index: Vec::new(),
             quality_rankings: Vec::new(),
             usage_frequencies: HashMap::new(),
+            query_context_counts: HashMap::new(),
+            query_demand_boost: 0.0,
             total_memory_size: 0,
             average_vector_dimension: 0,
-            last_consolidation_time: js_sys::Date::now(),
+            last_consolidation_time: now_ms(),
+            embedding_validation: false,
+            min_cluster_size: 1,
+            embedding_dim: BASE_EMBEDDING_DIM,
+            source_capsules: HashMap::new(),
+            blob_store: HashMap::new(),
+            blob_refs: HashMap::new(),
+            importance_boost_factor: 0.1,
+            retired_capsules: HashSet::new(),
         }
     }
 
     #[wasm_bindgen]
     pub fn store_memory_capsule(&mut self, capsule_json: &str, blockchain_hash: String) -> bool {
-        if let Ok(capsule) = serde_json::from_str::<MemoryCapsule>(capsule_json) {
-            // Generate high-dimensional semantic embedding
-            let embedding_vector = self.generate_semantic_embedding(&capsule);
-            let metadata_vector = self.generate_metadata_vector(&capsule);
+        match serde_json::from_str::<MemoryCapsule>(capsule_json) {
+            Ok(capsule) => self.store_capsule(capsule, blockchain_hash),
+            Err(_) => false,
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn semantic_search(&mut self, query_json: &str) -> String {
+        if let Ok(query) = serde_json::from_str::<VectorSearchQuery>(query_json) {
+            if self.embedding_validation {
+                if let Err(e) = self.check_query_vector(&query.query_vector) {
+                    console_log!("Rejected search query: {}", e);
+                    return "[]".to_string();
+                }
+            }
+            
+            let results = self.run_search(&query);
+            console_log!("Semantic search returned {} results", results.len());
+            serde_json::to_string(&results).unwrap_or_default()
+        } else {
+            console_log!("Failed to parse search query");
+            "[]".to_string()
+        }
+    }
+
+    /// Like semantic_search, but when nothing matches it drops the weakest remaining constraint
+    /// (time range, then quality threshold, then context filter) and retries. Returns
+    /// `{ results, relaxed_constraints }`, listing the constraints dropped in order, or `[]` like
+    /// semantic_search when the query is unparseable or rejected.
+    #[wasm_bindgen]
+    pub fn semantic_search_with_fallback(&mut self, query_json: &str) -> String {
+        let mut query = match serde_json::from_str::<VectorSearchQuery>(query_json) {
+            Ok(query) => query,
+            Err(_) => {
+                console_log!("Failed to parse search query");
+                return "[]".to_string();
+            }
+        };
+        if self.embedding_validation {
+            if let Err(e) = self.check_query_vector(&query.query_vector) {
+                console_log!("Rejected search query: {}", e);
+                return "[]".to_string();
+            }
+        }
+        
+        let mut relaxed_constraints = Vec::new();
+        let results = loop {
+            let results = self.run_search(&query);
+            if !results.is_empty() {
+                break results;
+            }
+            if query.time_range.is_some() {
+                query.time_range = None;
+                relaxed_constraints.push("time_range".to_string());
+            } else if query.quality_threshold > 0.0 {
+                query.quality_threshold = 0.0;
+                relaxed_constraints.push("quality_threshold".to_string());
+            } else if !query.context_filter.is_empty() {
+                query.context_filter.clear();
+                relaxed_constraints.push("context_filter".to_string());
+            } else {
+                break results;
+            }
+        };
+        
+        console_log!("Fallback search returned {} results after relaxing [{}]",
+            results.len(), relaxed_constraints.join(", "));
+        serde_json::to_string(&RelaxedSearchResponse { results, relaxed_constraints }).unwrap_or_default()
+    }
+
+    fn run_search(&mut self, query: &VectorSearchQuery) -> Vec<SearchResult> {
+        let mut results = Vec::new();
+        let mut accessed_capsules = Vec::new();
+        let hybrid_config = query.hybrid_config.clone().unwrap_or_default();
+        let max_context_count = self.query_context_counts.values().copied().max().unwrap_or(0);
+        
+        // With a budget, evaluate only the top-quality candidates to bound latency
+        let candidate_ids: Vec<&String> = match query.max_candidates {
+            Some(budget) => {
+                let mut seen = std::collections::HashSet::new();
+                self.quality_rankings.iter()
+                    .map(|(_, id)| id)
+                    .filter(|id| self.vector_index.contains_key(*id) && seen.insert(*id))
+                    .take(budget)
+                    .collect()
+            },
+            None => self.vector_index.keys().collect(),
+        };
+        
+        for capsule_id in candidate_ids {
+            let vector_entry = &self.vector_index[capsule_id];
+
+            // Skip if doesn't match context filter
+            if !query.context_filter.is_empty() {
+                let context_match = self.calculate_context_match(&query.context_filter, &vector_entry.context_tags);
+                if context_match < 0.3 {
+                    continue;
+                }
+            }
+            
+            // Skip if outside time range
+            if let Some((start_time, end_time)) = query.time_range {
+                if vector_entry.timestamp < start_time || vector_entry.timestamp > end_time {
+                    continue;
+                }
+            }
             
-            let vector_entry = VectorEntry {
-                capsule_id: capsule.capsule_id.clone(),
-                embedding_vector,
-                metadata_vector,
-                context_tags: capsule.semantic_tags.clone(),
-                timestamp: capsule.timestamp,
-                quality_score: self.calculate_enhanced_quality_score(&capsule),
-                importance_score: capsule.importance_score,
-                access_pattern: AccessPattern {
-                    total_accesses: 0,
-                    recent_accesses: Vec::new(),
-                    access_contexts: Vec::new(),
-                    collaborative_filters: Vec::new(),
+            // Skip if below quality threshold
+            if vector_entry.quality_score < query.quality_threshold {
+                continue;
+            }
+            
+            // Calculate similarity based on algorithm
+            let similarity_score = match query.search_algorithm {
+                SearchAlgorithm::CosineSimilarity => {
+                    cosine_similarity(&query.query_vector, &vector_entry.embedding_vector)
+                },
+                SearchAlgorithm::EuclideanDistance => {
+                    1.0 / (1.0 + euclidean_distance(&query.query_vector, &vector_entry.embedding_vector))
                 },
-                compression_ratio: self.calculate_compression_ratio(&capsule),
-                original_size: capsule.compressed_data.len(),
+                SearchAlgorithm::DotProduct => {
+                    query.query_vector.iter()
+                        .zip(vector_entry.embedding_vector.iter())
+                        .map(|(a, b)| a * b)
+                        .sum::<f64>()
+                },
+                SearchAlgorithm::Hybrid => {
+                    hybrid_config.score(&query.query_vector, &vector_entry.embedding_vector)
+                }
             };
             
-            // Store in vector index
-            self.vector_index.insert(capsule.capsule_id.clone(), vector_entry.clone());
+            // Calculate context match
+            let context_match = if query.context_filter.is_empty() {
+                1.0
+            } else {
+                self.calculate_context_match(&query.context_filter, &vector_entry.context_tags)
+            };
             
-            // Store blockchain reference
-            self.blockchain_hashes.insert(capsule.capsule_id.clone(), blockchain_hash);
+            // Calculate recency boost
+            let current_time = now_ms();
+            let age_hours = (current_time - vector_entry.timestamp) / (1000.0 * 3600.0);
+            let recency_score = (-age_hours / 168.0).exp(); // Decay over a week
             
-            // Update semantic clusters
-            self.update_semantic_clusters(&capsule.capsule_id, &capsule.semantic_tags);
+            // Calculate combined relevance score
+            let mut relevance_score = (similarity_score * 0.5) + 
+                                (vector_entry.quality_score * 0.3) + 
+                                (context_match * 0.1) + 
+                                (recency_score * 0.1);
             
-            // Update temporal index
-            self.temporal_index.push((capsule.timestamp, capsule.capsule_id.clone()));
-            self.temporal_index.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            // Favor capsules in contexts that searches keep asking for
+            if self.query_demand_boost > 0.0 && max_context_count > 0 {
+                let demand = vector_entry.context_tags.iter()
+                    .filter_map(|tag| self.query_context_counts.get(tag))
+                    .copied()
+                    .max()
+                    .unwrap_or(0) as f64 / max_context_count as f64;
+                relevance_score += self.query_demand_boost * demand;
+            }
             
-            // Update quality rankings
-            self.quality_rankings.push((vector_entry.quality_score, capsule.capsule_id.clone()));
-            self.quality_rankings.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+            // Check blockchain verification
+            let blockchain_verified = self.blockchain_hashes.contains_key(capsule_id);
             
-            // Update statistics
-            self.total_memory_size += capsule.compressed_data.len();
-            self.update_average_vector_dimension(&vector_entry.embedding_vector);
+            let content = if query.include_content {
+                self.shareable_content(capsule_id)
+            } else {
+                None
+            };
             
-            console_log!("Stored memory capsule {} in vector database", capsule.capsule_id);
-            true
-        } else {
-            false
+            results.push(SearchResult {
+                capsule_id: capsule_id.clone(),
+                similarity_score,
+                quality_score: vector_entry.quality_score,
+                relevance_score,
+                context_match,
+                blockchain_verified,
+                content,
+            });
+            
+            // Track accessed capsules for later update
+            accessed_capsules.push(capsule_id.clone());
+        }
+        
+        // Count each filter tag that matched at least one result
+        for tag in &query.context_filter {
+            let matched = accessed_capsules.iter()
+                .any(|id| self.vector_index[id].context_tags.contains(tag));
+            if matched {
+                *self.query_context_counts.entry(tag.clone()).or_insert(0) += 1;
+            }
         }
+        
+        // Update access patterns after the search loop
+        let current_time = now_ms();
+        for capsule_id in accessed_capsules {
+            if let Some(entry) = self.vector_index.get_mut(&capsule_id) {
+                entry.access_pattern.total_accesses += 1;
+                entry.access_pattern.recent_accesses.push(current_time);
+                
+                // Keep only recent accesses (last 24 hours)
+                entry.access_pattern.recent_accesses.retain(|&time| current_time - time < 86400000.0);
+                
+                // Update usage frequency
+                *self.usage_frequencies.entry(capsule_id).or_insert(0) += 1;
+            }
+        }
+        
+        // Sort by relevance score
+        results.sort_by(|a, b| b.relevance_score.partial_cmp(&a.relevance_score).unwrap());
+        
+        // Limit results
+        results.truncate(query.max_results);
+        results
     }
 
     #[wasm_bindgen]
-    pub fn semantic_search(&mut self, query_json: &str) -> String {
-        if let Ok(query) = serde_json::from_str::<VectorSearchQuery>(query_json) {
-            let mut results = Vec::new();
-            let mut accessed_capsules = Vec::new();
+    pub fn set_embedding_validation(&mut self, enabled: bool) {
+        self.embedding_validation = enabled;
+    }
+
+    /// Collapse tag clusters with fewer than `min_size` capsules into the "misc" bucket
+    #[wasm_bindgen]
+    pub fn set_min_cluster_size(&mut self, min_size: usize) {
+        self.min_cluster_size = min_size.max(1);
+    }
+
+    /// How often each context tag matched a search result
+    #[wasm_bindgen]
+    pub fn get_query_context_stats(&self) -> String {
+        serde_json::to_string(&self.query_context_counts).unwrap_or_default()
+    }
+
+    #[wasm_bindgen]
+    pub fn set_query_demand_boost(&mut self, boost: f64) {
+        self.query_demand_boost = boost.max(0.0);
+    }
+
+    #[wasm_bindgen]
+    pub fn set_importance_boost_factor(&mut self, factor: f64) {
+        self.importance_boost_factor = factor.max(0.0);
+    }
+
+    /// Merge tag clusters whose centroid embeddings have cosine similarity of at least `threshold`.
+    /// The larger cluster keeps its tag; later capsules with the merged tag join it. Returns merge count.
+    #[wasm_bindgen]
+    pub fn merge_similar_clusters(&mut self, threshold: f64) -> usize {
+        let mut merged = 0;
+        
+        loop {
+            let mut tags: Vec<String> = self.semantic_clusters.keys().cloned().collect();
+            tags.sort();
+            let centroids: Vec<Vec<f64>> = tags.iter()
+                .map(|tag| self.cluster_centroid(&self.semantic_clusters[tag]))
+                .collect();
             
-            for (capsule_id, vector_entry) in &self.vector_index {
-                // Skip if doesn't match context filter
-                if !query.context_filter.is_empty() {
-                    let context_match = self.calculate_context_match(&query.context_filter, &vector_entry.context_tags);
-                    if context_match < 0.3 {
-                        continue;
-                    }
-                }
-                
-                // Skip if outside time range
-                if let Some((start_time, end_time)) = query.time_range {
-                    if vector_entry.timestamp < start_time || vector_entry.timestamp > end_time {
-                        continue;
+            let mut pair = None;
+            'search: for i in 0..tags.len() {
+                for j in (i + 1)..tags.len() {
+                    if cosine_similarity(&centroids[i], &centroids[j]) >= threshold {
+                        pair = Some((i, j));
+                        break 'search;
                     }
                 }
-                
-                // Skip if below quality threshold
-                if vector_entry.quality_score < query.quality_threshold {
-                    continue;
-                }
-                
-                // Calculate similarity based on algorithm
-                let similarity_score = match query.search_algorithm {
-                    SearchAlgorithm::CosineSimilarity => {
-                        cosine_similarity(&query.query_vector, &vector_entry.embedding_vector)
-                    },
-                    SearchAlgorithm::EuclideanDistance => {
-                        1.0 / (1.0 + euclidean_distance(&query.query_vector, &vector_entry.embedding_vector))
-                    },
-                    SearchAlgorithm::DotProduct => {
-                        query.query_vector.iter()
-                            .zip(vector_entry.embedding_vector.iter())
-                            .map(|(a, b)| a * b)
-                            .sum::<f64>()
-                    },
-                    SearchAlgorithm::Hybrid => {
-                        let cosine = cosine_similarity(&query.query_vector, &vector_entry.embedding_vector);
-                        let euclidean = 1.0 / (1.0 + euclidean_distance(&query.query_vector, &vector_entry.embedding_vector));
-                        (cosine * 0.7) + (euclidean * 0.3)
-                    }
-                };
-                
-                // Calculate context match
-                let context_match = if query.context_filter.is_empty() {
-                    1.0
-                } else {
-                    self.calculate_context_match(&query.context_filter, &vector_entry.context_tags)
-                };
-                
-                // Calculate recency boost
-                let current_time = js_sys::Date::now();
-                let age_hours = (current_time - vector_entry.timestamp) / (1000.0 * 3600.0);
-                let recency_score = (-age_hours / 168.0).exp(); // Decay over a week
-                
-                // Calculate combined relevance score
-                let relevance_score = (similarity_score * 0.5) + 
-                                    (vector_entry.quality_score * 0.3) + 
-                                    (context_match * 0.1) + 
-                                    (recency_score * 0.1);
-                
-                // Check blockchain verification
-                let blockchain_verified = self.blockchain_hashes.contains_key(capsule_id);
-                
-                results.push(SearchResult {
-                    capsule_id: capsule_id.clone(),
-                    similarity_score,
-                    quality_score: vector_entry.quality_score,
-                    relevance_score,
-                    context_match,
-                    blockchain_verified,
-                });
-                
-                // Track accessed capsules for later update
-                accessed_capsules.push(capsule_id.clone());
             }
             
-            // Update access patterns after the search loop
-            let current_time = js_sys::Date::now();
-            for capsule_id in accessed_capsules {
-                if let Some(entry) = self.vector_index.get_mut(&capsule_id) {
-                    entry.access_pattern.total_accesses += 1;
-                    entry.access_pattern.recent_accesses.push(current_time);
-                    
-                    // Keep only recent accesses (last 24 hours)
-                    entry.access_pattern.recent_accesses.retain(|&time| current_time - time < 86400000.0);
-                    
-                    // Update usage frequency
-                    *self.usage_frequencies.entry(capsule_id).or_insert(0) += 1;
+            let (i, j) = match pair {
+                Some(pair) => pair,
+                None => break,
+            };
+            let (keep, absorb) = if self.semantic_clusters[&tags[j]].len() > self.semantic_clusters[&tags[i]].len() {
+                (tags[j].clone(), tags[i].clone())
+            } else {
+                (tags[i].clone(), tags[j].clone())
+            };
+            
+            let absorbed_members = self.semantic_clusters.remove(&absorb).unwrap_or_default();
+            let members = self.semantic_clusters.entry(keep.clone()).or_default();
+            for capsule_id in absorbed_members {
+                if !members.contains(&capsule_id) {
+                    members.push(capsule_id);
                 }
             }
             
-            // Sort by relevance score
-            results.sort_by(|a, b| b.relevance_score.partial_cmp(&a.relevance_score).unwrap());
+            for target in self.cluster_aliases.values_mut() {
+                if *target == absorb {
+                    *target = keep.clone();
+                }
+            }
+            console_log!("Merged semantic cluster '{}' into '{}'", absorb, keep);
+            self.cluster_aliases.insert(absorb, keep);
+            merged += 1;
+        }
+        
+        merged
+    }
+
+    /// Change the embedding width; existing entries stay stale until reembed_all is called
+    #[wasm_bindgen]
+    pub fn set_embedding_dim(&mut self, dim: usize) -> Result<(), DnnError> {
+        if dim == 0 {
+            return Err(DnnError::InvalidInput("embedding dimension must be at least 1".to_string()));
+        }
+        self.embedding_dim = dim;
+        console_log!("Embedding dimension set to {}; call reembed_all to migrate stored vectors", dim);
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn get_embedding_dim(&self) -> usize {
+        self.embedding_dim
+    }
+
+    /// Regenerate every stored embedding under the current configuration and rebuild indices.
+    /// Returns the number of entries re-embedded.
+    #[wasm_bindgen]
+    pub fn reembed_all(&mut self) -> usize {
+        let mut reembedded = 0;
+        let mut rejected = Vec::new();
+        
+        let capsule_ids: Vec<String> = self.source_capsules.keys().cloned().collect();
+        for capsule_id in &capsule_ids {
+            let capsule = match self.materialize_source(capsule_id) {
+                Some(capsule) => capsule,
+                None => continue,
+            };
+            let mut embedding_vector = self.generate_semantic_embedding(&capsule);
+            let metadata_vector = self.generate_metadata_vector(&capsule);
             
-            // Limit results
-            results.truncate(query.max_results);
+            if self.embedding_validation {
+                if !is_finite_vector(&embedding_vector) || !is_finite_vector(&metadata_vector) {
+                    rejected.push(capsule_id.clone());
+                    continue;
+                }
+                normalize_vector(&mut embedding_vector);
+            }
             
-            console_log!("Semantic search returned {} results", results.len());
-            serde_json::to_string(&results).unwrap_or_default()
-        } else {
-            console_log!("Failed to parse search query");
-            "[]".to_string()
+            if let Some(entry) = self.vector_index.get_mut(capsule_id) {
+                entry.embedding_vector = embedding_vector;
+                entry.metadata_vector = metadata_vector;
+                reembedded += 1;
+            }
+        }
+        
+        // Entries whose capsule no longer passes validation are dropped rather than left stale
+        for capsule_id in &rejected {
+            self.remove_entry(capsule_id);
         }
+        
+        self.recompute_semantic_clusters();
+        self.update_average_vector_dimension(&[]);
+        
+        console_log!("Re-embedded {} memory capsules at dimension {} ({} rejected)",
+            reembedded, self.embedding_dim, rejected.len());
+        reembedded
     }
 
     #[wasm_bindgen]
@@ -285,7 +595,7 @@ impl VectorMemoryDatabase {
     pub fn consolidate_memory(&mut self) -> bool {
         console_log!("Starting memory consolidation process");
         
-        let current_time = js_sys::Date::now();
+        let current_time = now_ms();
         
         // Remove old, unused memories (older than 30 days with no recent access)
         let cutoff_time = current_time - (30.0 * 24.0 * 3600.0 * 1000.0);
@@ -302,26 +612,20 @@ impl VectorMemoryDatabase {
         
         // Remove obsolete entries
         for capsule_id in to_remove {
-            self.vector_index.remove(&capsule_id);
-            self.blockchain_hashes.remove(&capsule_id);
-            self.usage_frequencies.remove(&capsule_id);
-            
-            // Clean up indices
-            for cluster_capsules in self.semantic_clusters.values_mut() {
-                cluster_capsules.retain(|id| id != &capsule_id);
-            }
-            
-            self.temporal_index.retain(|(_, id)| id != &capsule_id);
-            self.quality_rankings.retain(|(_, id)| id != &capsule_id);
+            self.remove_entry(&capsule_id);
         }
         
         // Recompute semantic clusters based on current vectors
         self.recompute_semantic_clusters();
         
+        // Let heavily reused memories rise in the rankings
+        self.recompute_importance();
+        
+        // Store identical node-memory blobs once
+        self.compact_blobs();
+        
         // Update statistics
-        self.total_memory_size = self.vector_index.values()
-            .map(|entry| entry.original_size)
-            .sum();
+        self.recompute_total_memory_size();
         
         self.last_consolidation_time = current_time;
         
@@ -329,9 +633,134 @@ impl VectorMemoryDatabase {
         true
     }
 
+    // Boost importance from access counts and collaborative links, starting from the
+    // capsule's original score so repeated consolidations don't compound the boost
+    fn recompute_importance(&mut self) {
+        let capsule_ids: Vec<String> = self.vector_index.keys().cloned().collect();
+        for capsule_id in &capsule_ids {
+            let mut boosted = match self.materialize_source(capsule_id) {
+                Some(source) => source,
+                None => continue,
+            };
+            let entry = match self.vector_index.get_mut(capsule_id) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            
+            let usage = (1.0 + entry.access_pattern.total_accesses as f64).ln()
+                + entry.access_pattern.collaborative_filters.len() as f64 * 0.5;
+            let importance = (boosted.importance_score * (1.0 + self.importance_boost_factor * usage)).clamp(0.0, 1.0);
+            
+            boosted.importance_score = importance;
+            entry.importance_score = importance;
+            entry.quality_score = Self::calculate_enhanced_quality_score(&boosted);
+        }
+        
+        self.quality_rankings = self.vector_index.iter()
+            .map(|(capsule_id, entry)| (entry.quality_score, capsule_id.clone()))
+            .collect();
+        self.quality_rankings.sort_by(|a, b| b.0.total_cmp(&a.0));
+    }
+
+    // Personal capsules stay private to the device and are never inlined into results
+    fn shareable_content(&self, capsule_id: &str) -> Option<CapsuleContent> {
+        let capsule = self.source_capsules.get(capsule_id)?;
+        if matches!(capsule.privacy_level, crate::memory::PrivacyLevel::Personal) {
+            return None;
+        }
+        Some(CapsuleContent {
+            semantic_tags: capsule.semantic_tags.clone(),
+            context_vector: capsule.context_vector.clone(),
+            timestamp: capsule.timestamp,
+        })
+    }
+
+    fn check_query_vector(&self, query_vector: &[f64]) -> Result<(), DnnError> {
+        if !is_finite_vector(query_vector) {
+            return Err(DnnError::InvalidInput("query vector contains NaN or Inf".to_string()));
+        }
+        if query_vector.len() != self.embedding_dim {
+            return Err(DnnError::DimensionMismatch { expected: self.embedding_dim, actual: query_vector.len() });
+        }
+        Ok(())
+    }
+
+    // Source capsule with its compressed_data restored from the blob store if it was compacted
+    fn materialize_source(&self, capsule_id: &str) -> Option<MemoryCapsule> {
+        let mut capsule = self.source_capsules.get(capsule_id)?.clone();
+        if let Some(blob) = self.blob_refs.get(capsule_id).and_then(|hash| self.blob_store.get(hash)) {
+            capsule.compressed_data = blob.clone();
+        }
+        Some(capsule)
+    }
+
+    // Move byte-identical compressed_data into the shared blob store and drop unreferenced blobs.
+    // Returns the number of capsules whose data was deduplicated against an existing blob.
+    fn compact_blobs(&mut self) -> usize {
+        use std::hash::{Hash, Hasher};
+        
+        let mut deduplicated = 0;
+        for (capsule_id, capsule) in self.source_capsules.iter_mut() {
+            if capsule.compressed_data.is_empty() || self.blob_refs.contains_key(capsule_id) {
+                continue;
+            }
+            
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            capsule.compressed_data.hash(&mut hasher);
+            let hash = hasher.finish();
+            
+            match self.blob_store.get(&hash) {
+                Some(existing) if *existing == capsule.compressed_data => {
+                    capsule.compressed_data = Vec::new();
+                    deduplicated += 1;
+                },
+                Some(_) => continue, // Hash collision with different bytes; keep inline
+                None => {
+                    self.blob_store.insert(hash, std::mem::take(&mut capsule.compressed_data));
+                }
+            }
+            self.blob_refs.insert(capsule_id.clone(), hash);
+        }
+        
+        let referenced: HashSet<u64> = self.blob_refs.values().copied().collect();
+        self.blob_store.retain(|hash, _| referenced.contains(hash));
+        
+        if deduplicated > 0 {
+            console_log!("Compaction deduplicated node memories of {} capsules", deduplicated);
+        }
+        deduplicated
+    }
+
+    // Inline data of uncompacted entries plus each shared blob counted once
+    fn recompute_total_memory_size(&mut self) {
+        let inline: usize = self.vector_index.iter()
+            .filter(|(capsule_id, _)| !self.blob_refs.contains_key(*capsule_id))
+            .map(|(_, entry)| entry.original_size)
+            .sum();
+        let shared: usize = self.blob_store.values().map(|blob| blob.len()).sum();
+        self.total_memory_size = inline + shared;
+    }
+
+    fn remove_entry(&mut self, capsule_id: &str) {
+        self.vector_index.remove(capsule_id);
+        self.retired_capsules.insert(capsule_id.to_string());
+        self.blockchain_hashes.remove(capsule_id);
+        self.usage_frequencies.remove(capsule_id);
+        self.source_capsules.remove(capsule_id);
+        self.blob_refs.remove(capsule_id);
+        
+        // Clean up indices
+        for cluster_capsules in self.semantic_clusters.values_mut() {
+            cluster_capsules.retain(|id| id != capsule_id);
+        }
+        
+        self.temporal_index.retain(|(_, id)| id != capsule_id);
+        self.quality_rankings.retain(|(_, id)| id != capsule_id);
+    }
+
     fn generate_semantic_embedding(&self, capsule: &MemoryCapsule) -> Vec<f64> {
         // Generate high-dimensional embedding from memory capsule content
-        let mut embedding = vec![0.0; 128]; // 128-dimensional embedding
+        let mut embedding = vec![0.0; BASE_EMBEDDING_DIM]; // 128-dimensional feature layout
         
         // Encode context vector
         for (i, &val) in capsule.context_vector.iter().enumerate() {
@@ -371,6 +800,15 @@ impl VectorMemoryDatabase {
             *val = rand::random::<f64>() * 0.01; // Small random noise
         }
         
+        // Fold or zero-pad the feature layout to the configured width
+        if self.embedding_dim != BASE_EMBEDDING_DIM {
+            let mut projected = vec![0.0; self.embedding_dim];
+            for (i, &val) in embedding.iter().enumerate() {
+                projected[i % self.embedding_dim] += val;
+            }
+            embedding = projected;
+        }
+        
         // Normalize the embedding vector
         let magnitude: f64 = embedding.iter().map(|x| x * x).sum::<f64>().sqrt();
         if magnitude > 0.0 {
@@ -397,7 +835,7 @@ impl VectorMemoryDatabase {
         ]
     }
 
-    fn calculate_enhanced_quality_score(&self, capsule: &MemoryCapsule) -> f64 {
+    fn calculate_enhanced_quality_score(capsule: &MemoryCapsule) -> f64 {
         let mut quality = capsule.novelty_score * 0.3 + capsule.importance_score * 0.3;
         
         // Bonus for rich semantic tags
@@ -418,22 +856,51 @@ impl VectorMemoryDatabase {
             1.0
         };
         
-        (quality * size_score).min(1.0).max(0.0)
+        (quality * size_score).clamp(0.0, 1.0)
     }
 
+    // Compressed / uncompressed size; 1.0 when the uncompressed size is unknown (older capsules)
     fn calculate_compression_ratio(&self, capsule: &MemoryCapsule) -> f64 {
-        // Estimate compression ratio (simplified)
-        let estimated_uncompressed = capsule.compressed_data.len() as f64 * 3.0; // Assume 3:1 compression
-        capsule.compressed_data.len() as f64 / estimated_uncompressed
+        if capsule.uncompressed_size == 0 {
+            return 1.0;
+        }
+        capsule.compressed_data.len() as f64 / capsule.uncompressed_size as f64
     }
 
     fn update_semantic_clusters(&mut self, capsule_id: &str, tags: &[String]) {
         for tag in tags {
-            self.semantic_clusters
-                .entry(tag.clone())
-                .or_insert_with(Vec::new)
-                .push(capsule_id.to_string());
+            let cluster_tag = self.canonical_cluster_tag(tag);
+            let members = self.semantic_clusters.entry(cluster_tag).or_default();
+            if !members.iter().any(|id| id == capsule_id) {
+                members.push(capsule_id.to_string());
+            }
+        }
+    }
+
+    fn canonical_cluster_tag(&self, tag: &str) -> String {
+        self.cluster_aliases.get(tag).cloned().unwrap_or_else(|| tag.to_string())
+    }
+
+    fn cluster_centroid(&self, capsule_ids: &[String]) -> Vec<f64> {
+        let mut centroid: Vec<f64> = Vec::new();
+        let mut count = 0;
+        
+        for entry in capsule_ids.iter().filter_map(|id| self.vector_index.get(id)) {
+            if centroid.is_empty() {
+                centroid = vec![0.0; entry.embedding_vector.len()];
+            }
+            for (sum, value) in centroid.iter_mut().zip(entry.embedding_vector.iter()) {
+                *sum += value;
+            }
+            count += 1;
         }
+        
+        if count > 0 {
+            for sum in centroid.iter_mut() {
+                *sum /= count as f64;
+            }
+        }
+        centroid
     }
 
     fn calculate_context_match(&self, query_contexts: &[String], entry_contexts: &[String]) -> f64 {
@@ -452,10 +919,8 @@ impl VectorMemoryDatabase {
         let total_dims: usize = self.vector_index.values()
             .map(|entry| entry.embedding_vector.len())
             .sum();
-        let total_entries = self.vector_index.len();
-        
-        if total_entries > 0 {
-            self.average_vector_dimension = total_dims / total_entries;
+        if let Some(average) = total_dims.checked_div(self.vector_index.len()) {
+            self.average_vector_dimension = average;
         }
     }
 
@@ -476,15 +941,28 @@ impl VectorMemoryDatabase {
             .map(|(id, &count)| (id.clone(), count))
             .collect();
         
-        usage_vec.sort_by(|a, b| b.1.cmp(&a.1));
+        usage_vec.sort_by_key(|b| std::cmp::Reverse(b.1));
         usage_vec.truncate(limit);
         usage_vec
     }
 
     fn get_cluster_distribution(&self) -> HashMap<String, usize> {
-        self.semantic_clusters.iter()
-            .map(|(tag, capsules)| (tag.clone(), capsules.len()))
-            .collect()
+        let mut distribution = HashMap::new();
+        let mut misc_capsules = HashSet::new();
+        
+        for (tag, capsules) in &self.semantic_clusters {
+            if capsules.len() >= self.min_cluster_size {
+                distribution.insert(tag.clone(), capsules.len());
+            } else {
+                misc_capsules.extend(capsules.iter());
+            }
+        }
+        
+        // Count each capsule once even if several of its small tags were collapsed
+        if !misc_capsules.is_empty() {
+            *distribution.entry(MISC_CLUSTER_TAG.to_string()).or_insert(0) += misc_capsules.len();
+        }
+        distribution
     }
 
     fn get_temporal_distribution(&self) -> Vec<(String, usize)> {
@@ -537,15 +1015,43 @@ impl VectorMemoryDatabase {
         
         for (capsule_id, vector_entry) in &self.vector_index {
             for tag in &vector_entry.context_tags {
-                self.semantic_clusters
-                    .entry(tag.clone())
-                    .or_insert_with(Vec::new)
-                    .push(capsule_id.clone());
+                let cluster_tag = self.cluster_aliases.get(tag).unwrap_or(tag);
+                let members = self.semantic_clusters.entry(cluster_tag.clone()).or_default();
+                if !members.contains(capsule_id) {
+                    members.push(capsule_id.clone());
+                }
             }
         }
     }
 
     // Accessor methods for internal use
+    pub fn get_blockchain_hash(&self, capsule_id: &str) -> String {
+        self.blockchain_hashes.get(capsule_id).cloned().unwrap_or_default()
+    }
+
+    /// Approximate bytes used by the vector index (embeddings, metadata, tags, access logs) and retained source capsules
+    pub fn get_index_memory_estimate(&self) -> usize {
+        let f64_size = std::mem::size_of::<f64>();
+        self.vector_index.iter()
+            .map(|(capsule_id, entry)| {
+                capsule_id.capacity()
+                    + std::mem::size_of::<VectorEntry>()
+                    + entry.capsule_id.capacity()
+                    + (entry.embedding_vector.capacity() + entry.metadata_vector.capacity()) * f64_size
+                    + entry.context_tags.iter().map(|tag| tag.capacity()).sum::<usize>()
+                    + entry.access_pattern.recent_accesses.capacity() * f64_size
+                    + entry.access_pattern.access_contexts.iter().map(|c| c.capacity()).sum::<usize>()
+                    + entry.access_pattern.collaborative_filters.iter().map(|c| c.capacity()).sum::<usize>()
+            })
+            .sum::<usize>()
+            + self.source_capsules.values()
+                .map(|capsule| std::mem::size_of::<MemoryCapsule>()
+                    + capsule.context_vector.capacity() * f64_size
+                    + capsule.compressed_data.capacity())
+                .sum::<usize>()
+            + self.blob_store.values().map(|blob| blob.capacity()).sum::<usize>()
+    }
+
     pub fn get_vector_count(&self) -> usize {
         self.vector_index.len()
     }
@@ -559,7 +1065,7 @@ impl VectorMemoryDatabase {
     }
     
     pub fn get_semantic_cluster_count(&self) -> usize {
-        self.semantic_clusters.len()
+        self.get_cluster_distribution().len()
     }
     
     pub fn get_temporal_entry_count(&self) -> usize {
@@ -577,4 +1083,407 @@ pub struct MemoryTrends {
     pub temporal_distribution: Vec<(String, usize)>,
     pub quality_distribution: Vec<(String, usize)>,
     pub blockchain_verification_rate: f64,
-} 
\ No newline at end of file
+} 
+
+impl VectorMemoryDatabase {
+    pub(crate) fn store_capsule(&mut self, capsule: MemoryCapsule, blockchain_hash: String) -> bool {
+        // Generate high-dimensional semantic embedding
+        let mut embedding_vector = self.generate_semantic_embedding(&capsule);
+        let metadata_vector = self.generate_metadata_vector(&capsule);
+        
+        if self.embedding_validation {
+            if !is_finite_vector(&embedding_vector) || !is_finite_vector(&metadata_vector) {
+                console_log!("Rejected memory capsule {}: embedding contains NaN or Inf", capsule.capsule_id);
+                return false;
+            }
+            normalize_vector(&mut embedding_vector);
+        }
+        
+        let vector_entry = VectorEntry {
+            capsule_id: capsule.capsule_id.clone(),
+            embedding_vector,
+            metadata_vector,
+            context_tags: capsule.semantic_tags.clone(),
+            timestamp: capsule.timestamp,
+            quality_score: Self::calculate_enhanced_quality_score(&capsule),
+            importance_score: capsule.importance_score,
+            access_pattern: AccessPattern {
+                total_accesses: 0,
+                recent_accesses: Vec::new(),
+                access_contexts: Vec::new(),
+                collaborative_filters: Vec::new(),
+            },
+            compression_ratio: self.calculate_compression_ratio(&capsule),
+            original_size: capsule.compressed_data.len(),
+        };
+        
+        // Store in vector index
+        self.vector_index.insert(capsule.capsule_id.clone(), vector_entry.clone());
+        self.retired_capsules.remove(&capsule.capsule_id);
+        
+        // Store blockchain reference
+        self.blockchain_hashes.insert(capsule.capsule_id.clone(), blockchain_hash);
+        
+        // Keep the source capsule for re-embedding; its data is inline until the next compaction
+        self.source_capsules.insert(capsule.capsule_id.clone(), capsule.clone());
+        self.blob_refs.remove(&capsule.capsule_id);
+        
+        // Update semantic clusters
+        self.update_semantic_clusters(&capsule.capsule_id, &capsule.semantic_tags);
+        
+        // Update temporal index
+        self.temporal_index.push((capsule.timestamp, capsule.capsule_id.clone()));
+        self.temporal_index.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        
+        // Update quality rankings
+        self.quality_rankings.push((vector_entry.quality_score, capsule.capsule_id.clone()));
+        self.quality_rankings.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        
+        // Update statistics
+        self.total_memory_size += capsule.compressed_data.len();
+        self.update_average_vector_dimension(&vector_entry.embedding_vector);
+        
+        console_log!("Stored memory capsule {} in vector database", capsule.capsule_id);
+        true
+    }
+
+    /// (capsule_id, blockchain_hash) for every indexed capsule; the hash is "" if none was recorded
+    pub fn blockchain_links(&self) -> Vec<(&str, &str)> {
+        self.vector_index.keys()
+            .map(|id| (id.as_str(), self.blockchain_hashes.get(id).map_or("", |hash| hash.as_str())))
+            .collect()
+    }
+
+    pub fn has_entry(&self, capsule_id: &str) -> bool {
+        self.vector_index.contains_key(capsule_id)
+    }
+
+    /// Whether the capsule was removed on purpose rather than lost
+    pub fn is_retired(&self, capsule_id: &str) -> bool {
+        self.retired_capsules.contains(capsule_id)
+    }
+
+    /// Check that the temporal and quality indices mirror vector_index
+    pub fn check_index_integrity(&self) -> Vec<IntegrityIssue> {
+        let mut issues = Vec::new();
+        let indices: [(&str, &Vec<(f64, String)>); 2] = [
+            ("temporal_index", &self.temporal_index),
+            ("quality_rankings", &self.quality_rankings),
+        ];
+        
+        for (name, index) in indices {
+            let mut seen = HashSet::new();
+            for (_, capsule_id) in index {
+                if !self.vector_index.contains_key(capsule_id) {
+                    issues.push(IntegrityIssue::new(IntegritySeverity::Error, "vector_db",
+                        format!("{} references missing capsule {}", name, capsule_id)));
+                } else if !seen.insert(capsule_id) {
+                    issues.push(IntegrityIssue::new(IntegritySeverity::Warning, "vector_db",
+                        format!("{} lists capsule {} more than once", name, capsule_id)));
+                }
+            }
+            for capsule_id in self.vector_index.keys() {
+                if !seen.contains(capsule_id) {
+                    issues.push(IntegrityIssue::new(IntegritySeverity::Error, "vector_db",
+                        format!("capsule {} is missing from {}", capsule_id, name)));
+                }
+            }
+        }
+        
+        if self.temporal_index.windows(2).any(|pair| pair[0].0 > pair[1].0) {
+            issues.push(IntegrityIssue::new(IntegritySeverity::Error, "vector_db",
+                "temporal_index is not sorted by timestamp".to_string()));
+        }
+        if self.quality_rankings.windows(2).any(|pair| pair[0].0 < pair[1].0) {
+            issues.push(IntegrityIssue::new(IntegritySeverity::Error, "vector_db",
+                "quality_rankings is not sorted by descending quality".to_string()));
+        }
+        for (timestamp, capsule_id) in &self.temporal_index {
+            if let Some(entry) = self.vector_index.get(capsule_id) {
+                if entry.timestamp != *timestamp {
+                    issues.push(IntegrityIssue::new(IntegritySeverity::Warning, "vector_db",
+                        format!("temporal_index timestamp for {} differs from its entry", capsule_id)));
+                }
+            }
+        }
+        issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::tests::sample_capsule;
+
+    #[test]
+    fn validation_rejects_nan_embeddings() {
+        let mut db = VectorMemoryDatabase::new();
+        db.set_embedding_validation(true);
+        let mut capsule = sample_capsule("nan", &["tag"]);
+        capsule.context_vector[3] = f64::NAN;
+
+        assert!(!db.store_capsule(capsule, "hash".to_string()));
+        assert_eq!(db.get_vector_count(), 0);
+        assert!(db.check_query_vector(&[f64::NAN; BASE_EMBEDDING_DIM]).is_err());
+    }
+
+    #[test]
+    fn validation_normalizes_stored_embeddings() {
+        let mut db = VectorMemoryDatabase::new();
+        db.set_embedding_validation(true);
+        let mut capsule = sample_capsule("large", &["tag"]);
+        capsule.context_vector = vec![40.0; 16];
+
+        assert!(db.store_capsule(capsule, "hash".to_string()));
+        let embedding = &db.vector_index["large"].embedding_vector;
+        let magnitude = embedding.iter().map(|x| x * x).sum::<f64>().sqrt();
+        assert!((magnitude - 1.0).abs() < 1e-9);
+    }
+
+    fn query(max_candidates: Option<usize>) -> VectorSearchQuery {
+        VectorSearchQuery {
+            query_vector: vec![1.0; BASE_EMBEDDING_DIM],
+            context_filter: Vec::new(),
+            time_range: None,
+            quality_threshold: 0.0,
+            max_results: 10,
+            search_algorithm: SearchAlgorithm::CosineSimilarity,
+            max_candidates,
+            hybrid_config: None,
+            include_content: false,
+        }
+    }
+
+    #[test]
+    fn candidate_budget_scans_highest_quality_first() {
+        let mut db = VectorMemoryDatabase::new();
+        for i in 0..10 {
+            let mut capsule = sample_capsule(&format!("capsule_{}", i), &["tag"]);
+            capsule.importance_score = i as f64 / 10.0;
+            assert!(db.store_capsule(capsule, format!("hash_{}", i)));
+        }
+
+        assert_eq!(db.run_search(&query(None)).len(), 10);
+        let mut ids: Vec<String> = db.run_search(&query(Some(3))).into_iter()
+            .map(|result| result.capsule_id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec!["capsule_7", "capsule_8", "capsule_9"]);
+    }
+
+    #[test]
+    fn small_tag_clusters_collapse_into_misc() {
+        let mut db = VectorMemoryDatabase::new();
+        for (id, tags) in [("a", &["shared", "solo_a"][..]), ("b", &["shared", "solo_b"]), ("c", &["solo_c"])] {
+            assert!(db.store_capsule(sample_capsule(id, tags), format!("hash_{}", id)));
+        }
+        assert_eq!(db.get_semantic_cluster_count(), 4);
+
+        db.set_min_cluster_size(2);
+        let distribution = db.get_cluster_distribution();
+        assert_eq!(distribution.len(), 2);
+        assert_eq!(distribution["shared"], 2);
+        assert_eq!(distribution[MISC_CLUSTER_TAG], 3);
+        assert_eq!(db.get_semantic_cluster_count(), 2);
+    }
+
+    fn varied_capsules() -> Vec<MemoryCapsule> {
+        (0..4).map(|i| {
+            let mut capsule = sample_capsule(&format!("capsule_{}", i), &["tag"]);
+            capsule.context_vector = (0..16).map(|j| ((i * 16 + j) as f64 * 0.37).sin()).collect();
+            capsule
+        }).collect()
+    }
+
+    fn similarity_ranking(db: &mut VectorMemoryDatabase, query_vector: Vec<f64>) -> Vec<(String, f64)> {
+        let mut search = query(None);
+        search.query_vector = query_vector;
+        db.run_search(&search).into_iter()
+            .map(|result| (result.capsule_id, result.similarity_score))
+            .collect()
+    }
+
+    #[test]
+    fn reembedding_migrates_to_the_new_dimension() {
+        let mut migrated = VectorMemoryDatabase::new();
+        let mut fresh = VectorMemoryDatabase::new();
+        fresh.set_embedding_dim(32).unwrap();
+        for capsule in varied_capsules() {
+            assert!(migrated.store_capsule(capsule.clone(), "hash".to_string()));
+            assert!(fresh.store_capsule(capsule, "hash".to_string()));
+        }
+
+        migrated.set_embedding_dim(32).unwrap();
+        assert!(migrated.set_embedding_dim(0).is_err());
+        assert_eq!(migrated.reembed_all(), 4);
+        assert!(migrated.vector_index.values().all(|entry| entry.embedding_vector.len() == 32));
+
+        let target = fresh.vector_index["capsule_2"].embedding_vector.clone();
+        let ranking = similarity_ranking(&mut migrated, target.clone());
+        assert_eq!(ranking[0].0, "capsule_2");
+        // Embeddings carry a little privacy noise, so scores agree only approximately
+        let expected = similarity_ranking(&mut fresh, target);
+        assert_eq!(ranking.len(), expected.len());
+        for ((id, score), (expected_id, expected_score)) in ranking.iter().zip(&expected) {
+            assert_eq!(id, expected_id);
+            assert!((score - expected_score).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn consolidation_boosts_frequently_accessed_capsules() {
+        let mut db = VectorMemoryDatabase::new();
+        for (id, tag) in [("used", "popular"), ("unused", "ignored")] {
+            let mut capsule = sample_capsule(id, &[tag]);
+            capsule.timestamp = now_ms(); // Too recent to be pruned as obsolete
+            assert!(db.store_capsule(capsule, format!("hash_{}", id)));
+        }
+        let mut search = query(None);
+        search.context_filter = vec!["popular".to_string()];
+        for _ in 0..5 {
+            assert_eq!(db.run_search(&search).len(), 1);
+        }
+
+        db.set_importance_boost_factor(0.2);
+        assert!(db.consolidate_memory());
+        assert!(db.vector_index["used"].importance_score > 0.5);
+        assert_eq!(db.vector_index["unused"].importance_score, 0.5);
+        assert_eq!(db.quality_rankings[0].1, "used");
+    }
+
+    #[test]
+    fn hybrid_weighted_only_on_euclidean_matches_euclidean_search() {
+        let mut db = VectorMemoryDatabase::new();
+        for capsule in varied_capsules() {
+            assert!(db.store_capsule(capsule, "hash".to_string()));
+        }
+        let query_vector = db.vector_index["capsule_1"].embedding_vector.iter().map(|x| x * 0.5 + 0.01).collect::<Vec<_>>();
+        let ranking = |db: &mut VectorMemoryDatabase, algorithm: SearchAlgorithm, hybrid_config: Option<HybridConfig>| {
+            let mut search = query(None);
+            search.query_vector = query_vector.clone();
+            search.search_algorithm = algorithm;
+            search.hybrid_config = hybrid_config;
+            db.run_search(&search).into_iter()
+                .map(|result| (result.capsule_id, result.similarity_score))
+                .collect::<Vec<_>>()
+        };
+
+        let euclidean = ranking(&mut db, SearchAlgorithm::EuclideanDistance, None);
+        assert_eq!(euclidean.len(), 4);
+        for weight in [1.0, 3.0] {
+            let config = HybridConfig { metrics: vec![(SimilarityMetric::Euclidean, weight), (SimilarityMetric::Cosine, 0.0)] };
+            let hybrid = ranking(&mut db, SearchAlgorithm::Hybrid, Some(config));
+            assert_eq!(hybrid.len(), euclidean.len());
+            for ((id, score), (expected_id, expected_score)) in hybrid.iter().zip(&euclidean) {
+                assert_eq!(id, expected_id);
+                assert!((score - expected_score).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn near_identical_tag_clusters_merge() {
+        let mut db = VectorMemoryDatabase::new();
+        for (id, tag) in [("nav_a", "navigation"), ("nav_b", "navigate"), ("nav_c", "navigation")] {
+            let mut capsule = sample_capsule(id, &[tag]);
+            capsule.context_vector = vec![0.8; 16];
+            assert!(db.store_capsule(capsule, "hash".to_string()));
+        }
+        let mut distinct = sample_capsule("vision", &["vision"]);
+        distinct.context_vector = (0..16).map(|i| if i % 2 == 0 { 1.0 } else { -1.0 }).collect();
+        assert!(db.store_capsule(distinct, "hash".to_string()));
+
+        assert_eq!(db.merge_similar_clusters(0.999), 0);
+        assert_eq!(db.merge_similar_clusters(0.9), 1);
+        assert_eq!(db.semantic_clusters.len(), 2);
+        let mut members = db.semantic_clusters["navigation"].clone();
+        members.sort();
+        assert_eq!(members, ["nav_a", "nav_b", "nav_c"]);
+        assert_eq!(db.semantic_clusters["vision"], ["vision"]);
+    }
+
+
+    #[test]
+    fn repeated_context_queries_raise_its_count() {
+        let mut db = VectorMemoryDatabase::new();
+        assert!(db.store_capsule(sample_capsule("vision", &["vision"]), "hash".to_string()));
+        assert!(db.store_capsule(sample_capsule("audio", &["audio"]), "hash".to_string()));
+        let mut vision_query = query(None);
+        vision_query.context_filter = vec!["vision".to_string()];
+
+        for _ in 0..3 {
+            db.run_search(&vision_query);
+        }
+        let stats: HashMap<String, u32> = serde_json::from_str(&db.get_query_context_stats()).unwrap();
+        assert_eq!(stats["vision"], 3);
+        assert!(!stats.contains_key("audio"));
+    }
+
+
+    #[test]
+    fn identical_node_memory_is_stored_once() {
+        let mut db = VectorMemoryDatabase::new();
+        for id in ["first", "second"] {
+            let mut capsule = sample_capsule(id, &["tag"]);
+            capsule.timestamp = now_ms();
+            capsule.compressed_data = vec![7u8; 100];
+            assert!(db.store_capsule(capsule, format!("hash_{}", id)));
+        }
+        assert_eq!(db.get_total_memory_size(), 200);
+
+        assert!(db.consolidate_memory());
+        assert_eq!(db.blob_store.len(), 1);
+        assert_eq!(db.get_total_memory_size(), 100);
+        assert_eq!(db.materialize_source("second").unwrap().compressed_data, vec![7u8; 100]);
+    }
+
+
+    #[test]
+    fn include_content_inlines_tags_only_when_asked() {
+        let mut db = VectorMemoryDatabase::new();
+        assert!(db.store_capsule(sample_capsule("public", &["vision", "edge"]), "hash".to_string()));
+        let mut personal = sample_capsule("personal", &["diary"]);
+        personal.privacy_level = crate::memory::PrivacyLevel::Personal;
+        assert!(db.store_capsule(personal, "hash".to_string()));
+
+        let plain = db.run_search(&query(None));
+        assert_eq!(plain.len(), 2);
+        assert!(plain.iter().all(|result| result.content.is_none()));
+        assert!(!serde_json::to_string(&plain).unwrap().contains("semantic_tags"));
+
+        let mut with_content = query(None);
+        with_content.include_content = true;
+        let results = db.run_search(&with_content);
+        let public = results.iter().find(|result| result.capsule_id == "public").unwrap();
+        assert_eq!(public.content.as_ref().unwrap().semantic_tags, ["vision", "edge"]);
+        assert!(results.iter().find(|result| result.capsule_id == "personal").unwrap().content.is_none());
+    }
+
+
+    #[test]
+    fn fallback_relaxes_a_too_strict_time_range() {
+        let mut db = VectorMemoryDatabase::new();
+        assert!(db.store_capsule(sample_capsule("old", &["tag"]), "hash".to_string()));
+        let mut strict = query(None);
+        strict.time_range = Some((5_000.0, 6_000.0));
+        let strict_json = serde_json::to_string(&strict).unwrap();
+        assert_eq!(db.semantic_search(&strict_json), "[]");
+
+        let relaxed: RelaxedSearchResponse = serde_json::from_str(&db.semantic_search_with_fallback(&strict_json)).unwrap();
+        assert_eq!(relaxed.relaxed_constraints, ["time_range"]);
+        assert_eq!(relaxed.results.len(), 1);
+        assert_eq!(relaxed.results[0].capsule_id, "old");
+    }
+
+
+    #[test]
+    fn fallback_answers_a_bad_query_with_an_empty_list() {
+        let mut db = VectorMemoryDatabase::new();
+        db.set_embedding_validation(true);
+        assert_eq!(db.semantic_search_with_fallback("not json"), "[]");
+
+        let mut short = query(None);
+        short.query_vector.truncate(3);
+        assert_eq!(db.semantic_search_with_fallback(&serde_json::to_string(&short).unwrap()), "[]");
+    }
+}
@@ -1,17 +1,23 @@
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{
-    RtcPeerConnection, RtcDataChannel, RtcConfiguration, RtcIceServer,
+    RtcPeerConnection, RtcDataChannel, RtcConfiguration,
     RtcSessionDescription, RtcSessionDescriptionInit, RtcSdpType,
-    RtcIceCandidate, RtcIceCandidateInit, RtcDataChannelInit,
-    MessageEvent, Event, WebSocket, RtcPeerConnectionState
+    RtcIceCandidate, RtcIceCandidateInit, RtcDataChannelInit, RtcDataChannelEvent,
+    MessageEvent, Event, WebSocket
 };
-use js_sys::{Object, Reflect, Array};
 use serde::{Serialize, Deserialize};
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 use crate::console_log;
 use wasm_bindgen::closure::Closure;
 
+/// Reliable, ordered channel for memory capsules, blocks and everything else
+pub const DATA_CHANNEL: &str = "data";
+/// Unordered channel without retransmits for latency-sensitive ErrorPropagate signals
+pub const CONTROL_CHANNEL: &str = "control";
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct WebRTCOffer {
     pub sdp: String,
@@ -24,6 +30,32 @@ pub struct WebRTCAnswer {
     pub sdp_type: String,
 }
 
+/// A STUN or TURN server, in the shape of a browser RTCIceServer
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IceServer {
+    pub urls: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>, // TURN only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>, // TURN only
+}
+
+impl IceServer {
+    pub fn stun(url: &str) -> IceServer {
+        IceServer { urls: vec![url.to_string()], username: None, credential: None }
+    }
+}
+
+/// Transport figures extracted from RTCPeerConnection.getStats()
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RtcStatsSummary {
+    pub peer_id: String,
+    pub round_trip_time_ms: Option<f64>, // From the selected candidate pair, once measured
+    pub bytes_sent: f64,
+    pub bytes_received: f64,
+    pub packets_lost: f64,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ICECandidate {
     pub candidate: String,
@@ -31,13 +63,25 @@ pub struct ICECandidate {
     pub sdp_m_line_index: Option<u16>,
 }
 
+// Event handlers installed on a peer connection; dropping this frees them
+struct PeerConnectionCallbacks {
+    _onicecandidate: Closure<dyn FnMut(Event)>,
+    _onconnectionstatechange: Closure<dyn FnMut(Event)>,
+    _ondatachannel: Closure<dyn FnMut(RtcDataChannelEvent)>,
+}
+
 #[wasm_bindgen]
 #[derive(Clone)]
 pub struct WebRTCManager {
     device_id: String,
-    ice_servers: Vec<String>,
+    ice_servers: Vec<IceServer>,
     connected_peers: Vec<String>,
-    data_channels: HashMap<String, RtcDataChannel>, // peer_id -> data_channel
+    peer_connections: HashMap<String, RtcPeerConnection>, // peer_id -> the one connection used for offer/answer/ICE
+    data_channels: Rc<RefCell<HashMap<String, HashMap<String, RtcDataChannel>>>>, // peer_id -> label -> channel, also filled by ondatachannel
+    opened_channels: Rc<RefCell<Vec<String>>>, // peer_ids whose DATA_CHANNEL opened, filled by onopen
+    inbox: Rc<RefCell<Vec<String>>>, // Raw messages received on any data channel, filled by onmessage
+    peer_connection_callbacks: HashMap<String, Rc<PeerConnectionCallbacks>>, // Kept alive until close_connection
+    ice_candidate_callback: Rc<RefCell<Option<js_sys::Function>>>, // Called with (peer_id, ICECandidate JSON)
 }
 
 #[wasm_bindgen]
@@ -49,24 +93,26 @@ impl WebRTCManager {
         WebRTCManager {
             device_id,
             ice_servers: vec![
-                "stun:stun.l.google.com:19302".to_string(),
-                "stun:stun1.l.google.com:19302".to_string(),
+                IceServer::stun("stun:stun.l.google.com:19302"),
+                IceServer::stun("stun:stun1.l.google.com:19302"),
             ],
             connected_peers: Vec::new(),
-            data_channels: HashMap::new(),
+            peer_connections: HashMap::new(),
+            data_channels: Rc::new(RefCell::new(HashMap::new())),
+            opened_channels: Rc::new(RefCell::new(Vec::new())),
+            inbox: Rc::new(RefCell::new(Vec::new())),
+            peer_connection_callbacks: HashMap::new(),
+            ice_candidate_callback: Rc::new(RefCell::new(None)),
         }
     }
 
     #[wasm_bindgen]
     pub fn connect_signaling_server(&mut self, server_url: &str) -> Result<(), JsValue> {
-        console_log!("Connecting to signaling server: {}", server_url);
+        console_log!("Connecting to signaling server {} for device {}", server_url, self.device_id);
         
         let ws = WebSocket::new(server_url)?;
         ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
         
-        // Clone device_id for closure
-        let device_id = self.device_id.clone();
-        
         // On open - register with signaling server
         let onopen_callback = Closure::wrap(Box::new(move |_event: Event| {
             console_log!("Connected to signaling server, registering device");
@@ -91,51 +137,91 @@ impl WebRTCManager {
         Ok(())
     }
 
+    /// Create the peer connection for `peer_id`; an existing one is kept and reused
     #[wasm_bindgen]
     pub fn create_peer_connection(&mut self, peer_id: &str) -> Result<(), JsValue> {
-        console_log!("Creating peer connection for: {}", peer_id);
-        
-        // Create ICE server configuration
-        let ice_servers = Array::new();
-        for server_url in &self.ice_servers {
-            let ice_server = Object::new();
-            let urls = Array::new();
-            urls.push(&JsValue::from_str(server_url));
-            Reflect::set(&ice_server, &"urls".into(), &urls)?;
-            ice_servers.push(&ice_server);
+        if self.peer_connections.contains_key(peer_id) {
+            return Ok(());
         }
+        console_log!("Creating peer connection for: {}", peer_id);
         
-        let config = Object::new();
-        Reflect::set(&config, &"iceServers".into(), &ice_servers)?;
-        
-        // Convert Object to JsValue, then to RtcConfiguration
-        let config_value: JsValue = config.into();
-        let rtc_config: RtcConfiguration = config_value.into();
+        // Create ICE server configuration, including any TURN credentials
+        let config_value = js_sys::JSON::parse(&self.ice_configuration_json())?;
+        let rtc_config: RtcConfiguration = config_value.unchecked_into();
         let peer_connection = RtcPeerConnection::new_with_configuration(&rtc_config)?;
         
         // Set up event handlers
-        self.setup_peer_connection_handlers(&peer_connection, peer_id)?;
+        let callbacks = self.setup_peer_connection_handlers(&peer_connection, peer_id);
+        self.peer_connection_callbacks.insert(peer_id.to_string(), Rc::new(callbacks));
+        self.peer_connections.insert(peer_id.to_string(), peer_connection);
         
         console_log!("Peer connection created successfully for: {}", peer_id);
         Ok(())
     }
 
-    fn setup_peer_connection_handlers(&self, pc: &RtcPeerConnection, peer_id: &str) -> Result<(), JsValue> {
+    /// Relay through a TURN server when direct and STUN-assisted connections fail (e.g. symmetric NAT)
+    #[wasm_bindgen]
+    pub fn add_turn_server(&mut self, url: String, username: String, credential: String) {
+        self.ice_servers.retain(|server| !server.urls.contains(&url));
+        self.ice_servers.push(IceServer {
+            urls: vec![url],
+            username: Some(username),
+            credential: Some(credential),
+        });
+    }
+
+    /// RTCConfiguration JSON (`{"iceServers": [...]}`) used for new peer connections
+    #[wasm_bindgen]
+    pub fn ice_configuration_json(&self) -> String {
+        serde_json::json!({ "iceServers": self.ice_servers }).to_string()
+    }
+
+    // The stored connection for a peer; handles are cheap to clone and refer to the same connection
+    fn peer_connection(&self, peer_id: &str) -> Result<RtcPeerConnection, JsValue> {
+        self.peer_connections.get(peer_id)
+            .cloned()
+            .ok_or_else(|| JsValue::from_str(&format!("No peer connection for: {}", peer_id)))
+    }
+
+    /// Receive each locally generated ICE candidate as `(peer_id, candidate_json)`, where
+    /// candidate_json is a serialized ICECandidate, so it can be relayed to the remote peer
+    #[wasm_bindgen]
+    pub fn set_ice_candidate_callback(&mut self, cb: js_sys::Function) {
+        *self.ice_candidate_callback.borrow_mut() = Some(cb);
+    }
+
+    pub fn clear_ice_candidate_callback(&mut self) {
+        *self.ice_candidate_callback.borrow_mut() = None;
+    }
+
+    fn setup_peer_connection_handlers(&self, pc: &RtcPeerConnection, peer_id: &str) -> PeerConnectionCallbacks {
         let peer_id_clone = peer_id.to_string();
+        let ice_candidate_callback = self.ice_candidate_callback.clone();
         
-        // Handle ICE candidates
+        // Handle ICE candidates by handing them to the registered callback
         let onicecandidate_callback = Closure::wrap(Box::new(move |event: Event| {
             if let Some(candidate_event) = event.dyn_ref::<web_sys::RtcPeerConnectionIceEvent>() {
-                if let Some(_ice_candidate) = candidate_event.candidate() {
+                if let Some(ice_candidate) = candidate_event.candidate() {
                     console_log!("Generated ICE candidate for {}", peer_id_clone);
-                    // Send candidate via signaling server
-                    // This will be handled by the P2PNetwork layer
+                    let candidate = ICECandidate {
+                        candidate: ice_candidate.candidate(),
+                        sdp_mid: ice_candidate.sdp_mid(),
+                        sdp_m_line_index: ice_candidate.sdp_m_line_index(),
+                    };
+                    let candidate_json = serde_json::to_string(&candidate).unwrap_or_default();
+                    match ice_candidate_callback.borrow().as_ref() {
+                        Some(cb) => {
+                            if let Err(e) = cb.call2(&JsValue::NULL, &JsValue::from_str(&peer_id_clone), &JsValue::from_str(&candidate_json)) {
+                                console_log!("ICE candidate callback failed for {}: {:?}", peer_id_clone, e);
+                            }
+                        },
+                        None => console_log!("No ICE candidate callback set; candidate for {} dropped", peer_id_clone),
+                    }
                 }
             }
         }) as Box<dyn FnMut(Event)>);
         
         pc.set_onicecandidate(Some(onicecandidate_callback.as_ref().unchecked_ref()));
-        onicecandidate_callback.forget();
         
         // Handle connection state changes
         let peer_id_clone2 = peer_id.to_string();
@@ -144,42 +230,79 @@ impl WebRTCManager {
         }) as Box<dyn FnMut(Event)>);
         
         pc.set_onconnectionstatechange(Some(onconnectionstatechange_callback.as_ref().unchecked_ref()));
-        onconnectionstatechange_callback.forget();
         
-        Ok(())
+        // The answering side receives the offerer's data channel here
+        let peer_id_clone3 = peer_id.to_string();
+        let data_channels = self.data_channels.clone();
+        let opened_channels = self.opened_channels.clone();
+        let inbox = self.inbox.clone();
+        let ondatachannel_callback = Closure::wrap(Box::new(move |event: RtcDataChannelEvent| {
+            let channel = event.channel();
+            console_log!("Received data channel '{}' from peer: {}", channel.label(), peer_id_clone3);
+            if Self::setup_data_channel_handlers(&channel, &peer_id_clone3, opened_channels.clone(), inbox.clone()).is_ok() {
+                data_channels.borrow_mut()
+                    .entry(peer_id_clone3.clone())
+                    .or_default()
+                    .insert(channel.label(), channel);
+            }
+        }) as Box<dyn FnMut(RtcDataChannelEvent)>);
+        
+        pc.set_ondatachannel(Some(ondatachannel_callback.as_ref().unchecked_ref()));
+        
+        PeerConnectionCallbacks {
+            _onicecandidate: onicecandidate_callback,
+            _onconnectionstatechange: onconnectionstatechange_callback,
+            _ondatachannel: ondatachannel_callback,
+        }
     }
 
+    /// Open a data channel labelled `channel_name`. `ordered` and `max_retransmits` map to
+    /// RTCDataChannelInit; `None` retransmits until delivered, `Some(0)` never retransmits
     #[wasm_bindgen]
-    pub fn create_data_channel(&mut self, peer_id: &str, channel_name: &str) -> Result<(), JsValue> {
-        console_log!("Creating data channel '{}' for peer: {}", channel_name, peer_id);
+    pub fn create_data_channel(&mut self, peer_id: &str, channel_name: &str, ordered: bool, max_retransmits: Option<u16>) -> Result<(), JsValue> {
+        console_log!("Creating data channel '{}' for peer: {} (ordered: {}, max_retransmits: {:?})",
+                    channel_name, peer_id, ordered, max_retransmits);
         
-        // Create a new peer connection for this operation
-        let config = RtcConfiguration::new();
-        let pc = RtcPeerConnection::new_with_configuration(&config)?;
+        let pc = self.peer_connection(peer_id)?;
         
         // Create data channel options
-        let mut options = RtcDataChannelInit::new();
-        options.set_ordered(true);
+        let options = RtcDataChannelInit::new();
+        options.set_ordered(ordered);
+        if let Some(retransmits) = max_retransmits {
+            options.set_max_retransmits(retransmits);
+        }
         
         // Create the data channel
         let channel = pc.create_data_channel_with_data_channel_dict(channel_name, &options);
         
         // Store the data channel
-        self.data_channels.insert(peer_id.to_string(), channel.clone());
+        self.data_channels.borrow_mut()
+            .entry(peer_id.to_string())
+            .or_default()
+            .insert(channel_name.to_string(), channel.clone());
         
         // Set up event handlers
-        self.setup_data_channel_handlers(&channel, peer_id)?;
+        Self::setup_data_channel_handlers(&channel, peer_id, self.opened_channels.clone(), self.inbox.clone())?;
         
         console_log!("Data channel '{}' created for peer: {}", channel_name, peer_id);
         Ok(())
     }
 
-    fn setup_data_channel_handlers(&self, channel: &RtcDataChannel, peer_id: &str) -> Result<(), JsValue> {
+    fn setup_data_channel_handlers(
+        channel: &RtcDataChannel,
+        peer_id: &str,
+        opened_channels: Rc<RefCell<Vec<String>>>,
+        inbox: Rc<RefCell<Vec<String>>>,
+    ) -> Result<(), JsValue> {
         let peer_id_clone = peer_id.to_string();
+        let label = channel.label();
         
-        // Handle data channel open
+        // Handle data channel open; the connection counts as open once its DATA_CHANNEL is
         let onopen_callback = Closure::wrap(Box::new(move |_event: Event| {
-            console_log!("✅ Data channel opened for peer: {}", peer_id_clone);
+            console_log!("✅ Data channel '{}' opened for peer: {}", label, peer_id_clone);
+            if label == DATA_CHANNEL {
+                opened_channels.borrow_mut().push(peer_id_clone.clone());
+            }
         }) as Box<dyn FnMut(Event)>);
         
         channel.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
@@ -191,7 +314,8 @@ impl WebRTCManager {
             if let Ok(message) = event.data().dyn_into::<js_sys::JsString>() {
                 let message_str = String::from(message);
                 console_log!("📨 Received P2P message from {}: {}", peer_id_clone2, message_str);
-                // Message will be handled by P2PNetwork layer
+                // Queued for P2PNetwork::process_incoming_messages
+                inbox.borrow_mut().push(message_str);
             }
         }) as Box<dyn FnMut(MessageEvent)>);
         
@@ -223,18 +347,19 @@ impl WebRTCManager {
     pub async fn create_offer(&mut self, peer_id: &str) -> Result<String, JsValue> {
         console_log!("Creating offer for peer: {}", peer_id);
         
-        // Create a new peer connection for this operation
-        let config = RtcConfiguration::new();
-        let pc = RtcPeerConnection::new_with_configuration(&config)?;
+        self.create_peer_connection(peer_id)?;
+        let pc = self.peer_connection(peer_id)?;
         
-        // Create data channel before creating offer
-        self.create_data_channel(peer_id, "data")?;
+        // The offer must carry a data channel; keep any the caller already created
+        if !self.data_channels.borrow().contains_key(peer_id) {
+            self.create_data_channel(peer_id, DATA_CHANNEL, true, None)?;
+        }
         
         let offer = wasm_bindgen_futures::JsFuture::from(pc.create_offer()).await?;
         let offer_desc = offer.dyn_into::<RtcSessionDescription>()?;
         
         // Set local description - convert RtcSessionDescription to RtcSessionDescriptionInit
-        let mut local_desc = RtcSessionDescriptionInit::new(offer_desc.type_());
+        let local_desc = RtcSessionDescriptionInit::new(offer_desc.type_());
         local_desc.set_sdp(&offer_desc.sdp());
         
         let set_local_promise = pc.set_local_description(&local_desc);
@@ -255,12 +380,11 @@ impl WebRTCManager {
         let offer: WebRTCOffer = serde_json::from_str(offer_json)
             .map_err(|e| JsValue::from_str(&format!("Invalid offer JSON: {}", e)))?;
         
-        // Create a new peer connection for this operation
-        let config = RtcConfiguration::new();
-        let pc = RtcPeerConnection::new_with_configuration(&config)?;
+        self.create_peer_connection(peer_id)?;
+        let pc = self.peer_connection(peer_id)?;
         
         // Set remote description (the offer)
-        let mut remote_desc = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
+        let remote_desc = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
         remote_desc.set_sdp(&offer.sdp);
         
         let set_remote_promise = pc.set_remote_description(&remote_desc);
@@ -271,7 +395,7 @@ impl WebRTCManager {
         let answer_desc = answer.dyn_into::<RtcSessionDescription>()?;
         
         // Set local description - convert RtcSessionDescription to RtcSessionDescriptionInit
-        let mut local_desc = RtcSessionDescriptionInit::new(answer_desc.type_());
+        let local_desc = RtcSessionDescriptionInit::new(answer_desc.type_());
         local_desc.set_sdp(&answer_desc.sdp());
         
         let set_local_promise = pc.set_local_description(&local_desc);
@@ -292,11 +416,9 @@ impl WebRTCManager {
         let answer: WebRTCAnswer = serde_json::from_str(answer_json)
             .map_err(|e| JsValue::from_str(&format!("Invalid answer JSON: {}", e)))?;
         
-        // Create a new peer connection for this operation
-        let config = RtcConfiguration::new();
-        let pc = RtcPeerConnection::new_with_configuration(&config)?;
+        let pc = self.peer_connection(peer_id)?;
         
-        let mut remote_desc = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
+        let remote_desc = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
         remote_desc.set_sdp(&answer.sdp);
         
         let set_remote_promise = pc.set_remote_description(&remote_desc);
@@ -318,11 +440,9 @@ impl WebRTCManager {
         let ice_candidate: ICECandidate = serde_json::from_str(candidate_json)
             .map_err(|e| JsValue::from_str(&format!("Invalid candidate JSON: {}", e)))?;
         
-        // Create a new peer connection for this operation
-        let config = RtcConfiguration::new();
-        let pc = RtcPeerConnection::new_with_configuration(&config)?;
+        let pc = self.peer_connection(peer_id)?;
         
-        let mut candidate_init = RtcIceCandidateInit::new(&ice_candidate.candidate);
+        let candidate_init = RtcIceCandidateInit::new(&ice_candidate.candidate);
         if let Some(mid) = &ice_candidate.sdp_mid {
             candidate_init.set_sdp_mid(Some(mid));
         }
@@ -340,10 +460,23 @@ impl WebRTCManager {
 
     #[wasm_bindgen]
     pub fn send_data(&self, peer_id: &str, data: &str) -> Result<(), JsValue> {
-        console_log!("📤 Sending data to peer {} via WebRTC: {}", peer_id, data);
+        self.send_on_channel(peer_id, DATA_CHANNEL, data)
+    }
+
+    /// Send on the channel labelled `channel_name`, falling back to DATA_CHANNEL when that
+    /// channel does not exist or is not open yet
+    #[wasm_bindgen]
+    pub fn send_on_channel(&self, peer_id: &str, channel_name: &str, data: &str) -> Result<(), JsValue> {
+        console_log!("📤 Sending data to peer {} via WebRTC '{}': {}", peer_id, channel_name, data);
+        
+        let data_channels = self.data_channels.borrow();
+        let channels = data_channels.get(peer_id);
+        let preferred = channels
+            .and_then(|channels| channels.get(channel_name))
+            .filter(|channel| channel.ready_state() == web_sys::RtcDataChannelState::Open);
         
         // Check if we have a data channel for this peer
-        if let Some(channel) = self.data_channels.get(peer_id) {
+        if let Some(channel) = preferred.or_else(|| channels.and_then(|channels| channels.get(DATA_CHANNEL))) {
             // Check if the channel is ready
             if channel.ready_state() == web_sys::RtcDataChannelState::Open {
                 match channel.send_with_str(data) {
@@ -371,7 +504,7 @@ impl WebRTCManager {
     #[wasm_bindgen]
     pub fn is_connected(&self, peer_id: &str) -> bool {
         // Check if we have a data channel and it's open
-        if let Some(channel) = self.data_channels.get(peer_id) {
+        if let Some(channel) = self.data_channels.borrow().get(peer_id).and_then(|channels| channels.get(DATA_CHANNEL)) {
             channel.ready_state() == web_sys::RtcDataChannelState::Open
         } else {
             // Fallback: check connected_peers list
@@ -379,16 +512,43 @@ impl WebRTCManager {
         }
     }
 
+    /// Drain the raw messages received on data channels since the last call, oldest first
+    #[wasm_bindgen]
+    pub fn drain_inbox(&self) -> Vec<String> {
+        self.inbox.borrow_mut().drain(..).collect()
+    }
+
+    /// Drain the peers whose data channel has opened since the last call
+    pub fn take_opened_channels(&self) -> Vec<String> {
+        self.opened_channels.borrow_mut().drain(..).collect()
+    }
+
+    /// Live transport stats for a peer as RtcStatsSummary JSON, or `{"error": ...}` if there
+    /// is no connection or the browser rejects the request
+    #[wasm_bindgen]
+    pub async fn get_peer_rtc_stats(&self, peer_id: &str) -> String {
+        match self.collect_rtc_stats(peer_id).await {
+            Ok(summary) => serde_json::to_string(&summary).unwrap_or_default(),
+            Err(e) => {
+                let message = e.as_string().unwrap_or_else(|| format!("{:?}", e));
+                serde_json::json!({ "error": message }).to_string()
+            }
+        }
+    }
+
     #[wasm_bindgen]
     pub fn get_connection_stats(&self) -> String {
-        let open_channels = self.data_channels.values()
+        let data_channels = self.data_channels.borrow();
+        let open_channels = data_channels.values()
+            .flat_map(|channels| channels.values())
             .filter(|channel| channel.ready_state() == web_sys::RtcDataChannelState::Open)
             .count();
         
         let stats = serde_json::json!({
             "total_connections": self.connected_peers.len(),
             "active_channels": open_channels,
-            "data_channels": self.data_channels.len(),
+            "data_channels": data_channels.values().map(|channels| channels.len()).sum::<usize>(),
+            "peer_connections": self.peer_connections.len(),
             "connected_peers": self.connected_peers.len()
         });
         
@@ -399,11 +559,20 @@ impl WebRTCManager {
     pub fn close_connection(&mut self, peer_id: &str) -> Result<(), JsValue> {
         console_log!("🔒 Closing connection to peer: {}", peer_id);
         
-        // Close and remove the data channel
-        if let Some(channel) = self.data_channels.remove(peer_id) {
-            channel.close();
-            console_log!("📤 Closed data channel for peer: {}", peer_id);
+        // Close and remove the data channels
+        if let Some(channels) = self.data_channels.borrow_mut().remove(peer_id) {
+            for channel in channels.values() {
+                channel.close();
+            }
+            console_log!("📤 Closed {} data channel(s) for peer: {}", channels.len(), peer_id);
+        }
+        if let Some(pc) = self.peer_connections.remove(peer_id) {
+            pc.set_onicecandidate(None);
+            pc.set_onconnectionstatechange(None);
+            pc.set_ondatachannel(None);
+            pc.close();
         }
+        self.peer_connection_callbacks.remove(peer_id);
         
         // Remove from connected peers
         self.connected_peers.retain(|id| id != peer_id);
@@ -413,8 +582,74 @@ impl WebRTCManager {
     }
 }
 
+impl WebRTCManager {
+    // Record an open event as the data channel's onopen handler would
+    #[cfg(test)]
+    pub(crate) fn simulate_channel_open(&self, peer_id: &str) {
+        self.opened_channels.borrow_mut().push(peer_id.to_string());
+    }
+
+    pub async fn collect_rtc_stats(&self, peer_id: &str) -> Result<RtcStatsSummary, JsValue> {
+        let pc = self.peer_connection(peer_id)?;
+        let report = wasm_bindgen_futures::JsFuture::from(pc.get_stats()).await?;
+        
+        let number = |stats: &JsValue, key: &str| {
+            js_sys::Reflect::get(stats, &JsValue::from_str(key)).ok().and_then(|value| value.as_f64())
+        };
+        let text = |stats: &JsValue, key: &str| {
+            js_sys::Reflect::get(stats, &JsValue::from_str(key)).ok().and_then(|value| value.as_string())
+        };
+        let flag = |stats: &JsValue, key: &str| {
+            js_sys::Reflect::get(stats, &JsValue::from_str(key)).ok().and_then(|value| value.as_bool())
+        };
+        
+        let mut summary = RtcStatsSummary { peer_id: peer_id.to_string(), ..Default::default() };
+        let entries = js_sys::try_iter(&report)?
+            .ok_or_else(|| JsValue::from_str("stats report is not iterable"))?;
+        for entry in entries {
+            // Each entry of the maplike report is an [id, stats] pair
+            let stats = js_sys::Reflect::get(&entry?, &JsValue::from_f64(1.0))?;
+            match text(&stats, "type").as_deref() {
+                Some("candidate-pair") if flag(&stats, "nominated").unwrap_or(false) => {
+                    if let Some(rtt) = number(&stats, "currentRoundTripTime") {
+                        summary.round_trip_time_ms = Some(rtt * 1000.0);
+                    }
+                    summary.bytes_sent += number(&stats, "bytesSent").unwrap_or(0.0);
+                    summary.bytes_received += number(&stats, "bytesReceived").unwrap_or(0.0);
+                },
+                Some("inbound-rtp") | Some("remote-inbound-rtp") => {
+                    summary.packets_lost += number(&stats, "packetsLost").unwrap_or(0.0);
+                },
+                _ => {},
+            }
+        }
+        Ok(summary)
+    }
+}
+
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
     fn log(s: &str);
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ice_configuration_carries_turn_credentials() {
+        let mut manager = WebRTCManager::new("device".to_string());
+        manager.add_turn_server("turn:turn.example.org:3478".to_string(), "user".to_string(), "secret".to_string());
+        manager.add_turn_server("turn:turn.example.org:3478".to_string(), "user".to_string(), "rotated".to_string());
+
+        let config: serde_json::Value = serde_json::from_str(&manager.ice_configuration_json()).unwrap();
+        let servers = config["iceServers"].as_array().unwrap();
+        let turn: Vec<&serde_json::Value> = servers.iter().filter(|server| server.get("username").is_some()).collect();
+        assert_eq!(turn.len(), 1);
+        assert_eq!(turn[0]["urls"], serde_json::json!(["turn:turn.example.org:3478"]));
+        assert_eq!(turn[0]["username"], "user");
+        assert_eq!(turn[0]["credential"], "rotated");
+        // STUN entries stay without credential fields
+        assert!(servers.iter().any(|server| server.get("credential").is_none()));
+    }
+}